@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/*
+Connections accepted while every max_clients slot was taken, with overflow = "queue": parked in
+arrival order, sockets open but unread, until the accept loop can give the oldest one a slot or
+its queue_timeout_ms runs out. Owned by the accept loop alone, so no locking.
+*/
+pub struct AcceptQueue<T> {
+    waiting: VecDeque<(T, Instant)>,
+}
+
+impl<T> AcceptQueue<T> {
+    pub fn new() -> AcceptQueue<T> {
+        return AcceptQueue { waiting: VecDeque::new() };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.waiting.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.waiting.is_empty();
+    }
+
+    // Park `item` at the back, unless `depth` are waiting already: then it comes straight back.
+    pub fn park(&mut self, item: T, depth: usize, now: Instant) -> Result<(), T> {
+        if self.waiting.len() >= depth {
+            return Err(item);
+        }
+        self.waiting.push_back((item, now));
+        return Ok(());
+    }
+
+    // The one waiting longest, to be admitted.
+    pub fn next(&mut self) -> Option<T> {
+        return self.waiting.pop_front().map(|(item, _)| item);
+    }
+
+    // Remove and return everything parked for `timeout` or longer. The oldest are at the front.
+    pub fn expired(&mut self, timeout: Duration, now: Instant) -> Vec<T> {
+        let mut expired = Vec::new();
+        while let Some((_, since)) = self.waiting.front() && now.duration_since(*since) >= timeout {
+            expired.extend(self.next());
+        }
+        return expired;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fifo_bounded_and_expiring() {
+        let start = Instant::now();
+        let mut queue = AcceptQueue::new();
+        assert_eq!(queue.park("a", 2, start), Ok(()));
+        assert_eq!(queue.park("b", 2, start + Duration::from_millis(300)), Ok(()));
+        assert_eq!(queue.park("c", 2, start + Duration::from_millis(400)), Err("c"));
+
+        // Only those that waited the whole timeout expire; the rest keep their place.
+        assert!(queue.expired(Duration::from_secs(1), start + Duration::from_millis(999)).is_empty());
+        assert_eq!(queue.expired(Duration::from_secs(1), start + Duration::from_millis(1100)), ["a"]);
+        assert_eq!(queue.park("d", 2, start + Duration::from_millis(1200)), Ok(()));
+        assert_eq!((queue.next(), queue.next(), queue.next()), (Some("b"), Some("d"), None));
+        assert!(queue.is_empty());
+    }
+}
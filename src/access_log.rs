@@ -0,0 +1,512 @@
+use serde::Deserialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::log::{self, Level, error};
+use crate::request::Request;
+use crate::response::Response;
+
+// Lines waiting for the logger thread. A burst beyond this is dropped (and counted) rather than slowing requests down.
+pub const QUEUE_CAPACITY: usize = 4096;
+// Longest a line waits in the logger's buffer before it is written out.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+// A batch this big is written right away.
+const MAX_BATCH_BYTES: usize = 64 * 1024;
+
+/*
+The default access-log line ("combined"), close to the Combined Log Format:
+
+    127.0.0.1:51234 "GET /index.html HTTP/1.1" 200 1234 "http://example.com/" "Mozilla/5.0 ..."
+*/
+pub const COMBINED: &str = "$remote_addr \"$request_line\" $status $bytes_sent $referer $user_agent";
+// NCSA common log format (with an ISO 8601 time).
+pub const COMMON: &str = "$remote_addr - - [$time] \"$request_line\" $status $bytes_sent";
+// One JSON object per line. Every placeholder renders as a JSON value here.
+pub const JSON: &str = "{\"time\":$time,\"remote_addr\":$remote_addr,\"request_id\":$request_id,\"host\":$host,\
+\"request\":$request_line,\"rewritten_path\":$rewritten_path,\"status\":$status,\"bytes_sent\":$bytes_sent,\"duration_ms\":$duration_ms,\
+\"referer\":$referer,\"user_agent\":$user_agent,\"tag\":$tag}";
+
+// What there is to know about one request/response once it has been answered.
+pub struct Record<'a> {
+    pub peer: &'a str,
+    pub req: &'a Request,
+    pub status: u16,
+    // None for a streamed body (its size isn't known up front).
+    pub bytes_sent: Option<usize>,
+    // From the request's first byte until the response was written.
+    pub duration_ms: u128,
+    // "<connection id>-<request number on that connection>".
+    pub request_id: String,
+    pub time: SystemTime,
+    // Response::log_tag, for filtering routine requests out of the log.
+    pub tag: Option<&'static str>,
+}
+
+impl<'a> Record<'a> {
+    // The record for `response`, timestamped now. duration_ms is filled in once it has been sent.
+    pub fn new(peer: &'a str, req: &'a Request, response: &Response, request_id: String) -> Record<'a> {
+        return Record {
+            peer,
+            req,
+            status: response.status.code(),
+            bytes_sent: if response.head_only { Some(0) } else { response.stream.is_none().then_some(response.body.len()) },
+            duration_ms: 0,
+            request_id,
+            time: SystemTime::now(),
+            tag: response.log_tag,
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    RemoteAddr,
+    Time,
+    RequestLine,
+    Status,
+    BytesSent,
+    DurationMs,
+    UserAgent,
+    Referer,
+    RequestId,
+    Host,
+    Tag,
+    ApiKeyId,
+    RewrittenPath,
+}
+
+const FIELDS: [(&str, Field); 13] = [
+    ("remote_addr", Field::RemoteAddr),
+    ("time", Field::Time),
+    ("request_line", Field::RequestLine),
+    ("status", Field::Status),
+    ("bytes_sent", Field::BytesSent),
+    ("duration_ms", Field::DurationMs),
+    ("user_agent", Field::UserAgent),
+    ("referer", Field::Referer),
+    ("request_id", Field::RequestId),
+    ("host", Field::Host),
+    ("tag", Field::Tag),
+    ("api_key_id", Field::ApiKeyId),
+    ("rewritten_path", Field::RewrittenPath),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    Field(Field),
+}
+
+/*
+A compiled access_log_format: the format string split once, when the config is loaded, into
+literal text and placeholders, so rendering a line is a walk over the tokens and an unknown
+placeholder is a config error instead of a surprise in the log. "$$" is a literal '$'.
+
+The config value is either a preset name ("combined", the default; "common"; "json") or a
+format string such as "$remote_addr $status $duration_ms".
+
+In text formats, $user_agent, $referer and $host come out in double quotes, or as "-" when the
+client didn't send them. Those (and the request line) come straight from the client, so control
+characters (CR/LF in particular) are stripped and '"' is escaped: a crafted header can't forge
+extra log lines. In the json preset every field is a JSON value instead (a string, a number or
+null), escaped the JSON way.
+*/
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String")]
+pub struct AccessFormat {
+    tokens: Vec<Token>,
+    json: bool,
+}
+
+impl Default for AccessFormat {
+    fn default() -> AccessFormat {
+        return AccessFormat::parse(COMBINED).expect("the combined format is valid");
+    }
+}
+
+impl TryFrom<String> for AccessFormat {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<AccessFormat, String> {
+        return AccessFormat::parse(&spec);
+    }
+}
+
+impl AccessFormat {
+    pub fn parse(spec: &str) -> Result<AccessFormat, String> {
+        let (template, json) = match spec {
+            "combined" => (COMBINED, false),
+            "common" => (COMMON, false),
+            "json" => (JSON, true),
+            _ => (spec, false),
+        };
+
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(at) = rest.find('$') {
+            literal.push_str(&rest[..at]);
+            rest = &rest[at + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                literal.push('$');
+                rest = after;
+                continue;
+            }
+
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let name = &rest[..end];
+            if name.is_empty() {
+                return Err("access_log_format: expected a placeholder name after '$' (use $$ for a literal '$')".to_string());
+            }
+            let Some((_, field)) = FIELDS.iter().find(|(known, _)| *known == name) else {
+                return Err(format!("access_log_format: unknown placeholder ${}", name));
+            };
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+            }
+            tokens.push(Token::Field(*field));
+            rest = &rest[end..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        return Ok(AccessFormat { tokens, json });
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        let mut line = String::new();
+        for token in &self.tokens {
+            match token {
+                Token::Literal(text) => line.push_str(text),
+                Token::Field(field) if self.json => line.push_str(&json_value(*field, record)),
+                Token::Field(field) => line.push_str(&text_value(*field, record)),
+            }
+        }
+        return line;
+    }
+}
+
+fn text_value(field: Field, record: &Record) -> String {
+    let req = record.req;
+    return match field {
+        Field::RemoteAddr => record.peer.to_string(),
+        Field::Time => iso8601(record.time),
+        Field::RequestLine => escaped(&format!("{} {} {}", req.original_method(), req.original_path(), req.version)),
+        Field::Status => record.status.to_string(),
+        Field::BytesSent => record.bytes_sent.map_or("-".to_string(), |n| n.to_string()),
+        Field::DurationMs => record.duration_ms.to_string(),
+        Field::UserAgent => quoted(req.header("User-Agent")),
+        Field::Referer => quoted(req.header("Referer")),
+        Field::RequestId => record.request_id.clone(),
+        Field::Host => quoted(req.header("Host")),
+        Field::Tag => record.tag.unwrap_or("-").to_string(),
+        Field::ApiKeyId => req.api_key_id.as_deref().map_or("-".to_string(), escaped),
+        Field::RewrittenPath => req.rewritten_from.as_ref().map_or("-".to_string(), |_| escaped(&req.path)),
+    };
+}
+
+fn json_value(field: Field, record: &Record) -> String {
+    let req = record.req;
+    return match field {
+        Field::RemoteAddr => json_string(Some(record.peer)),
+        Field::Time => json_string(Some(&iso8601(record.time))),
+        Field::RequestLine => json_string(Some(&format!("{} {} {}", req.original_method(), req.original_path(), req.version))),
+        Field::Status => record.status.to_string(),
+        Field::BytesSent => record.bytes_sent.map_or("null".to_string(), |n| n.to_string()),
+        Field::DurationMs => record.duration_ms.to_string(),
+        Field::UserAgent => json_string(req.header("User-Agent")),
+        Field::Referer => json_string(req.header("Referer")),
+        Field::RequestId => json_string(Some(&record.request_id)),
+        Field::Host => json_string(req.header("Host")),
+        Field::Tag => json_string(record.tag),
+        Field::ApiKeyId => json_string(req.api_key_id.as_deref()),
+        Field::RewrittenPath => json_string(req.rewritten_from.as_ref().map(|_| req.path.as_str())),
+    };
+}
+
+// `value` with control characters dropped and '\' and '"' escaped.
+fn escaped(value: &str) -> String {
+    return value.chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+}
+
+// `value` in double quotes with control characters dropped, or "-" when absent.
+fn quoted(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "-".to_string();
+    };
+    return format!("\"{}\"", escaped(value));
+}
+
+// `value` as a JSON string literal, or null when absent.
+pub(crate) fn json_string(value: Option<&str>) -> String {
+    let Some(value) = value else {
+        return "null".to_string();
+    };
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    return out;
+}
+
+enum Message {
+    Line(String),
+    // Sent once, after the last worker is done: write what's left and exit.
+    Stop,
+}
+
+/*
+Where access-log lines go: a logger thread that owns the output (stdout, or access_log_path),
+fed through a bounded channel. Workers only format their line and hand it over without blocking;
+the thread writes lines in batches, at least every FLUSH_INTERVAL. If the queue is full the line
+is dropped and counted instead: a slow disk or console must never hold up a response.
+
+shutdown() drains the queue, so nothing the workers logged before it is lost.
+*/
+pub struct AccessLogger {
+    sender: SyncSender<Message>,
+    // Printing to the console: lines get the 📝 prefix (or [ACCESS] tag) other log messages have.
+    console: bool,
+    dropped: AtomicU64,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AccessLogger {
+    pub fn spawn<W: Write + Send + 'static>(out: W, console: bool, capacity: usize) -> AccessLogger {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let thread = thread::spawn(move || run_logger(out, receiver));
+        return AccessLogger { sender, console, dropped: AtomicU64::new(0), thread: Mutex::new(Some(thread)) };
+    }
+
+    // Queue `line` if messages at `level` are logged at all. Never blocks.
+    pub fn log(&self, level: Level, line: &str) {
+        if !log::enabled(level) {
+            return;
+        }
+        let line = if self.console {
+            log::render(level, &format!("📝 {}", line), log::plain())
+        } else {
+            line.to_string()
+        };
+        match self.sender.try_send(Message::Line(line)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Lines lost because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        return self.dropped.load(Ordering::Relaxed);
+    }
+
+    // Write out everything queued so far and stop the logger thread. Later lines are dropped.
+    pub fn shutdown(&self) {
+        let Some(thread) = self.thread.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+            return;
+        };
+        // Blocking on purpose: Stop has to queue up behind the lines already waiting.
+        let _ = self.sender.send(Message::Stop);
+        let _ = thread.join();
+    }
+}
+
+fn run_logger<W: Write>(mut out: W, receiver: Receiver<Message>) {
+    let mut batch = Vec::new();
+    let mut last_flush = Instant::now();
+    loop {
+        match receiver.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Message::Line(line)) => {
+                batch.extend_from_slice(line.as_bytes());
+                batch.push(b'\n');
+                if batch.len() >= MAX_BATCH_BYTES || last_flush.elapsed() >= FLUSH_INTERVAL {
+                    flush(&mut out, &mut batch);
+                    last_flush = Instant::now();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                flush(&mut out, &mut batch);
+                last_flush = Instant::now();
+            }
+            Ok(Message::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                flush(&mut out, &mut batch);
+                return;
+            }
+        }
+    }
+}
+
+// One write per batch, so lines from other threads (on the console) can't land in the middle of one.
+fn flush<W: Write>(out: &mut W, batch: &mut Vec<u8>) {
+    if batch.is_empty() {
+        return;
+    }
+    if let Err(e) = out.write_all(batch).and_then(|_| out.flush()) {
+        error!("❌ Cannot write the access log: {}", e);
+    }
+    batch.clear();
+}
+
+/*
+UTC time as "2026-10-16T08:30:05Z". Days since the epoch to a calendar date is Howard Hinnant's
+civil_from_days algorithm (eras of 400 years, with years starting in March so the leap day is last).
+*/
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rest) = (secs / 86400, secs % 86400);
+
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, rest / 3600, rest % 3600 / 60, rest % 60
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::request::parse_request;
+    use crate::response::HTTPStatus;
+
+    #[test]
+    fn test_logger_drains_on_shutdown() {
+        let dir = crate::util::temp_root("access_logger");
+        let path = dir.join("access.log");
+        let logger = std::sync::Arc::new(AccessLogger::spawn(std::fs::File::create(&path).unwrap(), false, 64));
+
+        // Four workers, far more lines than the queue holds: some may be dropped, none may be lost.
+        let workers: Vec<_> = (0..4).map(|worker| {
+            let logger = std::sync::Arc::clone(&logger);
+            thread::spawn(move || {
+                for n in 0..100 {
+                    logger.log(Level::Info, &format!("worker {} request {}", worker, n));
+                }
+            })
+        }).collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        logger.shutdown();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count() as u64 + logger.dropped(), 400);
+        assert!(written.lines().all(|line| line.starts_with("worker ")));
+
+        // After shutdown nothing more is written.
+        logger.log(Level::Info, "late");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    fn line(format: &str, peer: &str, req: &Request, response: &Response) -> String {
+        let mut record = Record::new(peer, req, response, "7-2".to_string());
+        record.time = UNIX_EPOCH + Duration::from_secs(1_792_139_405);
+        record.duration_ms = 12;
+        return AccessFormat::parse(format).unwrap().render(&record);
+    }
+
+    #[test]
+    fn test_access_line() {
+        let req = parse_request(
+            b"GET /a.html HTTP/1.1\r\nReferer: http://x/\r\nUser-Agent: Bot \"1\"\r\n\r\n"
+        ).unwrap();
+        let response = Response::new(HTTPStatus::Ok).body("text/html", "hello");
+        assert_eq!(
+            line("combined", "127.0.0.1:5000", &req, &response),
+            "127.0.0.1:5000 \"GET /a.html HTTP/1.1\" 200 5 \"http://x/\" \"Bot \\\"1\\\"\""
+        );
+
+        let req = parse_request(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        let response = Response::new(HTTPStatus::Forbidden);
+        assert_eq!(line("combined", "10.0.0.1:1", &req, &response), "10.0.0.1:1 \"GET / HTTP/1.0\" 403 0 - -");
+    }
+
+    #[test]
+    fn test_presets_and_custom_formats() {
+        let req = parse_request(b"GET /x HTTP/1.1\r\nHost: example.com\r\nUser-Agent: a\"b\\c\r\n\r\n").unwrap();
+        let response = Response::new(HTTPStatus::NotFound).body("text/plain", "nope");
+
+        assert_eq!(
+            line("common", "10.0.0.2:80", &req, &response),
+            "10.0.0.2:80 - - [2026-10-16T08:30:05Z] \"GET /x HTTP/1.1\" 404 4"
+        );
+        assert_eq!(
+            line("json", "10.0.0.2:80", &req, &response),
+            "{\"time\":\"2026-10-16T08:30:05Z\",\"remote_addr\":\"10.0.0.2:80\",\"request_id\":\"7-2\",\
+             \"host\":\"example.com\",\"request\":\"GET /x HTTP/1.1\",\"rewritten_path\":null,\"status\":404,\"bytes_sent\":4,\
+             \"duration_ms\":12,\"referer\":null,\"user_agent\":\"a\\\"b\\\\c\",\"tag\":null}"
+        );
+        assert_eq!(
+            line("$request_id $host $status $duration_ms ms $tag $$5", "10.0.0.2:80", &req, &response),
+            "7-2 \"example.com\" 404 12 ms - $5"
+        );
+
+        // The [api_auth] key the request got in with, by id.
+        let mut authed = parse_request(b"GET /api HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(line("$api_key_id", "10.0.0.2:80", &authed, &response), "-");
+        authed.api_key_id = Some("ci".to_string());
+        assert_eq!(line("$api_key_id", "10.0.0.2:80", &authed, &response), "ci");
+
+        // A [[rewrites]] alias: the request line keeps the path asked for, $rewritten_path has the one served.
+        let mut aliased = parse_request(b"GET /docs/a HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(line("$request_line $rewritten_path", "10.0.0.2:80", &aliased, &response), "GET /docs/a HTTP/1.1 -");
+        aliased.rewritten_from = Some(std::mem::replace(&mut aliased.path, "/documentation/a".to_string()));
+        assert_eq!(
+            line("$request_line $rewritten_path", "10.0.0.2:80", &aliased, &response),
+            "GET /docs/a HTTP/1.1 /documentation/a"
+        );
+    }
+
+    #[test]
+    fn test_unknown_placeholder_is_an_error() {
+        assert_eq!(
+            AccessFormat::parse("$remote_addr $statuss"),
+            Err("access_log_format: unknown placeholder $statuss".to_string())
+        );
+        assert!(AccessFormat::parse("cost: $").is_err());
+        assert!(AccessFormat::parse("$status$duration_ms").is_ok());
+    }
+
+    #[test]
+    fn test_quoted_strips_line_breaks() {
+        assert_eq!(quoted(Some("evil\r\n1.2.3.4 \"GET /admin\"")), "\"evil1.2.3.4 \\\"GET /admin\\\"\"");
+        assert_eq!(quoted(None), "-");
+        assert_eq!(json_string(Some("a\r\nb\u{1}")), "\"a\\r\\nb\\u0001\"");
+    }
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00Z");
+        assert_eq!(iso8601(UNIX_EPOCH + Duration::from_secs(1_792_139_405)), "2026-10-16T08:30:05Z");
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::atomic::Ordering;
+
+use crate::dispatch::ServerCtx;
+use crate::handlers;
+use crate::panics;
+use crate::request::Request;
+use crate::response::{HTTPStatus, IntoResponse, Response};
+use crate::util::constant_time_eq;
+use crate::log::{warn, info};
+
+// Where the admin endpoints live. They only exist while admin_token is set.
+pub const SHUTDOWN_PATH: &str = "/admin/shutdown";
+pub const CACHE_CLEAR_PATH: &str = "/admin/cache/clear";
+pub const CONNECTIONS_PATH: &str = "/admin/connections";
+pub const TIMINGS_PATH: &str = "/admin/timings";
+pub const MAINTENANCE_PATH: &str = "/admin/maintenance";
+
+// The token in an "Authorization: Bearer <token>" header, if that's what the header holds.
+pub fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Bearer") {
+        return None;
+    }
+    return Some(token.trim());
+}
+
+/*
+POST /admin/shutdown with the right bearer token: raise the shutdown flag and answer 202. The
+connection loop then wakes the accept loop, which stops taking connections, waits for the
+running ones and returns from run_server().
+No token: 401 (with a challenge). Wrong token: 403. Other methods: 405.
+*/
+pub fn shutdown(req: &Request, admin_token: &str, ctx: &ServerCtx) -> Response {
+    if let Some(refusal) = refusal(req, "POST", admin_token) {
+        return refusal;
+    }
+
+    info!("🛑 Shutdown requested via {}", SHUTDOWN_PATH);
+    ctx.shutdown.store(true, Ordering::SeqCst);
+    return handlers::accepted();
+}
+
+// POST /admin/cache/clear with the right bearer token: empty the response cache, answer 204.
+pub fn clear_cache(req: &Request, admin_token: &str, ctx: &ServerCtx) -> Response {
+    if let Some(refusal) = refusal(req, "POST", admin_token) {
+        return refusal;
+    }
+
+    let cleared = ctx.response_cache.clear();
+    info!("🧹 Response cache cleared via {} ({} entries)", CACHE_CLEAR_PATH, cleared);
+    return handlers::no_content();
+}
+
+/*
+GET /admin/connections with the right bearer token: one line per open connection (its worker
+thread, peer, state and counters), then the totals since startup. Plain text, for humans and grep:
+
+    #12 vibettp-worker-12 127.0.0.1:50123 idle requests=3 bytes_sent=5120 idle_ms=1200
+    open=1 requests=57 bytes_sent=123456 panics_total=0 buffers_allocated=2 buffers_reused=55
+*/
+pub fn connections(req: &Request, admin_token: &str, ctx: &ServerCtx) -> Response {
+    if let Some(refusal) = refusal(req, "GET", admin_token) {
+        return refusal;
+    }
+
+    let workers = ctx.connections.snapshot();
+    let mut text = String::new();
+    for worker in &workers {
+        text.push_str(&format!(
+            "#{} {} {} {} requests={} bytes_sent={} idle_ms={}\n",
+            worker.id, worker.thread, worker.peer, format!("{:?}", worker.state).to_lowercase(),
+            worker.requests, worker.bytes_sent, worker.idle_for.as_millis()
+        ));
+    }
+    text.push_str(&format!(
+        "open={} requests={} bytes_sent={} panics_total={} buffers_allocated={} buffers_reused={}\n",
+        workers.len(), ctx.stats.requests.load(Ordering::Relaxed), ctx.stats.bytes_sent.load(Ordering::Relaxed),
+        panics::panics_total(), ctx.buffer_pool.allocated(), ctx.buffer_pool.reused()
+    ));
+    return Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "no-store")
+        .body("text/plain; charset=utf-8", text);
+}
+
+// GET /admin/timings with the right bearer token: the per-phase histograms (see Timings::report()).
+pub fn timings(req: &Request, admin_token: &str, ctx: &ServerCtx) -> Response {
+    if let Some(refusal) = refusal(req, "GET", admin_token) {
+        return refusal;
+    }
+    return Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "no-store")
+        .body("text/plain; charset=utf-8", ctx.stats.timings.report());
+}
+
+/*
+POST /admin/maintenance with the right bearer token: switch maintenance mode (503 for everything
+but /health and the admin endpoints) to ?on=true or ?on=false, or flip it without the parameter.
+Answers 200 with the new state, "maintenance on" or "maintenance off".
+*/
+pub fn maintenance(req: &Request, admin_token: &str, ctx: &ServerCtx) -> Response {
+    if let Some(refusal) = refusal(req, "POST", admin_token) {
+        return refusal;
+    }
+
+    let on = match req.query_or("on", !ctx.in_maintenance()) {
+        Ok(on) => on,
+        Err(e) => return e.into_response(),
+    };
+    ctx.maintenance.store(on, Ordering::SeqCst);
+    info!("🚧 Maintenance mode {} via {}", if on { "on" } else { "off" }, MAINTENANCE_PATH);
+    return Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "no-store")
+        .body("text/plain; charset=utf-8", if on { "maintenance on" } else { "maintenance off" });
+}
+
+/*
+What every admin endpoint checks first: the right method, and the admin token as a bearer token.
+The response turning the request away, if either is wrong.
+*/
+fn refusal(req: &Request, method: &'static str, admin_token: &str) -> Option<Response> {
+    if req.method != method {
+        return Some(handlers::method_not_allowed(&[method]));
+    }
+    let Some(token) = req.header("Authorization").and_then(bearer_token) else {
+        return Some(handlers::unauthorized());
+    };
+    if !constant_time_eq(token.as_bytes(), admin_token.as_bytes()) {
+        warn!("🚫 Wrong admin token for {}", req.path);
+        return Some(handlers::forbidden());
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer   abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic YWJj"), None);
+        assert_eq!(bearer_token("Bearer"), None);
+    }
+}
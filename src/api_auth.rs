@@ -0,0 +1,106 @@
+use crate::admin::bearer_token;
+use crate::config::{ApiAuth, Config};
+use crate::handlers;
+use crate::request::Request;
+use crate::response::{HTTPStatus, Response};
+use crate::util::{constant_time_eq, under_prefix_ignoring_case};
+use crate::log::warn;
+
+/*
+API keys for URL prefixes ([api_auth]): a request under a protected prefix needs one of that
+prefix's tokens, as a bearer token or in the entry's own header, before any handler (or the disk)
+sees it. The id of the key it matched is kept on the request for handlers and the access log
+($api_key_id); the token itself goes nowhere. The admin endpoints keep their own admin_token.
+*/
+
+/*
+The [api_auth] entry covering `path` (canonical, as dispatch has it), the longest prefix first.
+Prefixes match ignoring case: the files behind "/API" are the ones behind "/api", and so are the
+routes with case_insensitive_routes.
+*/
+pub fn protecting<'a>(path: &str, config: &'a Config) -> Option<&'a ApiAuth> {
+    return config.api_auth.iter()
+        .filter(|(prefix, _)| under_prefix_ignoring_case(path, prefix))
+        .max_by_key(|(prefix, _)| prefix.trim_end_matches('/').len())
+        .map(|(_, auth)| auth);
+}
+
+/*
+What turns `req` away, if anything: a 401 with a plain Bearer challenge when it presents no token,
+with error="invalid_token" (RFC 6750 §3.1) when it presents one that isn't a key. Let in, it
+carries the matched key's id in api_key_id. Every key is compared, whether or not an earlier one
+matched, so timing says nothing about which.
+*/
+pub fn refusal(req: &mut Request, auth: &ApiAuth) -> Option<Response> {
+    let from_header = auth.header.as_deref().and_then(|name| req.header(name)).map(str::trim);
+    let Some(token) = req.header("Authorization").and_then(bearer_token).or(from_header) else {
+        return Some(handlers::unauthorized());
+    };
+
+    let mut matched = None;
+    for (id, key) in &auth.keys {
+        if constant_time_eq(token.as_bytes(), key.as_bytes()) {
+            matched = Some(id);
+        }
+    }
+    let Some(id) = matched else {
+        warn!("🚫 Wrong API key for {}", req.path);
+        return Some(Response::new(HTTPStatus::Unauthorized)
+            .header("WWW-Authenticate", "Bearer error=\"invalid_token\"")
+            .body("text/plain", "401 Unauthorized"));
+    };
+    req.api_key_id = Some(id.clone());
+    return None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::request::parse_request;
+
+    const API_AUTH: &str = "[api_auth]\n\"/api\" = { keys = { ci = \"ci-token\", dashboard = \"dash-token\" }, header = \"X-Api-Key\" }\n\
+                            \"/api/admin/\" = { keys = { ops = \"ops-token\" } }\n";
+
+    // The key id the request gets in with, or the refusal. None: the path isn't protected.
+    fn check(headers: &str, path: &str) -> Option<Result<String, Response>> {
+        let config = test_config(".", API_AUTH);
+        let mut req = parse_request(format!("GET {} HTTP/1.1\r\n{}\r\n", path, headers).as_bytes()).unwrap();
+        let auth = protecting(&req.path, &config)?;
+        return Some(match refusal(&mut req, auth) {
+            Some(response) => Err(response),
+            None => Ok(req.api_key_id.unwrap()),
+        });
+    }
+
+    #[test]
+    fn test_keys() {
+        // A valid token, either way it may come; the id of the key it matched comes back.
+        assert_eq!(check("Authorization: Bearer dash-token\r\n", "/api/items").unwrap().ok(), Some("dashboard".to_string()));
+        assert_eq!(check("X-Api-Key: ci-token\r\n", "/api").unwrap().ok(), Some("ci".to_string()));
+
+        // Missing: a plain challenge. Wrong: the challenge says so.
+        let missing = check("", "/api/items").unwrap().unwrap_err();
+        assert_eq!((missing.status.clone(), missing.header_value("WWW-Authenticate")), (HTTPStatus::Unauthorized.into(), Some("Bearer")));
+        let wrong = check("Authorization: Bearer ci-token2\r\n", "/api/items").unwrap().unwrap_err();
+        assert_eq!(wrong.status, HTTPStatus::Unauthorized);
+        assert_eq!(wrong.header_value("WWW-Authenticate"), Some("Bearer error=\"invalid_token\""));
+        // Another scheme isn't a bearer token, and the header only counts where it is configured.
+        assert!(check("Authorization: Basic Y2ktdG9rZW4=\r\n", "/api/items").unwrap().is_err());
+
+        // The longest prefix decides: the ops key only, and Bearer only.
+        assert_eq!(check("Authorization: Bearer ops-token\r\n", "/api/admin/x").unwrap().ok(), Some("ops".to_string()));
+        assert!(check("Authorization: Bearer ci-token\r\n", "/api/admin").unwrap().is_err());
+        assert!(check("X-Api-Key: ops-token\r\n", "/api/admin/x").unwrap().is_err());
+
+        // Case doesn't get around a prefix: on NTFS /API/items is the same file as /api/items.
+        assert!(check("", "/API/items").unwrap().is_err());
+        assert!(check("", "/Api").unwrap().is_err());
+        assert_eq!(check("Authorization: Bearer ops-token\r\n", "/API/Admin/x").unwrap().ok(), Some("ops".to_string()));
+
+        // Unprotected paths, a lookalike prefix included, aren't checked at all.
+        assert!(check("", "/index.html").is_none());
+        assert!(check("", "/apiary").is_none());
+        assert!(check("", "/APIARY").is_none());
+    }
+}
@@ -0,0 +1,238 @@
+/*
+Micro-benchmarks for the hot path, without pulling in criterion.
+Every benchmark is an ignored test whose name contains "bench", so they only run on request:
+
+    cargo test --release -- --ignored bench
+
+Each one runs a warm-up pass, then times a fixed number of iterations with Instant and prints
+the average ns/op and heap allocations/op to stdout (add `--nocapture` to see the numbers).
+*/
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::buffer_pool::BufferPool;
+use crate::canned::CannedResponses;
+use crate::config::test_config;
+use crate::dispatch::{add_response_headers, finalize, handle_request, ServerCtx};
+use crate::handlers;
+use crate::mime::content_type_for;
+use crate::request::parse_request;
+use crate::response::{build_response, HTTPStatus, Response};
+use crate::util::sanitize_path;
+
+/*
+The test binary's allocator: the system one, counting allocations per thread, so a benchmark
+sees its own and not those of tests running next to it. try_with(): a thread being torn down
+may still allocate after its counter is gone.
+*/
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        // Unsafe: forwarded as is, under the caller's guarantees.
+        return unsafe { System.alloc(layout) };
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Unsafe: forwarded as is, under the caller's guarantees.
+        unsafe { System.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        // Unsafe: forwarded as is, under the caller's guarantees.
+        return unsafe { System.realloc(ptr, layout, new_size) };
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Heap allocations (and reallocations) made on this thread so far.
+fn allocations() -> u64 {
+    return ALLOCATIONS.with(Cell::get);
+}
+
+// Run `f` `iterations` times after a short warm-up and report the average cost per call.
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    for _ in 0..(iterations / 10).max(1) {
+        f();
+    }
+
+    let allocated_before = allocations();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocated = allocations() - allocated_before;
+
+    println!(
+        "⏱️ {:<32} {:>10.1} ns/op {:>8.2} allocs/op ({} iterations)",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        allocated as f64 / iterations as f64,
+        iterations
+    );
+}
+
+// What a typical browser sends: request line plus 12 headers.
+const BROWSER_REQUEST: &str = "GET /docs/guide/index.html HTTP/1.1\r\n\
+Host: localhost:7878\r\n\
+Connection: keep-alive\r\n\
+Cache-Control: max-age=0\r\n\
+sec-ch-ua: \"Chromium\";v=\"124\", \"Not-A.Brand\";v=\"99\"\r\n\
+sec-ch-ua-mobile: ?0\r\n\
+sec-ch-ua-platform: \"Windows\"\r\n\
+Upgrade-Insecure-Requests: 1\r\n\
+User-Agent: Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Safari/537.36\r\n\
+Accept: text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8\r\n\
+Accept-Encoding: gzip, deflate, br\r\n\
+Accept-Language: en-US,en;q=0.9\r\n\
+Cookie: session=0123456789abcdef; theme=dark\r\n\
+\r\n";
+
+#[test]
+#[ignore]
+fn bench_parse_request() {
+    bench("parse_request (12 headers)", 100_000, || {
+        black_box(parse_request(black_box(BROWSER_REQUEST.as_bytes())));
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_build_response() {
+    let body = "x".repeat(1024);
+    bench("build_response (1KB body)", 100_000, || {
+        black_box(build_response(HTTPStatus::Ok, "OK", "text/html", black_box(&body)));
+    });
+}
+
+/*
+Before/after for the connection loop's own errors (a flood of unparseable requests, or of
+connections over max_clients): building, formatting and adding the [response_headers] each time,
+versus the canned bytes, which cost an Arc clone.
+*/
+#[test]
+#[ignore]
+fn bench_canned_error() {
+    let config = Arc::new(test_config(".", "[response_headers]\nServer = \"vibettp\"\nX-Frame-Options = \"DENY\"\n"));
+    bench("error response built per request", 100_000, || {
+        let response = add_response_headers(handlers::error(HTTPStatus::BadRequest), &config).header("Connection", "close");
+        black_box(response.to_bytes());
+    });
+    let canned = CannedResponses::new();
+    bench("error response canned", 100_000, || {
+        black_box(canned.get(HTTPStatus::BadRequest, &config));
+    });
+}
+
+/*
+Before/after for a flood of requests for missing files: a plain 404 finalized and serialized for
+each one, versus the canned bytes (see CannedResponses::not_found()). Canning takes finalize() and
+serialization off the path and those allocations with them; it doesn't make a 404 free. The last
+two lines show what every such request still allocates for parsing and for dispatch (normalizing
+the path, looking it up on disk, building the handler's 404), which canning can't avoid.
+*/
+#[test]
+#[ignore]
+fn bench_canned_not_found() {
+    let config = Arc::new(test_config(".", "[response_headers]\nServer = \"vibettp\"\nX-Frame-Options = \"DENY\"\n"));
+    let req = parse_request(b"GET /wp-login.php HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    bench("404 finalized per request", 100_000, || {
+        black_box(finalize(handlers::not_found(), &req, true, &config).to_bytes());
+    });
+    let canned = CannedResponses::new();
+    let response = handlers::not_found();
+    bench("404 canned", 100_000, || {
+        black_box(canned.not_found(black_box(&response), &req, true, &config));
+    });
+
+    // The whole answer to a missing file: parsing (on its own first), dispatch, the canned bytes.
+    const MISSING: &[u8] = b"GET /wp-login.php HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    bench("404 request parsed", 100_000, || {
+        black_box(parse_request(black_box(MISSING)));
+    });
+    let ctx = ServerCtx::new(crate::router::Router::new());
+    bench("404 parsed, dispatched, canned", 100_000, || {
+        let mut req = parse_request(black_box(MISSING)).unwrap();
+        let outcome = handle_request(&mut req, &config, &ctx);
+        black_box(canned.not_found(&outcome.response, &req, true, &config));
+    });
+}
+
+/*
+Before/after for sending a large file: to_bytes() builds one buffer (copying the whole body),
+write_to() sends the head and then the body from where it already is.
+*/
+#[test]
+#[ignore]
+fn bench_serialize_5mb() {
+    let response = Response::new(HTTPStatus::Ok).body("application/octet-stream", vec![b'x'; 5 * 1024 * 1024]);
+    bench("to_bytes (5MB body)", 200, || {
+        black_box(response.to_bytes());
+    });
+    let mut response = response;
+    bench("write_to (5MB body)", 200, || {
+        response.write_to(&mut std::io::sink()).unwrap();
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_sanitize_path() {
+    bench("sanitize_path (3 segments)", 1_000, || {
+        black_box(sanitize_path(black_box("/docs/guide/index.html"), black_box("."), false));
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_mime_lookup() {
+    let overrides = HashMap::from([("gltf".to_string(), "model/gltf+json".to_string())]);
+    bench("content_type_for (builtin hit)", 1_000_000, || {
+        black_box(content_type_for(black_box(Path::new("docs/photo.JPEG")), &overrides));
+    });
+}
+
+/*
+The connection loop's buffers for 10k requests, 8 per connection, with and without the pool:
+a connection checks out its request_data, each request a recv buffer, and the head is copied
+through them and parsed. There is no socket here (the loop only runs on WinSock), so this is
+the buffer traffic of handle_connection without the I/O. Prints how many checkouts allocated.
+*/
+#[test]
+#[ignore]
+fn bench_buffer_pool() {
+    const REQUESTS: u32 = 10_000;
+    for enabled in [false, true] {
+        let pool = BufferPool::new();
+        let start = Instant::now();
+        for _ in 0..REQUESTS / 8 {
+            let mut request_data = pool.checkout(8192, enabled);
+            for _ in 0..8 {
+                let mut buffer = pool.checkout(8192, enabled);
+                buffer.resize(8192, 0);
+                buffer[..BROWSER_REQUEST.len()].copy_from_slice(BROWSER_REQUEST.as_bytes());
+                request_data.extend_from_slice(&buffer[..BROWSER_REQUEST.len()]);
+                black_box(parse_request(&request_data));
+                request_data.clear();
+            }
+        }
+        println!(
+            "⏱️ buffer_pool = {:<5} {:>10.1} ns/request, {} buffers allocated, {} reused",
+            enabled, start.elapsed().as_nanos() as f64 / REQUESTS as f64, pool.allocated(), pool.reused()
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Size classes are powers of two from 1 KiB to 1 MiB, the bounds of recv_buffer_bytes.
+const MIN_CLASS_SHIFT: u32 = 10;
+const CLASSES: usize = 11;
+const MAX_CLASS_BYTES: usize = 1 << (MIN_CLASS_SHIFT as usize + CLASSES - 1);
+
+// Free buffers kept per size class; returns beyond that are dropped, so the pool can't keep growing.
+pub const MAX_PER_CLASS: usize = 16;
+
+/*
+Receive buffers handed back and forth between connections, so a busy server doesn't allocate
+and free the same sizable Vecs for every request. One free list per size class, each behind its
+own Mutex; a checkout takes the smallest class that fits and a returned buffer goes to the class
+its capacity covers (it may have grown meanwhile). Bigger than the largest class: never pooled.
+
+With buffer_pool = false in the config every checkout allocates and nothing is kept, for comparison.
+*/
+pub struct BufferPool {
+    classes: [Mutex<Vec<Vec<u8>>>; CLASSES],
+    // Checkouts that had to allocate, and ones served from a free list.
+    allocated: AtomicU64,
+    reused: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new() -> BufferPool {
+        return BufferPool {
+            classes: Default::default(),
+            allocated: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+        };
+    }
+
+    // An empty buffer with room for at least `size` bytes, back in the pool when the guard is dropped.
+    pub fn checkout(&self, size: usize, enabled: bool) -> PooledBuffer<'_> {
+        let class = class_for(size).filter(|_| enabled);
+        if let Some(class) = class {
+            let free = self.classes[class].lock().unwrap_or_else(|e| e.into_inner()).pop();
+            if let Some(buffer) = free {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                return PooledBuffer { buffer, pool: Some(self) };
+            }
+        }
+
+        self.allocated.fetch_add(1, Ordering::Relaxed);
+        let capacity = class.map_or(size, |class| 1 << (MIN_CLASS_SHIFT as usize + class));
+        return PooledBuffer { buffer: Vec::with_capacity(capacity), pool: class.map(|_| self) };
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        let capacity = buffer.capacity();
+        if !(1 << MIN_CLASS_SHIFT..MAX_CLASS_BYTES * 2).contains(&capacity) {
+            return;
+        }
+        // The largest class it can serve: floor(log2(capacity)).
+        let class = (usize::BITS - 1 - capacity.leading_zeros() - MIN_CLASS_SHIFT) as usize;
+        buffer.clear();
+        let mut free = self.classes[class].lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < MAX_PER_CLASS {
+            free.push(buffer);
+        }
+    }
+
+    pub fn allocated(&self) -> u64 {
+        return self.allocated.load(Ordering::Relaxed);
+    }
+
+    pub fn reused(&self) -> u64 {
+        return self.reused.load(Ordering::Relaxed);
+    }
+}
+
+// The smallest class holding `size` bytes, if there is one.
+fn class_for(size: usize) -> Option<usize> {
+    if size > MAX_CLASS_BYTES {
+        return None;
+    }
+    let shift = size.max(1 << MIN_CLASS_SHIFT).next_power_of_two().trailing_zeros();
+    return Some((shift - MIN_CLASS_SHIFT) as usize);
+}
+
+// A checked-out buffer: a Vec<u8> that goes back to its pool (if it came from one) when dropped.
+pub struct PooledBuffer<'a> {
+    buffer: Vec<u8>,
+    pool: Option<&'a BufferPool>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        return &self.buffer;
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        return &mut self.buffer;
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool {
+            pool.give_back(mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse_by_size_class() {
+        let pool = BufferPool::new();
+        let mut first = pool.checkout(8192, true);
+        first.extend_from_slice(b"request bytes");
+        drop(first);
+
+        // Same class: the same buffer, emptied. A smaller class can't use it.
+        let again = pool.checkout(5000, true);
+        assert!(again.is_empty() && again.capacity() >= 8192);
+        let small = pool.checkout(1024, true);
+        assert_eq!((pool.allocated(), pool.reused()), (2, 1));
+        drop((again, small));
+
+        // Grown past its class, a buffer is returned to the bigger one.
+        let mut grown = pool.checkout(1024, true);
+        grown.extend_from_slice(&[0; 3000]);
+        drop(grown);
+        let reused = pool.reused();
+        drop(pool.checkout(2048, true));
+        assert_eq!(pool.reused(), reused + 1);
+    }
+
+    #[test]
+    fn test_bounded_and_disabled() {
+        let pool = BufferPool::new();
+        let held: Vec<PooledBuffer> = (0..MAX_PER_CLASS + 4).map(|_| pool.checkout(4096, true)).collect();
+        drop(held);
+        assert_eq!(pool.classes[class_for(4096).unwrap()].lock().unwrap().len(), MAX_PER_CLASS);
+
+        // Disabled, or too big for any class: allocated every time and never kept.
+        let allocated = pool.allocated();
+        drop(pool.checkout(4096, false));
+        drop(pool.checkout(MAX_CLASS_BYTES + 1, true));
+        drop(pool.checkout(MAX_CLASS_BYTES + 1, true));
+        assert_eq!(pool.allocated(), allocated + 3);
+        assert_eq!(class_for(1), Some(0));
+        assert_eq!(class_for(MAX_CLASS_BYTES), Some(CLASSES - 1));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::config::Config;
+use crate::dispatch::{add_response_headers, finalize};
+use crate::handlers;
+use crate::request::{parse_request, Request};
+use crate::response::{HTTPStatus, Response};
+
+// The statuses the server sends on its own before (or instead of) handling a request, then closes.
+pub const CANNED_STATUSES: [HTTPStatus; 6] = [
+    HTTPStatus::BadRequest,
+    HTTPStatus::RequestTimeout,
+    HTTPStatus::ContentTooLarge,
+    HTTPStatus::TooManyRequests,
+    HTTPStatus::InternalServerError,
+    HTTPStatus::ServiceUnavailable,
+];
+
+/*
+The error responses of the connection loop itself (a 503 from the accept loop, a 400 for a
+request that doesn't parse, a 408 for one that stalls, ...), serialized once instead of being
+built, formatted and given the [response_headers] for every refusal. Each is the status's standard
+response (handlers::error()) plus the [response_headers] and Connection: close, so it depends on
+the config alone: the set is rebuilt the first time it is asked for with a config it wasn't built
+from, i.e. after a hot reload. Sending one only clones an Arc.
+
+Responses to requests that were handled still go through finalize(): they may keep the connection
+open, answer a HEAD or be compressed. The one exception is the plain 404 (a scanner's flood of
+them, say): for a GET, the finalized bytes are canned too, once for a connection kept open and
+once for one being closed, by finalize() itself so they can't differ from what it would produce.
+That spares each 404 the finalizing and serializing, not the work of getting to it: parsing and
+dispatch still allocate per request (bench_canned_not_found counts both).
+*/
+pub struct CannedResponses {
+    current: RwLock<Option<Arc<Canned>>>,
+}
+
+struct Canned {
+    // The config they were built from, compared by address.
+    config: Arc<Config>,
+    // Keyed by status code.
+    by_status: HashMap<u16, Arc<Vec<u8>>>,
+    // The 503 for a connection that waited queue_timeout_ms in the accept queue, with Retry-After.
+    queue_timeout: Arc<Vec<u8>>,
+    // handlers::not_found()'s headers and body, to recognize it by.
+    plain_not_found: (Vec<(String, String)>, Vec<u8>),
+    // And as finalize() sends it: [keep-alive, close].
+    not_found: [Arc<Vec<u8>>; 2],
+}
+
+impl CannedResponses {
+    pub fn new() -> CannedResponses {
+        return CannedResponses { current: RwLock::new(None) };
+    }
+
+    // The serialized response for `status` (one of CANNED_STATUSES; any other is built on the spot).
+    pub fn get(&self, status: HTTPStatus, config: &Arc<Config>) -> Arc<Vec<u8>> {
+        return match self.for_config(config).by_status.get(&(status as u16)) {
+            Some(bytes) => Arc::clone(bytes),
+            None => Arc::new(closing(handlers::error(status), config)),
+        };
+    }
+
+    // The 503 sent to a connection that waited too long in the accept queue.
+    pub fn queue_timeout(&self, config: &Arc<Config>) -> Arc<Vec<u8>> {
+        return Arc::clone(&self.for_config(config).queue_timeout);
+    }
+
+    /*
+    The canned bytes for `response` to `req`, if it is the plain 404 (handlers::not_found(), its
+    log tag aside) and would be finalized the standard way: not for a HEAD, an HTTP/1.0 status
+    line or a Server-Timing header. None: finalize and serialize it as usual.
+    */
+    pub fn not_found(&self, response: &Response, req: &Request, keep_alive: bool, config: &Arc<Config>) -> Option<Arc<Vec<u8>>> {
+        if response.status != HTTPStatus::NotFound || response.stream.is_some() || response.head_only || req.head_only
+            || (config.mirror_request_version && req.is_http_1_0()) || config.timing_header {
+            return None;
+        }
+        let canned = self.for_config(config);
+        let (headers, body) = &canned.plain_not_found;
+        if response.headers != *headers || response.body != *body {
+            return None;
+        }
+        return Some(Arc::clone(&canned.not_found[usize::from(!keep_alive)]));
+    }
+
+    fn for_config(&self, config: &Arc<Config>) -> Arc<Canned> {
+        if let Some(canned) = &*self.current.read().unwrap_or_else(|e| e.into_inner())
+            && Arc::ptr_eq(&canned.config, config) {
+            return Arc::clone(canned);
+        }
+
+        let canned = Arc::new(Canned::build(config));
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::clone(&canned));
+        return canned;
+    }
+}
+
+impl Canned {
+    fn build(config: &Arc<Config>) -> Canned {
+        let by_status = CANNED_STATUSES.iter()
+            .map(|status| (*status as u16, Arc::new(closing(handlers::error(*status), config))))
+            .collect();
+        // Another wait as long as this one is a fair guess at when to try again.
+        let retry_after = config.queue_timeout_ms.div_ceil(1000);
+        let queue_timeout = closing(handlers::unavailable("503 Service Unavailable", retry_after), config);
+        let get = parse_request(b"GET / HTTP/1.1\r\n\r\n").expect("a valid request");
+        let not_found = [true, false].map(|keep_alive| Arc::new(finalize(handlers::not_found(), &get, keep_alive, config).to_bytes()));
+        let plain = handlers::not_found();
+        return Canned {
+            config: Arc::clone(config),
+            by_status,
+            queue_timeout: Arc::new(queue_timeout),
+            plain_not_found: (plain.headers, plain.body.into_vec()),
+            not_found,
+        };
+    }
+}
+
+// `response` as sent on a connection that is closed after it.
+fn closing(response: Response, config: &Config) -> Vec<u8> {
+    return add_response_headers(response, config).header("Connection", "close").to_bytes();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_built_once_per_config() {
+        let canned = CannedResponses::new();
+        let config = Arc::new(test_config(".", "queue_timeout_ms = 2500\n[response_headers]\nServer = \"vibettp\"\n"));
+
+        let first = canned.get(HTTPStatus::ServiceUnavailable, &config);
+        let text = String::from_utf8(first.to_vec()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", text);
+        assert!(text.contains("\r\nServer: vibettp\r\n") && text.contains("\r\nConnection: close\r\n"), "{}", text);
+        assert!(text.ends_with("\r\n\r\n503 Service Unavailable"), "{}", text);
+        let queued = String::from_utf8(canned.queue_timeout(&config).to_vec()).unwrap();
+        assert!(queued.contains("\r\nRetry-After: 3\r\n"), "{}", queued);
+
+        // Asked again with the same config: the same buffer, nothing rebuilt.
+        for status in CANNED_STATUSES {
+            assert!(Arc::ptr_eq(&canned.get(status, &config), &canned.get(status, &config)));
+        }
+        assert!(Arc::ptr_eq(&first, &canned.get(HTTPStatus::ServiceUnavailable, &config)));
+
+        // A reloaded config (another Arc, even with the same settings) gets a fresh set.
+        let reloaded = Arc::new(test_config(".", ""));
+        let rebuilt = canned.get(HTTPStatus::ServiceUnavailable, &reloaded);
+        assert!(!Arc::ptr_eq(&first, &rebuilt));
+        assert!(!String::from_utf8_lossy(&rebuilt).contains("Server: vibettp"));
+
+        // Not a canned status: still the standard response, just not kept.
+        let other = canned.get(HTTPStatus::Forbidden, &reloaded);
+        assert!(other.starts_with(b"HTTP/1.1 403 Forbidden\r\n"));
+        assert!(!Arc::ptr_eq(&other, &canned.get(HTTPStatus::Forbidden, &reloaded)));
+    }
+
+    #[test]
+    fn test_plain_not_found() {
+        let canned = CannedResponses::new();
+        let config = Arc::new(test_config(".", "[response_headers]\nServer = \"vibettp\"\n"));
+        let get = parse_request(b"GET /missing HTTP/1.1\r\n\r\n").unwrap();
+
+        // The same bytes finalize() would have produced, either way the connection goes.
+        for keep_alive in [true, false] {
+            let bytes = canned.not_found(&handlers::not_found(), &get, keep_alive, &config).unwrap();
+            assert_eq!(*bytes, finalize(handlers::not_found(), &get, keep_alive, &config).to_bytes());
+        }
+        let tagged = handlers::not_found().log_tag("favicon");
+        assert!(canned.not_found(&tagged, &get, true, &config).is_some());
+
+        // Anything else is finalized as usual.
+        let mut head = parse_request(b"GET /missing HTTP/1.1\r\n\r\n").unwrap();
+        head.head_only = true;
+        assert!(canned.not_found(&handlers::not_found(), &head, true, &config).is_none());
+        let custom = Response::new(HTTPStatus::NotFound).body("text/html", "<h1>Gone fishing</h1>");
+        assert!(canned.not_found(&custom, &get, true, &config).is_none());
+        assert!(canned.not_found(&handlers::not_found().header("Vary", "Accept"), &get, true, &config).is_none());
+        assert!(canned.not_found(&handlers::forbidden(), &get, true, &config).is_none());
+        let timed = Arc::new(test_config(".", "timing_header = true\n"));
+        assert!(canned.not_found(&handlers::not_found(), &get, true, &timed).is_none());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config;
+use crate::loadgen;
+
+/**
+The command line's subcommands, which use the library without starting the server:
+
+```text
+vibettp check [--config path]     validate and resolve the config, exit 0 or 1
+vibettp bench --url ... [...]     load-test a running server (see loadgen.rs)
+```
+
+Returns the exit code if `args` (without the program name) named one, None otherwise.
+*/
+pub fn run_subcommand(args: &[String]) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+    return match command.as_str() {
+        "check" => Some(check(rest)),
+        "bench" => Some(bench(rest)),
+        _ => None,
+    };
+}
+
+fn check(args: &[String]) -> i32 {
+    let path = match args {
+        [] => config::CONFIG_PATH,
+        [flag, path] if flag == "--config" => path.as_str(),
+        _ => {
+            eprintln!("❌ usage: vibettp check [--config path]");
+            return 2;
+        }
+    };
+    match config::check(path) {
+        Ok(summary) => {
+            for line in summary {
+                println!("⚙️ {}", line);
+            }
+            println!("✅ {} is valid.", path);
+            return 0;
+        }
+        Err(errors) => {
+            for e in errors {
+                eprintln!("❌ {}", e);
+            }
+            return 1;
+        }
+    }
+}
+
+fn bench(args: &[String]) -> i32 {
+    match loadgen::parse_args(args.iter().cloned()) {
+        Ok(options) => {
+            loadgen::run(&options);
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("❌ {}\n{}", e, loadgen::USAGE);
+            return 2;
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::config::Config;
+use crate::request::Request;
+use crate::response::{HTTPStatus, Response};
+use crate::log::debug;
+
+// Media types compressed when the config doesn't list its own. "type/*" matches a whole family.
+pub const DEFAULT_COMPRESSION_TYPES: [&str; 4] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+];
+
+/*
+Does an Accept-Encoding value allow gzip? Only an explicit "gzip" (or the legacy "x-gzip") or a
+"*" wildcard counts, and a q=0 weight on the token refuses it (RFC 9110 §12.5.3).
+"gzip;q=0, *" is a refusal: the specific token wins over the wildcard.
+*/
+pub fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let Some(value) = accept_encoding else {
+        return false;
+    };
+
+    let mut wildcard = false;
+    for item in value.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let allowed = parts
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map(|(_, q)| q.trim().parse::<f32>().map(|q| q > 0.0).unwrap_or(false))
+            .unwrap_or(true);
+
+        if coding == "gzip" || coding == "x-gzip" {
+            return allowed;
+        }
+        if coding == "*" {
+            wildcard = allowed;
+        }
+    }
+    return wildcard;
+}
+
+// Is `content_type` (parameters such as charset ignored) on the list? Case-insensitive.
+pub fn type_matches(content_type: &str, patterns: &[String]) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    return patterns.iter().any(|pattern| {
+        let pattern = pattern.trim().to_ascii_lowercase();
+        match pattern.strip_suffix("/*") {
+            Some(family) => media_type.split_once('/').is_some_and(|(t, _)| t == family),
+            None => media_type == pattern,
+        }
+    });
+}
+
+/*
+The whole decision, minus the bytes: compression is on, the client takes gzip, the body is big
+enough to be worth it and its type is one that actually shrinks (images, archives and video
+are already compressed).
+*/
+pub fn should_compress(content_type: &str, body_len: usize, accept_encoding: Option<&str>, config: &Config) -> bool {
+    return config.compression
+        && body_len >= config.compression_min_bytes
+        && type_matches(content_type, &config.compression_types)
+        && accepts_gzip(accept_encoding);
+}
+
+pub fn gzip(data: &[u8], level: u32) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    // Writing into a Vec can't fail.
+    encoder.write_all(data).expect("gzip into memory failed");
+    return encoder.finish().expect("gzip into memory failed");
+}
+
+/*
+Gzip `resp` in place if should_compress() says so. Only complete 200 bodies are touched: byte
+ranges refer to the uncompressed representation. If gzip doesn't make the body smaller, it is
+sent as is. Responses left alone get no Content-Encoding or Vary header at all.
+*/
+pub fn maybe_compress(req: &Request, resp: &mut Response, config: &Config) {
+    if resp.status != HTTPStatus::Ok || resp.header_value("Content-Encoding").is_some() {
+        return;
+    }
+    let Some(content_type) = resp.header_value("Content-Type") else {
+        return;
+    };
+    if !should_compress(content_type, resp.body.len(), req.header("Accept-Encoding"), config) {
+        return;
+    }
+
+    let compressed = gzip(&resp.body, config.compression_level);
+    if compressed.len() >= resp.body.len() {
+        return;
+    }
+    debug!("🗜️ gzip: {} → {} bytes", resp.body.len(), compressed.len());
+    resp.body = compressed.into();
+    resp.headers.push(("Content-Encoding".to_string(), "gzip".to_string()));
+    resp.add_vary("Accept-Encoding");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::request::parse_request;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn types(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|t| t.to_string()).collect();
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let cases: [(Option<&str>, bool); 12] = [
+            (None, false),
+            (Some(""), false),
+            (Some("gzip"), true),
+            (Some("GZIP"), true),
+            (Some("x-gzip"), true),
+            (Some("deflate, gzip;q=0.5"), true),
+            (Some("br, deflate"), false),
+            (Some("gzip;q=0"), false),
+            (Some("gzip; q=0.000"), false),
+            (Some("*"), true),
+            (Some("*;q=0"), false),
+            (Some("gzip;q=0, *"), false),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(accepts_gzip(value), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_type_matches() {
+        let defaults = types(&DEFAULT_COMPRESSION_TYPES);
+        assert!(type_matches("text/html", &defaults));
+        assert!(type_matches("text/css; charset=utf-8", &defaults));
+        assert!(type_matches("Application/JSON", &defaults));
+        assert!(!type_matches("image/png", &defaults));
+        assert!(!type_matches("application/zip", &defaults));
+        assert!(!type_matches("textual/html", &defaults));
+        assert!(type_matches("image/svg+xml", &types(&["image/svg+xml"])));
+        assert!(!type_matches("text/html", &[]));
+    }
+
+    #[test]
+    fn test_should_compress() {
+        let config = test_config(".", "compression_min_bytes = 100\n");
+        assert!(should_compress("text/html", 100, Some("gzip"), &config));
+        assert!(!should_compress("text/html", 99, Some("gzip"), &config));
+        assert!(!should_compress("image/png", 5000, Some("gzip"), &config));
+        assert!(!should_compress("text/html", 5000, None, &config));
+
+        let config = test_config(".", "compression = false\n");
+        assert!(!should_compress("text/html", 5000, Some("gzip"), &config));
+
+        let config = test_config(".", "compression_types = [\"image/svg+xml\"]\n");
+        assert!(should_compress("image/svg+xml", 5000, Some("gzip"), &config));
+        assert!(!should_compress("text/html", 5000, Some("gzip"), &config));
+    }
+
+    #[test]
+    fn test_maybe_compress() {
+        let config = test_config(".", "");
+        let req = parse_request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let text = "hello world ".repeat(500);
+
+        let mut resp = Response::new(HTTPStatus::Ok).body("text/plain", text.clone());
+        maybe_compress(&req, &mut resp, &config);
+        assert_eq!(resp.header_value("Content-Encoding"), Some("gzip"));
+        assert_eq!(resp.header_value("Vary"), Some("Accept-Encoding"));
+        let mut decoded = String::new();
+        GzDecoder::new(&resp.body[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, text);
+
+        // Too small, partial, or not asked for: untouched, no headers added.
+        let mut small = Response::new(HTTPStatus::Ok).body("text/plain", "hi");
+        maybe_compress(&req, &mut small, &config);
+        let mut partial = Response::new(HTTPStatus::PartialContent).body("text/plain", text.clone());
+        maybe_compress(&req, &mut partial, &config);
+        let plain_req = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut plain = Response::new(HTTPStatus::Ok).body("text/plain", text.clone());
+        maybe_compress(&plain_req, &mut plain, &config);
+        for resp in [small, partial, plain] {
+            assert!(resp.header_value("Content-Encoding").is_none());
+            assert!(resp.header_value("Vary").is_none());
+        }
+    }
+
+    #[test]
+    fn test_vary_on_negotiated_and_compressed() {
+        let config = test_config(".", "");
+        let req = parse_request(b"GET /items HTTP/1.1\r\nAccept: application/json\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let json = format!("[{}]", vec!["{\"id\":1}"; 200].join(","));
+        let mut resp = match req.prefers(&["text/html", "application/json"]) {
+            Some("application/json") => Response::new(HTTPStatus::Ok).body("application/json", json),
+            _ => Response::new(HTTPStatus::Ok).body("text/html", "<ul></ul>"),
+        }.vary("Accept");
+        maybe_compress(&req, &mut resp, &config);
+        assert_eq!(resp.header_value("Content-Encoding"), Some("gzip"));
+        assert_eq!(resp.header_value("Vary"), Some("Accept, Accept-Encoding"));
+        assert_eq!(resp.headers.iter().filter(|(name, _)| name == "Vary").count(), 1);
+    }
+
+    #[test]
+    fn test_gzip_levels() {
+        let data = "abcabcabc".repeat(1000);
+        for level in [0, 1, 6, 9] {
+            let mut decoded = Vec::new();
+            GzDecoder::new(&gzip(data.as_bytes(), level)[..]).read_to_end(&mut decoded).unwrap();
+            assert_eq!(decoded, data.as_bytes(), "level {}", level);
+        }
+    }
+}
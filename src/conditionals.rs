@@ -0,0 +1,252 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::range::{parse_range_specs, RangeSpec};
+use crate::request::Request;
+use crate::util::parse_http_date;
+
+/// One entity tag from a conditional header: `"abc"`, or the weak `W/"abc"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityTag {
+    pub weak: bool,
+    /// The quoted part, quotes included.
+    pub opaque: String,
+}
+
+impl EntityTag {
+    /// `"abc"` or `W/"abc"`; None for anything else.
+    pub fn parse(text: &str) -> Option<EntityTag> {
+        let text = text.trim();
+        let (weak, opaque) = match text.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        if opaque.len() < 2 || !opaque.starts_with('"') || !opaque.ends_with('"') || opaque[1..opaque.len() - 1].contains('"') {
+            return None;
+        }
+        return Some(EntityTag { weak, opaque: opaque.to_string() });
+    }
+
+    /// RFC 9110 §8.8.3.2: the strong comparison needs both tags strong; the weak one ignores W/.
+    pub fn matches(&self, other: &EntityTag, strong: bool) -> bool {
+        return self.opaque == other.opaque && !(strong && (self.weak || other.weak));
+    }
+}
+
+/// If-Match or If-None-Match: "*" or a list of tags. Members that aren't tags are dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EtagCondition {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EtagCondition {
+    fn parse(value: &str) -> EtagCondition {
+        if value.trim() == "*" {
+            return EtagCondition::Any;
+        }
+        return EtagCondition::Tags(value.split(',').filter_map(EntityTag::parse).collect());
+    }
+
+    /// True if `current` (None: the resource doesn't exist) is one the condition names.
+    pub fn matches(&self, current: Option<&str>, strong: bool) -> bool {
+        let Some(current) = current.and_then(EntityTag::parse) else {
+            return false;
+        };
+        return match self {
+            EtagCondition::Any => true,
+            EtagCondition::Tags(tags) => tags.iter().any(|tag| tag.matches(&current, strong)),
+        };
+    }
+}
+
+/// If-Range: the Range only applies to this version of the resource.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IfRange {
+    Etag(EntityTag),
+    Date(SystemTime),
+    /// Neither a tag nor an IMF-fixdate: it can't hold, so the whole resource is sent.
+    Invalid,
+}
+
+/**
+The conditional headers of a request, parsed once (see Request::conditionals()). Dates that
+aren't IMF-fixdates are None, as if the header weren't there (RFC 9110 §13.1.3-4); a Range that
+is ignored (another unit, bad syntax, too many members) is None too.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conditionals {
+    pub if_match: Option<EtagCondition>,
+    pub if_none_match: Option<EtagCondition>,
+    pub if_modified_since: Option<SystemTime>,
+    pub if_unmodified_since: Option<SystemTime>,
+    pub if_range: Option<IfRange>,
+    pub range: Option<Vec<RangeSpec>>,
+    /// GET (HEAD is handled as one): the only method that gets a 304 or a Range.
+    pub is_get: bool,
+}
+
+impl Conditionals {
+    pub fn parse(req: &Request) -> Conditionals {
+        let if_range = req.header("If-Range").map(|value| {
+            if let Some(tag) = EntityTag::parse(value) {
+                return IfRange::Etag(tag);
+            }
+            return parse_http_date(value).map_or(IfRange::Invalid, IfRange::Date);
+        });
+        return Conditionals {
+            if_match: req.header("If-Match").map(EtagCondition::parse),
+            if_none_match: req.header("If-None-Match").map(EtagCondition::parse),
+            if_modified_since: req.header("If-Modified-Since").and_then(parse_http_date),
+            if_unmodified_since: req.header("If-Unmodified-Since").and_then(parse_http_date),
+            if_range,
+            range: req.header("Range").and_then(parse_range_specs),
+            is_get: req.method == "GET",
+        };
+    }
+}
+
+/// What the conditions are being checked against: the selected representation as it is now.
+pub struct FileMeta<'a> {
+    /// Its ETag; None when there is no such resource (yet), as for a PUT creating it.
+    pub etag: Option<&'a str>,
+    pub modified: Option<SystemTime>,
+}
+
+/// The outcome of evaluate_preconditions().
+#[derive(Debug, PartialEq)]
+pub enum PreconditionResult {
+    /// 412: a condition on the current version failed.
+    PreconditionFailed,
+    /// 304: the client's copy is current.
+    NotModified,
+    /// Go ahead, with the Range applied (use_range) or the whole representation.
+    Proceed { use_range: bool },
+}
+
+/*
+RFC 9110 §13.2.2, in its order: If-Match (or, without it, If-Unmodified-Since) guards against
+changing or reading a version other than the expected one; If-None-Match (or, without it and only
+for GET, If-Modified-Since) spares resending what the client has, which for any other method is a
+failure instead; then a GET's Range applies unless If-Range names another version. Times compare
+in whole seconds, all an HTTP-date carries.
+*/
+pub fn evaluate_preconditions(conditionals: &Conditionals, meta: &FileMeta) -> PreconditionResult {
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    if let Some(condition) = &conditionals.if_match {
+        if !condition.matches(meta.etag, true) {
+            return PreconditionResult::PreconditionFailed;
+        }
+    } else if let (Some(since), Some(modified)) = (conditionals.if_unmodified_since, meta.modified)
+        && seconds(modified) > seconds(since) {
+        return PreconditionResult::PreconditionFailed;
+    }
+
+    if let Some(condition) = &conditionals.if_none_match {
+        if condition.matches(meta.etag, false) {
+            return match conditionals.is_get {
+                true => PreconditionResult::NotModified,
+                false => PreconditionResult::PreconditionFailed,
+            };
+        }
+    } else if conditionals.is_get && let (Some(since), Some(modified)) = (conditionals.if_modified_since, meta.modified)
+        && seconds(modified) <= seconds(since) {
+        return PreconditionResult::NotModified;
+    }
+
+    let use_range = conditionals.is_get && conditionals.range.is_some() && match &conditionals.if_range {
+        None => true,
+        // Only a strong tag can vouch for the bytes being the same.
+        Some(IfRange::Etag(tag)) => meta.etag.and_then(EntityTag::parse).is_some_and(|current| tag.matches(&current, true)),
+        Some(IfRange::Date(date)) => meta.modified.is_some_and(|modified| seconds(modified) == seconds(*date)),
+        Some(IfRange::Invalid) => false,
+    };
+    return PreconditionResult::Proceed { use_range };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request;
+    use std::time::Duration;
+
+    use PreconditionResult::{NotModified, PreconditionFailed, Proceed};
+
+    // Sun, 06 Nov 1994 08:49:37 GMT, and a second either side.
+    const DATE: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+    const EARLIER: &str = "Sun, 06 Nov 1994 08:49:36 GMT";
+    const LATER: &str = "Sun, 06 Nov 1994 08:49:38 GMT";
+
+    fn evaluate(method: &str, headers: &str, etag: Option<&str>) -> PreconditionResult {
+        let req = parse_request(format!("{} /f HTTP/1.1\r\n{}\r\n", method, headers).as_bytes()).unwrap();
+        // Half a second past DATE: within the same whole second.
+        let modified = etag.map(|_| UNIX_EPOCH + Duration::from_millis(784_111_777_500));
+        return evaluate_preconditions(&Conditionals::parse(&req), &FileMeta { etag, modified });
+    }
+
+    #[test]
+    fn test_evaluate_preconditions() {
+        let current = Some("\"v2\"");
+        let cases: [(&str, &str, Option<&str>, PreconditionResult); 34] = [
+            // Unconditional.
+            ("GET", "", current, Proceed { use_range: false }),
+            ("PUT", "", None, Proceed { use_range: false }),
+            // If-Match: strong comparison; "*" needs the resource to exist.
+            ("PUT", "If-Match: \"v1\", \"v2\"\r\n", current, Proceed { use_range: false }),
+            ("PUT", "If-Match: \"v1\"\r\n", current, PreconditionFailed),
+            ("PUT", "If-Match: W/\"v2\"\r\n", current, PreconditionFailed),
+            ("PUT", "If-Match: *\r\n", current, Proceed { use_range: false }),
+            ("PUT", "If-Match: *\r\n", None, PreconditionFailed),
+            ("GET", "If-Match: \"v1\"\r\n", current, PreconditionFailed),
+            ("PUT", "If-Match: garbage\r\n", current, PreconditionFailed),
+            // If-Unmodified-Since, only without If-Match; invalid dates are ignored.
+            ("PUT", &format!("If-Unmodified-Since: {}\r\n", DATE), current, Proceed { use_range: false }),
+            ("PUT", &format!("If-Unmodified-Since: {}\r\n", EARLIER), current, PreconditionFailed),
+            ("PUT", &format!("If-Match: \"v2\"\r\nIf-Unmodified-Since: {}\r\n", EARLIER), current, Proceed { use_range: false }),
+            ("PUT", "If-Unmodified-Since: yesterday\r\n", current, Proceed { use_range: false }),
+            ("PUT", &format!("If-Unmodified-Since: {}\r\n", EARLIER), None, Proceed { use_range: false }),
+            // If-None-Match: weak comparison; 304 for GET, 412 for anything else.
+            ("GET", "If-None-Match: \"v2\"\r\n", current, NotModified),
+            ("GET", "If-None-Match: \"v1\", W/\"v2\"\r\n", current, NotModified),
+            ("GET", "If-None-Match: \"v1\"\r\n", current, Proceed { use_range: false }),
+            ("GET", "If-None-Match: *\r\n", current, NotModified),
+            ("PUT", "If-None-Match: *\r\n", current, PreconditionFailed),
+            ("PUT", "If-None-Match: *\r\n", None, Proceed { use_range: false }),
+            ("DELETE", "If-None-Match: \"v2\"\r\n", current, PreconditionFailed),
+            // If-Match wins over If-None-Match.
+            ("GET", "If-Match: \"v1\"\r\nIf-None-Match: \"v2\"\r\n", current, PreconditionFailed),
+            // If-Modified-Since: GET only, and only without If-None-Match.
+            ("GET", &format!("If-Modified-Since: {}\r\n", DATE), current, NotModified),
+            ("GET", &format!("If-Modified-Since: {}\r\n", EARLIER), current, Proceed { use_range: false }),
+            ("POST", &format!("If-Modified-Since: {}\r\n", LATER), current, Proceed { use_range: false }),
+            ("GET", &format!("If-None-Match: \"v1\"\r\nIf-Modified-Since: {}\r\n", LATER), current, Proceed { use_range: false }),
+            // Range, for GET, unless If-Range names another version.
+            ("GET", "Range: bytes=0-9\r\n", current, Proceed { use_range: true }),
+            ("GET", "Range: items=0-9\r\n", current, Proceed { use_range: false }),
+            ("PUT", "Range: bytes=0-9\r\n", current, Proceed { use_range: false }),
+            ("GET", "Range: bytes=0-9\r\nIf-Range: \"v2\"\r\n", current, Proceed { use_range: true }),
+            ("GET", "Range: bytes=0-9\r\nIf-Range: \"v1\"\r\n", current, Proceed { use_range: false }),
+            ("GET", "Range: bytes=0-9\r\nIf-Range: W/\"v2\"\r\n", current, Proceed { use_range: false }),
+            ("GET", &format!("Range: bytes=0-9\r\nIf-Range: {}\r\n", DATE), current, Proceed { use_range: true }),
+            ("GET", &format!("Range: bytes=0-9\r\nIf-Range: {}\r\n", LATER), current, Proceed { use_range: false }),
+        ];
+        for (method, headers, etag, expected) in cases {
+            assert_eq!(evaluate(method, headers, etag), expected, "{} with {:?} against {:?}", method, headers, etag);
+        }
+
+        // A 304 or 412 comes before Range is even looked at.
+        assert_eq!(evaluate("GET", "Range: bytes=0-9\r\nIf-None-Match: \"v2\"\r\n", current), NotModified);
+        assert_eq!(evaluate("GET", "Range: bytes=0-9\r\nIf-Range: yesterday\r\n", current), Proceed { use_range: false });
+    }
+
+    #[test]
+    fn test_parse() {
+        let req = parse_request(b"GET / HTTP/1.1\r\nIf-None-Match: \"a\", W/\"b\", junk\r\nRange: bytes=0-1,-5\r\n\r\n").unwrap();
+        let conditionals = Conditionals::parse(&req);
+        let tag = |weak: bool, opaque: &str| EntityTag { weak, opaque: opaque.to_string() };
+        assert_eq!(conditionals.if_none_match, Some(EtagCondition::Tags(vec![tag(false, "\"a\""), tag(true, "\"b\"")])));
+        assert_eq!(conditionals.range, Some(vec![RangeSpec::Bounded(0, 1), RangeSpec::Suffix(5)]));
+        assert_eq!((conditionals.if_match, conditionals.if_range, conditionals.is_get), (None, None, true));
+        assert_eq!(EntityTag::parse("\"a\"b\""), None);
+    }
+}
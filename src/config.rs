@@ -1,18 +1,980 @@
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
+use serde::de::Visitor;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-/*
+use crate::access_log::AccessFormat;
+use crate::compress::DEFAULT_COMPRESSION_TYPES;
+use crate::mime::validate_mime_types;
+use crate::request::Host;
+use crate::response::validate_response_headers;
+use crate::log::{error, warn, info, Level, Style};
+
+/// Where the server looks for its configuration, relative to the working directory.
+pub const CONFIG_PATH: &str = "config.toml";
+
+// Sane bounds for recv_buffer_bytes and max_header_bytes.
+pub(crate) const MIN_BUFFER_BYTES: usize = 1024;
+pub(crate) const MAX_BUFFER_BYTES: usize = 1024 * 1024;
+
+// response_write_timeout when it isn't set: five minutes.
+const DEFAULT_RESPONSE_WRITE_TIMEOUT_MS: u64 = 5 * 60 * 1000;
+
+/**
 #[derive(Deserialize)] is a Rust attribute macro that tells the compiler to automatically
 generate code to allow a struct to be deserialized — in this case, from a format like TOML,
 JSON, YAML, etc. Used to load structured data (like TOML) into Rust structs.
 */
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Debug, PartialEq)]
 pub struct Config {
+    /// Where static files are served from.
     pub root_directory: String,
+    /// Keep connections open between requests (when the client agrees).
     pub keep_alive: bool,
-    pub timeout_seconds: u64,
+    /// timeout / keep_alive_timeout (or their *_ms and *_seconds spellings), see Timeouts.
+    #[serde(flatten)]
+    pub timeouts: Timeouts,
+    /// Connections served at once; more get 503 (see evict_idle_when_full).
     pub max_clients: usize,
+    /// Hard ceiling on worker threads alive at once, independent of max_clients admission.
+    #[serde(default = "default_max_worker_threads")]
+    pub max_worker_threads: usize,
+    /// When max_clients are all taken, close the longest-idle keep-alive connection instead of a 503.
+    #[serde(default)]
+    pub evict_idle_when_full: bool,
+    /// What happens to a connection over max_clients: "reject" (503 at once, the default) or "queue".
+    #[serde(default)]
+    pub overflow: Overflow,
+    /// With overflow = "queue": connections waiting for a slot at most; more get 503.
+    #[serde(default = "default_queue_depth")]
+    pub queue_depth: usize,
+    /// With overflow = "queue": longest a connection waits for a slot before its 503, in milliseconds.
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    /// error, warn, info (default), debug or trace; see log.rs. --quiet / -v override it.
+    #[serde(default)]
+    pub log_level: Level,
+    /// "emoji", "plain" ([INFO]-style tags) or "auto" (default: emoji only on a capable console).
+    #[serde(default)]
+    pub log_style: Style,
+    /// Access-log line: "combined" (default), "common", "json" or a $placeholder string (see access_log.rs).
+    #[serde(default)]
+    pub access_log_format: AccessFormat,
+    /// Append access-log lines to this file instead of printing them (only applied at startup).
+    #[serde(default)]
+    pub access_log_path: Option<String>,
+    /// Record every connection's exact bytes under dump/ (see dump.rs). Never on by default.
+    #[serde(default)]
+    pub debug_dump: bool,
+    /// Requests whose User-Agent contains any of these (case-insensitive) get 403. Empty: no blocking.
+    #[serde(default)]
+    pub blocked_user_agents: Vec<String>,
+    /// Handle a POST carrying X-HTTP-Method-Override as the method it names (not CONNECT or TRACE). Off by default.
+    #[serde(default)]
+    pub method_override: bool,
+    /// Answer every request with a 301 to the same URL on https:// (see dispatch::https_location()).
+    #[serde(default)]
+    pub redirect_to_https: bool,
+    /// Port in those redirects; 443 (the default) is left out of the URL.
+    #[serde(default = "default_https_port")]
+    pub https_port: u16,
+    /// Host name for redirects of requests that have no Host header.
+    #[serde(default)]
+    pub canonical_host: Option<String>,
+    /// Host names served ("example.com", "[::1]"); a request naming another gets 421. Empty: any host.
+    #[serde(default)]
+    pub enforce_host: Vec<String>,
+    /// Open connections allowed from one client IP (0 = no limit); extra ones get 429.
+    #[serde(default)]
+    pub max_connections_per_ip: usize,
+    /// Don't apply max_connections_per_ip to 127.0.0.0/8 and ::1 (handy when testing locally).
+    #[serde(default)]
+    pub exempt_loopback: bool,
+    /// Every connection starts with a PROXY protocol v1 line naming the real client (see proxy.rs).
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    /// Address (or host name) to listen on, resolved at startup.
     pub bind_address: String,
+    /// TCP port to listen on.
     pub port: u16,
+    /// Optional [mime_types] table (extension = "media/type"), merged over the built-in map.
+    #[serde(default)]
+    pub mime_types: HashMap<String, String>,
+    /// File names tried, in order, when a directory is requested. Empty disables index serving.
+    #[serde(default = "default_index_files")]
+    pub index_files: Vec<String>,
+    /// Optional [[mounts]]: URL prefixes served from directories other than root_directory.
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    /// Optional [[rewrites]]: internal path aliases applied before routing (no redirect).
+    #[serde(default)]
+    pub rewrites: Vec<Rewrite>,
+    /// Serve files/directories whose name starts with a dot (.env, .git/...). Off by default.
+    #[serde(default)]
+    pub serve_hidden_files: bool,
+    /// File extensions (without the dot, case-insensitive) that are never served statically.
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+    /// File names (case-insensitive) that are never served statically, in any directory, e.g. "web.config".
+    #[serde(default)]
+    pub denied_filenames: Vec<String>,
+    /// Serve files reached through symlinks/junctions (still only if they resolve inside the root).
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Serve a file requested by its 8.3 short name (SECRET~1.TXT) if its long name passes the deny rules; otherwise 404.
+    #[serde(default)]
+    pub resolve_short_names: bool,
+    /// Extensions (case-insensitive) always sent as attachments (Content-Disposition).
+    #[serde(default)]
+    pub download_extensions: Vec<String>,
+    /// Answer HTTP/1.0 requests with an HTTP/1.0 status line (HTTP/1.1 is always legal, and the default).
+    #[serde(default)]
+    pub mirror_request_version: bool,
+    /// Match routes ignoring case ("/About" reaches "/about"). Needs a restart. Off by default.
+    #[serde(default)]
+    pub case_insensitive_routes: bool,
+    /// Add SECURITY_HEADERS (nosniff, DENY framing, no-referrer) to 2xx/3xx responses. Off by default.
+    #[serde(default)]
+    pub security_headers: bool,
+    /// ETags (a hash of the body) on 200s from route handlers, and 304 for a matching If-None-Match. Off by default.
+    #[serde(default)]
+    pub dynamic_etags: bool,
+    /// Log each request's per-phase timing breakdown at debug level. Off by default.
+    #[serde(default)]
+    pub timing_log: bool,
+    /// Send that breakdown to the client in a Server-Timing header (browser devtools show it). Off by default.
+    #[serde(default)]
+    pub timing_header: bool,
+    /// Gzip responses for clients that accept it (see compress.rs). On by default.
+    #[serde(default = "default_compression")]
+    pub compression: bool,
+    /// Bodies smaller than this many bytes are sent uncompressed.
+    #[serde(default = "default_compression_min_bytes")]
+    pub compression_min_bytes: usize,
+    /// Media types worth compressing; "text/*" matches a whole family.
+    #[serde(default = "default_compression_types")]
+    pub compression_types: Vec<String>,
+    /// gzip level, 0 (store) to 9 (smallest).
+    #[serde(default = "default_compression_level")]
+    pub compression_level: u32,
+    /// Size of the buffer each recv() call reads into.
+    #[serde(default = "default_buffer_bytes")]
+    pub recv_buffer_bytes: usize,
+    /// Accept header lines ending in a bare "\n" as well as "\r\n" (default). Off: those get 400.
+    #[serde(default = "default_lenient_line_endings")]
+    pub lenient_line_endings: bool,
+    /// Largest request line + headers accepted (413 beyond that).
+    #[serde(default = "default_buffer_bytes")]
+    pub max_header_bytes: usize,
+    /// Reuse receive buffers across requests and connections (see buffer_pool.rs). On by default.
+    #[serde(default = "default_buffer_pool")]
+    pub buffer_pool: bool,
+    /// Bodies larger than this (bytes) are written to a spool file instead of kept in memory.
+    #[serde(default = "default_body_memory_threshold")]
+    pub body_memory_threshold: usize,
+    /// Where spool files go; empty means the system's temporary directory.
+    #[serde(default)]
+    pub spool_directory: String,
+    /// Largest request body (bytes) accepted where no [limits] entry applies.
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+    /// Accept PUT uploads into upload_directory. Off by default.
+    #[serde(default)]
+    pub allow_put: bool,
+    /// Accept DELETE for files inside upload_directory (never anywhere else). Off by default.
+    #[serde(default)]
+    pub allow_delete: bool,
+    /// Where uploads are stored; also served (read-only) under upload_prefix. Empty: no uploads.
+    #[serde(default)]
+    pub upload_directory: String,
+    /// URL prefix of the upload area.
+    #[serde(default = "default_upload_prefix")]
+    pub upload_prefix: String,
+    /// How a client connection is closed: "graceful" (the default), "off" or { timeout_seconds = N }.
+    #[serde(default)]
+    pub linger: Linger,
+    /// Longest one response may take to send, e.g. "5m" (the default); 0 means no limit.
+    #[serde(default, deserialize_with = "response_write_timeout")]
+    pub response_write_timeout: Option<ConfigDuration>,
+    /// Cap on how fast each connection's responses are sent, in bytes per second (0: unlimited).
+    #[serde(default)]
+    pub max_bytes_per_second_per_connection: u64,
+    /// Largest static file served, in bytes (0: no limit). Bigger ones get 403, ranges included.
+    #[serde(default = "default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// In-memory cache of small static files (see static_cache.rs). Either limit at 0 disables it.
+    #[serde(default = "default_cache_max_bytes")]
+    pub cache_max_bytes: usize,
+    /// Most files the static cache holds.
+    #[serde(default = "default_cache_max_entries")]
+    pub cache_max_entries: usize,
+    /// Cache for routes registered with Router::cache_for() (see response_cache.rs). Either limit at 0 disables it.
+    #[serde(default = "default_response_cache_max_bytes")]
+    pub response_cache_max_bytes: usize,
+    /// Most responses that cache holds.
+    #[serde(default = "default_cache_max_entries")]
+    pub response_cache_max_entries: usize,
+    /// Bearer token for POST /admin/shutdown. Unset (the default): the endpoint doesn't exist.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Serve files at all. Off: unrouted paths are 404 without touching the disk. Needs a restart.
+    #[serde(default = "default_serve_static_files")]
+    pub serve_static_files: bool,
+    /// File served as /favicon.ico (any location). Unset: /favicon.ico is an empty 204. Needs a restart.
+    #[serde(default)]
+    pub favicon_path: Option<String>,
+    /// robots.txt text, or { path = "..." } of a file. Takes precedence over root_directory/robots.txt.
+    /// Setting or removing it needs a restart (a file's contents are read on each request).
+    #[serde(default)]
+    pub robots_txt: Option<RobotsTxt>,
+    /// Answer a GET for a missing static file with the root index file (single-page app routing).
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// Serve .md files as HTML rendered from them (see markdown.rs); ?raw=1 still gets the source.
+    #[serde(default)]
+    pub render_markdown: bool,
+    /// List a directory that has none of the index_files (HTML, or JSON for Accept: application/json)
+    /// instead of a 404. Hidden and denied entries are left out.
+    #[serde(default)]
+    pub directory_listing: bool,
+    /// Enable built-in debugging routes (/debug/echo, /debug/panic). Never turn on for a public server.
+    #[serde(default)]
+    pub debug_endpoints: bool,
+    /// Start in maintenance mode: 503 for everything but /health and the admin endpoints. Only read
+    /// at startup; POST /admin/maintenance switches it at runtime.
+    #[serde(default)]
+    pub maintenance: bool,
+    /// Body of the maintenance 503.
+    #[serde(default = "default_maintenance_message")]
+    pub maintenance_message: String,
+    /// Retry-After of the maintenance 503, in seconds.
+    #[serde(default = "default_maintenance_retry_after_seconds")]
+    pub maintenance_retry_after_seconds: u64,
+    /// Optional [limits] table: URL prefix = max body bytes. The longest matching prefix wins.
+    /// A route's own limit (Router::max_body()) wins over it.
+    #[serde(default)]
+    pub limits: HashMap<String, usize>,
+    /// Optional [response_headers] table: name = value, added to every response unless the handler
+    /// set that header. Sorted (a BTreeMap), so they always go out in the same order.
+    #[serde(default)]
+    pub response_headers: BTreeMap<String, String>,
+    /// Optional [api_auth] table: URL prefix = the API keys it takes (see ApiAuth). The longest
+    /// matching prefix decides.
+    #[serde(default)]
+    pub api_auth: BTreeMap<String, ApiAuth>,
+}
+
+/**
+A length of time written the way people think of it: "500ms", "5s", "2m", "1h". A bare integer
+is a number of seconds, which is what the older *_seconds keys always held. Fractions ("1.5s")
+are refused rather than rounded; say "1500ms".
+*/
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConfigDuration(pub Duration);
+
+impl ConfigDuration {
+    /// Parse "500ms", "5s", "2m", "1h" or a bare number of seconds.
+    pub fn parse(text: &str) -> Result<ConfigDuration, String> {
+        let text = text.trim();
+        let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let invalid = |why: &str| format!("invalid duration {:?}: {}", text, why);
+        if unit.starts_with('.') || unit.starts_with(',') {
+            return Err(invalid("use a whole number (e.g. \"1500ms\" instead of \"1.5s\")"));
+        }
+        let value: u64 = number.parse().map_err(|_| invalid("expected a number followed by ms, s, m or h"))?;
+        let millis = match unit.trim() {
+            "ms" => Some(value),
+            "s" | "" => value.checked_mul(1000),
+            "m" => value.checked_mul(60 * 1000),
+            "h" => value.checked_mul(60 * 60 * 1000),
+            _ => return Err(invalid("the unit must be ms, s, m or h")),
+        };
+        let millis = millis.ok_or_else(|| invalid("too large"))?;
+        return Ok(ConfigDuration(Duration::from_millis(millis)));
+    }
+
+    /// The duration in whole milliseconds.
+    pub fn as_millis(&self) -> u64 {
+        return self.0.as_millis() as u64;
+    }
+}
+
+/*
+Hand-written rather than derived: the value is either a TOML integer (seconds) or a string with a
+unit, and a float gets its own error instead of serde's generic "invalid type".
+*/
+impl<'de> Deserialize<'de> for ConfigDuration {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ConfigDuration, D::Error> {
+        struct DurationVisitor;
+
+        impl Visitor<'_> for DurationVisitor {
+            type Value = ConfigDuration;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                return f.write_str("a duration such as \"500ms\", \"5s\", \"2m\" or \"1h\", or a whole number of seconds");
+            }
+
+            fn visit_i64<E: de::Error>(self, seconds: i64) -> Result<ConfigDuration, E> {
+                let seconds = u64::try_from(seconds).map_err(|_| E::custom(format!("invalid duration {}: must not be negative", seconds)))?;
+                return self.visit_u64(seconds);
+            }
+
+            fn visit_u64<E: de::Error>(self, seconds: u64) -> Result<ConfigDuration, E> {
+                return ConfigDuration::parse(&seconds.to_string()).map_err(E::custom);
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<ConfigDuration, E> {
+                return Err(E::custom(format!("invalid duration {}: use a whole number, or a string like \"1500ms\"", value)));
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<ConfigDuration, E> {
+                return ConfigDuration::parse(text).map_err(E::custom);
+            }
+        }
+
+        return deserializer.deserialize_any(DurationVisitor);
+    }
+}
+
+/**
+The connection timeouts, in milliseconds:
+- timeout_ms: how long to wait for the rest of a request once its first bytes have arrived.
+- keep_alive_timeout_ms: how long an idle keep-alive connection is held open between requests
+  (default: timeout_ms).
+
+In the file each can be written three ways: `timeout = "30s"` (a ConfigDuration), `timeout_ms =
+30000`, or the older `timeout_seconds = 30`, which also takes a ConfigDuration now. A plain serde
+alias can't do the conversions, hence the detour through RawTimeouts.
+*/
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(try_from = "RawTimeouts")]
+pub struct Timeouts {
+    /// Time allowed to send a whole request, from its first byte.
+    pub timeout_ms: u64,
+    /// Idle time allowed between requests; None means the same as timeout_ms.
+    pub keep_alive_timeout_ms: Option<u64>,
+}
+
+// The timeout keys exactly as written in the file.
+#[derive(Deserialize)]
+struct RawTimeouts {
+    #[serde(default, deserialize_with = "timeout")]
+    timeout: Option<ConfigDuration>,
+    timeout_ms: Option<u64>,
+    #[serde(default, deserialize_with = "timeout_seconds")]
+    timeout_seconds: Option<ConfigDuration>,
+    #[serde(default, deserialize_with = "keep_alive_timeout")]
+    keep_alive_timeout: Option<ConfigDuration>,
+    keep_alive_timeout_ms: Option<u64>,
+    #[serde(default, deserialize_with = "keep_alive_timeout_seconds")]
+    keep_alive_timeout_seconds: Option<ConfigDuration>,
+}
+
+/*
+Flattened fields lose their key in serde's errors ("invalid duration "5x"", but where?), so each
+duration key is read through one of these to put its name in front.
+*/
+fn named_duration<'de, D: Deserializer<'de>>(name: &str, deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return Option::<ConfigDuration>::deserialize(deserializer).map_err(|e| de::Error::custom(format!("{}: {}", name, e)));
+}
+
+fn timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return named_duration("timeout", deserializer);
+}
+
+fn timeout_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return named_duration("timeout_seconds", deserializer);
+}
+
+fn keep_alive_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return named_duration("keep_alive_timeout", deserializer);
+}
+
+fn keep_alive_timeout_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return named_duration("keep_alive_timeout_seconds", deserializer);
+}
+
+fn response_write_timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ConfigDuration>, D::Error> {
+    return named_duration("response_write_timeout", deserializer);
+}
+
+impl TryFrom<RawTimeouts> for Timeouts {
+    type Error = String;
+
+    fn try_from(raw: RawTimeouts) -> Result<Timeouts, String> {
+        let timeout_ms = pick_millis("timeout", raw.timeout, raw.timeout_ms, raw.timeout_seconds)?
+            .ok_or_else(|| "missing field `timeout` (or `timeout_ms`, `timeout_seconds`)".to_string())?;
+        let keep_alive_timeout_ms = pick_millis(
+            "keep_alive_timeout", raw.keep_alive_timeout, raw.keep_alive_timeout_ms, raw.keep_alive_timeout_seconds
+        )?;
+        return Ok(Timeouts { timeout_ms, keep_alive_timeout_ms });
+    }
+}
+
+// Whichever of `<name>`, `<name>_ms` and `<name>_seconds` is set, in milliseconds. Setting more than one is an error.
+fn pick_millis(
+    name: &str, duration: Option<ConfigDuration>, millis: Option<u64>, seconds: Option<ConfigDuration>
+) -> Result<Option<u64>, String> {
+    match (duration, millis, seconds) {
+        (Some(d), None, None) | (None, None, Some(d)) => return Ok(Some(d.as_millis())),
+        (None, Some(ms), None) => return Ok(Some(ms)),
+        (None, None, None) => return Ok(None),
+        _ => return Err(format!("set only one of {}, {}_ms and {}_seconds", name, name, name)),
+    }
+}
+
+/**
+What GET /robots.txt answers with, when configured: the text itself,
+
+```toml
+robots_txt = "User-agent: *\nDisallow: /private/\n"
+```
+
+or a file to read it from (anywhere; read on each request, so edits to it show up at once):
+
+```toml
+robots_txt = { path = "deploy/robots.txt" }
+```
+*/
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum RobotsTxt {
+    Inline(String),
+    File { path: String },
+}
+
+/**
+How a client connection is torn down. With "graceful" (the default) the server shuts down its
+sending side, reads and discards what the client still sends for a moment, then closes: the client
+sees the whole response followed by a FIN, never a reset. "off" closes at once, which may reset
+the connection if request bytes are still unread. A timeout sets SO_LINGER, so closesocket() waits
+up to that many seconds for the data to go out, after the same shutdown and drain:
+
+```toml
+linger = { timeout_seconds = 5 }
+```
+*/
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(try_from = "RawLinger")]
+pub enum Linger {
+    Off,
+    #[default]
+    Graceful,
+    Timeout(u16),
+}
+
+// The linger value as written in the file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLinger {
+    Mode(String),
+    Timeout { timeout_seconds: u16 },
+}
+
+impl TryFrom<RawLinger> for Linger {
+    type Error = String;
+
+    fn try_from(raw: RawLinger) -> Result<Linger, String> {
+        return match raw {
+            RawLinger::Mode(mode) if mode == "off" => Ok(Linger::Off),
+            RawLinger::Mode(mode) if mode == "graceful" => Ok(Linger::Graceful),
+            RawLinger::Mode(mode) => Err(format!("invalid linger {:?}: expected \"off\", \"graceful\" or {{ timeout_seconds = N }}", mode)),
+            RawLinger::Timeout { timeout_seconds } => Ok(Linger::Timeout(timeout_seconds)),
+        };
+    }
+}
+
+/**
+What the accept loop does with a connection when all max_clients slots are taken. "reject" answers
+503 straight away. "queue" parks it, unread, until a slot frees up: up to queue_depth connections
+wait, first come first served, each for at most queue_timeout_ms before getting a 503 with
+Retry-After. A full queue rejects at once.
+*/
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    #[default]
+    Reject,
+    Queue,
+}
+
+/// One [[mounts]] entry: requests under `prefix` are served from `directory`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Mount {
+    /// URL prefix, e.g. "/assets".
+    pub prefix: String,
+    /// Directory the files under `prefix` come from.
+    pub directory: String,
+}
+
+/**
+One [[rewrites]] entry. By default `from` must match the whole path; with `prefix = true` it
+matches a leading run of segments, which is replaced by `to` ("/docs/a" → "/documentation/a").
+*/
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Rewrite {
+    /// Path (or leading segments, with `prefix`) to rewrite.
+    pub from: String,
+    /// What it becomes.
+    pub to: String,
+    /// Match leading segments instead of the whole path.
+    #[serde(default)]
+    pub prefix: bool,
+}
+
+/**
+One [api_auth] entry: requests under its prefix need one of `keys`, as "Authorization: Bearer
+<token>" or, when `header` is set, as that header's whole value ("X-Api-Key: <token>").
+*/
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ApiAuth {
+    /// Key id = token. The id is what the request carries on (and the access log shows), never the token.
+    pub keys: BTreeMap<String, String>,
+    /// A header that may carry the token instead of Authorization.
+    #[serde(default)]
+    pub header: Option<String>,
+}
+
+fn default_index_files() -> Vec<String> {
+    return vec!["index.html".to_string()];
+}
+
+fn default_max_worker_threads() -> usize {
+    return 64;
+}
+
+fn default_serve_static_files() -> bool {
+    return true;
+}
+
+fn default_compression() -> bool {
+    return true;
+}
+
+fn default_queue_depth() -> usize {
+    return 16;
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    return 5000;
+}
+
+fn default_maintenance_message() -> String {
+    return "Down for maintenance, back shortly.".to_string();
+}
+
+fn default_maintenance_retry_after_seconds() -> u64 {
+    return 60;
+}
+
+fn default_https_port() -> u16 {
+    return 443;
+}
+
+fn default_lenient_line_endings() -> bool {
+    return true;
+}
+
+fn default_buffer_pool() -> bool {
+    return true;
+}
+
+fn default_compression_min_bytes() -> usize {
+    return 1024;
+}
+
+fn default_compression_types() -> Vec<String> {
+    return DEFAULT_COMPRESSION_TYPES.iter().map(|t| t.to_string()).collect();
+}
+
+fn default_compression_level() -> u32 {
+    return 6;
+}
+
+fn default_upload_prefix() -> String {
+    return "/uploads".to_string();
+}
+
+fn default_max_file_bytes() -> u64 {
+    return 1024 * 1024 * 1024;
+}
+
+fn default_cache_max_bytes() -> usize {
+    return 8 * 1024 * 1024;
+}
+
+fn default_response_cache_max_bytes() -> usize {
+    return 4 * 1024 * 1024;
+}
+
+fn default_cache_max_entries() -> usize {
+    return 256;
+}
+
+fn default_buffer_bytes() -> usize {
+    return 8192;
+}
+
+fn default_body_memory_threshold() -> usize {
+    return 256 * 1024;
+}
+
+fn default_max_body_size() -> usize {
+    return 1024 * 1024;
+}
+
+impl Config {
+    /// Time allowed to finish a request once it has started.
+    pub fn request_timeout_ms(&self) -> u64 {
+        return self.timeouts.timeout_ms;
+    }
+
+    /// Idle time allowed between two requests on a persistent connection.
+    pub fn idle_timeout_ms(&self) -> u64 {
+        return self.timeouts.keep_alive_timeout_ms.unwrap_or(self.timeouts.timeout_ms);
+    }
+
+    /// How long to wait for the client's next bytes: idle between requests, or mid-request.
+    pub fn read_timeout_ms(&self, idle: bool) -> u64 {
+        return if idle { self.idle_timeout_ms() } else { self.request_timeout_ms() };
+    }
+
+    /// Time allowed to send one whole response to a (slow) client; None: no limit.
+    pub fn response_write_timeout_ms(&self) -> Option<u64> {
+        let ms = self.response_write_timeout.map_or(DEFAULT_RESPONSE_WRITE_TIMEOUT_MS, |d| d.as_millis());
+        return (ms > 0).then_some(ms);
+    }
+
+    /// Reject values that would parse fine but make the server misbehave.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.root_directory.trim().is_empty() {
+            return Err("root_directory must not be empty".to_string());
+        }
+        if self.timeouts.timeout_ms == 0 {
+            return Err("timeout must be at least 1ms".to_string());
+        }
+        if self.timeouts.keep_alive_timeout_ms == Some(0) {
+            return Err("keep_alive_timeout must be at least 1ms".to_string());
+        }
+        // Anything made of digits and dots must be a complete a.b.c.d; the resolver would take
+        // "127.0.0" as 127.0.0.0 and "10.1" as 10.0.0.1, which is never what was meant.
+        let numeric = self.bind_address.chars().all(|c| c.is_ascii_digit() || c == '.');
+        if self.bind_address.trim().is_empty() || (numeric && self.bind_address.parse::<Ipv4Addr>().is_err()) {
+            return Err(format!(
+                "bind_address {:?} is not a valid IPv4 address (four numbers 0-255, like 127.0.0.1) or host name",
+                self.bind_address
+            ));
+        }
+        if self.max_clients == 0 {
+            return Err("max_clients must be at least 1".to_string());
+        }
+        if self.max_worker_threads == 0 {
+            return Err("max_worker_threads must be at least 1".to_string());
+        }
+        validate_mime_types(&self.mime_types)?;
+        validate_response_headers(&self.response_headers)?;
+        for mount in &self.mounts {
+            if !mount.prefix.starts_with('/') {
+                return Err(format!("mounts: prefix {:?} must start with '/'", mount.prefix));
+            }
+            if mount.directory.trim().is_empty() {
+                return Err(format!("mounts: directory for {:?} must not be empty", mount.prefix));
+            }
+        }
+        for rewrite in &self.rewrites {
+            if !rewrite.from.starts_with('/') || !rewrite.to.starts_with('/') {
+                return Err(format!("rewrites: {:?} -> {:?} must both start with '/'", rewrite.from, rewrite.to));
+            }
+        }
+        for name in &self.index_files {
+            if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+                return Err(format!("index_files: {:?} must be a plain file name", name));
+            }
+        }
+        if (self.allow_put || self.allow_delete) && self.upload_directory.trim().is_empty() {
+            return Err("allow_put and allow_delete require an upload_directory".to_string());
+        }
+        if !self.upload_prefix.starts_with('/') || self.upload_prefix.trim_end_matches('/').is_empty() {
+            return Err(format!("upload_prefix {:?} must start with '/' and not be the root", self.upload_prefix));
+        }
+        for prefix in self.limits.keys() {
+            if !prefix.starts_with('/') {
+                return Err(format!("limits: prefix {:?} must start with '/'", prefix));
+            }
+        }
+        for (prefix, auth) in &self.api_auth {
+            if !prefix.starts_with('/') {
+                return Err(format!("api_auth: prefix {:?} must start with '/'", prefix));
+            }
+            if auth.keys.is_empty() {
+                return Err(format!("api_auth: {:?} has no keys, so nothing could get in", prefix));
+            }
+            if let Some((id, _)) = auth.keys.iter().find(|(_, token)| token.trim().is_empty()) {
+                return Err(format!("api_auth: key {:?} for {:?} must not be empty", id, prefix));
+            }
+            if auth.header.as_ref().is_some_and(|header| header.trim().is_empty()) {
+                return Err(format!("api_auth: header for {:?} must not be empty (remove it to take Bearer tokens only)", prefix));
+            }
+        }
+        // TOML strings are always UTF-8; a file has to be checked.
+        if let Some(RobotsTxt::File { path }) = &self.robots_txt {
+            let contents = fs::read(path).map_err(|e| format!("robots_txt: cannot read {}: {}", path, e))?;
+            if std::str::from_utf8(&contents).is_err() {
+                return Err(format!("robots_txt: {} is not valid UTF-8", path));
+            }
+        }
+        if self.favicon_path.as_ref().is_some_and(|path| path.trim().is_empty()) {
+            return Err("favicon_path must not be empty (remove it to answer /favicon.ico with 204)".to_string());
+        }
+        if self.admin_token.as_ref().is_some_and(|token| token.trim().is_empty()) {
+            return Err("admin_token must not be empty (remove it to disable the admin endpoint)".to_string());
+        }
+        for (name, value) in [("recv_buffer_bytes", self.recv_buffer_bytes), ("max_header_bytes", self.max_header_bytes)] {
+            if !(MIN_BUFFER_BYTES..=MAX_BUFFER_BYTES).contains(&value) {
+                return Err(format!("{} must be between {} and {}, got {}", name, MIN_BUFFER_BYTES, MAX_BUFFER_BYTES, value));
+            }
+        }
+        if let Some(host) = &self.canonical_host
+            && !Host::parse(host).is_some_and(|parsed| !parsed.name.is_empty() && parsed.port.is_none()) {
+            return Err(format!("canonical_host {:?} must be a host name without a port", host));
+        }
+        if self.blocked_user_agents.iter().any(|agent| agent.trim().is_empty()) {
+            return Err("blocked_user_agents: entries must not be empty (they would block everyone)".to_string());
+        }
+        if self.compression_level > 9 {
+            return Err(format!("compression_level must be between 0 and 9, got {}", self.compression_level));
+        }
+        return Ok(());
+    }
+}
+
+/// Read, parse and validate a config file. The error names the file and what went wrong.
+pub fn load_config(path: &str) -> Result<Config, String> {
+    let raw = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    let config: Config = toml::from_str(&raw).map_err(|e| format!("failed to parse {}: {}", path, e))?;
+    config.validate().map_err(|e| format!("invalid {}: {}", path, e))?;
+    return Ok(config);
+}
+
+/**
+The IPv4 address to listen on for bind_address: either an address ("127.0.0.1", "0.0.0.0") or a
+host name ("localhost", "myserver.lan"), resolved once at startup to its first IPv4 address.
+*/
+pub fn resolve_bind_address(bind_address: &str) -> Result<Ipv4Addr, String> {
+    match bind_address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => return Ok(ip),
+        Ok(IpAddr::V6(_)) => return Err(format!("bind_address {:?}: IPv6 is not supported yet", bind_address)),
+        Err(_) => {}
+    }
+    let resolved = (bind_address, 0).to_socket_addrs()
+        .map_err(|e| format!("bind_address {:?} is not an IP address and could not be resolved: {}", bind_address, e))?;
+    for addr in resolved {
+        if let IpAddr::V4(ip) = addr.ip() {
+            return Ok(ip);
+        }
+    }
+    return Err(format!("bind_address {:?} has no IPv4 address", bind_address));
+}
+
+/*
+The configuration currently in effect, shared by the accept loop and every worker.
+
+The outer Arc lets every thread hold the same lock; the RwLock guards which Config is current;
+the inner Arc lets a reader take a cheap snapshot (clone the Arc, drop the lock) and keep using
+it for a whole request even if a reload swaps in a new Config halfway through.
+*/
+pub(crate) type SharedConfig = Arc<RwLock<Arc<Config>>>;
+
+pub(crate) fn shared(config: Config) -> SharedConfig {
+    return Arc::new(RwLock::new(Arc::new(config)));
+}
+
+// Snapshot of the current configuration.
+pub(crate) fn current(shared: &SharedConfig) -> Arc<Config> {
+    // A poisoned lock still holds a complete Config (swaps are a single assignment).
+    let guard = shared.read().unwrap_or_else(|e| e.into_inner());
+    return Arc::clone(&guard);
+}
+
+/*
+Decide what a freshly loaded config turns into once applied to a running server.
+The listening socket is bound once at startup, so bind_address and port can't change without a
+restart: they are kept at their old values and the change is logged instead of silently ignored.
+*/
+fn reconcile(old: &Config, mut new: Config) -> Config {
+    if new.bind_address != old.bind_address || new.port != old.port {
+        warn!(
+            "⚠️ bind_address/port changed to {}:{} — this requires a restart; still listening on {}:{}.",
+            new.bind_address, new.port, old.bind_address, old.port
+        );
+        new.bind_address = old.bind_address.clone();
+        new.port = old.port;
+    }
+    return new;
+}
+
+/*
+Start a background thread that re-reads the config file whenever its modification time changes
+(polling every `interval`; no file-notification dependency needed) and swaps it into `shared`.
+A file that fails to read, parse or validate leaves the old config in effect.
+*/
+pub(crate) fn spawn_config_watcher(path: String, shared: SharedConfig, interval: Duration) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
+        let modified = |path: &str| -> Option<SystemTime> {
+            return fs::metadata(path).and_then(|m| m.modified()).ok();
+        };
+        let mut last_seen = modified(&path);
+
+        loop {
+            thread::sleep(interval);
+
+            let now = modified(&path);
+            if now == last_seen {
+                continue;
+            }
+            last_seen = now;
+
+            match load_config(&path) {
+                Ok(new) => {
+                    let old = current(&shared);
+                    let new = reconcile(&old, new);
+                    *shared.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(new);
+                    info!("🔄 Reloaded {}.", path);
+                }
+                Err(e) => error!("❌ Config reload failed, keeping the previous settings: {}", e),
+            }
+        }
+    });
+}
+
+/**
+What a valid Config comes to once it meets the machine it runs on: bind_address resolved, the
+directories canonicalized, command-line overrides applied. Built by resolve(), shown by the
+startup banner and by `vibettp check`, so both always describe the same thing.
+*/
+pub struct Effective {
+    /// bind_address resolved, or why it can't be.
+    pub bind_ip: Result<Ipv4Addr, String>,
+    /// root_directory made absolute (as configured if it doesn't exist).
+    pub root_directory: PathBuf,
+    /// (prefix, directory) of each [[mounts]] entry.
+    pub mounts: Vec<(String, PathBuf)>,
+    /// log_level, or the command line's override.
+    pub log_level: Level,
+    /// Directories that don't exist or aren't directories. The server still starts (they may appear later).
+    pub problems: Vec<String>,
+}
+
+// `path` made absolute, or why that's not possible (it must be an existing directory).
+fn canonical_directory(setting: &str, path: &str) -> Result<PathBuf, String> {
+    let canonical = fs::canonicalize(path).map_err(|e| format!("{} {:?}: {}", setting, path, e))?;
+    if !canonical.is_dir() {
+        return Err(format!("{} {:?} is not a directory", setting, path));
+    }
+    return Ok(canonical);
+}
+
+/// Resolve a loaded config against this machine (see Effective).
+pub fn resolve(config: &Config, cli_level: Option<Level>) -> Effective {
+    let mut problems = Vec::new();
+    let mut directory = |setting: &str, path: &str| match canonical_directory(setting, path) {
+        Ok(canonical) => canonical,
+        Err(e) => {
+            problems.push(e);
+            PathBuf::from(path)
+        }
+    };
+    let root_directory = directory("root_directory", &config.root_directory);
+    let mounts = config.mounts.iter()
+        .map(|mount| (mount.prefix.clone(), directory(&format!("mounts: directory for {:?}", mount.prefix), &mount.directory)))
+        .collect();
+    return Effective {
+        bind_ip: resolve_bind_address(&config.bind_address),
+        root_directory,
+        mounts,
+        log_level: cli_level.unwrap_or(config.log_level),
+        problems,
+    };
+}
+
+impl Effective {
+    /// Everything wrong with it: an unusable bind_address first, then the directory problems.
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors: Vec<String> = self.bind_ip.as_ref().err().cloned().into_iter().collect();
+        errors.extend(self.problems.iter().cloned());
+        return errors;
+    }
+
+    /// The settings that matter most, one line each, as the banner and `vibettp check` print them.
+    pub fn summary(&self, config: &Config) -> Vec<String> {
+        let bind = match &self.bind_ip {
+            Ok(ip) if ip.to_string() == config.bind_address => format!("{}:{}", ip, config.port),
+            Ok(ip) => format!("{}:{} ({})", ip, config.port, config.bind_address),
+            Err(_) => format!("{}:{} (unresolved)", config.bind_address, config.port),
+        };
+        let mut lines = vec![
+            format!("listen: {}", bind),
+            format!("root_directory: {}", display_path(&self.root_directory)),
+        ];
+        for (prefix, directory) in &self.mounts {
+            lines.push(format!("mount: {} → {}", prefix, display_path(directory)));
+        }
+        if config.allow_put || config.allow_delete {
+            lines.push(format!("uploads: {} → {}", config.upload_prefix, config.upload_directory));
+        }
+        lines.push(format!(
+            "clients: max_clients {}, max_worker_threads {}, max_connections_per_ip {}",
+            config.max_clients, config.max_worker_threads, config.max_connections_per_ip
+        ));
+        lines.push(format!(
+            "timeouts: request {}ms, keep_alive {} (idle {}ms)",
+            config.request_timeout_ms(), config.keep_alive, config.idle_timeout_ms()
+        ));
+        lines.push(format!("logging: {:?} level, {:?} style", self.log_level, config.log_style));
+        return lines;
+    }
+}
+
+fn display_path(path: &Path) -> String {
+    return path.display().to_string();
+}
+
+/**
+`vibettp check`: load the config at `path` the way the server would and resolve it, without
+binding anything. Ok: the summary lines. Err: every problem found (a file that can't be read
+or parsed stops there; otherwise validation and resolution errors are all collected).
+*/
+pub fn check(path: &str) -> Result<Vec<String>, Vec<String>> {
+    let raw = fs::read_to_string(path).map_err(|e| vec![format!("failed to read {}: {}", path, e)])?;
+    let config: Config = toml::from_str(&raw).map_err(|e| vec![format!("failed to parse {}: {}", path, e)])?;
+    let mut errors: Vec<String> = config.validate().err().into_iter().collect();
+    let effective = resolve(&config, None);
+    errors.extend(effective.errors());
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(effective.summary(&config));
+}
+
+/*
+A valid Config for unit tests elsewhere in the crate: the required keys with sensible values,
+`root` as root_directory, plus whatever extra TOML (keys first, then tables) a test needs.
+*/
+#[cfg(test)]
+pub(crate) fn test_config(root: &str, extra: &str) -> Config {
+    let raw = format!(
+        "root_directory = {:?}\nkeep_alive = true\ntimeout_ms = 5000\nmax_clients = 4\n\
+         bind_address = \"127.0.0.1\"\nport = 7878\n{}",
+        root, extra
+    );
+    return toml::from_str(&raw).expect("invalid test config");
 }
 
 #[cfg(test)]
@@ -20,11 +982,239 @@ mod tests {
     use std::fs;
     use super::*;
 
+    const VALID: &str = r#"
+        root_directory = "."
+        keep_alive = true
+        timeout_seconds = 5
+        max_clients = 4
+        bind_address = "127.0.0.1"
+        port = 7878
+    "#;
+
     #[test]
     fn test_config_defaults() {
-        let raw = fs::read_to_string("config.toml").expect("❌ Failed to read config file");
+        let raw = fs::read_to_string("tests/config.toml").expect("❌ Failed to read config file");
         let config: Config = toml::from_str(&raw).expect("❌ Failed to parse config");
         assert_eq!(config.bind_address, "127.0.0.1");
         assert_eq!(config.port, 7878);
     }
+
+    #[test]
+    fn test_validate() {
+        let config: Config = toml::from_str(VALID).unwrap();
+        assert!(config.validate().is_ok());
+
+        let broken = Config { max_clients: 0, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { timeouts: Timeouts { timeout_ms: 0, ..config.timeouts }, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { compression_level: 10, ..config.clone() };
+        assert!(broken.validate().is_err());
+        for host in ["example.com:443", "https://example.com", ""] {
+            let broken = Config { canonical_host: Some(host.to_string()), ..config.clone() };
+            assert!(broken.validate().is_err(), "{:?}", host);
+        }
+        let broken = Config { recv_buffer_bytes: 512, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { max_header_bytes: MAX_BUFFER_BYTES + 1, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { allow_put: true, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { allow_delete: true, ..config.clone() };
+        assert!(broken.validate().is_err());
+        let broken = Config { upload_prefix: "/".to_string(), ..config.clone() };
+        assert!(broken.validate().is_err());
+        for address in ["127.0.0", "127.0.0.256", "10.1", "1.2.3.4.5", ""] {
+            let broken = Config { bind_address: address.to_string(), ..config.clone() };
+            let error = broken.validate().unwrap_err();
+            assert!(error.contains(&format!("{:?}", address)), "{}", error);
+        }
+        let hostname = Config { bind_address: "localhost".to_string(), ..config.clone() };
+        assert!(hostname.validate().is_ok());
+
+        let raw = format!("{}\n[mime_types]\ngltf = \"model/gltf json\"\n", VALID);
+        let broken: Config = toml::from_str(&raw).unwrap();
+        assert!(broken.validate().is_err());
+
+        let raw = format!("{}\n[limits]\n\"upload\" = 10\n", VALID);
+        let broken: Config = toml::from_str(&raw).unwrap();
+        assert!(broken.validate().is_err());
+
+        for api_auth in ["\"/api\" = { keys = {} }", "\"/api\" = { keys = { ci = \" \" } }", "\"api\" = { keys = { ci = \"t\" } }"] {
+            let broken: Config = toml::from_str(&format!("{}\n[api_auth]\n{}\n", VALID, api_auth)).unwrap();
+            assert!(broken.validate().unwrap_err().starts_with("api_auth: "), "{}", api_auth);
+        }
+
+        // An unknown access-log placeholder fails when the file is loaded, not per request.
+        let raw = format!("access_log_format = \"$remote_addr $bogus\"\n{}", VALID);
+        let error = toml::from_str::<Config>(&raw).unwrap_err().to_string();
+        assert!(error.contains("unknown placeholder $bogus"), "{}", error);
+    }
+
+    #[test]
+    fn test_check() {
+        let dir = crate::util::temp_root("check");
+        fs::create_dir_all(dir.join("public")).unwrap();
+        fs::create_dir_all(dir.join("assets")).unwrap();
+        fs::write(dir.join("not_a_dir"), "x").unwrap();
+        let fixture = |name: &str, toml: &str| {
+            let path = dir.join(name);
+            fs::write(&path, toml).unwrap();
+            return path.to_str().unwrap().to_string();
+        };
+        let root = |path: &str| dir.join(path).to_str().unwrap().to_string();
+
+        let good = fixture("good.toml", &format!(
+            "{}root_directory = {:?}\n[[mounts]]\nprefix = \"/assets\"\ndirectory = {:?}\n",
+            VALID.replace("root_directory = \".\"", ""), root("public"), root("assets")
+        ));
+        let summary = check(&good).unwrap();
+        assert_eq!(summary[0], "listen: 127.0.0.1:7878");
+        let public = fs::canonicalize(dir.join("public")).unwrap();
+        assert!(summary.contains(&format!("root_directory: {}", public.display())), "{:?}", summary);
+        assert!(summary.iter().any(|line| line.starts_with("mount: /assets → ")));
+
+        // Everything wrong is reported, not just the first thing.
+        let broken = fixture("broken.toml", &format!(
+            "{}root_directory = {:?}\nmax_worker_threads = 0\n[[mounts]]\nprefix = \"/assets\"\ndirectory = {:?}\n",
+            VALID.replace("root_directory = \".\"", ""), root("missing"), root("not_a_dir")
+        ));
+        let errors = check(&broken).unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(errors[0], "max_worker_threads must be at least 1");
+        assert!(errors[1].starts_with("root_directory "), "{}", errors[1]);
+        assert!(errors[2].contains("is not a directory"), "{}", errors[2]);
+
+        let unparsable = fixture("unparsable.toml", "root_directory = ");
+        assert!(check(&unparsable).unwrap_err()[0].starts_with("failed to parse"));
+        assert!(check(&root("nowhere.toml")).unwrap_err()[0].starts_with("failed to read"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_resolve_bind_address() {
+        assert_eq!(resolve_bind_address("127.0.0.1"), Ok(Ipv4Addr::LOCALHOST));
+        assert_eq!(resolve_bind_address("0.0.0.0"), Ok(Ipv4Addr::UNSPECIFIED));
+        assert!(resolve_bind_address("localhost").is_ok_and(|ip| ip.is_loopback()));
+        assert!(resolve_bind_address("::1").is_err());
+
+        let error = resolve_bind_address("not a host!").unwrap_err();
+        assert!(error.contains("\"not a host!\""), "{}", error);
+    }
+
+    #[test]
+    fn test_read_timeouts() {
+        // VALID still uses the old timeout_seconds spelling.
+        let config: Config = toml::from_str(VALID).unwrap();
+        assert_eq!((config.read_timeout_ms(true), config.read_timeout_ms(false)), (5000, 5000));
+
+        let raw = format!("keep_alive_timeout_seconds = 60\n{}", VALID);
+        let config: Config = toml::from_str(&raw).unwrap();
+        assert_eq!((config.read_timeout_ms(true), config.read_timeout_ms(false)), (60000, 5000));
+
+        let config = test_config(".", "keep_alive_timeout_ms = 250\n");
+        assert_eq!((config.read_timeout_ms(true), config.read_timeout_ms(false)), (250, 5000));
+
+        // Both spellings of the same timeout, or none at all: refused.
+        let raw = format!("timeout_ms = 300\n{}", VALID);
+        assert!(toml::from_str::<Config>(&raw).is_err());
+        let raw = VALID.replace("timeout_seconds = 5", "");
+        assert!(toml::from_str::<Config>(&raw).is_err());
+    }
+
+    #[test]
+    fn test_response_write_timeout() {
+        assert_eq!(test_config(".", "").response_write_timeout_ms(), Some(300_000));
+        assert_eq!(test_config(".", "response_write_timeout = \"30s\"\n").response_write_timeout_ms(), Some(30_000));
+        assert_eq!(test_config(".", "response_write_timeout = 0\n").response_write_timeout_ms(), None);
+        let raw = format!("response_write_timeout = \"soon\"\n{}", VALID);
+        let error = toml::from_str::<Config>(&raw).unwrap_err().to_string();
+        assert!(error.contains("response_write_timeout: invalid duration"), "{}", error);
+    }
+
+    #[test]
+    fn test_read_overflow() {
+        let config = test_config(".", "");
+        assert_eq!((config.overflow, config.queue_depth, config.queue_timeout_ms), (Overflow::Reject, 16, 5000));
+        let config = test_config(".", "overflow = \"queue\"\nqueue_depth = 2\nqueue_timeout_ms = 750\n");
+        assert_eq!((config.overflow, config.queue_depth, config.queue_timeout_ms), (Overflow::Queue, 2, 750));
+        assert!(toml::from_str::<Config>(&format!("overflow = \"wait\"\n{}", VALID)).is_err());
+    }
+
+    #[test]
+    fn test_read_linger() {
+        assert_eq!(test_config(".", "").linger, Linger::Graceful);
+        assert_eq!(test_config(".", "linger = \"off\"\n").linger, Linger::Off);
+        assert_eq!(test_config(".", "linger = { timeout_seconds = 5 }\n").linger, Linger::Timeout(5));
+
+        let raw = format!("linger = \"abrupt\"\n{}", VALID);
+        let error = toml::from_str::<Config>(&raw).unwrap_err().to_string();
+        assert!(error.contains("invalid linger \"abrupt\""), "{}", error);
+        let raw = format!("linger = {{ timeout_seconds = 70000 }}\n{}", VALID);
+        assert!(toml::from_str::<Config>(&raw).is_err());
+    }
+
+    #[test]
+    fn test_config_duration() {
+        let ms = |text: &str| ConfigDuration::parse(text).map(|d| d.as_millis());
+        assert_eq!(ms("500ms"), Ok(500));
+        assert_eq!(ms("5s"), Ok(5000));
+        assert_eq!(ms("2m"), Ok(120_000));
+        assert_eq!(ms("1h"), Ok(3_600_000));
+        assert_eq!(ms("30"), Ok(30_000));
+        assert!(ms("1.5s").unwrap_err().contains("whole number"));
+        assert!(ms("5 parsecs").unwrap_err().contains("\"5 parsecs\""));
+        assert!(ms("ms").is_err());
+
+        // In the file: strings with units, bare integers as seconds, and errors that say what was wrong.
+        let config = test_config(".", "").timeouts;
+        let parse = |line: &str| toml::from_str::<Config>(&format!("{}\n{}", line, VALID.replace("timeout_seconds = 5", "")));
+        assert_eq!(parse("timeout = \"250ms\"").unwrap().timeouts, Timeouts { timeout_ms: 250, ..config });
+        assert_eq!(parse("timeout = 7").unwrap().timeouts, Timeouts { timeout_ms: 7000, ..config });
+        assert_eq!(parse("timeout_seconds = \"2m\"").unwrap().timeouts, Timeouts { timeout_ms: 120_000, ..config });
+        let error = parse("timeout = \"5x\"").unwrap_err().to_string();
+        assert!(error.contains("timeout: invalid duration \"5x\""), "{}", error);
+        let error = parse("keep_alive_timeout_seconds = 2.5\ntimeout = 1").unwrap_err().to_string();
+        assert!(error.contains("keep_alive_timeout_seconds: invalid duration 2.5"), "{}", error);
+    }
+
+    #[test]
+    fn test_reconcile_keeps_listener_settings() {
+        let old: Config = toml::from_str(VALID).unwrap();
+        let new = Config { port: 9999, max_clients: 8, ..old.clone() };
+        let applied = reconcile(&old, new);
+        assert_eq!(applied.port, 7878);
+        assert_eq!(applied.max_clients, 8);
+    }
+
+    #[test]
+    fn test_watcher_reloads_and_survives_bad_files() {
+        let path = std::env::temp_dir().join(format!("vibettp_reload_{}.toml", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        fs::write(&path, VALID).unwrap();
+
+        let config = shared(load_config(&path).unwrap());
+        spawn_config_watcher(path.clone(), Arc::clone(&config), Duration::from_millis(20));
+
+        let wait_for = |expected: usize| {
+            for _ in 0..100 {
+                if current(&config).max_clients == expected {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            return false;
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        fs::write(&path, VALID.replace("max_clients = 4", "max_clients = 9")).unwrap();
+        assert!(wait_for(9), "change was not picked up");
+
+        // A broken file must leave the last good config in place.
+        fs::write(&path, "max_clients = ").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(current(&config).max_clients, 9);
+
+        let _ = fs::remove_file(&path);
+    }
 }
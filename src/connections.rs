@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// What a connection's worker is doing, as far as the housekeeping is concerned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnState {
+    // Waiting for the next request on a persistent connection: safe to close.
+    Idle,
+    // Receiving or answering a request: left alone.
+    Active,
+    // Already told to close; its worker just hasn't noticed yet.
+    Closing,
+}
+
+struct Connection {
+    peer: String,
+    // Name of the worker thread serving it ("vibettp-worker-<id>"), as debuggers show it.
+    thread: String,
+    state: ConnState,
+    last_activity: Instant,
+    // Responses sent so far and their size in bytes (head included).
+    requests: u64,
+    bytes_sent: u64,
+    // Wakes the worker out of its select() (the server shuts the socket down both ways).
+    close: Box<dyn Fn() + Send>,
+}
+
+/// One open connection as GET /admin/connections shows it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerInfo {
+    pub id: u64,
+    pub thread: String,
+    pub peer: String,
+    pub state: ConnState,
+    pub requests: u64,
+    pub bytes_sent: u64,
+    pub idle_for: Duration,
+}
+
+/*
+Every open client connection, so the accept loop can close idle ones itself instead of waiting
+for each worker's select() to time out: at shutdown, after keep_alive_timeout was lowered by a
+reload, and (with evict_idle_when_full) to make room when the server is full.
+
+Closing happens under the lock, and a worker deregisters (drops its ConnectionEntry) before it
+closes its socket, so a socket handle is never shut down after the OS may have reused it.
+*/
+#[derive(Default)]
+pub struct Connections {
+    open: Mutex<HashMap<u64, Connection>>,
+}
+
+/*
+A connection's place in the registry, held by its worker. Dropping it removes the connection,
+which also happens while a panicking worker unwinds.
+*/
+pub struct ConnectionEntry {
+    connections: Arc<Connections>,
+    id: u64,
+}
+
+impl ConnectionEntry {
+    // Record what the worker is doing now; Idle and Active also count as activity.
+    pub fn set_state(&self, state: ConnState) {
+        let mut open = self.connections.lock();
+        if let Some(connection) = open.get_mut(&self.id)
+            && connection.state != ConnState::Closing {
+            connection.state = state;
+            connection.last_activity = Instant::now();
+        }
+    }
+
+    // Count one response of `bytes` bytes sent on this connection.
+    pub fn record_response(&self, bytes: usize) {
+        if let Some(connection) = self.connections.lock().get_mut(&self.id) {
+            connection.requests += 1;
+            connection.bytes_sent += bytes as u64;
+            connection.last_activity = Instant::now();
+        }
+    }
+}
+
+impl Drop for ConnectionEntry {
+    fn drop(&mut self) {
+        self.connections.lock().remove(&self.id);
+    }
+}
+
+impl Connections {
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, Connection>> {
+        return self.open.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    // Close every connection idle for longer than `max_idle` (Duration::ZERO: all idle ones). Returns (id, peer) of each.
+    pub fn close_idle(&self, max_idle: Duration) -> Vec<(u64, String)> {
+        let mut open = self.lock();
+        let mut closed = Vec::new();
+        for (id, connection) in open.iter_mut() {
+            if connection.state == ConnState::Idle && connection.last_activity.elapsed() >= max_idle {
+                (connection.close)();
+                connection.state = ConnState::Closing;
+                closed.push((*id, connection.peer.clone()));
+            }
+        }
+        return closed;
+    }
+
+    // The open connections and their counters, oldest connection first.
+    pub fn snapshot(&self) -> Vec<WorkerInfo> {
+        let mut workers: Vec<WorkerInfo> = self.lock().iter()
+            .map(|(id, connection)| WorkerInfo {
+                id: *id,
+                thread: connection.thread.clone(),
+                peer: connection.peer.clone(),
+                state: connection.state,
+                requests: connection.requests,
+                bytes_sent: connection.bytes_sent,
+                idle_for: connection.last_activity.elapsed(),
+            })
+            .collect();
+        workers.sort_by_key(|worker| worker.id);
+        return workers;
+    }
+
+    // Close the connection that has been idle the longest, if any is idle at all.
+    pub fn evict_longest_idle(&self) -> Option<(u64, String)> {
+        let mut open = self.lock();
+        let (id, connection) = open.iter_mut()
+            .filter(|(_, connection)| connection.state == ConnState::Idle)
+            .min_by_key(|(_, connection)| connection.last_activity)?;
+        (connection.close)();
+        connection.state = ConnState::Closing;
+        return Some((*id, connection.peer.clone()));
+    }
+}
+
+/*
+Add a connection, Active until its worker says otherwise. `close` must make the worker give up.
+Called from the worker itself, whose thread name is recorded.
+*/
+pub fn register<F>(connections: &Arc<Connections>, id: u64, peer: String, close: F) -> ConnectionEntry
+where
+    F: Fn() + Send + 'static,
+{
+    let thread = std::thread::current().name().unwrap_or("(unnamed)").to_string();
+    let connection = Connection {
+        peer,
+        thread,
+        state: ConnState::Active,
+        last_activity: Instant::now(),
+        requests: 0,
+        bytes_sent: 0,
+        close: Box::new(close),
+    };
+    connections.lock().insert(id, connection);
+    return ConnectionEntry { connections: Arc::clone(connections), id };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    // A registered connection whose close() just raises a flag.
+    fn open(connections: &Arc<Connections>, id: u64) -> (ConnectionEntry, Arc<AtomicBool>) {
+        let closed = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&closed);
+        let entry = register(connections, id, format!("127.0.0.1:{}", 50000 + id), move || flag.store(true, Ordering::SeqCst));
+        return (entry, closed);
+    }
+
+    #[test]
+    fn test_evicts_longest_idle_only() {
+        let connections = Arc::new(Connections::default());
+        let (first, first_closed) = open(&connections, 1);
+        let (second, second_closed) = open(&connections, 2);
+        let (_busy, busy_closed) = open(&connections, 3);
+        first.set_state(ConnState::Idle);
+        thread::sleep(Duration::from_millis(5));
+        second.set_state(ConnState::Idle);
+
+        assert_eq!(connections.evict_longest_idle(), Some((1, "127.0.0.1:50001".to_string())));
+        assert!(first_closed.load(Ordering::SeqCst));
+        // Closing is final: going idle again doesn't make it a candidate twice.
+        first.set_state(ConnState::Idle);
+        assert_eq!(connections.evict_longest_idle().map(|(id, _)| id), Some(2));
+        assert!(second_closed.load(Ordering::SeqCst));
+        assert_eq!(connections.evict_longest_idle(), None);
+        assert!(!busy_closed.load(Ordering::SeqCst));
+
+        // Workers deregister on the way out.
+        drop(first);
+        drop(second);
+        assert_eq!(connections.lock().len(), 1);
+    }
+
+    #[test]
+    fn test_worker_counters_add_up() {
+        use std::sync::Barrier;
+        use crate::stats::ServerStats;
+        use crate::workers::Workers;
+
+        let connections = Arc::new(Connections::default());
+        let stats = Arc::new(ServerStats::default());
+        // Both workers stay registered until the main thread has looked at them.
+        let counted = Arc::new(Barrier::new(3));
+        let checked = Arc::new(Barrier::new(3));
+        let mut workers = Workers::new();
+        for worker in 0..2u64 {
+            let (connections, stats) = (Arc::clone(&connections), Arc::clone(&stats));
+            let (counted, checked) = (Arc::clone(&counted), Arc::clone(&checked));
+            workers.spawn(format!("127.0.0.1:{}", 50000 + worker), move |id| {
+                let entry = register(&connections, id, format!("127.0.0.1:{}", 50000 + worker), || {});
+                for n in 0..50 + worker as usize * 25 {
+                    entry.record_response(100 + n);
+                    stats.record_response(100 + n);
+                }
+                counted.wait();
+                checked.wait();
+            }).unwrap();
+        }
+
+        counted.wait();
+        let snapshot = connections.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert!(snapshot.iter().all(|worker| worker.thread == format!("vibettp-worker-{}", worker.id)));
+        assert_eq!(snapshot.iter().map(|worker| worker.requests).collect::<Vec<_>>(), [50, 75]);
+        let requests: u64 = snapshot.iter().map(|worker| worker.requests).sum();
+        let bytes: u64 = snapshot.iter().map(|worker| worker.bytes_sent).sum();
+        assert_eq!(requests, stats.requests.load(std::sync::atomic::Ordering::Relaxed));
+        assert_eq!(bytes, stats.bytes_sent.load(std::sync::atomic::Ordering::Relaxed));
+        checked.wait();
+        workers.join_all();
+        assert!(connections.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_close_idle_by_age() {
+        let connections = Arc::new(Connections::default());
+        let (old, old_closed) = open(&connections, 1);
+        old.set_state(ConnState::Idle);
+        thread::sleep(Duration::from_millis(30));
+        let (fresh, fresh_closed) = open(&connections, 2);
+        fresh.set_state(ConnState::Idle);
+
+        let closed = connections.close_idle(Duration::from_millis(20));
+        assert_eq!(closed.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [1]);
+        assert!(old_closed.load(Ordering::SeqCst) && !fresh_closed.load(Ordering::SeqCst));
+
+        // Shutdown: every idle connection, however fresh.
+        assert_eq!(connections.close_idle(Duration::ZERO).len(), 1);
+        assert!(fresh_closed.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,1653 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::admin;
+use crate::api_auth;
+use crate::buffer_pool::BufferPool;
+use crate::canned::CannedResponses;
+use crate::conditionals::{evaluate_preconditions, FileMeta, PreconditionResult};
+use crate::config::{Config, CONFIG_PATH};
+use crate::connections::Connections;
+use crate::dump;
+use crate::handlers;
+use crate::mime::content_type_for;
+use crate::markdown;
+use crate::listing;
+use crate::panics::RequestContext;
+use crate::request::{Host, Request, TargetForm};
+use crate::response::{content_disposition_attachment, BodyBytes, HTTPStatus, Response, SECURITY_HEADERS};
+use crate::router::{Fallback, RouteMatch, Router};
+use crate::static_cache::{etag_for, would_cache, CachedFile, StaticCache};
+use crate::stats::ServerStats;
+use crate::response_cache::ResponseCache;
+use crate::upload;
+use crate::util::{normalize_path, apply_rewrites, is_denied_static_path, resolve_static_path, resolve_static_file, expand_short_names, fnv1a_64, under_prefix};
+use crate::log::{info, warn, debug};
+
+/*
+What request handling shares across connections. run_server() builds it once and every worker
+gets it through an Arc.
+*/
+pub struct ServerCtx {
+    pub router: Router,
+    // Small static files kept in memory (see static_cache.rs).
+    pub static_cache: StaticCache,
+    // Markdown files as rendered pages, each good for one version of its file (render_markdown).
+    pub rendered_cache: StaticCache,
+    // Responses of routes registered with cache_for() (see response_cache.rs).
+    pub response_cache: ResponseCache,
+    // The connection loop's own error responses, serialized once per config (see canned.rs).
+    pub canned: CannedResponses,
+    // Receive buffers reused across requests and connections (see buffer_pool.rs).
+    pub buffer_pool: BufferPool,
+    // Set by POST /admin/shutdown: stop accepting connections and let the current ones finish.
+    pub shutdown: AtomicBool,
+    // Maintenance mode: 503 for everything but /health and the admin endpoints (POST /admin/maintenance).
+    // Shared with the /health route, which reports it.
+    pub maintenance: Arc<AtomicBool>,
+    // The config file, canonicalized: never served, even if it lives under root_directory.
+    pub config_file: Option<PathBuf>,
+    // The debug_dump directory, canonicalized: never served either, as dumps hold every client's credentials.
+    pub dump_directory: Option<PathBuf>,
+    // The server's counters and open connections, which GET /admin/connections reports.
+    pub stats: Arc<ServerStats>,
+    pub connections: Arc<Connections>,
+}
+
+impl ServerCtx {
+    pub fn new(router: Router) -> ServerCtx {
+        return ServerCtx {
+            router,
+            static_cache: StaticCache::new(),
+            rendered_cache: StaticCache::new(),
+            response_cache: ResponseCache::new(),
+            canned: CannedResponses::new(),
+            buffer_pool: BufferPool::new(),
+            shutdown: AtomicBool::new(false),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            config_file: Path::new(CONFIG_PATH).canonicalize().ok(),
+            dump_directory: Path::new(".").canonicalize().ok().map(|dir| dir.join(dump::DUMP_DIRECTORY)),
+            stats: Arc::new(ServerStats::default()),
+            connections: Arc::new(Connections::default()),
+        };
+    }
+
+    pub fn shutdown_requested(&self) -> bool {
+        return self.shutdown.load(Ordering::SeqCst);
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        return self.maintenance.load(Ordering::SeqCst);
+    }
+
+    // True if `file` is the config file. Only files with its name are canonicalized to compare.
+    pub fn is_config_file(&self, file: &Path) -> bool {
+        return self.config_file.as_ref().is_some_and(|config_file| {
+            file.file_name() == config_file.file_name() && file.canonicalize().ok().as_ref() == Some(config_file)
+        });
+    }
+
+    /*
+    True if `path` is the dump directory or lies directly in it (the dumps are all at its top level).
+    Only a path named like the directory, or in a directory named like it, is canonicalized to compare.
+    */
+    pub fn is_dump_path(&self, path: &Path) -> bool {
+        let Some(dump_directory) = &self.dump_directory else {
+            return false;
+        };
+        let named = |path: &Path| path.file_name().is_some_and(|name| name.eq_ignore_ascii_case(dump::DUMP_DIRECTORY));
+        let directory = match path.parent() {
+            _ if named(path) => path,
+            Some(parent) if named(parent) => parent,
+            _ => return false,
+        };
+        return directory.canonicalize().ok().as_ref() == Some(dump_directory);
+    }
+}
+
+// What the connection loop should do with a parsed request.
+pub struct Outcome {
+    pub response: Response,
+    // The connection must be closed after this response, whatever the client asked for.
+    pub close: bool,
+}
+
+impl Outcome {
+    fn keep(response: Response) -> Outcome {
+        return Outcome { response, close: false };
+    }
+
+    fn close(response: Response) -> Outcome {
+        return Outcome { response, close: true };
+    }
+}
+
+/*
+Turn a parsed request into a response. Everything between "the headers have arrived" and
+"bytes go back on the socket" lives here, away from the WinSock code, so it can be unit tested.
+
+`req.path` is replaced by its normalized (and possibly rewritten) form along the way.
+*/
+pub fn handle_request(req: &mut Request, config: &Config, ctx: &ServerCtx) -> Outcome {
+    // The request as the client sent it, for the panic hook's log line should a handler panic.
+    let query = req.query.as_ref().map_or(String::new(), |query| format!("?{}", query));
+    let _context = RequestContext::enter(format!("{} {}{} {}", req.method, req.path, query, req.version));
+
+    // Only HTTP/1.x is spoken here; an HTTP/2.0 or 0.9 request line can't be answered in kind.
+    if req.version.major != 1 {
+        return Outcome::close(handlers::http_version_not_supported());
+    }
+
+    /*
+    With method_override, a POST may say what it really is (clients behind proxies that only pass
+    GET and POST tunnel PUT and DELETE this way). Everything from here on, the 405/Allow check and
+    the allow_put/allow_delete gates included, sees the method it names.
+    */
+    if config.method_override && req.method == "POST" && let Some(method) = req.header(METHOD_OVERRIDE_HEADER) {
+        let method = method.trim().to_string();
+        if !OVERRIDABLE_METHODS.contains(&method.as_str()) {
+            info!("🚫 Refusing {} override to {:?}.", METHOD_OVERRIDE_HEADER, method);
+            return Outcome::close(handlers::bad_request());
+        }
+        debug!("🔁 POST {} handled as {}.", req.path, method);
+        req.override_method(&method);
+    }
+
+    /*
+    HEAD is GET without the body. It takes the GET path all the way (routes, conditionals, ranges,
+    static files) and only finalize() drops the body, so the two can't disagree on a header.
+    */
+    if req.method == "HEAD" {
+        req.head_only = true;
+        req.override_method("GET");
+    }
+
+    // With enforce_host, a request for a site not listed was meant for some other server.
+    if let Some(host) = &req.host && !serves_host(&config.enforce_host, host) {
+        info!("🧭 Misdirected request for host {:?}.", host.name);
+        return Outcome::keep(handlers::misdirected_request());
+    }
+
+    // A redirecting listener answers everything the same way, without routing or touching the disk.
+    if config.redirect_to_https {
+        return match https_location(req, config) {
+            Some(location) => Outcome::keep(handlers::moved_permanently(&location)),
+            None => Outcome::close(handlers::bad_request()),
+        };
+    }
+
+    // Unwanted clients (scrapers, say) are refused before anything else looks at the request.
+    if is_blocked_agent(req.header("User-Agent"), &config.blocked_user_agents) {
+        info!("🚫 Blocked User-Agent: {:?}", req.header("User-Agent").unwrap_or(""));
+        return Outcome::keep(handlers::forbidden());
+    }
+
+    // CONNECT tunnels (authority-form targets) are recognised but not supported.
+    if req.target_form == TargetForm::Authority {
+        return Outcome::close(handlers::not_implemented());
+    }
+
+    let allowed = allowed_methods(config);
+
+    // "OPTIONS *" asks about the server as a whole; it never reaches routing.
+    if req.target_form == TargetForm::Asterisk {
+        return Outcome::keep(handlers::options_asterisk(&allowed));
+    }
+
+    /*
+    Canonicalize the path once, so the route table and sanitize_path() both see
+    "/about" for "//about", "/./about" or "/about/.". A ".." that survives
+    percent-decoding is an escape attempt: 400.
+    Internal aliases from [[rewrites]] then apply to the canonical path.
+    */
+    let normalized = normalize_path(&req.path);
+    let rewritten = normalized.as_deref().and_then(|path| apply_rewrites(path, &config.rewrites));
+    match rewritten {
+        Some(path) => {
+            if Some(&path) != normalized.as_ref() {
+                debug!("↪️ Rewrote {} → {}", req.path, path);
+                req.rewritten_from = normalized;
+            }
+            req.path = path;
+        }
+        None => return Outcome::close(handlers::bad_request()),
+    }
+
+    // The admin endpoint only exists when a token is configured; otherwise it's just a missing path.
+    if req.path == admin::SHUTDOWN_PATH && let Some(token) = &config.admin_token {
+        return Outcome::close(admin::shutdown(req, token, ctx));
+    }
+    if req.path == admin::CACHE_CLEAR_PATH && let Some(token) = &config.admin_token {
+        return Outcome::keep(admin::clear_cache(req, token, ctx));
+    }
+    if req.path == admin::CONNECTIONS_PATH && let Some(token) = &config.admin_token {
+        return Outcome::keep(admin::connections(req, token, ctx));
+    }
+    if req.path == admin::TIMINGS_PATH && let Some(token) = &config.admin_token {
+        return Outcome::keep(admin::timings(req, token, ctx));
+    }
+    if req.path == admin::MAINTENANCE_PATH && let Some(token) = &config.admin_token {
+        return Outcome::keep(admin::maintenance(req, token, ctx));
+    }
+
+    /*
+    Maintenance turns away everything else, before routes or the disk are involved. /health is
+    still routed, so a load balancer can tell "up but in maintenance" from "down".
+    */
+    if ctx.in_maintenance() && req.path != handlers::HEALTH_PATH {
+        return Outcome::keep(handlers::unavailable(&config.maintenance_message, config.maintenance_retry_after_seconds));
+    }
+
+    // [api_auth] prefixes want an API key before anything under them runs, uploads included.
+    if let Some(auth) = api_auth::protecting(&req.path, config) && let Some(refusal) = api_auth::refusal(req, auth) {
+        return Outcome::keep(refusal);
+    }
+
+    // Block disallowed methods
+    if !allowed.contains(&req.method.as_str()) {
+        return Outcome::close(handlers::method_not_allowed(&allowed));
+    }
+
+    // Uploads only exist below upload_prefix; everywhere else PUT is just another wrong method.
+    if req.method == "PUT" {
+        if upload::upload_subpath(&req.path, config).is_none() {
+            // Everything the server accepts, except PUT itself: what any other 405 lists.
+            let elsewhere: Vec<&str> = allowed.iter().copied().filter(|method| *method != "PUT").collect();
+            return Outcome::close(handlers::method_not_allowed(&elsewhere));
+        }
+        return Outcome::keep(upload::put(req, config));
+    }
+
+    // DELETE is confined to the upload area; anything else is refused before the disk is touched.
+    if req.method == "DELETE" {
+        if upload::upload_subpath(&req.path, config).is_none() {
+            info!("🚫 Refusing DELETE outside the upload area: {}", req.path);
+            return Outcome::keep(handlers::forbidden());
+        }
+        return Outcome::keep(upload::delete(req, config));
+    }
+
+    // Built-in debugging endpoints don't exist at all unless debug_endpoints is on.
+    if config.debug_endpoints && req.path == "/debug/echo" {
+        return Outcome::keep(handlers::echo(req));
+    }
+    if config.debug_endpoints && req.path == "/debug/panic" {
+        return call_handler(|| handlers::panic_on_purpose(req));
+    }
+
+    // Try route match first: the handler registered for this method and path.
+    match ctx.router.lookup(&req.method, &req.path) {
+        RouteMatch::Found(handler) => {
+            // Routes registered with cache_for() answer GETs from memory while the entry is fresh.
+            if req.method == "GET" && let Some(ttl) = ctx.router.cache_ttl(&req.method, &req.path) {
+                let (max_bytes, max_entries) = (config.response_cache_max_bytes, config.response_cache_max_entries);
+                return call_handler(|| {
+                    let response = ctx.response_cache.get_or_insert(req, ttl, max_bytes, max_entries, || handler(req));
+                    dynamic_etag(response, req, config)
+                });
+            }
+            return call_handler(|| dynamic_etag(handler(req), req, config));
+        }
+        RouteMatch::WrongMethod(allowed) => return Outcome::close(handlers::method_not_allowed(&allowed)),
+        RouteMatch::NotRouted => {}
+    }
+
+    // No route: whatever the router falls back to.
+    match ctx.router.fallback() {
+        Fallback::StaticFiles => {}
+        Fallback::NotFound => return Outcome::keep(handlers::not_found()),
+        Fallback::Custom(handler) => return call_handler(|| dynamic_etag(handler(req), req, config)),
+    }
+
+    // Dotfiles and denied extensions look exactly like missing files.
+    if is_denied_static_path(&req.path, config) {
+        info!("🙈 Refusing to serve hidden/denied path {}", req.path);
+        return Outcome::keep(handlers::not_found());
+    }
+
+    // Fallback to static file serving
+    let Some(safe_path) = resolve_static_path(&req.path, config) else {
+        // Malicious path or error
+        return Outcome::close(handlers::bad_request());
+    };
+
+    // An 8.3 alias (SECRET~1.TXT) must not get around the deny rules: they apply to the long name.
+    if let Some(long_path) = expand_short_names(&req.path, &safe_path) {
+        if !config.resolve_short_names || is_denied_static_path(&long_path, config) {
+            info!("🙈 Refusing short-name alias {} (for {})", req.path, long_path);
+            return Outcome::keep(handlers::not_found());
+        }
+        // Nor the API keys: an alias of a protected directory (/API~1/...) needs the key too.
+        if let Some(auth) = api_auth::protecting(&long_path, config) && let Some(refusal) = api_auth::refusal(req, auth) {
+            return Outcome::keep(refusal);
+        }
+    }
+
+    // The wire dumps hold raw request heads, Authorization included: like the config file, never served.
+    if ctx.is_dump_path(&safe_path) {
+        info!("🙈 Refusing to serve the wire dumps as {}", req.path);
+        return Outcome::keep(handlers::not_found());
+    }
+
+    // Directories resolve to their first existing index file, if any.
+    let file = resolve_static_file(&safe_path, &config.index_files);
+    // Without one, the directory's listing, when they're enabled.
+    if file.is_none() && config.directory_listing && safe_path.is_dir() {
+        let json = req.prefers(&["text/html", "application/json"]) == Some("application/json");
+        let hidden = |url_path: &str, entry: &Path| {
+            is_denied_static_path(url_path, config) || ctx.is_config_file(entry) || ctx.is_dump_path(entry)
+        };
+        if let Some(response) = listing::listing(&safe_path, &req.path, json, hidden) {
+            return Outcome::keep(response);
+        }
+    }
+    if let Some(file) = &file && ctx.is_config_file(file) {
+        info!("🙈 Refusing to serve the config file as {}", req.path);
+        return Outcome::keep(handlers::not_found());
+    }
+    // Too big to serve at all, whole or in part; checked before a single byte is read.
+    if let Some(file) = &file && config.max_file_bytes > 0
+        && let Ok(metadata) = fs::metadata(file) && metadata.is_file() && metadata.len() > config.max_file_bytes {
+        warn!("📦 Refusing {}: {} bytes is over max_file_bytes ({}).", req.path, metadata.len(), config.max_file_bytes);
+        return Outcome::keep(handlers::forbidden());
+    }
+    let file = file
+        .and_then(|file| read_static_file(&file, config, &ctx.static_cache).map(|cached| (file, cached)));
+    let Some((file, cached)) = file else {
+        // Single-page apps route on the client: any other GET gets the app's index page.
+        if config.spa_fallback && req.method == "GET"
+            && let Some(index) = spa_index(config, &ctx.static_cache) {
+            return Outcome::keep(handlers::file(index.body, &index.content_type, None).header("ETag", &index.etag));
+        }
+        return Outcome::keep(handlers::not_found());
+    };
+
+    // Downloads: ?download=1 or a configured extension → save, don't render.
+    let extension = file.extension().and_then(|e| e.to_str());
+    let is_download_type = extension.is_some_and(|extension| {
+        config.download_extensions.iter().any(|d| d.eq_ignore_ascii_case(extension))
+    });
+    let is_download = is_download_type || req.has_query_flag("download", "1");
+    let name = file.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+
+    // Markdown notes are shown rendered, unless ?raw=1 (or a download) asks for the source.
+    let rendered = config.render_markdown && extension.is_some_and(|e| e.eq_ignore_ascii_case("md"))
+        && !is_download && !req.has_query_flag("raw", "1");
+    let etag = if rendered { rendered_etag(&cached.etag) } else { cached.etag.clone() };
+
+    // The conditional headers, all weighed against this representation in one place.
+    let meta = FileMeta { etag: Some(&etag), modified: Some(cached.modified) };
+    let use_range = match evaluate_preconditions(req.conditionals(), &meta) {
+        PreconditionResult::PreconditionFailed => return Outcome::keep(handlers::precondition_failed()),
+        PreconditionResult::NotModified => return Outcome::keep(not_modified(Response::new(HTTPStatus::Ok).header("ETag", &etag))),
+        PreconditionResult::Proceed { use_range } => use_range,
+    };
+
+    if rendered {
+        let html = rendered_page(&file, &cached, &etag, &name, config, &ctx.rendered_cache);
+        return Outcome::keep(Response::new(HTTPStatus::Ok)
+            .header("ETag", &etag)
+            .body("text/html; charset=utf-8", html));
+    }
+
+    let ranges = req.conditionals().range.as_deref().filter(|_| use_range);
+    let mut response = handlers::file(cached.body, &cached.content_type, ranges)
+        .header("ETag", &etag)
+        .header("Accept-Ranges", "bytes");
+    if is_download {
+        response = response.header("Content-Disposition", &content_disposition_attachment(&name));
+    }
+    return Outcome::keep(response);
+}
+
+/*
+Where redirect_to_https sends a request: "https://" + the Host's name (canonical_host without one)
++ ":https_port" unless that's 443 + the path and query exactly as sent. The client's port is
+dropped, since it is the plaintext one. None (400) without a host, or for a target that isn't a
+path ("*", CONNECT's authority).
+*/
+pub(crate) fn https_location(req: &Request, config: &Config) -> Option<String> {
+    if !matches!(req.target_form, TargetForm::Origin | TargetForm::Absolute) {
+        return None;
+    }
+    let name = match &req.host {
+        Some(host) if !host.name.is_empty() => host.name.clone(),
+        _ => config.canonical_host.as_ref()?.to_ascii_lowercase(),
+    };
+    let mut location = String::from("https://");
+    // An IPv6 literal gets its brackets back.
+    if name.contains(':') && !name.starts_with('[') {
+        location.push_str(&format!("[{}]", name));
+    }
+    else {
+        location.push_str(&name);
+    }
+    if config.https_port != 443 {
+        location.push_str(&format!(":{}", config.https_port));
+    }
+    location.push_str(&req.path);
+    if let Some(query) = &req.query {
+        location.push('?');
+        location.push_str(query);
+    }
+    return Some(location);
+}
+
+// True if enforce_host is empty or lists `host`'s name (any case; brackets around IPv6 optional).
+fn serves_host(enforced: &[String], host: &Host) -> bool {
+    return enforced.is_empty() || enforced.iter().any(|name| {
+        let name = name.trim();
+        let name = name.strip_prefix('[').and_then(|n| n.strip_suffix(']')).unwrap_or(name);
+        name.eq_ignore_ascii_case(&host.name)
+    });
+}
+
+// True if `user_agent` contains one of the `blocked` substrings, ignoring case.
+fn is_blocked_agent(user_agent: Option<&str>, blocked: &[String]) -> bool {
+    let Some(user_agent) = user_agent else {
+        return false;
+    };
+    let user_agent = user_agent.to_ascii_lowercase();
+    return blocked.iter().any(|pattern| user_agent.contains(&pattern.to_ascii_lowercase()));
+}
+
+/*
+A streamed response can only be delimited by closing the connection for an HTTP/1.0 client, which
+doesn't understand chunked framing. HTTP/1.1 clients get it chunked and keep the connection.
+A HEAD request gets no body at all, so nothing needs delimiting.
+*/
+pub fn ends_by_close(response: &Response, req: &Request) -> bool {
+    return response.stream.is_some() && req.is_http_1_0() && !req.head_only;
+}
+
+/*
+Last touches before a response to `req` is serialized: tell the client whether the connection
+stays open, and (only if mirror_request_version is set) answer an HTTP/1.0 client with an
+HTTP/1.0 status line. HTTP/1.1 in the status line is always allowed, so that's the default.
+A streamed body is marked chunked here for HTTP/1.1 clients, and successful responses get the
+security_headers preset, minus whatever the handler or [response_headers] set. The answer to a
+HEAD request keeps every header of the GET one and loses the body.
+*/
+pub fn finalize(mut response: Response, req: &Request, keep_alive: bool, config: &Config) -> Response {
+    if config.mirror_request_version && req.is_http_1_0() {
+        response.version = "HTTP/1.0";
+    }
+    response.head_only = req.head_only;
+    // Only static files honor Range (and say so); anything else can't be resumed part way.
+    if response.status == HTTPStatus::Ok && response.header_value("Accept-Ranges").is_none() {
+        response = response.header("Accept-Ranges", "none");
+    }
+    response = add_response_headers(response, config);
+    if config.security_headers && matches!(response.status.class(), 2 | 3) {
+        for (name, value) in SECURITY_HEADERS {
+            if response.header_value(name).is_none() {
+                response = response.header(name, value);
+            }
+        }
+    }
+    if response.stream.is_some() && !req.is_http_1_0() {
+        response = response.header("Transfer-Encoding", "chunked");
+    }
+    if !keep_alive {
+        return response.header("Connection", "close");
+    }
+    // Tell the client how long the idle connection will be kept, so it can avoid reusing a dead one.
+    // The header counts whole seconds; rounding down keeps the client on the safe side.
+    return response
+        .header("Connection", "keep-alive")
+        .header("Keep-Alive", &format!("timeout={}", config.idle_timeout_ms() / 1000));
+}
+
+/*
+Run a handler. A panic in it becomes a 500 and the connection is closed, instead of the worker
+thread dying mid-connection with nothing sent; the panic hook (panics.rs) has already logged it
+with the request.
+*/
+fn call_handler<F: FnOnce() -> Response>(handler: F) -> Outcome {
+    return match panic::catch_unwind(AssertUnwindSafe(handler)) {
+        Ok(response) => Outcome::keep(response),
+        Err(_) => Outcome::close(handlers::internal_server_error()),
+    };
+}
+
+// Headers a 304 keeps from the 200 it stands for (RFC 7232 §4.1); the rest describe the body.
+const NOT_MODIFIED_HEADERS: [&str; 5] = ["ETag", "Cache-Control", "Vary", "Expires", "Content-Location"];
+
+/*
+With dynamic_etags: a strong ETag from a hash of a handler's 200 body (unless it set one itself),
+and for a GET that already has that version, a 304 without the body. Handlers are still run, so
+this saves bandwidth, not work. Streamed bodies (the hash would need the whole body up front)
+and Cache-Control: no-store responses are left alone.
+*/
+fn dynamic_etag(response: Response, req: &Request, config: &Config) -> Response {
+    let no_store = response.header_value("Cache-Control")
+        .is_some_and(|value| value.split(',').any(|directive| directive.trim().eq_ignore_ascii_case("no-store")));
+    if !config.dynamic_etags || response.status != HTTPStatus::Ok || response.stream.is_some() || response.is_chunked() || no_store {
+        return response;
+    }
+    let response = match response.header_value("ETag") {
+        Some(_) => response,
+        None => {
+            let etag = format!("\"{:016x}\"", fnv1a_64(&response.body));
+            response.header("ETag", &etag)
+        }
+    };
+
+    // Only a 304 is worth acting on: the handler has already run, so a 412 would come too late.
+    let meta = FileMeta { etag: response.header_value("ETag"), modified: None };
+    if evaluate_preconditions(req.conditionals(), &meta) != PreconditionResult::NotModified {
+        return response;
+    }
+    return not_modified(response);
+}
+
+// The 304 standing in for `response`: its status and body dropped, only NOT_MODIFIED_HEADERS kept.
+pub(crate) fn not_modified(response: Response) -> Response {
+    let mut not_modified = Response::new(HTTPStatus::NotModified);
+    not_modified.headers = response.headers.iter()
+        .filter(|(name, _)| NOT_MODIFIED_HEADERS.iter().any(|kept| kept.eq_ignore_ascii_case(name)))
+        .cloned()
+        .collect();
+    not_modified.log_tag = response.log_tag;
+    return not_modified;
+}
+
+// The root directory's index file, which a single-page app serves for all of its client-side routes.
+fn spa_index(config: &Config, cache: &StaticCache) -> Option<CachedFile> {
+    let index = resolve_static_file(Path::new(&config.root_directory), &config.index_files)?;
+    return read_static_file(&index, config, cache);
+}
+
+/*
+The [response_headers] from the config, appended after the handler's own headers. A header the
+handler already set is left alone, so a handler can always override the configured value.
+finalize() applies this to every response; the connection loop to the ones it sends itself.
+*/
+pub fn add_response_headers(mut response: Response, config: &Config) -> Response {
+    for (name, value) in &config.response_headers {
+        if response.header_value(name).is_none() {
+            response = response.header(name, value);
+        }
+    }
+    return response;
+}
+
+/*
+Contents, Content-Type and ETag of a static file: from the cache while the file's mtime is
+unchanged, otherwise read from disk (and cached, if small enough). None if it can't be read.
+*/
+fn read_static_file(file: &Path, config: &Config, cache: &StaticCache) -> Option<CachedFile> {
+    let metadata = fs::metadata(file).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    if let Some(hit) = cache.get(file, modified) {
+        return Some(hit);
+    }
+
+    let body = fs::read(file).ok()?;
+    let cacheable = would_cache(body.len(), config.cache_max_bytes, config.cache_max_entries);
+    let cached = CachedFile {
+        content_type: content_type_for(file, &config.mime_types).to_string(),
+        etag: etag_for(body.len() as u64, modified),
+        // Only a body the cache keeps is moved into an Arc; any other goes to the response as read.
+        body: if cacheable { BodyBytes::shared(body.into()) } else { body.into() },
+        modified,
+    };
+    if cacheable {
+        cache.insert(file.to_path_buf(), cached.clone(), config.cache_max_bytes, config.cache_max_entries);
+    }
+    return Some(cached);
+}
+
+// The page a Markdown file renders to, rendered once per version of the file: its (rendered) ETag.
+fn rendered_page(file: &Path, source: &CachedFile, etag: &str, name: &str, config: &Config, cache: &StaticCache) -> BodyBytes {
+    if let Some(hit) = cache.get(file, source.modified) && hit.etag == etag {
+        return hit.body;
+    }
+    let html = markdown::render_page(&String::from_utf8_lossy(&source.body), name).into_bytes();
+    if !would_cache(html.len(), config.cache_max_bytes, config.cache_max_entries) {
+        return html.into();
+    }
+    let page = CachedFile {
+        body: BodyBytes::shared(html.into()),
+        content_type: "text/html; charset=utf-8".to_string(),
+        etag: etag.to_string(),
+        modified: source.modified,
+    };
+    let html = page.body.clone();
+    cache.insert(file.to_path_buf(), page, config.cache_max_bytes, config.cache_max_entries);
+    return html;
+}
+
+// The rendered page is another representation of the file, so it gets a tag of its own: "<file's>-html".
+fn rendered_etag(etag: &str) -> String {
+    return format!("{}-html\"", etag.trim_end_matches('"'));
+}
+
+const METHOD_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
+
+// What X-HTTP-Method-Override may name: never CONNECT or TRACE, which a POST mustn't turn into.
+const OVERRIDABLE_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "PATCH", "OPTIONS"];
+
+// Methods the server accepts at all with this config: GET, POST and HEAD, plus PUT/DELETE if enabled.
+pub fn allowed_methods(config: &Config) -> Vec<&'static str> {
+    let mut methods = handlers::ALLOWED_METHODS.to_vec();
+    methods.push("HEAD");
+    if config.allow_put {
+        methods.push("PUT");
+    }
+    if config.allow_delete {
+        methods.push("DELETE");
+    }
+    return methods;
+}
+
+/*
+Largest body accepted for `req`, decided from the head alone so an oversized upload can be
+refused before it is read. The canonical (normalized, rewritten) path is looked up in the
+router first: a route registered with max_body() has the last word. Otherwise it is matched
+against the [limits] prefixes per segment, longest first; "/" matches everything. Paths that
+don't normalize get max_body_size: handle_request() rejects them anyway.
+*/
+pub fn body_limit(req: &Request, config: &Config, router: &Router) -> usize {
+    let path = normalize_path(&req.path).and_then(|path| apply_rewrites(&path, &config.rewrites));
+    let Some(path) = path else {
+        return config.max_body_size;
+    };
+    if let Some(limit) = router.body_limit(&req.method, &path) {
+        return limit;
+    }
+
+    let mut best: Option<(&str, usize)> = None;
+    for (prefix, limit) in &config.limits {
+        let prefix = prefix.trim_end_matches('/');
+        if under_prefix(&path, prefix) && best.is_none_or(|(longest, _)| prefix.len() > longest.len()) {
+            best = Some((prefix, *limit));
+        }
+    }
+    return best.map_or(config.max_body_size, |(_, limit)| limit);
+}
+
+/*
+What to send when the client goes quiet. A keep-alive connection idling between requests
+(nothing pending) is just closed; only a half-sent request earns a 408.
+*/
+pub fn on_timeout(pending: &[u8]) -> Option<Response> {
+    if pending.is_empty() {
+        return None;
+    }
+    return Some(handlers::request_timeout());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::config::test_config;
+    use crate::request::{parse_request, Body};
+    use crate::response::HTTPStatus;
+
+    fn routes() -> Router {
+        let mut router = Router::new();
+        crate::router::register_default_routes(&mut router, &test_config(".", ""), &Arc::default());
+        return router;
+    }
+
+    // What run_server() builds: the default routes for `config`, /health sharing the maintenance flag.
+    fn server_ctx(config: &Config) -> ServerCtx {
+        let maintenance = Arc::default();
+        let mut router = Router::new();
+        crate::router::register_default_routes(&mut router, config, &maintenance);
+        let mut ctx = ServerCtx::new(router);
+        ctx.maintenance = maintenance;
+        return ctx;
+    }
+
+    fn run(raw: &str, config: &Config) -> (Request, Outcome) {
+        let mut req = parse_request(raw.as_bytes()).unwrap();
+        let outcome = handle_request(&mut req, config, &server_ctx(config));
+        return (req, outcome);
+    }
+
+    #[test]
+    fn test_https_redirect() {
+        let config = test_config(".", "redirect_to_https = true\n");
+        let location = |raw: &str, config: &Config| {
+            let (_, outcome) = run(raw, config);
+            (outcome.response.status.code(), outcome.response.header_value("Location").map(str::to_string))
+        };
+        let moved = |url: &str| (301, Some(url.to_string()));
+
+        // Path and query kept, the plaintext port dropped; routes and files never looked at.
+        assert_eq!(location("GET /a/../b?x=1&y HTTP/1.1\r\nHost: Example.com:8080\r\n\r\n", &config), moved("https://example.com/a/../b?x=1&y"));
+        assert_eq!(location("POST /nowhere HTTP/1.1\r\nHost: [::1]:80\r\n\r\n", &config), moved("https://[::1]/nowhere"));
+        assert_eq!(location("GET http://example.com/p HTTP/1.1\r\n\r\n", &config), moved("https://example.com/p"));
+
+        // A nonstandard HTTPS port is spelled out; canonical_host stands in for a missing Host.
+        let custom = test_config(".", "redirect_to_https = true\nhttps_port = 8443\ncanonical_host = \"www.example.com\"\n");
+        assert_eq!(location("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n", &custom), moved("https://example.com:8443/"));
+        assert_eq!(location("GET /x HTTP/1.0\r\n\r\n", &custom), moved("https://www.example.com:8443/x"));
+        assert_eq!(location("GET /x HTTP/1.1\r\nHost: \r\n\r\n", &custom), moved("https://www.example.com:8443/x"));
+
+        // No usable host at all, or no path to redirect: 400.
+        assert_eq!(location("GET /x HTTP/1.0\r\n\r\n", &config), (400, None));
+        assert_eq!(location("OPTIONS * HTTP/1.1\r\nHost: example.com\r\n\r\n", &config), (400, None));
+    }
+
+    #[test]
+    fn test_enforce_host() {
+        let open = test_config(".", "");
+        let (_, outcome) = run("GET /about HTTP/1.1\r\nHost: evil.example\r\n\r\n", &open);
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+
+        // Names match whatever the case and port; IPv6 literals with or without brackets in the list.
+        let config = test_config(".", "enforce_host = [\"Example.com\", \"[::1]\"]\n");
+        for host in ["example.com", "EXAMPLE.COM:9999", "[::1]:7878"] {
+            let (_, outcome) = run(&format!("GET /about HTTP/1.1\r\nHost: {}\r\n\r\n", host), &config);
+            assert_eq!(outcome.response.status, HTTPStatus::Ok, "{}", host);
+        }
+        let (_, outcome) = run("GET /about HTTP/1.1\r\nHost: evil.example\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::MisdirectedRequest);
+        // Nothing to misdirect without a Host (an HTTP/1.0 client, say).
+        let (_, outcome) = run("GET /about HTTP/1.0\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+    }
+
+    #[test]
+    fn test_routes_and_errors() {
+        let config = test_config(".", "");
+        let (req, outcome) = run("GET //about HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(req.path, "/about");
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+        assert!(!outcome.close);
+
+        let (_, outcome) = run("PUT / HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+        assert!(outcome.close);
+        // A GET-only route: HEAD is served too, so it's allowed.
+        let (_, outcome) = run("POST /health HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+        assert_eq!(outcome.response.header_value("Allow"), Some("GET, HEAD"));
+
+        let (_, outcome) = run("GET /about HTTP/2.0\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::HttpVersionNotSupported);
+        assert!(outcome.close);
+
+        let (_, outcome) = run("CONNECT example.com:443 HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::NotImplemented);
+    }
+
+    #[test]
+    fn test_put_needs_flag_and_upload_area() {
+        let dir = crate::util::temp_root("dispatch_put");
+        let (_, outcome) = run("PUT /uploads/a.txt HTTP/1.1\r\n\r\n", &test_config(".", ""));
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+        assert_eq!(outcome.response.header_value("Allow"), Some("GET, POST, HEAD"));
+
+        let config = test_config(".", &format!("allow_put = true\nupload_directory = {:?}\n", dir.to_str().unwrap()));
+        let (_, outcome) = run("PUT /about HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+        assert_eq!(outcome.response.header_value("Allow"), Some("GET, POST, HEAD"));
+        let (_, outcome) = run("PUT /uploads/a.txt HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Created);
+
+        // And the upload is readable at the same URL.
+        let (_, outcome) = run("GET /uploads/a.txt HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_delete_confined_to_upload_area() {
+        let dir = crate::util::temp_root("dispatch_delete");
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+
+        let (_, outcome) = run("DELETE /uploads/a.txt HTTP/1.1\r\n\r\n", &test_config(".", ""));
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+
+        let config = test_config(".", &format!("allow_delete = true\nupload_directory = {:?}\n", dir.to_str().unwrap()));
+        let (_, outcome) = run("DELETE /Cargo.toml HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Forbidden);
+        assert!(std::path::Path::new("Cargo.toml").exists());
+        let (_, outcome) = run("DELETE /uploads/../Cargo.toml HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::BadRequest);
+
+        let (_, outcome) = run("DELETE /uploads/a.txt HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::NoContent);
+        assert!(!dir.join("a.txt").exists());
+        let (_, outcome) = run("DELETE /uploads/a.txt HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::NotFound);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_method_override() {
+        let dir = crate::util::temp_root("dispatch_override");
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        let upload = format!("allow_delete = true\nupload_directory = {:?}\n", dir.to_str().unwrap());
+        let delete = "POST /uploads/a.txt HTTP/1.1\r\nX-HTTP-Method-Override: DELETE\r\n\r\n";
+
+        // Off (the default): just a POST, which doesn't delete anything.
+        let (req, outcome) = run(delete, &test_config(".", &upload));
+        assert_eq!(req.method, "POST");
+        assert_ne!(outcome.response.status, HTTPStatus::NoContent);
+        assert!(dir.join("a.txt").exists());
+
+        // On: the delete handler runs, and the log still sees the POST that was sent.
+        let config = test_config(".", &format!("method_override = true\n{}", upload));
+        let (req, outcome) = run(delete, &config);
+        assert_eq!(outcome.response.status, HTTPStatus::NoContent);
+        assert!(!dir.join("a.txt").exists());
+        assert_eq!((req.method.as_str(), req.original_method()), ("DELETE", "POST"));
+
+        // The gates see the overridden method: PUT isn't allowed here. Only POST can be overridden.
+        let (_, outcome) = run("POST /uploads/b.txt HTTP/1.1\r\nX-HTTP-Method-Override: PUT\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::MethodNotAllowed);
+        let (req, _) = run("GET /about HTTP/1.1\r\nX-HTTP-Method-Override: DELETE\r\n\r\n", &config);
+        assert_eq!(req.method, "GET");
+        for refused in ["CONNECT", "TRACE", "delete", "BREW"] {
+            let raw = format!("POST / HTTP/1.1\r\nX-HTTP-Method-Override: {}\r\n\r\n", refused);
+            assert_eq!(run(&raw, &config).1.response.status, HTTPStatus::BadRequest, "{}", refused);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_http_1_0_connection_header() {
+        let config = test_config(".", "");
+        let (req, outcome) = run("GET / HTTP/1.0\r\n\r\n", &config);
+        let keep_alive = !outcome.close && config.keep_alive && req.keep_alive;
+        let bytes = finalize(outcome.response, &req, keep_alive, &config).to_bytes();
+        let text = String::from_utf8(bytes).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+        assert!(text.contains("\r\nConnection: close\r\n"), "{}", text);
+
+        let config = test_config(".", "keep_alive_timeout_seconds = 60\n");
+        let (req, outcome) = run("GET / HTTP/1.1\r\n\r\n", &config);
+        let text = String::from_utf8(finalize(outcome.response, &req, true, &config).to_bytes()).unwrap();
+        assert!(text.contains("\r\nConnection: keep-alive\r\nKeep-Alive: timeout=60\r\n"), "{}", text);
+    }
+
+    #[test]
+    fn test_head_mirrors_get() {
+        let dir = crate::util::temp_root("dispatch_head");
+        fs::write(dir.join("page.txt"), "0123456789").unwrap();
+        let config = test_config(dir.to_str().unwrap(), "");
+        let answer = |method: &str, extra: &str| {
+            let (req, outcome) = run(&format!("{} /page.txt HTTP/1.1\r\n{}\r\n", method, extra), &config);
+            return finalize(outcome.response, &req, true, &config);
+        };
+
+        // Same status and headers, Content-Length and Content-Range included; HEAD sends no body.
+        for extra in ["", "Range: bytes=2-5\r\n", "Range: bytes=50-\r\n"] {
+            let (get, head) = (answer("GET", extra), answer("HEAD", extra));
+            assert_eq!(get.head_bytes(), head.head_bytes(), "{:?}", extra);
+            assert_eq!(head.to_bytes(), head.head_bytes());
+        }
+        let ranged = answer("HEAD", "Range: bytes=2-5\r\n");
+        assert_eq!(ranged.status, HTTPStatus::PartialContent);
+        assert_eq!(ranged.header_value("Content-Range"), Some("bytes 2-5/10"));
+        assert_eq!(ranged.header_value("Accept-Ranges"), Some("bytes"));
+        let text = String::from_utf8(ranged.head_bytes()).unwrap();
+        assert!(text.contains("\r\nContent-Length: 4\r\n"), "{}", text);
+
+        // Routes don't honor Range. The access log still shows the method the client sent.
+        let (req, outcome) = run("HEAD /about HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(req.original_method(), "HEAD");
+        let mut about = finalize(outcome.response, &req, true, &config);
+        assert_eq!(about.header_value("Accept-Ranges"), Some("none"));
+        let mut out = Vec::new();
+        about.write_to(&mut out).unwrap();
+        assert!(out.ends_with(b"\r\n\r\n"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_blocked_user_agents() {
+        let blocked = vec!["scrapybot".to_string(), "curl/".to_string()];
+        assert!(is_blocked_agent(Some("Mozilla/5.0 (compatible; ScrapyBot/2.1)"), &blocked));
+        assert!(is_blocked_agent(Some("CURL/8.4.0"), &blocked));
+        assert!(!is_blocked_agent(Some("Mozilla/5.0 Firefox/128.0"), &blocked));
+        assert!(!is_blocked_agent(None, &blocked));
+        assert!(!is_blocked_agent(Some("curl/8.4.0"), &[]));
+
+        let config = test_config(".", "blocked_user_agents = [\"ScrapyBot\"]\n");
+        let (_, outcome) = run("GET / HTTP/1.1\r\nUser-Agent: x ScrapyBot/2.1 y\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Forbidden);
+        let (_, outcome) = run("GET / HTTP/1.1\r\nUser-Agent: Firefox\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+    }
+
+    #[test]
+    fn test_streamed_response_framing() {
+        let config = test_config(".", "");
+        let streamed = || Response::new(HTTPStatus::Ok).stream("text/plain", |w| w.write_all(b"abc"));
+
+        // HTTP/1.1: chunked, and the connection stays open.
+        let req = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!ends_by_close(&streamed(), &req));
+        let mut response = finalize(streamed(), &req, true, &config);
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("\r\nTransfer-Encoding: chunked\r\nConnection: keep-alive\r\n"), "{}", text);
+        assert!(text.ends_with("\r\n\r\n3\r\nabc\r\n0\r\n\r\n"), "{}", text);
+
+        // HTTP/1.0: no chunked framing, the body ends when the connection closes.
+        let req = parse_request(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(ends_by_close(&streamed(), &req));
+        let mut response = finalize(streamed(), &req, false, &config);
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(!text.contains("Transfer-Encoding"), "{}", text);
+        assert!(text.ends_with("\r\nConnection: close\r\n\r\nabc"), "{}", text);
+    }
+
+    #[test]
+    fn test_security_headers() {
+        let req = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let config = test_config(".", "security_headers = true\n");
+
+        let response = finalize(handlers::no_content(), &req, true, &config);
+        for (name, value) in SECURITY_HEADERS {
+            assert_eq!(response.header_value(name), Some(value));
+        }
+        // Error responses don't get them; off (the default), nothing does.
+        let response = finalize(handlers::not_found(), &req, true, &config);
+        assert!(SECURITY_HEADERS.iter().all(|(name, _)| response.header_value(name).is_none()));
+        let response = finalize(handlers::no_content(), &req, true, &test_config(".", ""));
+        assert!(response.header_value("X-Frame-Options").is_none());
+
+        // A value the handler chose wins, and isn't sent twice.
+        let own = Response::new(HTTPStatus::Ok).header("X-Frame-Options", "SAMEORIGIN");
+        let response = finalize(own, &req, true, &config);
+        assert_eq!(response.headers.iter().filter(|(name, _)| name == "X-Frame-Options").count(), 1);
+        assert_eq!(response.header_value("X-Frame-Options"), Some("SAMEORIGIN"));
+    }
+
+    #[test]
+    fn test_response_headers_precedence() {
+        let req = parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let config = test_config(".", "security_headers = true\n\
+            [response_headers]\n\"X-Served-By\" = \"edge-3\"\n\"X-Frame-Options\" = \"SAMEORIGIN\"\n");
+
+        // Config over the preset; on error responses too.
+        let response = finalize(handlers::no_content(), &req, true, &config);
+        assert_eq!(response.header_value("X-Served-By"), Some("edge-3"));
+        assert_eq!(response.header_value("X-Frame-Options"), Some("SAMEORIGIN"));
+        assert_eq!(response.headers.iter().filter(|(name, _)| name == "X-Frame-Options").count(), 1);
+        let response = finalize(handlers::not_found(), &req, true, &config);
+        assert_eq!(response.header_value("X-Served-By"), Some("edge-3"));
+
+        // The handler over the config.
+        let own = Response::new(HTTPStatus::Ok).header("X-Served-By", "handler");
+        let response = finalize(own, &req, true, &config);
+        assert_eq!(response.header_value("X-Served-By"), Some("handler"));
+        assert_eq!(response.headers.iter().filter(|(name, _)| name == "X-Served-By").count(), 1);
+
+        // Bad tables are refused when the config is loaded.
+        let config = test_config(".", "[response_headers]\n\"Content-Length\" = \"0\"\n");
+        assert!(config.validate().is_err());
+        let config = test_config(".", "[response_headers]\n\"X-A\" = \"1\\r\\nX-B: 2\"\n");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_mirror_request_version() {
+        let config = test_config(".", "mirror_request_version = true\n");
+        let (req, outcome) = run("GET / HTTP/1.0\r\n\r\n", &config);
+        let text = String::from_utf8(finalize(outcome.response, &req, false, &config).to_bytes()).unwrap();
+        assert!(text.starts_with("HTTP/1.0 200 OK\r\n"), "{}", text);
+
+        let (req, outcome) = run("GET / HTTP/1.1\r\n\r\n", &config);
+        let text = String::from_utf8(finalize(outcome.response, &req, true, &config).to_bytes()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 200 OK\r\n"), "{}", text);
+    }
+
+    #[test]
+    fn test_on_timeout() {
+        assert!(on_timeout(b"").is_none());
+        let response = on_timeout(b"GET / HTTP/1.1\r\nHost: loc").unwrap();
+        assert_eq!(response.status, HTTPStatus::RequestTimeout);
+    }
+
+    #[test]
+    fn test_debug_echo() {
+        let raw = "POST /debug/echo?name=a+b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 300\r\n\r\n";
+
+        // Off (the default): just a path like any other, i.e. not found.
+        let (_, outcome) = run(raw, &test_config(".", ""));
+        assert_eq!(outcome.response.status, HTTPStatus::NotFound);
+
+        let config = test_config(".", "debug_endpoints = true\n");
+        let mut req = parse_request(raw.as_bytes()).unwrap();
+        req.body = Body::Bytes(vec![b'x'; 300]);
+        let outcome = handle_request(&mut req, &config, &ServerCtx::new(routes()));
+        assert_eq!(outcome.response.status, HTTPStatus::Ok);
+        let text = String::from_utf8(outcome.response.body.into_vec()).unwrap();
+        assert!(text.contains("method: POST\npath: /debug/echo\n"), "{}", text);
+        assert!(text.contains("  name = a b\n"), "{}", text);
+        assert!(text.contains("  Host: localhost\n"), "{}", text);
+        assert!(text.contains("body length: 300\n"), "{}", text);
+        assert!(text.contains(&format!("\"{}\"", "x".repeat(handlers::ECHO_BODY_PREVIEW))), "{}", text);
+        assert!(!text.contains(&"x".repeat(handlers::ECHO_BODY_PREVIEW + 1)), "{}", text);
+    }
+
+    #[test]
+    fn test_handler_errors_become_standard_responses() {
+        let config = test_config(".", "");
+        let (req, outcome) = run("GET /greet?name=Ada HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.body, b"Hello, Ada!\n");
+
+        // Err(BadRequest) from `?` looks exactly like the server's own 400, headers included.
+        let (_, outcome) = run("GET /greet HTTP/1.1\r\n\r\n", &config);
+        assert!(!outcome.close);
+        let response = finalize(outcome.response, &req, true, &config);
+        let own = finalize(handlers::bad_request(), &req, true, &config);
+        assert_eq!(response.to_bytes(), own.to_bytes());
+        let text = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", text);
+        assert!(text.contains("\r\nContent-Type: text/plain\r\n") && text.contains("\r\nContent-Length: 15\r\n"), "{}", text);
+        assert!(text.contains("\r\nConnection: keep-alive\r\n") && text.ends_with("\r\n\r\n400 Bad Request"), "{}", text);
+
+        use handlers::OrStatus;
+        assert_eq!("x".parse::<u8>().or_status(HTTPStatus::NotFound), Err(HTTPStatus::NotFound));
+        assert_eq!(Some(1).or_bad_request(), Ok(1));
+        assert_eq!(handlers::error(HTTPStatus::Unauthorized).header_value("WWW-Authenticate"), Some("Bearer"));
+    }
+
+    #[test]
+    fn test_favicon() {
+        let raw = "GET /favicon.ico HTTP/1.1\r\n\r\n";
+
+        // Not configured: a quiet, tagged 204 rather than a 404.
+        let (_, outcome) = run(raw, &test_config(".", ""));
+        assert_eq!(outcome.response.status, HTTPStatus::NoContent);
+        assert_eq!(outcome.response.log_tag, Some("favicon"));
+
+        // Configured: that file, from outside root_directory, cacheable.
+        let dir = crate::util::temp_root("dispatch_favicon");
+        let icon = dir.join("brand.ico");
+        fs::write(&icon, [0u8, 0, 1, 0]).unwrap();
+        let config = test_config(".", &format!("favicon_path = {:?}\n", icon.to_str().unwrap()));
+        let ctx = server_ctx(&config);
+        let send = |raw: &str| handle_request(&mut parse_request(raw.as_bytes()).unwrap(), &config, &ctx).response;
+        let response = send(raw);
+        assert_eq!(response.status, HTTPStatus::Ok);
+        assert_eq!(response.body, [0, 0, 1, 0]);
+        assert_eq!(response.header_value("Content-Type"), Some("image/x-icon"));
+        assert_eq!(response.header_value("Cache-Control"), Some("public, max-age=604800"));
+        assert!(response.header_value("ETag").is_some_and(|etag| etag.starts_with("\"4-")));
+        assert_eq!(response.log_tag, Some("favicon"));
+
+        // Read when the routes were registered, and revalidated like any static file.
+        let _ = fs::remove_dir_all(&dir);
+        let etag = response.header_value("ETag").unwrap();
+        let revalidated = send(&format!("GET /favicon.ico HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag));
+        assert_eq!((revalidated.status.clone(), revalidated.body.len()), (HTTPStatus::NotModified.into(), 0));
+        assert_eq!((revalidated.header_value("ETag"), revalidated.log_tag), (Some(etag), Some("favicon")));
+        assert_eq!(send(raw).body, [0, 0, 1, 0]);
+        let failed = send("GET /favicon.ico HTTP/1.1\r\nIf-Match: \"other\"\r\n\r\n");
+        assert_eq!(failed.status, HTTPStatus::PreconditionFailed);
+    }
+
+    #[test]
+    fn test_robots_txt_precedence() {
+        let dir = crate::util::temp_root("dispatch_robots");
+        fs::write(dir.join("robots.txt"), "from root").unwrap();
+        fs::write(dir.join("deploy.txt"), "from file").unwrap();
+        let root = dir.to_str().unwrap();
+        let get = |config: &Config| run("GET /robots.txt HTTP/1.1\r\n\r\n", config).1.response;
+
+        // Not configured: the static file, as before.
+        assert_eq!(get(&test_config(root, "")).body, b"from root");
+
+        // Configured (inline or as a file): that wins over root_directory/robots.txt.
+        let response = get(&test_config(root, "robots_txt = \"User-agent: *\\nDisallow: /\\n\"\n"));
+        assert_eq!(response.body, b"User-agent: *\nDisallow: /\n");
+        assert_eq!(response.header_value("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.header_value("Cache-Control"), Some("public, max-age=3600"));
+        let file = dir.join("deploy.txt");
+        let config = test_config(root, &format!("robots_txt = {{ path = {:?} }}\n", file.to_str().unwrap()));
+        assert!(config.validate().is_ok());
+        assert_eq!(get(&config).body, b"from file");
+
+        // A file that is missing or isn't text is refused when the config is loaded.
+        let config = test_config(root, "robots_txt = { path = \"no/such/robots.txt\" }\n");
+        assert!(config.validate().unwrap_err().contains("no/such/robots.txt"));
+        fs::write(dir.join("binary.txt"), [0xff, 0xfe, 0x00]).unwrap();
+        let config = test_config(root, &format!("robots_txt = {{ path = {:?} }}\n", dir.join("binary.txt").to_str().unwrap()));
+        assert!(config.validate().unwrap_err().contains("UTF-8"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let dir = crate::util::temp_root("dispatch_fallback");
+        fs::write(dir.join("index.html"), "<div id=app></div>").unwrap();
+        fs::write(dir.join("app.js"), "start()").unwrap();
+        let root = dir.to_str().unwrap();
+        let request = |raw: &str, config: &Config, router: Router| {
+            let mut req = parse_request(raw.as_bytes()).unwrap();
+            return handle_request(&mut req, config, &ServerCtx::new(router)).response;
+        };
+
+        // Static files off: a file that exists is still a 404, routes still work.
+        let mut router = routes();
+        router.set_fallback(Fallback::NotFound);
+        let config = test_config(root, "");
+        assert_eq!(request("GET /index.html HTTP/1.1\r\n\r\n", &config, router).status, HTTPStatus::NotFound);
+        let mut router = routes();
+        router.set_fallback(Fallback::NotFound);
+        assert_eq!(request("GET /about HTTP/1.1\r\n\r\n", &config, router).status, HTTPStatus::Ok);
+
+        let mut router = routes();
+        router.set_fallback(Fallback::custom(handlers::about));
+        assert_eq!(request("GET /nowhere HTTP/1.1\r\n\r\n", &config, router).body, b"<h1>About us</h1>");
+
+        // SPA: client-side routes get the index page; real files are served as usual.
+        let config = test_config(root, "spa_fallback = true\n");
+        let response = request("GET /some/client/route HTTP/1.1\r\n\r\n", &config, routes());
+        assert_eq!((response.status, response.body.into_vec()), (HTTPStatus::Ok.into(), b"<div id=app></div>".to_vec()));
+        assert_eq!(request("GET /app.js HTTP/1.1\r\n\r\n", &config, routes()).body, b"start()");
+        assert_eq!(request("POST /some/client/route HTTP/1.1\r\n\r\n", &config, routes()).status, HTTPStatus::NotFound);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_denied_files_are_never_read() {
+        let dir = crate::util::temp_root("dispatch_denied");
+        for name in ["web.config", "dump.SQL", "config.toml", "index.html"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let config = test_config(dir.to_str().unwrap(), "denied_extensions = [\"sql\"]\ndenied_filenames = [\"Web.Config\"]\n");
+        let mut ctx = ServerCtx::new(routes());
+        ctx.config_file = dir.join("config.toml").canonicalize().ok();
+
+        for path in ["/WEB.CONFIG", "/dump.SQL", "/config.toml", "/Config.toml"] {
+            let mut req = parse_request(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()).unwrap();
+            assert_eq!(handle_request(&mut req, &config, &ctx).response.status, HTTPStatus::NotFound, "{}", path);
+        }
+        let mut req = parse_request(b"GET /index.html HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(handle_request(&mut req, &config, &ctx).response.body, b"index.html");
+
+        // Refused before being opened: only the served file ever made it into the static cache.
+        let cached = |name: &str| {
+            let file = dir.join(name);
+            ctx.static_cache.get(&file, fs::metadata(&file).unwrap().modified().unwrap()).is_some()
+        };
+        assert!(cached("index.html"));
+        assert!(!cached("web.config") && !cached("dump.SQL") && !cached("config.toml"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_wire_dumps_are_never_served() {
+        let dir = crate::util::temp_root("dispatch_dumps");
+        fs::create_dir(dir.join("dump")).unwrap();
+        fs::write(dir.join("dump").join("1-1700000000000.txt"), "Authorization: Bearer s3cret").unwrap();
+        fs::create_dir(dir.join("pages")).unwrap();
+        fs::write(dir.join("pages").join("dump"), "a page").unwrap();
+        let config = test_config(dir.to_str().unwrap(), "directory_listing = true\n");
+        let mut ctx = ServerCtx::new(routes());
+        ctx.dump_directory = dir.join("dump").canonicalize().ok();
+        let get = |path: &str| {
+            let mut req = parse_request(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()).unwrap();
+            return handle_request(&mut req, &config, &ctx).response;
+        };
+
+        for path in ["/dump/1-1700000000000.txt", "/dump/", "/dump", "//dump/./1-1700000000000.txt"] {
+            assert_eq!(get(path).status, HTTPStatus::NotFound, "{}", path);
+        }
+        // Left out of the root's listing; something else called "dump" is still served.
+        assert!(!String::from_utf8_lossy(&get("/").body).contains("dump"));
+        assert_eq!(get("/pages/dump").body, b"a page");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_rendered_markdown_is_cached_per_etag() {
+        let dir = crate::util::temp_root("dispatch_markdown");
+        let path = dir.join("notes.md");
+        fs::write(&path, "# One\n").unwrap();
+        let config = test_config(dir.to_str().unwrap(), "render_markdown = true\n");
+        let ctx = ServerCtx::new(routes());
+        let get = || {
+            let mut req = parse_request(b"GET /notes.md HTTP/1.1\r\n\r\n").unwrap();
+            return handle_request(&mut req, &config, &ctx).response;
+        };
+
+        let first = get();
+        assert!(String::from_utf8_lossy(&first.body).contains("<h1>One</h1>"));
+        let cached = ctx.rendered_cache.get(&path, fs::metadata(&path).unwrap().modified().unwrap()).unwrap();
+        assert_eq!((Some(cached.etag.as_str()), &cached.body[..]), (first.header_value("ETag"), &first.body[..]));
+
+        // A new version of the file is rendered again, under its new ETag.
+        let later = fs::metadata(&path).unwrap().modified().unwrap() + std::time::Duration::from_secs(5);
+        fs::write(&path, "# Two, longer\n").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let second = get();
+        assert!(String::from_utf8_lossy(&second.body).contains("<h1>Two, longer</h1>"));
+        assert_ne!(second.header_value("ETag"), first.header_value("ETag"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cached_bodies_are_shared() {
+        let dir = crate::util::temp_root("dispatch_shared_bodies");
+        fs::write(dir.join("small.txt"), "0123456789").unwrap();
+        fs::write(dir.join("big.bin"), vec![b'x'; 2048]).unwrap();
+        let config = test_config(dir.to_str().unwrap(), "cache_max_bytes = 1024\n");
+        let ctx = ServerCtx::new(routes());
+        let get = |raw: &str| handle_request(&mut parse_request(raw.as_bytes()).unwrap(), &config, &ctx).response;
+        let buffer = |response: &Response| match &response.body {
+            BodyBytes::Shared(bytes, _) => Some(Arc::as_ptr(bytes) as *const u8),
+            BodyBytes::Owned(_) => None,
+        };
+
+        // Cached: every response, a single range included, is sent from the cache's own buffer.
+        let first = get("GET /small.txt HTTP/1.1\r\n\r\n");
+        let second = get("GET /small.txt HTTP/1.1\r\n\r\n");
+        let range = get("GET /small.txt HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n");
+        assert!(buffer(&first).is_some());
+        assert_eq!((buffer(&second), buffer(&range)), (buffer(&first), buffer(&first)));
+        assert_eq!(range.body, b"234");
+
+        // Too big to cache: the bytes read go straight into the response.
+        let big = get("GET /big.bin HTTP/1.1\r\n\r\n");
+        assert_eq!((buffer(&big), big.body.len()), (None, 2048));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_max_file_bytes() {
+        let dir = crate::util::temp_root("dispatch_max_file");
+        fs::File::create(dir.join("big.bin")).unwrap().set_len(1025).unwrap();
+        fs::write(dir.join("small.txt"), "small").unwrap();
+        let root = dir.to_str().unwrap();
+        let get = |raw: &str, config: &Config| run(raw, config).1.response;
+
+        let config = test_config(root, "max_file_bytes = 1024\n");
+        assert_eq!(get("GET /big.bin HTTP/1.1\r\n\r\n", &config).status, HTTPStatus::Forbidden);
+        assert_eq!(get("GET /big.bin HTTP/1.1\r\nRange: bytes=0-9\r\n\r\n", &config).status, HTTPStatus::Forbidden);
+        assert_eq!(get("GET /small.txt HTTP/1.1\r\n\r\n", &config).body, b"small");
+
+        // 0: no limit.
+        let config = test_config(root, "max_file_bytes = 0\n");
+        assert_eq!(get("GET /big.bin HTTP/1.1\r\n\r\n", &config).body.len(), 1025);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_static_conditionals() {
+        let dir = crate::util::temp_root("dispatch_conditionals");
+        fs::write(dir.join("page.txt"), "0123456789").unwrap();
+        let config = test_config(dir.to_str().unwrap(), "");
+        let get = |headers: &str| run(&format!("GET /page.txt HTTP/1.1\r\n{}\r\n", headers), &config).1.response;
+        let etag = get("").header_value("ETag").unwrap().to_string();
+
+        let not_modified = get(&format!("If-None-Match: {}\r\n", etag));
+        assert_eq!((not_modified.status.clone(), not_modified.body.len()), (HTTPStatus::NotModified.into(), 0));
+        assert_eq!(not_modified.header_value("ETag"), Some(etag.as_str()));
+        assert_eq!(get("If-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n").status, HTTPStatus::NotModified);
+        assert_eq!(get("If-Match: \"other\"\r\n").status, HTTPStatus::PreconditionFailed);
+
+        // The Range applies only while If-Range still names this version.
+        let partial = get(&format!("Range: bytes=0-3\r\nIf-Range: {}\r\n", etag));
+        assert_eq!((partial.status, partial.body.into_vec()), (HTTPStatus::PartialContent.into(), b"0123".to_vec()));
+        let stale = get("Range: bytes=0-3\r\nIf-Range: \"other\"\r\n");
+        assert_eq!((stale.status, stale.body.into_vec()), (HTTPStatus::Ok.into(), b"0123456789".to_vec()));
+        // A HEAD is handled as its GET, conditions included.
+        assert_eq!(run(&format!("HEAD /page.txt HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag), &config).1.response.status, HTTPStatus::NotModified);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_unc_and_verbatim_targets() {
+        let config = test_config(".", "");
+        for target in ["/%5C%5Cserver%5Cshare", "/%5C%5C%3F%5CC:%5CWindows%5Cwin.ini"] {
+            let (_, outcome) = run(&format!("GET {} HTTP/1.1\r\n\r\n", target), &config);
+            assert_eq!(outcome.response.status, HTTPStatus::BadRequest, "{}", target);
+        }
+        // Doubled slashes (also as %2F) are collapsed first: just a file inside the root.
+        let (req, outcome) = run("GET //etc/passwd HTTP/1.1\r\n\r\n", &config);
+        assert_eq!((req.path.as_str(), outcome.response.status), ("/etc/passwd", HTTPStatus::NotFound.into()));
+    }
+
+    #[test]
+    fn test_short_name_alias() {
+        let dir = crate::util::temp_root("dispatch_short_names");
+        fs::write(dir.join("app-settings.config"), "key=1").unwrap();
+        fs::write(dir.join("public-announcement.txt"), "hello").unwrap();
+        if !dir.join("APP-SE~1.CON").exists() {
+            println!("⚠️ Skipping: no 8.3 short names on this volume.");
+            let _ = fs::remove_dir_all(&dir);
+            return;
+        }
+        let root = dir.to_str().unwrap();
+        let get = |path: &str, config: &Config| run(&format!("GET {} HTTP/1.1\r\n\r\n", path), config).1.response;
+
+        // By default any alias is a 404, even for a file that may be served under its long name.
+        let config = test_config(root, "denied_extensions = [\"config\"]\n");
+        assert_eq!(get("/PUBLIC~1.TXT", &config).status, HTTPStatus::NotFound);
+        assert_eq!(get("/public-announcement.txt", &config).body, b"hello");
+
+        // Resolved: served if the long name passes the deny rules. The alias's .CON hid the .config.
+        let config = test_config(root, "denied_extensions = [\"config\"]\nresolve_short_names = true\n");
+        assert_eq!(get("/PUBLIC~1.TXT", &config).body, b"hello");
+        assert_eq!(get("/APP-SE~1.CON", &config).status, HTTPStatus::NotFound);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_error_level_is_silent() {
+        use crate::log::{self, capture::capture_at, Level};
+
+        let dir = crate::util::temp_root("dispatch_quiet");
+        fs::write(dir.join("page.txt"), "x".repeat(4096)).unwrap();
+        let config = test_config(dir.to_str().unwrap(), "");
+        let ctx = ServerCtx::new(routes());
+
+        // One request/response cycle, as the connection loop runs it.
+        let cycle = || {
+            let mut req = parse_request(b"GET /page.txt?x#frag HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+            let mut outcome = handle_request(&mut req, &config, &ctx);
+            crate::compress::maybe_compress(&req, &mut outcome.response, &config);
+            let mut response = finalize(outcome.response, &req, true, &config);
+            let record = crate::access_log::Record::new("127.0.0.1:1", &req, &response, "1-1".to_string());
+            log::info!("📝 {}", config.access_log_format.render(&record));
+            response.write_to(&mut Vec::new()).unwrap();
+        };
+
+        // At a level of the capture's own: the global one is shared with every other test running.
+        let (_, lines) = capture_at(Level::Trace, cycle);
+        assert!(lines.iter().any(|(level, _)| *level == Level::Trace), "{:?}", lines);
+
+        let (_, lines) = capture_at(Level::Error, cycle);
+        // Nothing at all: no stdout output, and no errors either for a request that succeeds.
+        assert!(lines.is_empty(), "{:?}", lines);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_static_cache_follows_mtime() {
+        let dir = crate::util::temp_root("dispatch_cache");
+        let path = dir.join("page.txt");
+        let config = test_config(dir.to_str().unwrap(), "");
+        let ctx = ServerCtx::new(routes());
+        let cache = &ctx.static_cache;
+        let get = |ctx: &ServerCtx| {
+            let mut req = parse_request(b"GET /page.txt HTTP/1.1\r\n\r\n").unwrap();
+            handle_request(&mut req, &config, ctx).response
+        };
+
+        fs::write(&path, "one").unwrap();
+        let first = get(&ctx);
+        assert_eq!(first.body, b"one");
+        assert!(first.header_value("ETag").is_some());
+        assert!(cache.get(&path, fs::metadata(&path).unwrap().modified().unwrap()).is_some());
+
+        // Same mtime: still served from memory, even though the disk changed underneath.
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, "two").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(get(&ctx).body, b"one");
+
+        // A newer mtime invalidates the entry.
+        let later = modified + std::time::Duration::from_secs(5);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let second = get(&ctx);
+        assert_eq!(second.body, b"two");
+        assert_ne!(second.header_value("ETag"), first.header_value("ETag"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_cached_route_runs_handler_once_per_ttl() {
+        use std::sync::atomic::AtomicUsize;
+        use std::time::Duration;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut router = Router::new();
+        router.get("/stats", move |_req: &Request| format!("<p>{}</p>", counter.fetch_add(1, Ordering::SeqCst) + 1))
+            .cache_for(Duration::from_millis(200));
+        let ctx = ServerCtx::new(router);
+        let config = test_config(".", "admin_token = \"s3cret\"\n");
+        let get = || {
+            let mut req = parse_request(b"GET /stats HTTP/1.1\r\n\r\n").unwrap();
+            handle_request(&mut req, &config, &ctx).response
+        };
+
+        let first = get();
+        let second = get();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!((first.header_value("X-Cache"), second.header_value("X-Cache")), (Some("MISS"), Some("HIT")));
+        assert_eq!(second.body, b"<p>1</p>");
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(get().body, b"<p>2</p>");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // The admin endpoint empties it on demand.
+        let mut clear = parse_request(b"POST /admin/cache/clear HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").unwrap();
+        assert_eq!(handle_request(&mut clear, &config, &ctx).response.status, HTTPStatus::NoContent);
+        assert_eq!(get().header_value("X-Cache"), Some("MISS"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_dynamic_etags() {
+        use std::sync::atomic::AtomicUsize;
+
+        let version = Arc::new(AtomicUsize::new(1));
+        let current = Arc::clone(&version);
+        let mut router = Router::new();
+        router.get("/page", move |_req: &Request| format!("<p>v{}</p>", current.load(Ordering::SeqCst)));
+        router.get("/private", |_req: &Request| Response::new(HTTPStatus::Ok).header("Cache-Control", "no-store").body("text/plain", "x"));
+        let ctx = ServerCtx::new(router);
+        let config = test_config(".", "dynamic_etags = true\n");
+        let get = |raw: String| handle_request(&mut parse_request(raw.as_bytes()).unwrap(), &config, &ctx).response;
+        let conditional = |etag: &str| get(format!("GET /page HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag));
+
+        let first = get("GET /page HTTP/1.1\r\n\r\n".to_string());
+        let etag = first.header_value("ETag").unwrap().to_string();
+        assert_eq!(get("GET /page HTTP/1.1\r\n\r\n".to_string()).header_value("ETag"), Some(etag.as_str()));
+
+        let not_modified = conditional(&etag);
+        assert_eq!((not_modified.status.clone(), not_modified.body.len()), (HTTPStatus::NotModified.into(), 0));
+        assert_eq!(not_modified.header_value("ETag"), Some(etag.as_str()));
+        assert_eq!(not_modified.header_value("Content-Type"), None);
+        assert!(!String::from_utf8_lossy(&not_modified.to_bytes()).contains("Content-Length"));
+
+        // New body, new tag: the old one no longer matches.
+        version.store(2, Ordering::SeqCst);
+        let changed = conditional(&etag);
+        assert_eq!((changed.status.clone(), changed.body.as_slice()), (HTTPStatus::Ok.into(), b"<p>v2</p>".as_slice()));
+        assert_ne!(changed.header_value("ETag"), Some(etag.as_str()));
+
+        assert_eq!(get("GET /private HTTP/1.1\r\n\r\n".to_string()).header_value("ETag"), None);
+        let off = test_config(".", "");
+        assert_eq!(handle_request(&mut parse_request(b"GET /page HTTP/1.1\r\n\r\n").unwrap(), &off, &ctx).response.header_value("ETag"), None);
+    }
+
+    #[test]
+    fn test_admin_connections() {
+        let ctx = ServerCtx::new(routes());
+        let config = test_config(".", "admin_token = \"s3cret\"\n");
+        let entry = crate::connections::register(&ctx.connections, 7, "127.0.0.1:50007".to_string(), || {});
+        entry.record_response(120);
+        ctx.stats.record_response(120);
+
+        let mut req = parse_request(b"GET /admin/connections HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").unwrap();
+        let response = handle_request(&mut req, &config, &ctx).response;
+        let text = String::from_utf8(response.body.into_vec()).unwrap();
+        assert!(text.starts_with("#7 "), "{}", text);
+        assert!(text.contains(" 127.0.0.1:50007 active requests=1 bytes_sent=120 "), "{}", text);
+        assert!(text.contains("\nopen=1 requests=1 bytes_sent=120 panics_total="), "{}", text);
+
+        let mut req = parse_request(b"GET /admin/connections HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(handle_request(&mut req, &config, &ctx).response.status, HTTPStatus::Unauthorized);
+    }
+
+    #[test]
+    fn test_handler_panic_is_logged_and_answered() {
+        use crate::log::{capture::capture, Level};
+
+        crate::panics::install_hook();
+        let config = test_config(".", "debug_endpoints = true\n");
+        let ctx = ServerCtx::new(routes());
+        let before = crate::panics::panics_total();
+        let (outcomes, lines) = capture(|| {
+            ["GET /debug/panic?first HTTP/1.1\r\n\r\n", "GET /debug/panic?second HTTP/1.1\r\n\r\n"].map(|raw| {
+                let mut req = parse_request(raw.as_bytes()).unwrap();
+                handle_request(&mut req, &config, &ctx)
+            })
+        });
+
+        for outcome in &outcomes {
+            assert_eq!((outcome.response.status.clone(), outcome.close), (HTTPStatus::InternalServerError.into(), true));
+        }
+        // Other tests may panic meanwhile (on purpose), so the counter is only known to have moved by 2 or more.
+        assert!(crate::panics::panics_total() >= before + 2);
+        let logged: Vec<&String> = lines.iter()
+            .filter(|(level, line)| *level == Level::Error && line.contains("while handling \"GET /debug/panic?"))
+            .map(|(_, line)| line)
+            .collect();
+        assert_eq!(logged.len(), 2, "{:?}", lines);
+        assert!(logged[1].contains("?second HTTP/1.1\"") && logged[1].contains("requested by GET /debug/panic"), "{}", logged[1]);
+
+        // Off by default: just another missing path.
+        let mut req = parse_request(b"GET /debug/panic HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(handle_request(&mut req, &test_config(".", ""), &ctx).response.status, HTTPStatus::NotFound);
+    }
+
+    #[test]
+    fn test_admin_shutdown() {
+        let shutdown = |raw: &str, config: &Config| {
+            let ctx = ServerCtx::new(routes());
+            let mut req = parse_request(raw.as_bytes()).unwrap();
+            let outcome = handle_request(&mut req, config, &ctx);
+            (outcome.response.status, ctx.shutdown_requested())
+        };
+
+        // Disabled: indistinguishable from any other missing path.
+        let disabled = test_config(".", "");
+        let with_token = "POST /admin/shutdown HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n";
+        assert_eq!(shutdown(with_token, &disabled), (HTTPStatus::NotFound.into(), false));
+
+        let config = test_config(".", "admin_token = \"s3cret\"\n");
+        assert_eq!(shutdown("POST /admin/shutdown HTTP/1.1\r\n\r\n", &config), (HTTPStatus::Unauthorized.into(), false));
+        assert_eq!(
+            shutdown("POST /admin/shutdown HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n", &config),
+            (HTTPStatus::Forbidden.into(), false)
+        );
+        assert_eq!(
+            shutdown("GET /admin/shutdown HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n", &config),
+            (HTTPStatus::MethodNotAllowed.into(), false)
+        );
+        assert_eq!(shutdown(with_token, &config), (HTTPStatus::Accepted.into(), true));
+    }
+
+    #[test]
+    fn test_api_auth() {
+        let config = test_config(".", "[api_auth]\n\"/about\" = { keys = { ci = \"ci-token\" } }\n");
+
+        let (req, outcome) = run("GET /about HTTP/1.1\r\nAuthorization: Bearer ci-token\r\n\r\n", &config);
+        assert_eq!((outcome.response.status, req.api_key_id), (HTTPStatus::Ok.into(), Some("ci".to_string())));
+        for raw in ["GET /about HTTP/1.1\r\nAuthorization: Bearer nope\r\n\r\n", "GET /about HTTP/1.1\r\n\r\n", "GET //about/ HTTP/1.1\r\n\r\n"] {
+            let (req, outcome) = run(raw, &config);
+            assert_eq!((outcome.response.status, req.api_key_id), (HTTPStatus::Unauthorized.into(), None), "{:?}", raw);
+        }
+
+        // Elsewhere nothing is asked for, and nothing recorded.
+        let (req, outcome) = run("GET / HTTP/1.1\r\n\r\n", &config);
+        assert_eq!((outcome.response.status, req.api_key_id), (HTTPStatus::Ok.into(), None));
+        // In any case.
+        let (_, outcome) = run("GET /ABOUT HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(outcome.response.status, HTTPStatus::Unauthorized);
+    }
+
+    #[test]
+    fn test_api_auth_static_files() {
+        let dir = crate::util::temp_root("dispatch_api_auth");
+        fs::create_dir(dir.join("api-internal")).unwrap();
+        fs::write(dir.join("api-internal").join("secret.json"), "{}").unwrap();
+        let root = dir.to_str().unwrap();
+        let auth = "resolve_short_names = true\n[api_auth]\n\"/api-internal\" = { keys = { ci = \"ci-token\" } }\n";
+        let config = test_config(root, auth);
+        let get = |path: &str, headers: &str| run(&format!("GET {} HTTP/1.1\r\n{}\r\n", path, headers), &config).1.response;
+
+        assert_eq!(get("/api-internal/secret.json", "Authorization: Bearer ci-token\r\n").body, b"{}");
+        // The file system ignores case, so the prefix does too.
+        for path in ["/api-internal/secret.json", "/API-INTERNAL/secret.json", "/Api-Internal/secret.json"] {
+            assert_eq!(get(path, "").status, HTTPStatus::Unauthorized, "{}", path);
+        }
+        // An 8.3 alias of the directory, where the volume has them, is checked under its long name.
+        if dir.join("API-IN~1").exists() {
+            assert_eq!(get("/API-IN~1/secret.json", "").status, HTTPStatus::Unauthorized);
+            assert_eq!(get("/API-IN~1/secret.json", "Authorization: Bearer ci-token\r\n").body, b"{}");
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_maintenance_mode() {
+        let config = test_config(".", "admin_token = \"s3cret\"\nmaintenance_message = \"Back at noon\"\n");
+        let ctx = server_ctx(&config);
+        let send = |raw: &str| handle_request(&mut parse_request(raw.as_bytes()).unwrap(), &config, &ctx).response;
+        let toggle = |query: &str| send(&format!("POST /admin/maintenance{} HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n", query));
+
+        assert_eq!(send("GET /health HTTP/1.1\r\n\r\n").body, b"ok");
+        assert_eq!(toggle("").body, b"maintenance on");
+        for raw in ["GET / HTTP/1.1\r\n\r\n", "GET /Cargo.toml HTTP/1.1\r\n\r\n", "POST /nowhere HTTP/1.1\r\n\r\n"] {
+            let response = send(raw);
+            assert_eq!(response.status, HTTPStatus::ServiceUnavailable);
+            assert_eq!((response.header_value("Retry-After"), response.body.as_slice()), (Some("60"), b"Back at noon".as_slice()));
+        }
+
+        // /health and the admin endpoints still answer, the latter still wanting the token.
+        for raw in ["GET /health HTTP/1.1\r\n\r\n", "HEAD /health HTTP/1.1\r\n\r\n"] {
+            let health = send(raw);
+            assert_eq!((health.status.clone(), health.body.as_slice()), (HTTPStatus::Ok.into(), b"degraded".as_slice()));
+        }
+        assert_eq!(send("GET /admin/connections HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").status, HTTPStatus::Ok);
+        assert_eq!(send("POST /admin/maintenance HTTP/1.1\r\n\r\n").status, HTTPStatus::Unauthorized);
+        assert_eq!(toggle("?on=maybe").status, HTTPStatus::BadRequest);
+        assert_eq!(toggle("?on=true").body, b"maintenance on");
+
+        assert_eq!(toggle("?on=false").body, b"maintenance off");
+        assert_eq!(send("GET / HTTP/1.1\r\n\r\n").status, HTTPStatus::Ok);
+        assert_eq!(send("GET /health HTTP/1.1\r\n\r\n").body, b"ok");
+    }
+
+    #[test]
+    fn test_body_limit() {
+        let config = test_config(".", "[limits]\n\"/contact\" = 65536\n\"/upload/\" = 52428800\n\"/upload/avatars\" = 1024\n");
+        let mut router = routes();
+        router.post("/upload/form", handlers::echo_body).max_body(2048);
+        let limit = |path: &str| {
+            let req = parse_request(format!("POST {} HTTP/1.1\r\n\r\n", path).as_bytes()).unwrap();
+            body_limit(&req, &config, &router)
+        };
+        assert_eq!(limit("/contact"), 65536);
+        assert_eq!(limit("//contact/"), 65536);
+        assert_eq!(limit("/contacts"), config.max_body_size);
+        assert_eq!(limit("/upload/big.iso"), 52428800);
+        assert_eq!(limit("/upload/avatars/me.png"), 1024);
+        assert_eq!(limit("/"), config.max_body_size);
+        // The route's own limit wins over the [limits] prefix it sits under.
+        assert_eq!(limit("/upload/form"), 2048);
+
+        // A 100KB post: too big for /contact, fine for the default.
+        assert!(100 * 1024 > limit("/contact"));
+        assert!(100 * 1024 <= limit("/"));
+    }
+}
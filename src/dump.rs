@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::log::error;
+
+// Where the dumps go, relative to the working directory. Never served, even under root_directory.
+pub const DUMP_DIRECTORY: &str = "dump";
+
+// Bytes recorded per connection (both directions together); anything after that is left out.
+pub const MAX_DUMP_BYTES: usize = 1024 * 1024;
+
+/*
+Wire-level dump of one connection, for debug_dump. Three files per connection, named
+"<connection id>-<unix millis>":
+- .in: the exact bytes received,
+- .out: the exact bytes sent,
+- .txt: both, in order, as a readable transcript (">>>" inbound, "<<<" outbound) with anything
+  non-printable hex-escaped, so a stray \0 or a bare \r can actually be seen.
+*/
+struct WireDump {
+    inbound: File,
+    outbound: File,
+    transcript: File,
+    recorded: usize,
+}
+
+impl WireDump {
+    fn create(directory: &Path, connection_id: u64) -> io::Result<WireDump> {
+        fs::create_dir_all(directory)?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let base = directory.join(format!("{}-{}", connection_id, millis));
+        let with = |extension: &str| -> PathBuf { base.with_extension(extension) };
+        return Ok(WireDump {
+            inbound: File::create(with("in"))?,
+            outbound: File::create(with("out"))?,
+            transcript: File::create(with("txt"))?,
+            recorded: 0,
+        });
+    }
+
+    fn record(&mut self, marker: &str, data: &[u8]) -> io::Result<()> {
+        if self.recorded >= MAX_DUMP_BYTES {
+            return Ok(());
+        }
+        let kept = &data[..data.len().min(MAX_DUMP_BYTES - self.recorded)];
+        self.recorded += kept.len();
+
+        let raw = if marker == ">>>" { &mut self.inbound } else { &mut self.outbound };
+        raw.write_all(kept)?;
+        writeln!(self.transcript, "{} {} bytes", marker, data.len())?;
+        writeln!(self.transcript, "{}", escape(kept))?;
+        if self.recorded >= MAX_DUMP_BYTES {
+            writeln!(self.transcript, "[dump limit of {} bytes reached, nothing more is recorded]", MAX_DUMP_BYTES)?;
+        }
+        return Ok(());
+    }
+}
+
+thread_local! {
+    // Each connection has its own worker thread, so the thread's dump is the connection's.
+    static DUMP: RefCell<Option<WireDump>> = const { RefCell::new(None) };
+}
+
+// Start dumping this thread's connection into DUMP_DIRECTORY.
+pub fn start(connection_id: u64) {
+    match WireDump::create(Path::new(DUMP_DIRECTORY), connection_id) {
+        Ok(dump) => DUMP.with(|cell| *cell.borrow_mut() = Some(dump)),
+        Err(e) => error!("❌ Cannot create the wire dump for connection #{}: {}", connection_id, e),
+    }
+}
+
+// Bytes just received from the client (no-op unless start() was called on this thread).
+pub fn inbound(data: &[u8]) {
+    record(">>>", data);
+}
+
+// Bytes just sent to the client.
+pub fn outbound(data: &[u8]) {
+    record("<<<", data);
+}
+
+// Stop dumping and close the files.
+pub fn stop() {
+    DUMP.with(|cell| *cell.borrow_mut() = None);
+}
+
+fn record(marker: &str, data: &[u8]) {
+    DUMP.with(|cell| {
+        let mut dump = cell.borrow_mut();
+        if let Some(wire) = dump.as_mut()
+            && let Err(e) = wire.record(marker, data) {
+            error!("❌ Wire dump failed, disabling it for this connection: {}", e);
+            *dump = None;
+        }
+    });
+}
+
+/*
+Printable ASCII as is; everything else as an escape: \r and \t by name, \n by name followed by
+an actual line break (so the transcript keeps the shape of the request), other bytes as \xHH.
+*/
+pub fn escape(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'\r' => text.push_str("\\r"),
+            b'\n' => text.push_str("\\n\n"),
+            b'\t' => text.push_str("\\t"),
+            b'\\' => text.push_str("\\\\"),
+            0x20..=0x7e => text.push(byte as char),
+            _ => text.push_str(&format!("\\x{:02X}", byte)),
+        }
+    }
+    return text;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_root;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"GET / HTTP/1.1\r\n"), "GET / HTTP/1.1\\r\\n\n");
+        assert_eq!(escape(b"a\0b\xff\tc\\"), "a\\x00b\\xFF\\tc\\\\");
+    }
+
+    #[test]
+    fn test_dump_files_and_cap() {
+        let dir = temp_root("dump");
+        let mut dump = WireDump::create(&dir, 7).unwrap();
+        dump.record(">>>", b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        dump.record("<<<", &vec![b'x'; MAX_DUMP_BYTES]).unwrap();
+        dump.record(">>>", b"ignored").unwrap();
+        drop(dump);
+
+        let file = |extension: &str| {
+            let entry = fs::read_dir(&dir).unwrap()
+                .map(|e| e.unwrap().path())
+                .find(|p| p.extension().is_some_and(|e| e == extension))
+                .unwrap();
+            return fs::read(entry).unwrap();
+        };
+        assert_eq!(file("in"), b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(file("out").len(), MAX_DUMP_BYTES - 18);
+        let transcript = String::from_utf8(file("txt")).unwrap();
+        assert!(transcript.starts_with(">>> 18 bytes\nGET / HTTP/1.1\\r\\n\n\\r\\n\n"), "{}", &transcript[..60]);
+        assert!(transcript.contains("[dump limit"));
+        assert!(!transcript.contains("ignored"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,39 +1,346 @@
-use crate::response::build_response;
-use crate::response::HTTPStatus;
+use std::fs;
+use std::io::Write;
 
-pub fn home() -> Vec<u8> {
-    // A fixed HTTP 200 OK response with simple HTML body
-    build_response(HTTPStatus::Ok, "OK", "text/html", "<h1>Welcome home!</h1>")
+use crate::conditionals::{evaluate_preconditions, FileMeta, PreconditionResult};
+use crate::config::RobotsTxt;
+use crate::dispatch::not_modified;
+use crate::response::{BodyBytes, HTTPStatus, Response};
+use crate::range::{resolve_ranges, content_range, new_boundary, multipart_byteranges, RangeRequest, RangeSpec};
+use crate::request::Request;
+use crate::router::HandlerResult;
+use crate::static_cache::{etag_for, CachedFile};
+use crate::log::error;
+
+// How much of the request body /debug/echo reflects back. Keeps the echo from amplifying uploads.
+pub(crate) const ECHO_BODY_PREVIEW: usize = 256;
+
+// Liveness check for load balancers, answered even in maintenance mode.
+pub(crate) const HEALTH_PATH: &str = "/health";
+
+/// Methods the server supports at all. Anything else is answered with 405.
+pub const ALLOWED_METHODS: [&str; 2] = ["GET", "POST"];
+
+/**
+Turns a missing value or a failure into an error status, so a handler can bail out with `?`:
+
+```ignore
+let name = query.get("name").or_bad_request()?;
+```
+
+The router then answers with handlers::error() for that status.
+*/
+pub trait OrStatus<T> {
+    fn or_status(self, status: HTTPStatus) -> Result<T, HTTPStatus>;
+
+    fn or_bad_request(self) -> Result<T, HTTPStatus> where Self: Sized {
+        self.or_status(HTTPStatus::BadRequest)
+    }
+}
+
+impl<T> OrStatus<T> for Option<T> {
+    fn or_status(self, status: HTTPStatus) -> Result<T, HTTPStatus> {
+        self.ok_or(status)
+    }
+}
+
+impl<T, E> OrStatus<T> for Result<T, E> {
+    fn or_status(self, status: HTTPStatus) -> Result<T, HTTPStatus> {
+        self.map_err(|_| status)
+    }
+}
+
+/// Fixed pages: a 200 with this HTML body (see IntoResponse).
+pub fn home(_req: &Request) -> &'static str {
+    "<h1>Welcome home!</h1>"
+}
+
+/**
+The same page as HTML for browsers and as JSON for API clients, whichever the Accept header
+prefers. A client that accepts neither still gets the HTML. Vary tells caches the answer depends
+on Accept.
+*/
+pub fn about(req: &Request) -> Response {
+    let response = match req.prefers(&["text/html", "application/json"]) {
+        Some("application/json") => Response::new(HTTPStatus::Ok).body("application/json", "{\"title\":\"About us\"}"),
+        _ => Response::new(HTTPStatus::Ok).body("text/html; charset=utf-8", "<h1>About us</h1>"),
+    };
+    response.vary("Accept")
+}
+
+/**
+GET /favicon.ico. Browsers ask for it on every page, so it has its own route instead of
+producing a 404 (and a log line) each time: the configured file if there is one, wherever it
+lives, cached for a week (and a 304 for a browser that has this version); otherwise an empty 204.
+`icon` is None without favicon_path, and Some(None) if load_favicon() couldn't read it (404).
+Either way the access log tags it "favicon".
+*/
+pub fn favicon(req: &Request, icon: Option<&Option<CachedFile>>) -> Response {
+    let icon = match icon {
+        None => return Response::new(HTTPStatus::NoContent).log_tag("favicon"),
+        Some(None) => return not_found().log_tag("favicon"),
+        Some(Some(icon)) => icon,
+    };
+    let response = Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "public, max-age=604800")
+        .header("ETag", &icon.etag)
+        .log_tag("favicon");
+    let meta = FileMeta { etag: Some(&icon.etag), modified: Some(icon.modified) };
+    match evaluate_preconditions(req.conditionals(), &meta) {
+        PreconditionResult::NotModified => not_modified(response),
+        PreconditionResult::PreconditionFailed => precondition_failed().log_tag("favicon"),
+        PreconditionResult::Proceed { .. } => response.body(&icon.content_type, icon.body.clone()),
+    }
+}
+
+/// The favicon_path file, read once when the routes are registered. None (and an error logged) if it can't be.
+pub fn load_favicon(path: &str) -> Option<CachedFile> {
+    let read = fs::metadata(path).and_then(|metadata| Ok((metadata.modified()?, fs::read(path)?)));
+    match read {
+        Ok((modified, icon)) => Some(CachedFile {
+            etag: etag_for(icon.len() as u64, modified),
+            body: BodyBytes::shared(icon.into()),
+            content_type: "image/x-icon".to_string(),
+            modified,
+        }),
+        Err(e) => {
+            error!("❌ Cannot read favicon_path {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// GET /robots.txt when robots_txt is configured: that text, cacheable for an hour.
+pub fn robots_txt(robots: &RobotsTxt) -> Response {
+    let text = match robots {
+        RobotsTxt::Inline(text) => text.clone(),
+        RobotsTxt::File { path } => match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("❌ Cannot read robots_txt file {}: {}", path, e);
+                return internal_server_error();
+            }
+        },
+    };
+    Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "public, max-age=3600")
+        .body("text/plain; charset=utf-8", text)
+}
+
+/// GET /echo: how to use it.
+pub fn echo_usage(_req: &Request) -> HandlerResult {
+    Ok(Response::new(HTTPStatus::Ok)
+        .body("text/plain; charset=utf-8", "POST a body to /echo and it is sent back unchanged, with the same Content-Type.\n"))
+}
+
+/// POST /echo: the request body, as it came.
+pub fn echo_body(req: &Request) -> HandlerResult {
+    let body = req.body.preview(req.body.len()).or_status(HTTPStatus::InternalServerError)?;
+    let content_type = req.header("Content-Type").unwrap_or("application/octet-stream");
+    Ok(Response::new(HTTPStatus::Ok).body(content_type, body))
+}
+
+/// GET /greet?name=Ada: a greeting. No (or an empty) name is the client's mistake: 400.
+pub fn greet(req: &Request) -> HandlerResult {
+    let name = req.query_pairs().into_iter()
+        .find(|(key, value)| key == "name" && !value.is_empty())
+        .map(|(_, value)| value)
+        .or_bad_request()?;
+    Ok(Response::new(HTTPStatus::Ok).body("text/plain; charset=utf-8", format!("Hello, {}!\n", name)))
+}
+
+/// /debug/panic: panics, to check that a failing handler is logged and answered with a 500. Only routed with debug_endpoints.
+pub(crate) fn panic_on_purpose(req: &Request) -> Response {
+    panic!("/debug/panic requested by {} {}", req.method, req.path);
+}
+
+/*
+A static file, sent byte-for-byte (binary files included) with the given Content-Type.
+Given the ranges of a Range header that applies (see conditionals::evaluate_preconditions()),
+only the requested bytes are sent: one range as a plain 206, several as a 206
+multipart/byteranges body, nothing satisfiable as 416. The whole file and a single range are
+sent from `contents` itself; only a multipart body is assembled anew.
+Returned unserialized so the caller can still attach headers (Content-Disposition, ...).
+*/
+pub(crate) fn file(contents: BodyBytes, content_type: &str, ranges: Option<&[RangeSpec]>) -> Response {
+    let length = contents.len() as u64;
+    let ranges = match ranges {
+        Some(specs) => resolve_ranges(specs, length),
+        None => RangeRequest::Full,
+    };
+
+    match ranges {
+        RangeRequest::Full => Response::new(HTTPStatus::Ok).body(content_type, contents),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let (first, last) = ranges[0];
+            Response::new(HTTPStatus::PartialContent)
+                .header("Content-Range", &content_range(first, last, length))
+                .body(content_type, contents.slice(first as usize..last as usize + 1))
+        }
+        RangeRequest::Partial(ranges) => {
+            let boundary = new_boundary();
+            let body = multipart_byteranges(&contents, &ranges, content_type, &boundary);
+            Response::new(HTTPStatus::PartialContent)
+                .body(&format!("multipart/byteranges; boundary={}", boundary), body)
+        }
+        RangeRequest::Unsatisfiable => {
+            Response::new(HTTPStatus::RangeNotSatisfiable)
+                .header("Content-Range", &format!("bytes */{}", length))
+                .body("text/plain", "416 Range Not Satisfiable")
+        }
+    }
+}
+
+/*
+/debug/echo: a plain-text dump of everything the server parsed out of the request. Only routed
+when debug_endpoints is on. The body is summarized by its length and at most
+ECHO_BODY_PREVIEW bytes, so the response is never much larger than the request head.
+With ?stream=1 the same text is streamed one line at a time (chunked, for HTTP/1.1 clients),
+followed by an X-Echo-Lines trailer with the number of lines.
+*/
+pub(crate) fn echo(req: &Request) -> Response {
+    let mut text = format!("method: {}\npath: {}\nversion: {}\nquery:\n", req.method, req.path, req.version);
+    for (name, value) in req.query_pairs() {
+        text.push_str(&format!("  {} = {}\n", name, value));
+    }
+    text.push_str("headers:\n");
+    for (name, value) in &req.headers {
+        text.push_str(&format!("  {}: {}\n", name, value));
+    }
+    let preview = req.body.preview(ECHO_BODY_PREVIEW).unwrap_or_default();
+    text.push_str(&format!("body length: {}\n", req.body.len()));
+    text.push_str(&format!("body (first {} bytes): {:?}\n", preview.len(), String::from_utf8_lossy(&preview)));
+
+    if req.has_query_flag("stream", "1") {
+        return Response::new(HTTPStatus::Ok)
+            .stream("text/plain; charset=utf-8", move |w| {
+                let mut lines = 0;
+                for line in text.split_inclusive('\n') {
+                    w.write_all(line.as_bytes())?;
+                    lines += 1;
+                }
+                w.trailer("X-Echo-Lines", &lines.to_string());
+                Ok(())
+            })
+            .header("Trailer", "X-Echo-Lines");
+    }
+    Response::new(HTTPStatus::Ok).body("text/plain; charset=utf-8", text)
+}
+
+// Answer to the server-wide "OPTIONS * HTTP/1.1" capabilities query: no body, just Allow.
+pub(crate) fn options_asterisk(allowed: &[&str]) -> Response {
+    Response::new(HTTPStatus::NoContent)
+        .header("Allow", &allowed.join(", "))
+}
+
+// GET /health: "ok", or "degraded" while in maintenance mode. Still a 200: the server is up.
+pub(crate) fn health(degraded: bool) -> Response {
+    Response::new(HTTPStatus::Ok)
+        .header("Cache-Control", "no-store")
+        .body("text/plain; charset=utf-8", if degraded { "degraded" } else { "ok" })
+}
+
+/// A new resource now exists at `location` (a URL path).
+pub fn created(location: &str) -> Response {
+    Response::new(HTTPStatus::Created)
+        .header("Location", location)
+        .body("text/plain", "201 Created")
+}
+
+/// 301: the resource lives at `location` (a full URL) from now on.
+pub fn moved_permanently(location: &str) -> Response {
+    Response::new(HTTPStatus::MovedPermanently)
+        .header("Location", location)
+        .body("text/plain", "301 Moved Permanently")
+}
+
+/// The request was taken on, but its effect (e.g. a shutdown) happens afterwards.
+pub fn accepted() -> Response {
+    Response::new(HTTPStatus::Accepted).body("text/plain", "202 Accepted")
+}
+
+/**
+The standard response for an error status: what a handler's Err(status) becomes, and the same
+thing the server itself sends for that status (401 keeps its challenge header).
+*/
+pub fn error(status: HTTPStatus) -> Response {
+    match status {
+        HTTPStatus::Unauthorized => unauthorized(),
+        _ => Response::new(status).body("text/plain", format!("{} {}", status as u16, status.reason_phrase())),
+    }
+}
+
+/// 204 No Content.
+pub fn no_content() -> Response {
+    Response::new(HTTPStatus::NoContent)
+}
+
+/// 400 Bad Request.
+pub fn bad_request() -> Response {
+    error(HTTPStatus::BadRequest)
+}
+
+/// No credentials at all. The challenge tells the client which scheme to use (RFC 6750).
+pub fn unauthorized() -> Response {
+    Response::new(HTTPStatus::Unauthorized)
+        .header("WWW-Authenticate", "Bearer")
+        .body("text/plain", "401 Unauthorized")
+}
+
+/// 403 Forbidden.
+pub fn forbidden() -> Response {
+    error(HTTPStatus::Forbidden)
+}
+
+/// 404 Not Found.
+pub fn not_found() -> Response {
+    error(HTTPStatus::NotFound)
+}
+
+/// RFC 7231 §6.5.5: a 405 must say which methods the resource does support.
+pub fn method_not_allowed(allowed: &[&str]) -> Response {
+    Response::new(HTTPStatus::MethodNotAllowed)
+        .header("Allow", &allowed.join(", "))
+        .body("text/plain", "405 Method Not Allowed")
 }
 
-pub fn about() -> Vec<u8> {
-    build_response(HTTPStatus::Ok, "OK", "text/html", "<h1>About us</h1>")
+/// 408 Request Timeout.
+pub fn request_timeout() -> Response {
+    error(HTTPStatus::RequestTimeout)
 }
 
-pub fn file(body: &str) -> Vec<u8> {
-    build_response(HTTPStatus::Ok, "OK", "text/html", body)
+/// 413 Content Too Large.
+pub fn content_too_large() -> Response {
+    error(HTTPStatus::ContentTooLarge)
 }
 
-pub fn bad_request() -> Vec<u8> {
-    build_response(HTTPStatus::BadRequest, "Bad Request", "text/plain", "400 Bad Request")
+/// 500 Internal Server Error.
+pub fn internal_server_error() -> Response {
+    error(HTTPStatus::InternalServerError)
 }
 
-pub fn not_found() -> Vec<u8> {
-    build_response(HTTPStatus::NotFound, "Not Found", "text/plain", "404 Not Found")
+/// 501 Not Implemented.
+pub fn not_implemented() -> Response {
+    error(HTTPStatus::NotImplemented)
 }
 
-pub fn method_not_allowed() -> Vec<u8> {
-    build_response(HTTPStatus::MethodNotAllowed, "Method Not Allowed", "text/plain", "405 Method Not Allowed")
+/// 412 Precondition Failed: an If-Match or If-Unmodified-Since didn't hold (see upload.rs).
+pub fn precondition_failed() -> Response {
+    error(HTTPStatus::PreconditionFailed)
 }
 
-pub fn request_timeout() -> Vec<u8> {
-    build_response(HTTPStatus::RequestTimeout, "Request Timeout", "text/plain", "408 Request Timeout")
+/// 421 Misdirected Request: the Host names a site this server doesn't serve (see enforce_host).
+pub fn misdirected_request() -> Response {
+    error(HTTPStatus::MisdirectedRequest)
 }
 
-pub fn content_too_large() -> Vec<u8> {
-    build_response(HTTPStatus::ContentTooLarge, "Content Too Large", "text/plain", "413 Content Too Large")
+/// 505 HTTP Version Not Supported.
+pub fn http_version_not_supported() -> Response {
+    error(HTTPStatus::HttpVersionNotSupported)
 }
 
-pub fn service_unavailable() -> Vec<u8> {
-    build_response(HTTPStatus::ServiceUnavailable, "Service Unavailable", "text/plain", "503 Service Unavailable")
+/// 503 with `message` as the body and Retry-After: come back in `retry_after_seconds`.
+pub fn unavailable(message: &str, retry_after_seconds: u64) -> Response {
+    Response::new(HTTPStatus::ServiceUnavailable)
+        .header("Retry-After", &retry_after_seconds.to_string())
+        .body("text/plain; charset=utf-8", message.to_string())
 }
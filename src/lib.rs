@@ -0,0 +1,48 @@
+/*!
+vibettp: a small HTTP/1.1 server on raw WinSock, as a library. The `vibettp` binary is a thin
+wrapper around server::run_with_default_config(); other programs can reuse the request parser,
+the responses and handlers, the router and the config loading on their own.
+*/
+
+// The API: what other crates may build on.
+pub mod request;
+pub mod response;
+pub mod handlers;
+pub mod config;
+pub mod router;
+pub mod server;
+pub mod log;
+// The binary's subcommands (`vibettp check`, `vibettp bench`).
+pub mod cli;
+pub mod loadgen;
+
+// Internals of the server loop and the static file path.
+mod util;
+mod dispatch;
+mod mime;
+mod range;
+mod conditionals;
+mod markdown;
+mod listing;
+mod compress;
+mod throttle;
+mod upload;
+mod static_cache;
+mod response_cache;
+mod canned;
+mod buffer_pool;
+mod stats;
+mod timing;
+mod workers;
+mod accept_queue;
+mod connections;
+mod proxy;
+mod admin;
+mod api_auth;
+mod panics;
+mod spool;
+mod access_log;
+mod dump;
+
+#[cfg(test)]
+mod benches;
@@ -0,0 +1,170 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::access_log::{iso8601, json_string};
+use crate::response::{HTTPStatus, Response};
+use crate::util::escape_html;
+
+/*
+Directory listings (directory_listing = true): what a directory without an index file answers
+instead of a 404. There is one scan and one entry list behind both formats, so they can't
+disagree: an HTML page for browsers, or, for a client whose Accept prefers application/json, an
+array of {name, size, modified, is_dir} objects. Entries the static path would refuse to serve
+(dotfiles, denied extensions, the config file) are left out of both.
+*/
+pub struct Entry {
+    pub name: String,
+    // In bytes; 0 for a directory.
+    pub size: u64,
+    pub modified: SystemTime,
+    pub is_dir: bool,
+}
+
+/*
+The listing of `dir`, requested as `url_path`, as JSON or HTML. `hidden` gets each entry's URL
+path and file, and says whether to leave it out. None if the directory can't be read.
+*/
+pub fn listing<F>(dir: &Path, url_path: &str, json: bool, hidden: F) -> Option<Response>
+where
+    F: Fn(&str, &Path) -> bool,
+{
+    let base = if url_path.ends_with('/') { url_path.to_string() } else { format!("{}/", url_path) };
+    let entries = scan(dir, &base, hidden).ok()?;
+    let response = match json {
+        true => Response::new(HTTPStatus::Ok).body("application/json", to_json(&entries)),
+        false => Response::new(HTTPStatus::Ok).body("text/html; charset=utf-8", to_html(&entries, &base)),
+    };
+    return Some(response.vary("Accept"));
+}
+
+// The entries of `dir` that aren't hidden, directories first, then by name (ignoring case).
+fn scan<F>(dir: &Path, base: &str, hidden: F) -> io::Result<Vec<Entry>>
+where
+    F: Fn(&str, &Path) -> bool,
+{
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        // A name that isn't valid UTF-8 couldn't be requested by its URL anyway.
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let path = entry.path();
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if hidden(&format!("{}{}", base, name), &path) {
+            continue;
+        }
+        entries.push(Entry {
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            is_dir: metadata.is_dir(),
+            name,
+        });
+    }
+    entries.sort_by(|a, b| (!a.is_dir, a.name.to_lowercase(), &a.name).cmp(&(!b.is_dir, b.name.to_lowercase(), &b.name)));
+    return Ok(entries);
+}
+
+fn to_json(entries: &[Entry]) -> String {
+    let objects: Vec<String> = entries.iter().map(|entry| format!(
+        "{{\"name\":{},\"size\":{},\"modified\":{},\"is_dir\":{}}}",
+        json_string(Some(&entry.name)), entry.size, json_string(Some(&iso8601(entry.modified))), entry.is_dir
+    )).collect();
+    return format!("[{}]", objects.join(","));
+}
+
+fn to_html(entries: &[Entry], base: &str) -> String {
+    let title = format!("Index of {}", escape_html(base));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<table>\n",
+        title, title
+    );
+    if base != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&encode_path(&format!("{}{}", base, entry.name))), slash, escape_html(&entry.name), slash,
+            if entry.is_dir { "-".to_string() } else { entry.size.to_string() }, iso8601(entry.modified)
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    return html;
+}
+
+// `path` percent-encoded for an href: everything but unreserved characters and the '/' separators.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    return encoded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::temp_root;
+
+    // The names a listing shows, in order, whichever format it is in (directories without their '/').
+    fn names(response: &Response) -> Vec<String> {
+        let body = String::from_utf8_lossy(&response.body);
+        if response.header_value("Content-Type") == Some("application/json") {
+            return body.split("{\"name\":\"").skip(1)
+                .map(|rest| rest.split("\",\"size\"").next().unwrap().replace("\\\"", "\""))
+                .collect();
+        }
+        return body.split("<tr><td><a href=\"").skip(1)
+            .map(|rest| rest.split_once("\">").unwrap().1.split("</a>").next().unwrap().replace("&amp;", "&").replace("&#39;", "'"))
+            .filter(|name| name != "../")
+            .map(|name| name.trim_end_matches('/').to_string())
+            .collect();
+    }
+
+    #[test]
+    fn test_json_and_html_agree() {
+        let dir = temp_root("listing");
+        fs::create_dir(dir.join("zeta")).unwrap();
+        fs::create_dir(dir.join("Alpha")).unwrap();
+        fs::write(dir.join("b.txt"), "four").unwrap();
+        fs::write(dir.join("R&D 'notes'.txt"), "").unwrap();
+        fs::write(dir.join("a.txt"), "1").unwrap();
+        fs::write(dir.join(".secret"), "hidden").unwrap();
+        let hidden = |url: &str, _: &Path| url.contains("/.");
+
+        let json = listing(&dir, "/files", true, hidden).unwrap();
+        let html = listing(&dir, "/files", false, hidden).unwrap();
+        assert_eq!(json.header_value("Vary"), Some("Accept"));
+        assert_eq!(html.header_value("Content-Type"), Some("text/html; charset=utf-8"));
+        assert_eq!(names(&json), ["Alpha", "zeta", "a.txt", "b.txt", "R&D 'notes'.txt"]);
+        assert_eq!(names(&json), names(&html));
+
+        let body = String::from_utf8(html.body.into_vec()).unwrap();
+        assert!(body.contains("<a href=\"/files/R%26D%20%27notes%27.txt\">R&amp;D &#39;notes&#39;.txt</a>"), "{}", body);
+        assert!(body.contains("<a href=\"/files/zeta/\">zeta/</a></td><td>-</td>"), "{}", body);
+        let body = String::from_utf8(json.body.into_vec()).unwrap();
+        assert!(body.starts_with("[{\"name\":\"Alpha\",\"size\":0,\"modified\":\"") && body.ends_with("\"is_dir\":false}]"), "{}", body);
+        assert!(body.contains("{\"name\":\"b.txt\",\"size\":4,"), "{}", body);
+        assert!(listing(&dir.join("missing"), "/missing", true, hidden).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_json_escaping() {
+        let entry = |name: &str| Entry { name: name.to_string(), size: 1, modified: UNIX_EPOCH, is_dir: false };
+        assert_eq!(
+            to_json(&[entry("say \"hi\"\\now\t.txt")]),
+            "[{\"name\":\"say \\\"hi\\\"\\\\now\\t.txt\",\"size\":1,\"modified\":\"1970-01-01T00:00:00Z\",\"is_dir\":false}]"
+        );
+        assert_eq!(to_json(&[]), "[]");
+    }
+}
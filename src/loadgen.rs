@@ -0,0 +1,345 @@
+/*
+`vibettp bench`: a small load generator, for sizing max_clients and max_worker_threads against
+a running server (this one or any other plain-HTTP server):
+
+    vibettp bench --url http://127.0.0.1:7878/ --connections 50 --duration 10s [--no-keep-alive]
+
+Each connection is a thread sending GETs one after the other, each as soon as the previous
+response has been read, until the time is up. Then it prints requests/sec, how many responses
+had each status code, and latency percentiles.
+*/
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::ConfigDuration;
+use crate::panics;
+
+/// Printed when the arguments don't make sense.
+pub const USAGE: &str = "usage: vibettp bench --url http://host[:port]/path [--connections N] [--duration 10s] [--no-keep-alive]";
+
+// No response within this long counts as an error, and the connection is dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What `vibettp bench` was asked to do.
+#[derive(Debug, PartialEq)]
+pub struct BenchOptions {
+    /// Host to connect to, as given in the URL.
+    pub host: String,
+    /// Port to connect to (80 if the URL has none).
+    pub port: u16,
+    /// Path and query to request, e.g. "/index.html".
+    pub path: String,
+    /// Concurrent connections, one thread each.
+    pub connections: usize,
+    /// How long to keep sending requests.
+    pub duration: Duration,
+    /// Send every request on the same connection (until the server closes it), or a new one each time.
+    pub keep_alive: bool,
+}
+
+/// The arguments after "bench".
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<BenchOptions, String> {
+    let mut url = None;
+    let mut connections = 10;
+    let mut duration = Duration::from_secs(10);
+    let mut keep_alive = true;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "--url" => url = Some(value("--url")?),
+            "--connections" | "-c" => {
+                connections = value("--connections")?.parse().map_err(|_| "--connections must be a number".to_string())?;
+            }
+            "--duration" | "-d" => duration = ConfigDuration::parse(&value("--duration")?)?.0,
+            "--no-keep-alive" => keep_alive = false,
+            other => return Err(format!("unknown argument {:?}", other)),
+        }
+    }
+
+    let (host, port, path) = parse_url(&url.ok_or("--url is required")?)?;
+    if connections == 0 {
+        return Err("--connections must be at least 1".to_string());
+    }
+    if duration.is_zero() {
+        return Err("--duration must be longer than 0".to_string());
+    }
+    return Ok(BenchOptions { host, port, path, connections, duration, keep_alive });
+}
+
+// "http://host[:port][/path]" → (host, port, path). Only plain http: the server has no TLS.
+fn parse_url(url: &str) -> Result<(String, u16, String), String> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| format!("{:?}: only http:// URLs are supported", url))?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => (&rest[..slash], &rest[slash..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("{:?}: invalid port", url))?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(format!("{:?}: no host", url));
+    }
+    return Ok((host.to_string(), port, path.to_string()));
+}
+
+/**
+Read one response: the status code, and whether the server is closing the connection after it.
+Understands the two framings a simple server uses: Content-Length, or none at all (the body
+runs until the connection closes). A chunked body is an error.
+*/
+pub fn read_response<R: BufRead>(reader: &mut R) -> io::Result<(u16, bool)> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidData, why.to_string());
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before a response"));
+    }
+    let mut parts = line.split_whitespace();
+    let version = parts.next().unwrap_or_default().to_string();
+    let status: u16 = parts.next().and_then(|code| code.parse().ok()).ok_or_else(|| invalid("malformed status line"))?;
+
+    let mut content_length = None;
+    let mut close = version == "HTTP/1.0";
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("connection closed inside the response head"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid("malformed header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.parse::<u64>().map_err(|_| invalid("invalid Content-Length"))?);
+        } else if name.eq_ignore_ascii_case("Connection") {
+            close = value.eq_ignore_ascii_case("close");
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(invalid("chunked responses are not supported"));
+        }
+    }
+
+    // No body with a 1xx, 204 or 304; otherwise Content-Length bytes, or everything until the close.
+    let bodyless = status < 200 || status == 204 || status == 304;
+    match content_length {
+        _ if bodyless => {}
+        Some(length) => {
+            let copied = io::copy(&mut reader.take(length), &mut io::sink())?;
+            if copied < length {
+                return Err(invalid("connection closed inside the body"));
+            }
+        }
+        None => {
+            io::copy(reader, &mut io::sink())?;
+            close = true;
+        }
+    }
+    return Ok((status, close));
+}
+
+// What one connection's thread saw.
+#[derive(Default)]
+struct Tally {
+    // Status code and latency of every response.
+    responses: Vec<(u16, Duration)>,
+    // Failed connects, timeouts, malformed responses.
+    errors: usize,
+}
+
+fn run_connection(options: &BenchOptions, deadline: Instant) -> Tally {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: vibettp-bench\r\nConnection: {}\r\n\r\n",
+        options.path, options.host, options.port, if options.keep_alive { "keep-alive" } else { "close" }
+    );
+    let mut tally = Tally::default();
+    let mut connection: Option<BufReader<TcpStream>> = None;
+
+    while Instant::now() < deadline {
+        if connection.is_none() {
+            match TcpStream::connect((options.host.as_str(), options.port)) {
+                Ok(stream) => {
+                    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+                    let _ = stream.set_nodelay(true);
+                    connection = Some(BufReader::new(stream));
+                }
+                Err(_) => {
+                    tally.errors += 1;
+                    // Don't spin on a server that isn't there.
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            }
+        }
+        let Some(reader) = connection.as_mut() else {
+            continue;
+        };
+
+        let start = Instant::now();
+        let result = reader.get_mut().write_all(request.as_bytes()).and_then(|_| read_response(reader));
+        match result {
+            Ok((status, close)) => {
+                tally.responses.push((status, start.elapsed()));
+                if close || !options.keep_alive {
+                    connection = None;
+                }
+            }
+            Err(_) => {
+                tally.errors += 1;
+                connection = None;
+            }
+        }
+    }
+    return tally;
+}
+
+// The latency below which `percent` of the (sorted) samples fall, nearest-rank.
+fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((percent / 100.0) * sorted.len() as f64).ceil() as usize;
+    return sorted[rank.clamp(1, sorted.len()) - 1];
+}
+
+/// Run the load test and print the results.
+pub fn run(options: &BenchOptions) {
+    println!(
+        "🏋️ {} connection(s) to http://{}:{}{} for {:?}{}...",
+        options.connections, options.host, options.port, options.path, options.duration,
+        if options.keep_alive { "" } else { " (a new connection per request)" }
+    );
+    let start = Instant::now();
+    let deadline = start + options.duration;
+    let tallies: Vec<Tally> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..options.connections)
+            .map(|_| scope.spawn(|| run_connection(options, deadline)))
+            .collect();
+        handles.into_iter().map(|handle| match handle.join() {
+            Ok(tally) => tally,
+            // Its responses are lost; say so rather than report a quietly smaller run.
+            Err(payload) => {
+                eprintln!("💥 A connection thread panicked: {}", panics::message(&*payload));
+                Tally::default()
+            }
+        }).collect()
+    });
+    let elapsed = start.elapsed();
+
+    let mut statuses: BTreeMap<u16, usize> = BTreeMap::new();
+    let mut latencies = Vec::new();
+    let mut errors = 0;
+    for tally in tallies {
+        errors += tally.errors;
+        for (status, latency) in tally.responses {
+            *statuses.entry(status).or_default() += 1;
+            latencies.push(latency);
+        }
+    }
+    latencies.sort();
+
+    println!("📊 {} responses in {:.2}s: {:.1} requests/sec, {} errors",
+        latencies.len(), elapsed.as_secs_f64(), latencies.len() as f64 / elapsed.as_secs_f64(), errors);
+    for (status, count) in &statuses {
+        println!("   {}: {}", status, count);
+    }
+    if !latencies.is_empty() {
+        println!("⏱️ Latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+            percentile(&latencies, 50.0), percentile(&latencies, 90.0),
+            percentile(&latencies, 99.0), latencies[latencies.len() - 1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        return list.iter().map(|s| s.to_string()).collect();
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let options = parse_args(args(&["--url", "http://127.0.0.1:7878/a?b=1", "--connections", "50", "--duration", "10s"])).unwrap();
+        assert_eq!(options, BenchOptions {
+            host: "127.0.0.1".to_string(),
+            port: 7878,
+            path: "/a?b=1".to_string(),
+            connections: 50,
+            duration: Duration::from_secs(10),
+            keep_alive: true,
+        });
+        let options = parse_args(args(&["--url", "http://localhost", "-d", "500ms", "--no-keep-alive"])).unwrap();
+        assert_eq!((options.port, options.path.as_str(), options.duration, options.keep_alive), (80, "/", Duration::from_millis(500), false));
+
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["--url", "https://example.com/"])).is_err());
+        assert!(parse_args(args(&["--url", "http://:80/"])).is_err());
+        assert!(parse_args(args(&["--url", "http://localhost", "--connections", "0"])).is_err());
+        assert!(parse_args(args(&["--url", "http://localhost", "--duration"])).is_err());
+        assert!(parse_args(args(&["--url", "http://localhost", "--fast"])).is_err());
+    }
+
+    #[test]
+    fn test_read_response_framing() {
+        // Two keep-alive responses back to back, then one delimited by the close.
+        let wire = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello\
+                    HTTP/1.1 204 No Content\r\n\r\n\
+                    HTTP/1.1 404 Not Found\r\nConnection: close\r\n\r\nuntil the end";
+        let mut reader = Cursor::new(wire.as_bytes());
+        assert_eq!(read_response(&mut reader).unwrap(), (200, false));
+        assert_eq!(read_response(&mut reader).unwrap(), (204, false));
+        assert_eq!(read_response(&mut reader).unwrap(), (404, true));
+        assert!(read_response(&mut reader).is_err());
+
+        let mut reader = Cursor::new(&b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok"[..]);
+        assert_eq!(read_response(&mut reader).unwrap(), (200, true));
+        let mut reader = Cursor::new(&b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort"[..]);
+        assert!(read_response(&mut reader).is_err());
+        let mut reader = Cursor::new(&b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"[..]);
+        assert!(read_response(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_run_connection_against_a_listener() {
+        // A stand-in server: answers every request on its one connection with a 200.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                if line == "\r\n" && reader.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").is_err() {
+                    break;
+                }
+                line.clear();
+            }
+        });
+
+        let options = parse_args(args(&["--url", &format!("http://127.0.0.1:{}/", port), "-c", "1", "-d", "200ms"])).unwrap();
+        let tally = run_connection(&options, Instant::now() + options.duration);
+        assert!(tally.responses.len() > 1, "only {} responses", tally.responses.len());
+        assert!(tally.responses.iter().all(|(status, _)| *status == 200));
+        assert_eq!(tally.errors, 0);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&sorted[..1], 90.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use serde::Deserialize;
+
+/**
+How chatty the server is. Each level includes the ones above it:
+- error: something failed (bind, a send, a disk write),
+- warn: something suspicious or refused for policy reasons,
+- info: connection lifecycle and one access-log line per request (the default),
+- debug: per-request details (parsed request line, raw request dump, rewrites),
+- trace: step-by-step tracing such as sanitize_path()'s.
+
+error and warn go to stderr, the rest to stdout.
+*/
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    #[default]
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+/**
+How messages look. "emoji" prints them as written ("📡 Client #1 connected ..."); "plain" swaps
+the emoji prefix for a bracketed tag ("[CONN] Client #1 connected ...") and the odd arrow for
+ASCII, for consoles that can't show emoji and for log pipelines. "auto" picks emoji only when
+stdout is a console that can display them (on Windows: set to the UTF-8 code page).
+*/
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Style {
+    #[default]
+    Auto,
+    Emoji,
+    Plain,
+}
+
+// Tags for emoji that mark a kind of event rather than a level; any other prefix becomes the level's tag.
+const TAGS: [(&str, &str); 7] = [
+    ("📡", "[CONN]"),
+    ("🔌", "[CONN]"),
+    ("📝", "[ACCESS]"),
+    ("⏱️", "[TIMEOUT]"),
+    ("💤", "[TIMEOUT]"),
+    ("🚫", "[DENY]"),
+    ("⛔️", "[DENY]"),
+];
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Log messages at `level` and more severe from now on.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Choose how messages look (see Style); auto is decided here, once.
+pub fn set_style(style: Style) {
+    let plain = match style {
+        Style::Auto => !console_shows_emoji(),
+        Style::Emoji => false,
+        Style::Plain => true,
+    };
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+fn console_shows_emoji() -> bool {
+    if !io::stdout().is_terminal() {
+        return false;
+    }
+    #[cfg(windows)]
+    {
+        // Unsafe: FFI call without arguments. 65001 is the UTF-8 code page.
+        return unsafe { windows_sys::Win32::System::Console::GetConsoleOutputCP() } == 65001;
+    }
+    #[cfg(not(windows))]
+    return true;
+}
+
+// The text actually printed for `message` at `level`, in emoji or plain style.
+pub(crate) fn render(level: Level, message: &str, plain: bool) -> String {
+    if !plain {
+        return message.to_string();
+    }
+    let tag = TAGS.iter()
+        .find(|(emoji, _)| message.starts_with(emoji))
+        .map(|(_, tag)| *tag)
+        .unwrap_or(match level {
+            Level::Error => "[ERROR]",
+            Level::Warn => "[WARN]",
+            Level::Info => "[INFO]",
+            Level::Debug => "[DEBUG]",
+            Level::Trace => "[TRACE]",
+        });
+    // The prefix is everything before the first ASCII character (emoji plus variation selectors).
+    let text = message.trim_start_matches(|c: char| !c.is_ascii()).trim_start();
+    return format!("{} {}", tag, text.replace('→', "->").replace('—', "-"));
+}
+
+// True if messages are printed in the plain style (see set_style()).
+pub(crate) fn plain() -> bool {
+    return PLAIN.load(Ordering::Relaxed);
+}
+
+// True if messages at `level` are currently printed.
+pub(crate) fn enabled(level: Level) -> bool {
+    return level as u8 <= LEVEL.load(Ordering::Relaxed);
+}
+
+/**
+The level asked for on the command line, if any: --quiet (or -q) for errors only, -v for debug,
+-vv for trace. It takes precedence over log_level in the config file.
+*/
+pub fn level_from_args<I: IntoIterator<Item = String>>(args: I) -> Option<Level> {
+    let mut level = None;
+    for arg in args {
+        match arg.as_str() {
+            "--quiet" | "-q" => level = Some(Level::Error),
+            "-v" | "--verbose" => level = Some(Level::Debug),
+            "-vv" => level = Some(Level::Trace),
+            other => eprintln!("⚠️ Ignoring unknown argument {:?}", other),
+        }
+    }
+    return level;
+}
+
+// Behind the macros below. Don't call directly.
+pub(crate) fn write(level: Level, args: fmt::Arguments) {
+    #[cfg(not(test))]
+    let shown = enabled(level);
+    #[cfg(test)]
+    let shown = capture::level().map_or(enabled(level), |max| level <= max);
+    if !shown {
+        return;
+    }
+    let line = render(level, &args.to_string(), PLAIN.load(Ordering::Relaxed));
+    #[cfg(test)]
+    if capture::record(level, &line) {
+        return;
+    }
+    if level <= Level::Warn {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+// Named `warning` here: a local `warn` would clash with the built-in #[warn] attribute.
+macro_rules! warning {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+macro_rules! trace {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Trace, format_args!($($arg)*)) };
+}
+pub(crate) use {error, warning as warn, info, debug, trace};
+
+/*
+Output hook for tests: while capture() runs its closure, whatever the current thread logs (and
+the level lets through) is collected instead of printed. capture_at() collects at a level of its
+own instead of the global one, which tests running in parallel must leave alone.
+*/
+#[cfg(test)]
+pub(crate) mod capture {
+    use std::cell::RefCell;
+
+    use super::Level;
+
+    type Captured = (Option<Level>, Vec<(Level, String)>);
+
+    thread_local! {
+        static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+    }
+
+    pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<(Level, String)>) {
+        return collect(None, f);
+    }
+
+    pub fn capture_at<T>(level: Level, f: impl FnOnce() -> T) -> (T, Vec<(Level, String)>) {
+        return collect(Some(level), f);
+    }
+
+    fn collect<T>(level: Option<Level>, f: impl FnOnce() -> T) -> (T, Vec<(Level, String)>) {
+        CAPTURED.with(|c| *c.borrow_mut() = Some((level, Vec::new())));
+        let result = f();
+        let (_, lines) = CAPTURED.with(|c| c.borrow_mut().take()).unwrap_or_default();
+        return (result, lines);
+    }
+
+    // The level set by capture_at() on this thread, if one is running.
+    pub(super) fn level() -> Option<Level> {
+        return CAPTURED.with(|c| c.borrow().as_ref().and_then(|(level, _)| *level));
+    }
+
+    pub(super) fn record(level: Level, line: &str) -> bool {
+        return CAPTURED.with(|c| match c.borrow_mut().as_mut() {
+            Some((_, lines)) => {
+                lines.push((level, line.to_string()));
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_from_args() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(level_from_args(args(&[])), None);
+        assert_eq!(level_from_args(args(&["--quiet"])), Some(Level::Error));
+        assert_eq!(level_from_args(args(&["-v"])), Some(Level::Debug));
+        assert_eq!(level_from_args(args(&["-vv"])), Some(Level::Trace));
+    }
+
+    #[test]
+    fn test_render_styles() {
+        let cases = [
+            (Level::Info, "📡 Client #3 connected from 10.0.0.2:5000.", "[CONN] Client #3 connected from 10.0.0.2:5000."),
+            (Level::Info, "⏱️ Timeout waiting for the request body.", "[TIMEOUT] Timeout waiting for the request body."),
+            (Level::Warn, "⚠️ Dropping trailer X: not declared", "[WARN] Dropping trailer X: not declared"),
+            (Level::Error, "❌ Upload to \"a\" failed", "[ERROR] Upload to \"a\" failed"),
+            (Level::Debug, "↪️ Rewrote /a → /b", "[DEBUG] Rewrote /a -> /b"),
+            (Level::Error, "Accept failed", "[ERROR] Accept failed"),
+        ];
+        for (level, message, plain) in cases {
+            assert_eq!(render(level, message, false), message);
+            assert_eq!(render(level, message, true), plain);
+        }
+    }
+
+    #[test]
+    fn test_level_order() {
+        assert!(Level::Error < Level::Warn && Level::Info < Level::Trace);
+        let parsed: Level = toml::from_str::<toml::Value>("level = \"debug\"").unwrap()["level"].clone().try_into().unwrap();
+        assert_eq!(parsed, Level::Debug);
+    }
+}
@@ -1,14 +1,10 @@
-// Declare modules
-mod winsock;
-mod util;
-mod response;
-mod request;
-mod handlers;
-mod config;
-
-use winsock::run_server;
-
+// The vibettp binary. Everything it does lives in the library (src/lib.rs).
 fn main() {
-    // Start the raw Winsock server
-    run_server();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // `vibettp check ...` and `vibettp bench ...` are tools; anything else starts the server.
+    if let Some(code) = vibettp::cli::run_subcommand(&args) {
+        std::process::exit(code);
+    }
+    vibettp::server::run_with_default_config();
 }
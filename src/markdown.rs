@@ -0,0 +1,460 @@
+/*
+A small CommonMark subset, enough for notes kept next to the site: ATX headings, paragraphs,
+flat bullet and numbered lists, fenced and indented code blocks, and inline code spans,
+*emphasis*, **strong** and [links](url). No raw HTML, tables, block quotes or nesting: anything
+else is ordinary text. Every bit of text is HTML-escaped, so a note containing <script> is shown,
+never run, and a link only becomes one for http(s), mailto or a relative URL (javascript: isn't).
+*/
+
+use crate::util::escape_html;
+
+// `markdown` as a complete HTML page titled `title` (the file name, say).
+pub fn render_page(markdown: &str, title: &str) -> String {
+    return format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title), to_html(markdown)
+    );
+}
+
+// The blocks of `markdown` as HTML, one per line (code blocks keep their own line breaks).
+pub fn to_html(markdown: &str) -> String {
+    let mut blocks = Blocks { html: String::new(), paragraph: Vec::new(), list: None };
+    let mut lines = markdown.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() {
+            blocks.end_paragraph();
+            blocks.end_list();
+            continue;
+        }
+
+        // Four spaces (or a tab) start a code block, except where they continue a paragraph or item.
+        if let Some(code) = code_indented(line) && blocks.paragraph.is_empty() && blocks.list.is_none() {
+            let mut text = format!("{}\n", code);
+            while let Some(code) = lines.peek().and_then(|next| code_indented(next)) {
+                text.push_str(code);
+                text.push('\n');
+                lines.next();
+            }
+            blocks.html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_html(&text)));
+            continue;
+        }
+
+        let content = line.trim_start_matches(' ');
+        if line.len() - content.len() < 4 && let Some(fence) = fence(content) {
+            blocks.end_paragraph();
+            blocks.end_list();
+            let info = content[fence.len()..].trim();
+            // An unclosed fence runs to the end of the document.
+            let mut text = String::new();
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with(fence) {
+                    break;
+                }
+                text.push_str(line);
+                text.push('\n');
+            }
+            match info.split_whitespace().next() {
+                Some(language) => blocks.html.push_str(&format!(
+                    "<pre><code class=\"language-{}\">{}</code></pre>\n", escape_html(language), escape_html(&text)
+                )),
+                None => blocks.html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_html(&text))),
+            }
+            continue;
+        }
+
+        if let Some((level, text)) = heading(content) {
+            blocks.end_paragraph();
+            blocks.end_list();
+            blocks.html.push_str(&format!("<h{}>{}</h{}>\n", level, inline(text), level));
+            continue;
+        }
+
+        if let Some((kind, start, text)) = list_item(content) {
+            blocks.end_paragraph();
+            match &mut blocks.list {
+                Some(list) if list.kind == kind => list.items.push(text.to_string()),
+                _ => {
+                    blocks.end_list();
+                    blocks.list = Some(List { kind, start, items: vec![text.to_string()] });
+                }
+            }
+            continue;
+        }
+
+        // Anything else continues the open list item, or the paragraph.
+        match &mut blocks.list {
+            Some(list) => {
+                let item = list.items.last_mut().expect("a list always has an item");
+                item.push('\n');
+                item.push_str(line.trim());
+            }
+            None => blocks.paragraph.push(line.trim()),
+        }
+    }
+
+    blocks.end_paragraph();
+    blocks.end_list();
+    return blocks.html;
+}
+
+struct List {
+    // "ul" or "ol".
+    kind: &'static str,
+    // Number of an ordered list's first item.
+    start: u32,
+    // Each item's text, continuation lines included.
+    items: Vec<String>,
+}
+
+// The output so far, and the paragraph or list still being collected.
+struct Blocks<'a> {
+    html: String,
+    paragraph: Vec<&'a str>,
+    list: Option<List>,
+}
+
+impl Blocks<'_> {
+    fn end_paragraph(&mut self) {
+        if !self.paragraph.is_empty() {
+            self.html.push_str(&format!("<p>{}</p>\n", inline(&self.paragraph.join("\n"))));
+            self.paragraph.clear();
+        }
+    }
+
+    fn end_list(&mut self) {
+        let Some(list) = self.list.take() else {
+            return;
+        };
+        match list.start {
+            1 => self.html.push_str(&format!("<{}>\n", list.kind)),
+            start => self.html.push_str(&format!("<{} start=\"{}\">\n", list.kind, start)),
+        }
+        for item in &list.items {
+            self.html.push_str(&format!("<li>{}</li>\n", inline(item)));
+        }
+        self.html.push_str(&format!("</{}>\n", list.kind));
+    }
+}
+
+// The rest of a line indented as code (four spaces or a tab), if it is.
+fn code_indented(line: &str) -> Option<&str> {
+    return line.strip_prefix("    ").or_else(|| line.strip_prefix('\t'));
+}
+
+// The fence a line opens a fenced code block with: three or more backticks or tildes.
+fn fence(line: &str) -> Option<&str> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.len() - line.trim_start_matches(marker).len();
+    if length < 3 {
+        return None;
+    }
+    return Some(&line[..length]);
+}
+
+// "## Title ##" → (2, "Title"). One to six #s, then a space or nothing.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let text = line.trim_start_matches('#');
+    let level = line.len() - text.len();
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with([' ', '\t'])) {
+        return None;
+    }
+    // An optional closing run of #s, when a space sets it apart.
+    let text = text.trim();
+    let unclosed = text.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        return Some((level, unclosed.trim_end()));
+    }
+    return Some((level, text));
+}
+
+// "- item" / "* item" / "+ item" → ("ul", 1, "item"); "3. item" / "3) item" → ("ol", 3, "item").
+fn list_item(line: &str) -> Option<(&'static str, u32, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(bullet) {
+            return Some(("ul", 1, text.trim()));
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if !(1..=9).contains(&digits) {
+        return None;
+    }
+    let text = line[digits..].strip_prefix(['.', ')'])?.strip_prefix(' ')?;
+    return Some(("ol", line[..digits].parse().ok()?, text.trim()));
+}
+
+// Inline markup of a paragraph, heading or list item, as HTML.
+fn inline(text: &str) -> String {
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut runs: Vec<Run> = Vec::new();
+    let mut previous = None;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        // Emphasis is only known once every run has been seen: see pair_runs().
+        if c == '*' || c == '_' {
+            let length = rest.len() - rest.trim_start_matches(c).len();
+            runs.push(Run::new(c, length, previous, rest[length..].chars().next()));
+            pieces.push(Piece::Run(runs.len() - 1));
+            previous = Some(c);
+            rest = &rest[length..];
+            continue;
+        }
+        let markup = match c {
+            '\\' => rest[1..].chars().next()
+                .filter(|next| next.is_ascii_punctuation())
+                .map(|next| (escape_html(&next.to_string()), 2)),
+            '`' => code_span(rest),
+            '[' => link(rest),
+            _ => None,
+        };
+        let (markup, consumed) = markup.unwrap_or_else(|| (escape_html(&rest[..c.len_utf8()]), c.len_utf8()));
+        match pieces.last_mut() {
+            Some(Piece::Html(html)) => html.push_str(&markup),
+            _ => pieces.push(Piece::Html(markup)),
+        }
+        previous = rest[..consumed].chars().last();
+        rest = &rest[consumed..];
+    }
+
+    pair_runs(&mut runs);
+    let mut html = String::new();
+    for piece in &pieces {
+        match piece {
+            Piece::Html(markup) => html.push_str(markup),
+            Piece::Run(i) => {
+                let run = &runs[*i];
+                html.push_str(&run.closes);
+                html.extend(std::iter::repeat_n(run.marker, run.left));
+                html.push_str(&run.opens);
+            }
+        }
+    }
+    return html;
+}
+
+// What inline() collects: finished HTML, or a run of `*` / `_` (an index into its runs).
+enum Piece {
+    Html(String),
+    Run(usize),
+}
+
+/*
+A run of `*` or `_`. It can open emphasis unless a space follows it, and close it unless a space
+precedes it; `_` also doesn't work inside words (snake_case).
+*/
+struct Run {
+    marker: char,
+    // Characters not used up by emphasis: they are shown as they are.
+    left: usize,
+    can_open: bool,
+    can_close: bool,
+    // Closing tags before the characters left, and opening tags after them.
+    closes: String,
+    opens: String,
+}
+
+impl Run {
+    fn new(marker: char, length: usize, previous: Option<char>, next: Option<char>) -> Run {
+        let in_word = |c: Option<char>| marker == '_' && c.is_some_and(char::is_alphanumeric);
+        return Run {
+            marker,
+            left: length,
+            can_open: next.is_some_and(|c| !c.is_whitespace()) && !in_word(previous),
+            can_close: previous.is_some_and(|c| !c.is_whitespace()) && !in_word(next),
+            closes: String::new(),
+            opens: String::new(),
+        };
+    }
+}
+
+/*
+*em* / _em_ and **strong** / __strong__: each run that can close takes the nearest open run of
+its marker, two characters at a time (strong) while both have two, else one (em), as often as
+both have some left. Runs of the other marker opened in between stay text. A stack of open runs
+per marker means every run is pushed and popped at most once, so a line full of unmatched
+asterisks costs no more than any other.
+*/
+fn pair_runs(runs: &mut [Run]) {
+    let mut open: [Vec<usize>; 2] = [Vec::new(), Vec::new()];
+    for closer in 0..runs.len() {
+        let stack = usize::from(runs[closer].marker == '_');
+        if runs[closer].can_close {
+            while let Some(&opener) = open[stack].last() {
+                while open[1 - stack].last().is_some_and(|&other| other > opener) {
+                    open[1 - stack].pop();
+                }
+                let width = if runs[opener].left >= 2 && runs[closer].left >= 2 { 2 } else { 1 };
+                let tag = if width == 2 { "strong" } else { "em" };
+                runs[opener].left -= width;
+                runs[opener].opens.insert_str(0, &format!("<{}>", tag));
+                runs[closer].left -= width;
+                runs[closer].closes.push_str(&format!("</{}>", tag));
+                if runs[opener].left == 0 {
+                    open[stack].pop();
+                }
+                if runs[closer].left == 0 {
+                    break;
+                }
+            }
+        }
+        if runs[closer].can_open && runs[closer].left > 0 {
+            open[stack].push(closer);
+        }
+    }
+}
+
+// `code` at the start of `text`: the HTML and how many bytes it took. Unmatched backticks are text.
+fn code_span(text: &str) -> Option<(String, usize)> {
+    let ticks = text.len() - text.trim_start_matches('`').len();
+    let end = text[ticks..].find(&text[..ticks])?;
+    let code = &text[ticks..ticks + end];
+    // One space on each side is padding, so `` `x` `` can show backticks.
+    let code = match code.strip_prefix(' ').and_then(|code| code.strip_suffix(' ')) {
+        Some(inner) if !inner.trim().is_empty() => inner,
+        _ => code,
+    };
+    return Some((format!("<code>{}</code>", escape_html(&code.replace('\n', " "))), ticks + end + ticks));
+}
+
+// [label](url) at the start of `text`. A URL that isn't safe to follow leaves just the label.
+fn link(text: &str) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut label_end = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    label_end = Some(i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let label_end = label_end?;
+    let target = text[label_end + 1..].strip_prefix('(')?;
+    // Parentheses in the URL are fine as long as they balance, as in "f(x)".
+    let mut depth = 0;
+    let target_end = target.char_indices().find(|(_, c)| {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return true,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        return false;
+    })?.0;
+    let url = target[..target_end].split_whitespace().next().unwrap_or("");
+    let url = url.strip_prefix('<').and_then(|url| url.strip_suffix('>')).unwrap_or(url);
+
+    let label = inline(&text[1..label_end]);
+    let consumed = label_end + 2 + target_end + 1;
+    if !is_safe_url(url) {
+        return Some((label, consumed));
+    }
+    return Some((format!("<a href=\"{}\">{}</a>", escape_html(url), label), consumed));
+}
+
+// Relative URLs and a few harmless schemes. Whatever else has a scheme (javascript:, data:) is not.
+fn is_safe_url(url: &str) -> bool {
+    let scheme_end = url.find([':', '/', '?', '#']);
+    return match scheme_end {
+        Some(i) if url[i..].starts_with(':') => {
+            let scheme = url[..i].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "\
+# Todo  #
+
+Remember the *milk* and **bread**,
+and `cargo test` <script>alert(1)</script> & more.
+
+- [docs](https://example.com/a?b=1&c=2)
+- a [bad link](javascript:alert(1))
+  wrapped
+1. ignored kind switch
+
+3) third
+4) fourth
+
+```rust
+fn main() { println!(\"<hi>\"); }
+```
+
+    indented <code>
+
+## snake_case_name and \\*not emphasis\\*
+";
+
+    #[test]
+    fn test_fixture_document() {
+        let expected = "\
+<h1>Todo</h1>
+<p>Remember the <em>milk</em> and <strong>bread</strong>,
+and <code>cargo test</code> &lt;script&gt;alert(1)&lt;/script&gt; &amp; more.</p>
+<ul>
+<li><a href=\"https://example.com/a?b=1&amp;c=2\">docs</a></li>
+<li>a bad link
+wrapped</li>
+</ul>
+<ol>
+<li>ignored kind switch</li>
+</ol>
+<ol start=\"3\">
+<li>third</li>
+<li>fourth</li>
+</ol>
+<pre><code class=\"language-rust\">fn main() { println!(&quot;&lt;hi&gt;&quot;); }
+</code></pre>
+<pre><code>indented &lt;code&gt;
+</code></pre>
+<h2>snake_case_name and *not emphasis*</h2>
+";
+        assert_eq!(to_html(NOTE), expected);
+    }
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(inline("*a **b** c*"), "<em>a <strong>b</strong> c</em>");
+        assert_eq!(inline("2 * 3 * 4"), "2 * 3 * 4");
+        assert_eq!(inline("__x__ and _y_"), "<strong>x</strong> and <em>y</em>");
+        assert_eq!(inline("`` a`b ``"), "<code>a`b</code>");
+        assert_eq!(inline("unclosed `tick and [bracket"), "unclosed `tick and [bracket");
+        assert_eq!(inline("[*x*](/notes/x.md)"), "<a href=\"/notes/x.md\"><em>x</em></a>");
+        assert_eq!(inline("[x](\" onclick=\"y)"), "<a href=\"&quot;\">x</a>");
+        assert_eq!(inline("[x](DATA:text/html,hi)"), "x");
+        assert_eq!(inline("***both***, **un*even"), "<em><strong>both</strong></em>, *<em>un</em>even");
+        assert_eq!(inline("*a _b* c_"), "<em>a _b</em> c_");
+    }
+
+    #[test]
+    fn test_unmatched_runs_stay_linear() {
+        // Every * can open and none can close: each one used to scan the rest of the line.
+        let text = "*a ".repeat(50_000);
+        assert_eq!(inline(&text), text);
+        let text = "**a* ".repeat(50_000);
+        assert_eq!(inline(&text).matches("<em>").count(), 50_000);
+    }
+
+    #[test]
+    fn test_page() {
+        let page = render_page("hi", "<notes>.md");
+        assert!(page.starts_with("<!DOCTYPE html>\n"), "{}", page);
+        assert!(page.contains("<title>&lt;notes&gt;.md</title>"), "{}", page);
+        assert!(page.contains("<body>\n<p>hi</p>\n</body>"), "{}", page);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+// Content-Type used when the extension is unknown (or there is none).
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+// Built-in extension → media type map. Extensions are lowercase, without the dot.
+const BUILTIN_MIME_TYPES: [(&str, &str); 22] = [
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("wasm", "application/wasm"),
+    ("mp4", "video/mp4"),
+];
+
+/*
+Pick the Content-Type for a file from its extension.
+
+`overrides` is the [mime_types] table from config.toml and wins over the built-in map, so a
+deployment can both add niche types (gltf = "model/gltf+json") and replace defaults.
+Extensions match case-insensitively: "INDEX.HTML" is served as HTML too.
+*/
+pub fn content_type_for<'a>(path: &Path, overrides: &'a HashMap<String, String>) -> &'a str {
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension,
+        None => return DEFAULT_MIME_TYPE,
+    };
+
+    if let Some((_, media_type)) = overrides.iter().find(|(ext, _)| ext.eq_ignore_ascii_case(extension)) {
+        return media_type;
+    }
+
+    return BUILTIN_MIME_TYPES.iter()
+        .find(|(ext, _)| ext.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| *media_type)
+        .unwrap_or(DEFAULT_MIME_TYPE);
+}
+
+// Config validation for [mime_types]: the values go straight into a response header.
+pub fn validate_mime_types(overrides: &HashMap<String, String>) -> Result<(), String> {
+    for (extension, media_type) in overrides {
+        if extension.is_empty() || extension.contains('.') {
+            return Err(format!("mime_types: invalid extension {:?} (give it without the dot)", extension));
+        }
+        if media_type.is_empty() || media_type.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err(format!("mime_types: invalid media type {:?} for {:?}", media_type, extension));
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        return pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    }
+
+    #[test]
+    fn test_builtin_lookup() {
+        let none = HashMap::new();
+        assert_eq!(content_type_for(Path::new("a/index.html"), &none), "text/html; charset=utf-8");
+        assert_eq!(content_type_for(Path::new("LOGO.PNG"), &none), "image/png");
+        assert_eq!(content_type_for(Path::new("archive.unknown"), &none), DEFAULT_MIME_TYPE);
+        assert_eq!(content_type_for(Path::new("Makefile"), &none), DEFAULT_MIME_TYPE);
+    }
+
+    #[test]
+    fn test_override_builtin() {
+        let table = overrides(&[("HTML", "application/xhtml+xml")]);
+        assert_eq!(content_type_for(Path::new("index.html"), &table), "application/xhtml+xml");
+    }
+
+    #[test]
+    fn test_new_extension() {
+        let table = overrides(&[("gltf", "model/gltf+json")]);
+        assert_eq!(content_type_for(Path::new("scene.GLTF"), &table), "model/gltf+json");
+        assert_eq!(content_type_for(Path::new("style.css"), &table), "text/css; charset=utf-8");
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(validate_mime_types(&overrides(&[("gltf", "model/gltf+json")])).is_ok());
+        assert!(validate_mime_types(&overrides(&[("gltf", "model/gltf json")])).is_err());
+        assert!(validate_mime_types(&overrides(&[("gltf", "model/gltf\r\nX-Evil: 1")])).is_err());
+        assert!(validate_mime_types(&overrides(&[(".gltf", "model/gltf+json")])).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic;
+use std::sync::Once;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use crate::log::error;
+
+// Panics since startup, on any thread. Reported by GET /admin/connections.
+static PANICS_TOTAL: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // The request line this thread is handling right now, if any (see RequestContext).
+    static CURRENT_REQUEST: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/*
+Marks the thread as handling a request until dropped, so a panic meanwhile is logged with the
+request that caused it. The hook runs before unwinding starts, while this is still in place.
+*/
+pub struct RequestContext {
+    previous: Option<String>,
+}
+
+impl RequestContext {
+    pub fn enter(request_line: String) -> RequestContext {
+        let previous = CURRENT_REQUEST.with(|current| current.replace(Some(request_line)));
+        return RequestContext { previous };
+    }
+}
+
+impl Drop for RequestContext {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_REQUEST.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+pub fn panics_total() -> u64 {
+    return PANICS_TOTAL.load(Ordering::Relaxed);
+}
+
+/*
+Replace the default panic message with an error-log line naming the thread (vibettp-worker-<id>
+is the connection) and the request being handled. Panics outside a request still get the default
+message too, backtrace hint included. Every panic is counted. Installing it twice is harmless.
+*/
+pub fn install_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PANICS_TOTAL.fetch_add(1, Ordering::Relaxed);
+            let thread = thread::current();
+            let thread = thread.name().unwrap_or("(unnamed)");
+            let request = CURRENT_REQUEST.with(|current| current.borrow().clone());
+            let location = info.location().map_or(String::new(), |at| format!(" at {}:{}", at.file(), at.line()));
+            match request {
+                Some(request) => error!("💥 Panic in {} while handling \"{}\": {}{}", thread, request, message(info.payload()), location),
+                None => {
+                    error!("💥 Panic in {}: {}{}", thread, message(info.payload()), location);
+                    default_hook(info);
+                }
+            }
+        }));
+    });
+}
+
+// The text passed to panic!(), when it was a string (it almost always is).
+pub fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message;
+    }
+    if let Some(message) = payload.downcast_ref::<String>() {
+        return message;
+    }
+    return "(non-string panic payload)";
+}
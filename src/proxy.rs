@@ -0,0 +1,112 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+// The longest a v1 header can be, CRLF included (the spec's worst case, "PROXY TCP6 ..." with full addresses).
+pub const MAX_V1_LEN: usize = 107;
+
+// What the start of a connection holds, as far as the PROXY protocol is concerned.
+#[derive(Debug, PartialEq)]
+pub enum ProxyHeader {
+    // No CRLF yet, and what is there could still become a valid header.
+    Incomplete,
+    // Not a PROXY v1 header: the connection is closed without an answer.
+    Invalid,
+    /*
+    A complete header of `len` bytes; the HTTP request starts right after it. `source` is the
+    client the load balancer is forwarding, or None for "PROXY UNKNOWN" (keep the socket peer).
+    */
+    Complete { source: Option<SocketAddr>, len: usize },
+}
+
+/*
+Parse a PROXY protocol v1 header at the start of `data`:
+
+    PROXY TCP4 203.0.113.7 10.0.0.1 56324 80\r\n
+
+Exactly one space between fields, addresses of the family the protocol names, decimal ports
+without a sign. "PROXY UNKNOWN" may be followed by anything up to the CRLF. Whatever follows
+the CRLF is left alone.
+*/
+pub fn parse_v1(data: &[u8]) -> ProxyHeader {
+    let Some(end) = data.windows(2).position(|pair| pair == b"\r\n") else {
+        let prefix_ok = data.iter().zip(b"PROXY ").all(|(a, b)| a == b);
+        return if prefix_ok && data.len() < MAX_V1_LEN { ProxyHeader::Incomplete } else { ProxyHeader::Invalid };
+    };
+    let len = end + 2;
+    if len > MAX_V1_LEN {
+        return ProxyHeader::Invalid;
+    }
+    let Ok(line) = std::str::from_utf8(&data[..end]) else {
+        return ProxyHeader::Invalid;
+    };
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    let source = match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4", src, dst, sport, dport] => {
+            match (src.parse::<Ipv4Addr>(), dst.parse::<Ipv4Addr>(), port(sport), port(dport)) {
+                (Ok(src), Ok(_), Some(sport), Some(_)) => Some(SocketAddr::new(IpAddr::V4(src), sport)),
+                _ => return ProxyHeader::Invalid,
+            }
+        }
+        ["PROXY", "TCP6", src, dst, sport, dport] => {
+            match (src.parse::<Ipv6Addr>(), dst.parse::<Ipv6Addr>(), port(sport), port(dport)) {
+                (Ok(src), Ok(_), Some(sport), Some(_)) => Some(SocketAddr::new(IpAddr::V6(src), sport)),
+                _ => return ProxyHeader::Invalid,
+            }
+        }
+        _ => return ProxyHeader::Invalid,
+    };
+    return ProxyHeader::Complete { source, len };
+}
+
+// A port as the header spells it: 1 to 5 digits, nothing else (u16's parser would also take "+80").
+fn port(text: &str) -> Option<u16> {
+    if text.is_empty() || text.len() > 5 || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    return text.parse().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1() {
+        let v4: SocketAddr = "203.0.113.7:56324".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let cases: [(&[u8], ProxyHeader); 16] = [
+            (b"PROXY TCP4 203.0.113.7 10.0.0.1 56324 80\r\nGET / HTTP/1.1\r\n", ProxyHeader::Complete { source: Some(v4), len: 42 }),
+            (b"PROXY TCP6 2001:db8::1 2001:db8::2 443 8443\r\n", ProxyHeader::Complete { source: Some(v6), len: 45 }),
+            (b"PROXY UNKNOWN\r\n", ProxyHeader::Complete { source: None, len: 15 }),
+            (b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n", ProxyHeader::Complete { source: None, len: 35 }),
+            // Not there yet.
+            (b"", ProxyHeader::Incomplete),
+            (b"PRO", ProxyHeader::Incomplete),
+            (b"PROXY TCP4 203.0.113.7", ProxyHeader::Incomplete),
+            // Malformed.
+            (b"GET / HTTP/1.1\r\n\r\n", ProxyHeader::Invalid),
+            (b"GET / HTTP/1.1", ProxyHeader::Invalid),
+            (b"PROXY TCP4 203.0.113.7 10.0.0.1 56324\r\n", ProxyHeader::Invalid),
+            (b"PROXY TCP4 203.0.113.7  10.0.0.1 56324 80\r\n", ProxyHeader::Invalid),
+            (b"PROXY TCP4 2001:db8::1 10.0.0.1 56324 80\r\n", ProxyHeader::Invalid),
+            (b"PROXY TCP4 203.0.113.7 10.0.0.1 +5632 80\r\n", ProxyHeader::Invalid),
+            (b"PROXY TCP4 203.0.113.7 10.0.0.1 65536 80\r\n", ProxyHeader::Invalid),
+            (b"proxy TCP4 203.0.113.7 10.0.0.1 56324 80\r\n", ProxyHeader::Invalid),
+            (b"PROXY UDP4 203.0.113.7 10.0.0.1 56324 80\r\n", ProxyHeader::Invalid),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(parse_v1(data), expected, "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn test_too_long_without_crlf() {
+        let mut data = b"PROXY UNKNOWN ".to_vec();
+        data.resize(MAX_V1_LEN, b'x');
+        assert_eq!(parse_v1(&data), ProxyHeader::Invalid);
+        data.truncate(MAX_V1_LEN - 3);
+        data.extend_from_slice(b"\r\n");
+        assert!(matches!(parse_v1(&data), ProxyHeader::Complete { source: None, .. }));
+    }
+}
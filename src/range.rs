@@ -0,0 +1,208 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// More ranges than this in one request is treated as abuse and the header is ignored.
+pub const MAX_RANGES: usize = 16;
+
+// What a Range header asks for, relative to a resource of known length.
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    // No Range header, or one we ignore (bad syntax, other unit, too many ranges): send it all.
+    Full,
+    // One or more satisfiable ranges, as inclusive (first, last) byte offsets, sorted and merged.
+    Partial(Vec<(u64, u64)>),
+    // Syntactically fine, but nothing overlaps the resource: 416.
+    Unsatisfiable,
+}
+
+// One member of a Range header, before the resource's length is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeSpec {
+    // "first-last", inclusive.
+    Bounded(u64, u64),
+    // "first-": from there to the end.
+    From(u64),
+    // "-n": the last n bytes.
+    Suffix(u64),
+}
+
+/*
+Parse a Range header value ("bytes=0-99,1000-1099", "bytes=500-", "bytes=-500") against a
+resource of `length` bytes (RFC 7233 §2.1). The same as parse_range_specs() followed by
+resolve_ranges(), which is what the static path does now (the specs come parsed with the request's
+Conditionals), so only the tests still take this shortcut.
+*/
+#[cfg(test)]
+pub fn parse_range(value: &str, length: u64) -> RangeRequest {
+    return match parse_range_specs(value) {
+        Some(specs) => resolve_ranges(&specs, length),
+        None => RangeRequest::Full,
+    };
+}
+
+/*
+The members of a Range header value, in the order sent. None for a header to ignore: another
+unit, bad syntax, or more than MAX_RANGES members.
+*/
+pub fn parse_range_specs(value: &str) -> Option<Vec<RangeSpec>> {
+    let specs = match value.split_once('=') {
+        Some((unit, specs)) if unit.trim().eq_ignore_ascii_case("bytes") => specs,
+        _ => return None,
+    };
+
+    let members: Vec<&str> = specs.split(',').map(str::trim).filter(|m| !m.is_empty()).collect();
+    if members.is_empty() || members.len() > MAX_RANGES {
+        return None;
+    }
+
+    let number = |s: &str| -> Option<u64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        return s.parse().ok();
+    };
+    let mut parsed = Vec::new();
+    for member in members {
+        let (first, last) = member.split_once('-')?;
+        let spec = match (first.is_empty(), last.is_empty()) {
+            (true, _) => RangeSpec::Suffix(number(last)?),
+            (false, true) => RangeSpec::From(number(first)?),
+            (false, false) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if last < first {
+                    return None;
+                }
+                RangeSpec::Bounded(first, last)
+            }
+        };
+        parsed.push(spec);
+    }
+    return Some(parsed);
+}
+
+/*
+The byte ranges `specs` select from a resource of `length` bytes.
+
+Members that start past the end are dropped; if none are left the whole request is
+unsatisfiable. Overlapping or adjacent members are coalesced (§4.1 allows this), which also
+stops "bytes=0-,0-,0-..." from multiplying the response size.
+*/
+pub fn resolve_ranges(specs: &[RangeSpec], length: u64) -> RangeRequest {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for spec in specs {
+        match *spec {
+            RangeSpec::Suffix(suffix) if suffix > 0 && length > 0 => ranges.push((length.saturating_sub(suffix), length - 1)),
+            RangeSpec::From(first) if first < length => ranges.push((first, length - 1)),
+            RangeSpec::Bounded(first, last) if first < length => ranges.push((first, last.min(length - 1))),
+            _ => {}
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::new();
+    for (first, last) in ranges {
+        match merged.last_mut() {
+            Some(previous) if first <= previous.1.saturating_add(1) => previous.1 = previous.1.max(last),
+            _ => merged.push((first, last)),
+        }
+    }
+    return RangeRequest::Partial(merged);
+}
+
+// Value of the Content-Range header for one part.
+pub fn content_range(first: u64, last: u64, length: u64) -> String {
+    return format!("bytes {}-{}/{}", first, last, length);
+}
+
+// A boundary that is unique per response, so it can't be guessed and planted inside a file.
+pub fn new_boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+    return format!("vibettp-{:016x}{:04x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff);
+}
+
+/*
+Assemble a multipart/byteranges body (RFC 7233 Appendix A): each range becomes a part with its
+own Content-Type and Content-Range headers, followed by the closing boundary.
+*/
+pub fn multipart_byteranges(data: &[u8], ranges: &[(u64, u64)], content_type: &str, boundary: &str) -> Vec<u8> {
+    let length = data.len() as u64;
+    let mut body = Vec::new();
+
+    for &(first, last) in ranges {
+        body.extend_from_slice(format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary, content_type, content_range(first, last, length)
+        ).as_bytes());
+        body.extend_from_slice(&data[first as usize..=last as usize]);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    return body;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let cases: [(&str, RangeRequest); 13] = [
+            ("bytes=0-99", RangeRequest::Partial(vec![(0, 99)])),
+            ("bytes=500-", RangeRequest::Partial(vec![(500, 999)])),
+            ("bytes=-200", RangeRequest::Partial(vec![(800, 999)])),
+            ("bytes=-5000", RangeRequest::Partial(vec![(0, 999)])),
+            ("bytes=900-5000", RangeRequest::Partial(vec![(900, 999)])),
+            ("bytes=0-99, 200-299", RangeRequest::Partial(vec![(0, 99), (200, 299)])),
+            // Out of order, overlapping and adjacent members are merged.
+            ("bytes=200-299,0-99,50-150,300-310", RangeRequest::Partial(vec![(0, 150), (200, 310)])),
+            // Unsatisfiable members are dropped; if nothing is left, 416.
+            ("bytes=0-9,5000-6000", RangeRequest::Partial(vec![(0, 9)])),
+            ("bytes=1000-", RangeRequest::Unsatisfiable),
+            // Ignored: bad syntax or other units.
+            ("bytes=9-1", RangeRequest::Full),
+            ("bytes=abc", RangeRequest::Full),
+            ("items=0-1", RangeRequest::Full),
+            ("bytes=+1-2", RangeRequest::Full),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(parse_range(value, 1000), expected, "value {:?}", value);
+        }
+    }
+
+    #[test]
+    fn test_parse_range_specs() {
+        assert_eq!(
+            parse_range_specs("bytes=5-9, 100-, -20"),
+            Some(vec![RangeSpec::Bounded(5, 9), RangeSpec::From(100), RangeSpec::Suffix(20)])
+        );
+        assert_eq!(parse_range_specs("bytes=9-1"), None);
+        assert_eq!(resolve_ranges(&[RangeSpec::From(100)], 50), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn test_too_many_ranges_ignored() {
+        let value = format!("bytes={}", (0..=MAX_RANGES).map(|i| format!("{}-{}", i * 10, i * 10)).collect::<Vec<_>>().join(","));
+        assert_eq!(parse_range(&value, 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let data = b"0123456789abcdefghij";
+        let body = multipart_byteranges(data, &[(0, 3), (10, 12)], "text/plain", "XYZ");
+        let expected = "--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-3/20\r\n\r\n0123\r\n\
+                        --XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 10-12/20\r\n\r\nabc\r\n\
+                        --XYZ--\r\n";
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_boundaries_differ() {
+        assert_ne!(new_boundary(), new_boundary());
+    }
+}
@@ -1,16 +1,455 @@
-// Represents a basic HTTP request with method and path only.
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::conditionals::Conditionals;
+use crate::util::percent_decode;
+use crate::log::debug;
+
+/// The four shapes a request target can take (RFC 7230 §5.3).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetForm {
+    // "/index.html?x=1" — the normal case.
+    Origin,
+    // "http://example.com/index.html" — sent by proxies and some clients.
+    Absolute,
+    // "example.com:443" — only used by CONNECT, which this server does not implement.
+    Authority,
+    // "*" — only used by OPTIONS, to ask about the server as a whole.
+    Asterisk,
+}
+
+/**
+The protocol version from the request line, "HTTP/1.1" being { major: 1, minor: 1 }. Only the
+strict "HTTP/<digit>.<digit>" form parses; versions other than 1.x parse too, and are answered
+with 505 later on.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HttpVersion {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl HttpVersion {
+    pub const HTTP_1_0: HttpVersion = HttpVersion { major: 1, minor: 0 };
+    pub const HTTP_1_1: HttpVersion = HttpVersion { major: 1, minor: 1 };
+
+    /// "HTTP/1.1" → 1.1. Case-sensitive, one digit each: "http/1.1" and "HTTP/11" are None.
+    pub fn parse(text: &str) -> Option<HttpVersion> {
+        let [major, b'.', minor] = *text.strip_prefix("HTTP/")?.as_bytes() else {
+            return None;
+        };
+        if !major.is_ascii_digit() || !minor.is_ascii_digit() {
+            return None;
+        }
+        return Some(HttpVersion { major: major - b'0', minor: minor - b'0' });
+    }
+
+    pub fn is_1_1(&self) -> bool {
+        return *self == HttpVersion::HTTP_1_1;
+    }
+
+    pub fn is_1_0(&self) -> bool {
+        return *self == HttpVersion::HTTP_1_0;
+    }
+}
+
+// The canonical form, as in the request line: "HTTP/1.1".
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "HTTP/{}.{}", self.major, self.minor);
+    }
+}
+
+/**
+The Host header, split into a name and an optional port: "example.com:8080", "localhost",
+"[::1]:7878". The name is lowercased and an IPv6 literal loses its brackets ("::1"). The port
+only has to be a number; it isn't compared with the listener's, which a proxy may have changed.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Host {
+    pub name: String,
+    pub port: Option<u16>,
+}
+
+impl Host {
+    /// None for a value that isn't a host: whitespace or URL syntax in it, a non-numeric port, a broken IPv6 literal.
+    pub fn parse(value: &str) -> Option<Host> {
+        let (name, port) = match value.strip_prefix('[') {
+            Some(bracketed) => {
+                let (literal, rest) = bracketed.split_once(']')?;
+                if literal.is_empty() || !literal.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.') {
+                    return None;
+                }
+                let port = match rest {
+                    "" => None,
+                    _ => Some(rest.strip_prefix(':')?),
+                };
+                (literal, port)
+            }
+            None => {
+                let (name, port) = match value.rsplit_once(':') {
+                    Some((name, port)) => (name, Some(port)),
+                    None => (value, None),
+                };
+                let invalid = |c: char| c.is_ascii_whitespace() || c.is_ascii_control() || "/?#@[]:\\".contains(c);
+                if name.contains(invalid) {
+                    return None;
+                }
+                (name, port)
+            }
+        };
+        // "host:" (an empty port) is allowed by the grammar and means the default port.
+        let port = match port {
+            None | Some("") => None,
+            Some(digits) if digits.len() <= 5 && digits.bytes().all(|b| b.is_ascii_digit()) => Some(digits.parse().ok()?),
+            Some(_) => return None,
+        };
+        return Some(Host { name: name.to_ascii_lowercase(), port });
+    }
+}
+
+/**
+A request body. Small bodies are kept in memory; ones over body_memory_threshold are written to a
+spool file as they arrive (see spool.rs) and handed over as the file's path.
+A spool file belongs to its Body: dropping the Body (and with it the Request) deletes the file,
+whichever way the request ends. Moving the file elsewhere first (an upload) simply keeps it.
+*/
+#[derive(Debug, PartialEq)]
+pub enum Body {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+impl Body {
+    /// Length in bytes (a spooled body is measured on disk).
+    pub fn len(&self) -> usize {
+        return match self {
+            Body::Bytes(bytes) => bytes.len(),
+            Body::File(path) => fs::metadata(path).map(|m| m.len() as usize).unwrap_or(0),
+        };
+    }
+
+    /// Whether the body has no bytes at all.
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// Up to `max` bytes from the start of the body (without reading a spooled one in full).
+    pub fn preview(&self, max: usize) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => return Ok(bytes[..bytes.len().min(max)].to_vec()),
+            Body::File(path) => {
+                let mut head = Vec::new();
+                File::open(path)?.take(max as u64).read_to_end(&mut head)?;
+                return Ok(head);
+            }
+        }
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        if let Body::File(path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Why a query parameter couldn't be read as the type a handler asked for.
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    /// The parameter isn't in the query string at all.
+    Missing(String),
+    /// It is, but its (decoded) value doesn't parse.
+    Invalid { name: String, value: String },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            QueryError::Missing(name) => write!(f, "missing query parameter `{}`", name),
+            QueryError::Invalid { name, value } => write!(f, "invalid value {:?} for query parameter `{}`", value, name),
+        };
+    }
+}
+
+/// Represents a basic HTTP request: request line, headers and the framing they declare.
+#[derive(Debug, PartialEq)]
 pub struct Request {
+    /// The method as sent, e.g. "GET".
     pub method: String,
+    /// Path component of the target, always starting with '/' for origin/absolute form.
     pub path: String,
-    pub version: String,
+    /// Raw query string (the part after '?', not decoded), if the target had one.
+    pub query: Option<String>,
+    /// Which of the four request-target forms the request line used.
+    pub target_form: TargetForm,
+    /// The protocol version; anything but 1.x gets a 505 (see dispatch.rs).
+    pub version: HttpVersion,
+    /// Whether the client wants the connection kept open (Connection header, or the version's default).
     pub keep_alive: bool,
+    /// The Host header, parsed; None if the request has none. An invalid one fails the parse (400).
+    pub host: Option<Host>,
+    /// The method on the request line, when another one replaced it: X-HTTP-Method-Override, or HEAD handled as GET (see dispatch.rs).
+    pub overridden_method: Option<String>,
+    /// The path the client asked for (normalized), when a [[rewrites]] rule replaced `path` (see dispatch.rs).
+    pub rewritten_from: Option<String>,
+    /// A HEAD request: it is handled as the GET it stands for and answered without the body.
+    pub head_only: bool,
+    /// Header (name, value) pairs in the order received. Names keep the client's casing.
+    pub headers: Vec<(String, String)>,
+    /// Declared body length, if the request carried a (valid) Content-Length.
+    pub content_length: Option<usize>,
+    /// True if the body is sent with Transfer-Encoding: chunked.
+    pub chunked: bool,
+    /// The body, filled in by the connection loop once all content_length bytes have arrived.
+    pub body: Body,
+    /// The id of the [api_auth] key the request was let in with, once dispatch has checked it.
+    pub api_key_id: Option<String>,
+    /// The conditional headers, parsed on first use (see conditionals()).
+    conditionals: OnceLock<Conditionals>,
+}
+
+impl Request {
+    /**
+    HTTP/1.0 clients get different defaults: no persistence unless asked for, and they don't
+    understand chunked transfer coding, so responses to them must be length- or close-delimited.
+    */
+    pub fn is_http_1_0(&self) -> bool {
+        return self.version.is_1_0();
+    }
+
+    /// The method as sent on the request line, even if X-HTTP-Method-Override changed `method` since.
+    pub fn original_method(&self) -> &str {
+        return self.overridden_method.as_deref().unwrap_or(&self.method);
+    }
+
+    /// The path as requested, even if a [[rewrites]] rule changed `path` since.
+    pub fn original_path(&self) -> &str {
+        return self.rewritten_from.as_deref().unwrap_or(&self.path);
+    }
+
+    /// Handle the request as `method` from now on, remembering the one it came with.
+    pub fn override_method(&mut self, method: &str) {
+        let original = std::mem::replace(&mut self.method, method.to_string());
+        self.overridden_method.get_or_insert(original);
+    }
+
+    /// True if the query string contains `name=value` exactly (no decoding), e.g. "download=1".
+    pub fn has_query_flag(&self, name: &str, value: &str) -> bool {
+        return self.query.as_deref().is_some_and(|query| {
+            query.split('&').any(|pair| pair.split_once('=') == Some((name, value)))
+        });
+    }
+
+    /**
+    The query string as decoded (name, value) pairs, in order: '+' means space, %XX escapes are
+    decoded, and a pair without '=' has an empty value. Undecodable parts are kept as sent.
+    */
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let decode = |part: &str| {
+            let spaced = part.replace('+', " ");
+            percent_decode(&spaced).unwrap_or(spaced)
+        };
+        return match self.query.as_deref() {
+            Some(query) => query.split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (decode(name), decode(value))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+    }
+
+    /**
+    The first value of query parameter `name`, parsed as T: `req.query_as::<u32>("page")?`.
+    Flags may also be written "1" and "0" for true and false.
+    */
+    pub fn query_as<T: FromStr>(&self, name: &str) -> Result<T, QueryError> {
+        let Some(value) = self.query_all(name).into_iter().next() else {
+            return Err(QueryError::Missing(name.to_string()));
+        };
+        // Only bool fails on "1" yet accepts "true", so this is the flag spelling and nothing else.
+        let parsed = value.parse().ok().or_else(|| match value.as_str() {
+            "1" => "true".parse().ok(),
+            "0" => "false".parse().ok(),
+            _ => None,
+        });
+        return parsed.ok_or_else(|| QueryError::Invalid { name: name.to_string(), value });
+    }
+
+    /// Like query_as(), with `default` when the parameter is missing. A value that doesn't parse is still an error.
+    pub fn query_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, QueryError> {
+        return match self.query_as(name) {
+            Err(QueryError::Missing(_)) => Ok(default),
+            other => other,
+        };
+    }
+
+    /// Every (decoded) value of a repeated parameter such as "?tag=a&tag=b", in order.
+    pub fn query_all(&self, name: &str) -> Vec<String> {
+        return self.query_pairs().into_iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value)
+            .collect();
+    }
+
+    /// Value of the first header with the given name (names are case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        return self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str());
+    }
+
+    /**
+    If-Match, If-None-Match, If-Modified-Since, If-Unmodified-Since, If-Range and Range, parsed
+    the first time they're asked for, for conditionals::evaluate_preconditions(). The method is
+    taken then too, so this isn't called before dispatch has applied any method override.
+    */
+    pub fn conditionals(&self) -> &Conditionals {
+        return self.conditionals.get_or_init(|| Conditionals::parse(self));
+    }
+
+    /**
+    True if If-None-Match lists `etag` (or is "*"), i.e. the client already has this version.
+    The comparison is the weak one RFC 7232 §3.2 asks for: a W/ prefix on either side is ignored.
+    */
+    pub fn if_none_match(&self, etag: &str) -> bool {
+        return self.conditionals().if_none_match.as_ref().is_some_and(|condition| condition.matches(Some(etag), false));
+    }
+
+    /**
+    True unless If-Match rules the write out: `current` is the target's ETag, None when it
+    doesn't exist. "*" asks for any existing version; otherwise one of the listed tags must be
+    `current` by the strong comparison of RFC 7232 §2.3.2, so W/ tags never match. No header:
+    always true.
+    */
+    pub fn if_match(&self, current: Option<&str>) -> bool {
+        return self.conditionals().if_match.as_ref().is_none_or(|condition| condition.matches(current, true));
+    }
+
+    /**
+    True unless If-Unmodified-Since is older than `modified` (whole seconds, as the header has no
+    more). No header, a date that isn't an IMF-fixdate, or no target at all (None): true.
+    */
+    pub fn if_unmodified_since(&self, modified: Option<SystemTime>) -> bool {
+        let (Some(since), Some(modified)) = (self.conditionals().if_unmodified_since, modified) else {
+            return true;
+        };
+        let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        return seconds(modified) <= seconds(since);
+    }
+
+    /**
+    Which of the `offered` media types (in the server's order of preference) the client wants
+    most, going by its Accept header. Each offer gets the q of the most specific range matching
+    it (see specificity()); the highest q wins and ties go to the earlier offer. None if the
+    client accepts none of them. No Accept header, or one that can't be parsed, accepts
+    anything: the first offer.
+    */
+    pub fn prefers<'a>(&self, offered: &[&'a str]) -> Option<&'a str> {
+        let accepted = parse_accept(self.header("Accept").unwrap_or("*/*"));
+        let mut best: Option<(&'a str, f32)> = None;
+        for &offer in offered {
+            let q = accepted.iter()
+                .filter(|(range, _)| media_range_matches(range, offer))
+                .max_by_key(|(range, _)| specificity(range))
+                .map_or(0.0, |(_, q)| *q);
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((offer, q));
+            }
+        }
+        return best.map(|(offer, _)| offer);
+    }
 }
 
-// Parses a raw HTTP request buffer into a Request struct.
+/**
+An Accept header as (media range, q) pairs, most preferred first: by q, then the more specific
+range first (see specificity()), then in the order sent. Ranges are lowercased and keep no
+parameters other than q. Anything malformed (no '/', a bad q) makes the whole header mean
+"accept anything": a confused client still gets an answer rather than a 400.
+*/
+pub fn parse_accept(value: &str) -> Vec<(String, f32)> {
+    let anything = vec![("*/*".to_string(), 1.0)];
+    let mut ranges = Vec::new();
+    for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let mut parts = item.split(';').map(str::trim);
+        let range = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let Some((kind, subtype)) = range.split_once('/') else {
+            return anything;
+        };
+        if kind.is_empty() || subtype.is_empty() || (kind == "*" && subtype != "*") {
+            return anything;
+        }
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((name, raw)) = param.split_once('=')
+                && name.trim().eq_ignore_ascii_case("q") {
+                match raw.trim().parse::<f32>() {
+                    Ok(parsed) if (0.0..=1.0).contains(&parsed) => q = parsed,
+                    _ => return anything,
+                }
+            }
+        }
+        ranges.push((range, q));
+    }
+    if ranges.is_empty() {
+        return anything;
+    }
+    // Stable, so equally preferred ranges stay in the order the client sent them.
+    ranges.sort_by(|(a, qa), (b, qb)| qb.total_cmp(qa).then(specificity(b).cmp(&specificity(a))));
+    return ranges;
+}
+
+// 0 for the catch-all range, 1 for a whole type ("text/" + wildcard), 2 for "text/html".
+fn specificity(range: &str) -> u8 {
+    return match range.split_once('/') {
+        Some(("*", _)) => 0,
+        Some((_, "*")) => 1,
+        _ => 2,
+    };
+}
+
+fn media_range_matches(range: &str, media_type: &str) -> bool {
+    let (kind, subtype) = range.split_once('/').unwrap_or((range, ""));
+    let (want_kind, want_subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+    return (kind == "*" || kind.eq_ignore_ascii_case(want_kind))
+        && (subtype == "*" || subtype.eq_ignore_ascii_case(want_subtype));
+}
+
+/// Parses a raw HTTP request buffer into a Request struct, accepting bare-LF line endings.
 pub fn parse_request(buffer: &[u8]) -> Option<Request> {
+    return parse_request_with(buffer, true);
+}
+
+/**
+Like parse_request(), with a choice of line endings: lenient accepts a bare "\n" wherever "\r\n"
+belongs (as some hand-written clients send), strict refuses a request that has one.
+*/
+pub fn parse_request_with(buffer: &[u8], lenient_line_endings: bool) -> Option<Request> {
+    /*
+    Only the request line and headers are text. Anything after the blank line is the body, which
+    may be arbitrary binary data (an uploaded image, say), so it must not take part in the UTF-8
+    check below. If no terminator is present yet, the whole buffer is treated as the head.
+    */
+    let head = match find_header_end(buffer, lenient_line_endings) {
+        Some(end) => &buffer[..end],
+        None => buffer,
+    };
+    if !lenient_line_endings && has_bare_lf(head) {
+        return None;
+    }
+
     // Convert raw bytes to UTF-8 string (fallible).
     // match is switch
-    let request_str = match std::str::from_utf8(buffer) {
+    let request_str = match std::str::from_utf8(head) {
         Ok(s) => s,
         Err(_) => return None,
     };
@@ -28,6 +467,7 @@ pub fn parse_request(buffer: &[u8]) -> Option<Request> {
 
     // Split the request string into lines.
     // The first line typically looks like: "GET /index.html HTTP/1.1"
+    // lines() ends a line at "\n" and drops a "\r" before it, so bare-LF lines come out the same.
     let mut lines = request_str.lines();
 
 
@@ -46,29 +486,104 @@ pub fn parse_request(buffer: &[u8]) -> Option<Request> {
     before it can be used.
     */
     if let Some(request_line) = lines.next() {
-        // Split by whitespace to extract method and path.
-        let mut parts = request_line.split_whitespace();
+        /*
+        Exactly "METHOD SP target SP version": single spaces, nothing before or after. Splitting on
+        any run of whitespace would quietly accept "GET  /a  HTTP/1.1", which a proxy in front
+        may read differently.
+        */
+        let parts: Vec<&str> = request_line.split(' ').collect();
+        if parts.len() != 3 || parts.iter().any(|part| part.is_empty() || part.contains(|c: char| c.is_ascii_whitespace())) {
+            return None;
+        }
+        let mut parts = parts.into_iter();
         let method = parts.next()?.to_string();
-        let path = parts.next()?.to_string();
-        let version = parts.next()?.to_string();
-
-        // Partial fix for 400 Bad Request
-        if !version.starts_with("HTTP/") {
+        let raw_target = parts.next()?;
+        if !is_valid_target(raw_target) {
             return None;
         }
-
-        let mut keep_alive: bool = false;
-        for line in lines {
-            if let Some(header_val) = line.strip_prefix("Connection:") {
-                keep_alive = header_val.trim().eq_ignore_ascii_case("keep-alive");
+        let (target_form, target, target_authority) = parse_target(&method, raw_target)?;
+        /*
+        Fragments ("#section") are client-side only and must not take part in routing or file
+        lookup. Split on the raw '#' before anything is decoded, so an escaped "%23" stays part
+        of the path.
+        */
+        let target = match target.split_once('#') {
+            Some((before, fragment)) => {
+                debug!("🔖 Ignoring URL fragment #{}", fragment);
+                before.to_string()
             }
+            None => target,
+        };
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), Some(query.to_string())),
+            None => (target, None),
+        };
+        // Strictly "HTTP/<digit>.<digit>"; anything else is a 400.
+        let version = HttpVersion::parse(parts.next()?)?;
 
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in lines {
             if line.is_empty() {
                 break; // reached the end of headers
             }
+
+            /*
+            Every header line is "Name: value". A line without a colon, or with whitespace
+            between the name and the colon ("Content-Length : 5"), is rejected outright: proxies
+            disagree on how to read those, which is exactly what request smuggling exploits.
+            */
+            let (name, value) = line.split_once(':')?;
+            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                return None;
+            }
+            let value = value.trim_matches(|c| c == ' ' || c == '\t');
+
+            headers.push((name.to_string(), value.to_string()));
+        }
+
+        /*
+        Persistence depends on the protocol version (RFC 7230 §6.3):
+        - HTTP/1.1 connections stay open unless the client sends "Connection: close";
+        - HTTP/1.0 connections close after one response unless it sends "Connection: keep-alive".
+        */
+        let connection_has = |token: &str| headers.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token));
+        let keep_alive = if version.is_1_0() {
+            connection_has("keep-alive") && !connection_has("close")
+        } else {
+            !connection_has("close")
+        };
+
+        let (content_length, chunked) = parse_framing(&headers)?;
+
+        /*
+        At most one Host, and a well-formed one: two could send proxy and server to different sites.
+        Without one, an absolute-form target's authority says which host is meant.
+        */
+        let mut hosts = headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("Host"));
+        let host = match (hosts.next(), hosts.next(), target_authority.as_deref()) {
+            (Some((_, value)), None, _) => Some(Host::parse(value)?),
+            (None, _, Some(authority)) => Some(Host::parse(authority)?),
+            (None, _, None) => None,
+            (Some(_), Some(_), _) => return None,
+        };
+
+        let request = Request {
+            method, path, query, target_form, version, keep_alive, host, headers, content_length, chunked,
+            overridden_method: None, rewritten_from: None, head_only: false, body: Body::Bytes(Vec::new()), api_key_id: None,
+            conditionals: OnceLock::new(),
+        };
+
+        // An absolute-form target names the host too; a Host header saying otherwise is a lie.
+        if let (Some(authority), Some(host)) = (target_authority, request.header("Host"))
+            && !authority.eq_ignore_ascii_case(host) {
+            return None;
         }
+
         // Return a populated Request struct if successful.
-        return Some(Request { method, path, version, keep_alive });
+        return Some(request);
     }
 
     /*
@@ -88,3 +603,610 @@ pub fn parse_request(buffer: &[u8]) -> Option<Request> {
     // If the format is wrong, return None.
     return None;
 }
+
+/*
+Check the raw request target byte by byte before anything else looks at it.
+
+Control bytes (< 0x21, which includes space and tab) and DEL (0x7F) are never legal in a target
+and must not reach the filesystem layer. Policy for the other characters RFC 3986 excludes:
+'<', '>' and '"' must be percent-encoded and are rejected raw; '#' is tolerated because some
+clients send fragments, which are stripped later. Everything else is left to later stages.
+*/
+fn is_valid_target(target: &str) -> bool {
+    return target.bytes().all(|b| b >= 0x21 && b != 0x7F && !matches!(b, b'<' | b'>' | b'"'));
+}
+
+/*
+Classify the request target and extract the path (plus query) that routing and sanitize_path
+work with.
+
+- "/about" (origin-form) is used as-is.
+- "http://example.com:8080/about?x=1" (absolute-form, http or https) becomes "/about?x=1",
+  and "example.com:8080" is returned so it can be checked against the Host header.
+  A URL with no path ("http://example.com") means "/".
+- "example.com:443" with CONNECT (authority-form) is recognised so it can be answered with
+  501 Not Implemented instead of being treated as a file name.
+- "*" (asterisk-form) is only meaningful with OPTIONS; with any other method it is malformed.
+
+Anything else is malformed and yields None.
+*/
+fn parse_target(method: &str, target: &str) -> Option<(TargetForm, String, Option<String>)> {
+    if method == "CONNECT" {
+        // host:port, nothing more.
+        let (host, port) = target.rsplit_once(':')?;
+        if host.is_empty() || host.contains('/') || port.parse::<u16>().is_err() {
+            return None;
+        }
+        return Some((TargetForm::Authority, target.to_string(), None));
+    }
+
+    if target == "*" {
+        if method != "OPTIONS" {
+            return None;
+        }
+        return Some((TargetForm::Asterisk, target.to_string(), None));
+    }
+
+    if target.starts_with('/') {
+        return Some((TargetForm::Origin, target.to_string(), None));
+    }
+
+    // Scheme names are case-insensitive ("HTTP://" is legal, if unusual).
+    let lower = target.to_ascii_lowercase();
+    let rest = if lower.starts_with("http://") {
+        &target["http://".len()..]
+    } else if lower.starts_with("https://") {
+        &target["https://".len()..]
+    } else {
+        return None;
+    };
+
+    // The authority runs up to the first '/', '?' or end of string.
+    let split = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(split);
+    if authority.is_empty() {
+        return None;
+    }
+
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    return Some((TargetForm::Absolute, path, Some(authority.to_string())));
+}
+
+/*
+Work out how the body is delimited, rejecting every ambiguous combination (RFC 7230 §3.3.3).
+
+When a front proxy and this server disagree on where a body ends, the leftover bytes get read
+as a second, "smuggled" request. The RFC says Transfer-Encoding wins over Content-Length, but
+the safer choice for a server that may sit behind a proxy is to refuse such requests (400):
+- Content-Length and Transfer-Encoding together;
+- a Transfer-Encoding whose final coding isn't chunked (the body length would be unknowable);
+- a Content-Length that isn't a plain decimal integer ("+5", "-5", "0x10", "5 5", ...);
+- several Content-Length values (repeated headers or a comma list) that don't all agree.
+
+Returns (content_length, chunked), or None if the request must be rejected.
+*/
+fn parse_framing(headers: &[(String, String)]) -> Option<(Option<usize>, bool)> {
+    let mut content_length: Option<usize> = None;
+    let mut codings: Vec<String> = Vec::new();
+
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("Content-Length") {
+            for item in value.split(',') {
+                let item = item.trim_matches(|c| c == ' ' || c == '\t');
+                if item.is_empty() || !item.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                // Too large for usize is as bad as malformed.
+                let length: usize = item.parse().ok()?;
+                if content_length.is_some_and(|previous| previous != length) {
+                    return None;
+                }
+                content_length = Some(length);
+            }
+        }
+        else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            codings.extend(
+                value.split(',')
+                    .map(|c| c.trim_matches(|c| c == ' ' || c == '\t').to_ascii_lowercase())
+                    .filter(|c| !c.is_empty())
+            );
+        }
+    }
+
+    if codings.is_empty() {
+        return Some((content_length, false));
+    }
+    if content_length.is_some() || codings.last().map(String::as_str) != Some("chunked") {
+        return None;
+    }
+    return Some((None, true));
+}
+
+/*
+Returns the index just past the blank line that terminates the headers, if present: "\r\n\r\n",
+or with lenient line endings also "\n\n" and the mixed "\n\r\n" (the last header line's "\r" is
+optional either way).
+*/
+pub(crate) fn find_header_end(buffer: &[u8], lenient_line_endings: bool) -> Option<usize> {
+    if !lenient_line_endings {
+        return buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+    }
+    for (i, byte) in buffer.iter().enumerate() {
+        if *byte != b'\n' {
+            continue;
+        }
+        match &buffer[i + 1..] {
+            [b'\n', ..] => return Some(i + 2),
+            [b'\r', b'\n', ..] => return Some(i + 3),
+            _ => {}
+        }
+    }
+    return None;
+}
+
+// True if some "\n" in the head isn't preceded by "\r" (only "\r\n" is allowed in strict mode).
+pub(crate) fn has_bare_lf(head: &[u8]) -> bool {
+    return head.iter().enumerate().any(|(i, byte)| *byte == b'\n' && (i == 0 || head[i - 1] != b'\r'));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+
+    // Tiny xorshift64 generator so the "random" inputs are reproducible without extra crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return self.0;
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            return (self.next() % n as u64) as usize;
+        }
+    }
+
+    // Whatever comes back must at least be a usable request.
+    fn check(buffer: &[u8]) {
+        if let Some(req) = parse_request(buffer) {
+            assert!(!req.method.is_empty(), "empty method for {:?}", buffer);
+            assert!(!req.path.is_empty(), "empty path for {:?}", buffer);
+            assert!(req.version.major <= 9 && req.version.minor <= 9, "bad version for {:?}", buffer);
+        }
+    }
+
+    #[test]
+    fn test_parse_valid_request() {
+        let req = parse_request(VALID.as_bytes()).expect("valid request rejected");
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.path, "/index.html");
+        assert_eq!(req.query, None);
+        assert_eq!(req.version, HttpVersion::HTTP_1_1);
+        assert!(req.keep_alive);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        let version = |raw: &str| parse_request(format!("GET / {}\r\n\r\n", raw).as_bytes()).map(|req| req.version);
+        assert!(version("HTTP/1.1").is_some_and(|v| v.is_1_1() && v.to_string() == "HTTP/1.1"));
+        assert!(version("HTTP/1.0").is_some_and(|v| v.is_1_0() && !v.is_1_1()));
+        // Parsed as such; dispatch answers it with 505.
+        assert_eq!(version("HTTP/2.0"), Some(HttpVersion { major: 2, minor: 0 }));
+        for bad in ["http/1.1", "HTTP/11", "HTTP/1.1.1", "HTTP/1.", "HTTP/a.b", "HTTP/1,1"] {
+            assert_eq!(version(bad), None, "{}", bad);
+        }
+    }
+
+    // Parse a request with the given extra header lines (each without the trailing CRLF).
+    fn with_headers(lines: &[&str]) -> Option<Request> {
+        let mut raw = String::from("POST /submit HTTP/1.1\r\nHost: localhost\r\n");
+        for line in lines {
+            raw.push_str(line);
+            raw.push_str("\r\n");
+        }
+        raw.push_str("\r\n");
+        return parse_request(raw.as_bytes());
+    }
+
+    #[test]
+    fn test_framing_accepted() {
+        let req = with_headers(&["Content-Length: 42"]).unwrap();
+        assert_eq!((req.content_length, req.chunked), (Some(42), false));
+        assert_eq!(req.header("content-length"), Some("42"));
+
+        let req = with_headers(&["Content-Length: 42, 42"]).unwrap();
+        assert_eq!(req.content_length, Some(42));
+
+        let req = with_headers(&["Transfer-Encoding: gzip, chunked"]).unwrap();
+        assert_eq!((req.content_length, req.chunked), (None, true));
+
+        let req = with_headers(&[]).unwrap();
+        assert_eq!((req.content_length, req.chunked), (None, false));
+    }
+
+    #[test]
+    fn test_reject_content_length_with_transfer_encoding() {
+        assert!(with_headers(&["Content-Length: 5", "Transfer-Encoding: chunked"]).is_none());
+        assert!(with_headers(&["Transfer-Encoding: chunked", "Content-Length: 5"]).is_none());
+    }
+
+    #[test]
+    fn test_reject_transfer_encoding_not_ending_in_chunked() {
+        assert!(with_headers(&["Transfer-Encoding: gzip"]).is_none());
+        assert!(with_headers(&["Transfer-Encoding: chunked, gzip"]).is_none());
+        assert!(with_headers(&["Transfer-Encoding: chunked", "Transfer-Encoding: gzip"]).is_none());
+    }
+
+    #[test]
+    fn test_reject_signed_content_length() {
+        assert!(with_headers(&["Content-Length: +5"]).is_none());
+        assert!(with_headers(&["Content-Length: -5"]).is_none());
+    }
+
+    #[test]
+    fn test_reject_non_decimal_content_length() {
+        assert!(with_headers(&["Content-Length: 0x10"]).is_none());
+        assert!(with_headers(&["Content-Length: 5 5"]).is_none());
+        assert!(with_headers(&["Content-Length: "]).is_none());
+        assert!(with_headers(&["Content-Length: 99999999999999999999999"]).is_none());
+    }
+
+    #[test]
+    fn test_reject_conflicting_content_lengths() {
+        assert!(with_headers(&["Content-Length: 5, 6"]).is_none());
+        assert!(with_headers(&["Content-Length: 5", "Content-Length: 6"]).is_none());
+        assert!(with_headers(&["Content-Length: 5,"]).is_none());
+    }
+
+    #[test]
+    fn test_reject_whitespace_before_colon() {
+        assert!(with_headers(&["Content-Length : 5"]).is_none());
+        assert!(with_headers(&["NoColonHere"]).is_none());
+    }
+
+    #[test]
+    fn test_absolute_form_http() {
+        let req = parse_request(b"GET http://example.com/about HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+        assert_eq!(req.target_form, TargetForm::Absolute);
+        assert_eq!(req.path, "/about");
+    }
+
+    #[test]
+    fn test_absolute_form_https_with_port_and_query() {
+        let req = parse_request(b"GET https://Example.com:8443/a/b?x=1 HTTP/1.1\r\nHost: example.com:8443\r\n\r\n").unwrap();
+        assert_eq!(req.target_form, TargetForm::Absolute);
+        assert_eq!(req.path, "/a/b");
+        assert_eq!(req.query.as_deref(), Some("x=1"));
+
+        let req = parse_request(b"GET http://example.com:8080 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.path, "/");
+    }
+
+    #[test]
+    fn test_absolute_form_host_mismatch() {
+        assert!(parse_request(b"GET http://example.com/ HTTP/1.1\r\nHost: evil.example\r\n\r\n").is_none());
+        assert!(parse_request(b"GET http://example.com:8080/ HTTP/1.1\r\nHost: example.com\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_malformed_targets() {
+        assert!(parse_request(b"GET ftp://example.com/ HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request(b"GET http:///about HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request(b"GET index.html HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request(b"CONNECT /about HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_authority_form() {
+        let req = parse_request(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n").unwrap();
+        assert_eq!(req.target_form, TargetForm::Authority);
+    }
+
+    #[test]
+    fn test_asterisk_form() {
+        let req = parse_request(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(req.target_form, TargetForm::Asterisk);
+        assert_eq!(req.path, "*");
+
+        assert!(parse_request(b"GET * HTTP/1.1\r\nHost: localhost\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_host_header() {
+        let host = |value: &str| Host::parse(value).map(|host| (host.name, host.port));
+        assert_eq!(host("Example.com:8080"), Some(("example.com".to_string(), Some(8080))));
+        assert_eq!(host("localhost"), Some(("localhost".to_string(), None)));
+        assert_eq!(host("localhost:"), Some(("localhost".to_string(), None)));
+        assert_eq!(host("[::1]:7878"), Some(("::1".to_string(), Some(7878))));
+        assert_eq!(host("[2001:DB8::1]"), Some(("2001:db8::1".to_string(), None)));
+        for bad in ["localhost:http", "localhost:99999", "localhost:-1", "::1", "[::1", "[::1]x", "[]", "a b", "evil/x", "user@host"] {
+            assert_eq!(host(bad), None, "{:?}", bad);
+        }
+
+        let req = parse_request(b"GET / HTTP/1.1\r\nHost: LocalHost:7878\r\n\r\n").unwrap();
+        assert_eq!(req.host, Some(Host { name: "localhost".to_string(), port: Some(7878) }));
+        assert_eq!(parse_request(b"GET / HTTP/1.0\r\n\r\n").unwrap().host, None);
+        let absolute = parse_request(b"GET http://Example.com:8080/a HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(absolute.host, Some(Host { name: "example.com".to_string(), port: Some(8080) }));
+        // A bad port, or two Host headers: 400.
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost: localhost:x\r\n\r\n").is_none());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost: a\r\nhost: b\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_request_line_single_spaces() {
+        let cases = [
+            ("GET /index.html HTTP/1.1", true),
+            ("GET  /index.html HTTP/1.1", false),
+            ("GET /index.html  HTTP/1.1", false),
+            (" GET /index.html HTTP/1.1", false),
+            ("GET /index.html HTTP/1.1 ", false),
+            ("GET\t/index.html HTTP/1.1", false),
+            ("GET /index.html\tHTTP/1.1", false),
+            ("GET /index.html HTTP/1.1 extra", false),
+            ("GET /index.html", false),
+            ("GET", false),
+            ("", false),
+        ];
+        for (line, valid) in cases {
+            let raw = format!("{}\r\nHost: localhost\r\n\r\n", line);
+            assert_eq!(parse_request(raw.as_bytes()).is_some(), valid, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_reject_control_and_whitespace_in_target() {
+        let cases: [&[u8]; 7] = [
+            b"GET /a\tb HTTP/1.1\r\n\r\n",
+            b"GET /a b HTTP/1.1\r\n\r\n",
+            b"GET /a\x01b HTTP/1.1\r\n\r\n",
+            b"GET /a\x7Fb HTTP/1.1\r\n\r\n",
+            b"GET /a<b>c HTTP/1.1\r\n\r\n",
+            b"GET /\"quoted\" HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1 extra\r\n\r\n",
+        ];
+        for raw in cases {
+            assert!(parse_request(raw).is_none(), "accepted {:?}", String::from_utf8_lossy(raw));
+        }
+
+        // Percent-encoded forms are fine; decoding happens later.
+        assert!(parse_request(b"GET /a%20b%3C%3E HTTP/1.1\r\n\r\n").is_some());
+    }
+
+    #[test]
+    fn test_fragment_stripped() {
+        let req = parse_request(b"GET /a#b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((req.path.as_str(), req.query.as_deref()), ("/a", None));
+
+        let req = parse_request(b"GET /a?x=1#b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((req.path.as_str(), req.query.as_deref()), ("/a", Some("x=1")));
+
+        // An escaped '#' is data, not a fragment delimiter.
+        let req = parse_request(b"GET /a%23b HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((req.path.as_str(), req.query.as_deref()), ("/a%23b", None));
+    }
+
+    #[test]
+    fn test_query_flag() {
+        let req = parse_request(b"GET /a.pdf?x=2&download=1 HTTP/1.1\r\n\r\n").unwrap();
+        assert!(req.has_query_flag("download", "1"));
+        assert!(!req.has_query_flag("x", "1"));
+        let req = parse_request(b"GET /a.pdf HTTP/1.1\r\n\r\n").unwrap();
+        assert!(!req.has_query_flag("download", "1"));
+    }
+
+    #[test]
+    fn test_query_pairs() {
+        let req = parse_request(b"GET /?q=a+b%26c&flag&&x=%ZZ HTTP/1.1\r\n\r\n").unwrap();
+        let pairs = req.query_pairs();
+        let expected = [("q", "a b&c"), ("flag", ""), ("x", "%ZZ")];
+        assert_eq!(pairs.len(), expected.len());
+        for ((name, value), (want_name, want_value)) in pairs.iter().zip(expected) {
+            assert_eq!((name.as_str(), value.as_str()), (want_name, want_value));
+        }
+        assert!(parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap().query_pairs().is_empty());
+    }
+
+    #[test]
+    fn test_typed_query() {
+        let req = parse_request(b"GET /?page=3&debug=1&verbose=false&limit=ten&tag=a&tag=b+c&q= HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.query_as::<u32>("page"), Ok(3));
+        assert_eq!(req.query_as::<bool>("debug"), Ok(true));
+        assert_eq!(req.query_as::<bool>("verbose"), Ok(false));
+        assert_eq!(req.query_as::<String>("q"), Ok(String::new()));
+        assert_eq!(req.query_as::<u32>("size"), Err(QueryError::Missing("size".to_string())));
+        let garbage = req.query_as::<u32>("limit");
+        assert_eq!(garbage, Err(QueryError::Invalid { name: "limit".to_string(), value: "ten".to_string() }));
+        assert_eq!(garbage.unwrap_err().to_string(), "invalid value \"ten\" for query parameter `limit`");
+        assert_eq!(req.query_as::<bool>("page"), Err(QueryError::Invalid { name: "page".to_string(), value: "3".to_string() }));
+        assert!(req.query_as::<i8>("q").is_err());
+
+        assert_eq!(req.query_or("size", 20u32), Ok(20));
+        assert_eq!(req.query_or("page", 1u32), Ok(3));
+        assert!(req.query_or("limit", 10u32).is_err());
+
+        assert_eq!(req.query_all("tag"), ["a", "b c"]);
+        assert!(req.query_all("size").is_empty());
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        let with = |value: &str| parse_request(format!("GET / HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", value).as_bytes()).unwrap();
+        assert!(with("\"abc\"").if_none_match("\"abc\""));
+        assert!(with("\"x\", W/\"abc\"").if_none_match("\"abc\""));
+        assert!(with("*").if_none_match("\"abc\""));
+        assert!(!with("\"abcd\"").if_none_match("\"abc\""));
+        assert!(!parse_request(b"GET / HTTP/1.1\r\n\r\n").unwrap().if_none_match("\"abc\""));
+    }
+
+    #[test]
+    fn test_if_match_and_unmodified_since() {
+        let with = |header: &str| parse_request(format!("PUT /f HTTP/1.1\r\n{}\r\n\r\n", header).as_bytes()).unwrap();
+        let unconditional = parse_request(b"PUT /f HTTP/1.1\r\n\r\n").unwrap();
+        assert!(unconditional.if_match(None) && unconditional.if_match(Some("\"abc\"")));
+        assert!(with("If-Match: \"x\", \"abc\"").if_match(Some("\"abc\"")));
+        assert!(!with("If-Match: \"abcd\"").if_match(Some("\"abc\"")));
+        assert!(!with("If-Match: W/\"abc\"").if_match(Some("\"abc\"")));
+        assert!(with("If-Match: *").if_match(Some("\"abc\"")));
+        assert!(!with("If-Match: *").if_match(None));
+
+        let modified = Some(UNIX_EPOCH + std::time::Duration::from_millis(784_111_777_500));
+        assert!(with("If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT").if_unmodified_since(modified));
+        assert!(!with("If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT").if_unmodified_since(modified));
+        assert!(with("If-Unmodified-Since: yesterday").if_unmodified_since(modified));
+        assert!(with("If-Unmodified-Since: Sun, 06 Nov 1994 08:49:36 GMT").if_unmodified_since(None));
+        assert!(unconditional.if_unmodified_since(modified));
+    }
+
+    #[test]
+    fn test_parse_accept() {
+        let cases: [(&str, &[(&str, f32)]); 8] = [
+            ("text/html", &[("text/html", 1.0)]),
+            ("*/*;q=0.1, application/json", &[("application/json", 1.0), ("*/*", 0.1)]),
+            ("text/*, text/html, */*", &[("text/html", 1.0), ("text/*", 1.0), ("*/*", 1.0)]),
+            ("Text/HTML;level=1;q=0.5, image/png", &[("image/png", 1.0), ("text/html", 0.5)]),
+            ("a/x;q=0.5, b/y;q=0.5, */*;q=0.1", &[("a/x", 0.5), ("b/y", 0.5), ("*/*", 0.1)]),
+            ("text/html;q=0, */*", &[("*/*", 1.0), ("text/html", 0.0)]),
+            // Malformed: accept anything.
+            ("text/html;q=2", &[("*/*", 1.0)]),
+            ("html, application/json", &[("*/*", 1.0)]),
+        ];
+        for (header, expected) in cases {
+            let parsed = parse_accept(header);
+            let parsed: Vec<(&str, f32)> = parsed.iter().map(|(range, q)| (range.as_str(), *q)).collect();
+            assert_eq!(parsed, expected, "{}", header);
+        }
+        assert_eq!(parse_accept(""), [("*/*".to_string(), 1.0)]);
+        assert_eq!(parse_accept("*/html"), [("*/*".to_string(), 1.0)]);
+        assert_eq!(parse_accept("text/html;q=abc"), [("*/*".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn test_prefers() {
+        const OFFERED: &[&str] = &["text/html", "application/json"];
+        let cases = [
+            (None, Some("text/html")),
+            (Some("application/json"), Some("application/json")),
+            (Some("application/json, */*;q=0.1"), Some("application/json")),
+            (Some("*/*;q=0.1, application/json"), Some("application/json")),
+            (Some("text/html, application/xhtml+xml, */*;q=0.8"), Some("text/html")),
+            (Some("application/*"), Some("application/json")),
+            (Some("*/*"), Some("text/html")),
+            // Equal q: the server's order decides.
+            (Some("application/json, text/html"), Some("text/html")),
+            // The most specific range wins, even with a lower q than a broader one.
+            (Some("*/*, text/html;q=0"), Some("application/json")),
+            (Some("image/png"), None),
+            (Some("text/html;q=0, application/json;q=0"), None),
+            (Some("not a media type"), Some("text/html")),
+        ];
+        for (accept, expected) in cases {
+            let raw = match accept {
+                Some(accept) => format!("GET /about HTTP/1.1\r\nAccept: {}\r\n\r\n", accept),
+                None => "GET /about HTTP/1.1\r\n\r\n".to_string(),
+            };
+            let req = parse_request(raw.as_bytes()).unwrap();
+            assert_eq!(req.prefers(OFFERED), expected, "{:?}", accept);
+        }
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let crlf = "GET /a HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi";
+        let lf = "GET /a HTTP/1.1\nHost: localhost\nContent-Length: 2\n\nhi";
+        let mixed = "GET /a HTTP/1.1\r\nHost: localhost\nContent-Length: 2\r\n\nhi";
+        for (raw, head_len) in [(crlf, 55), (lf, 51), (mixed, 53)] {
+            assert_eq!(find_header_end(raw.as_bytes(), true), Some(head_len), "{:?}", raw);
+            let req = parse_request_with(raw.as_bytes(), true).unwrap();
+            assert_eq!((req.path.as_str(), req.header("Host"), req.content_length), ("/a", Some("localhost"), Some(2)));
+        }
+
+        // Strict: only CRLF. A bare LF never ends the head and fails the parse (400).
+        assert_eq!(find_header_end(crlf.as_bytes(), false), Some(55));
+        assert!(parse_request_with(crlf.as_bytes(), false).is_some());
+        for raw in [lf, mixed] {
+            assert_eq!(find_header_end(raw.as_bytes(), false), None, "{:?}", raw);
+            assert!(has_bare_lf(raw.as_bytes()));
+            assert!(parse_request_with(raw.as_bytes(), false).is_none(), "{:?}", raw);
+        }
+        assert!(!has_bare_lf(crlf.as_bytes()));
+    }
+
+    #[test]
+    fn test_keep_alive_defaults_by_version() {
+        let keep_alive = |raw: &[u8]| parse_request(raw).unwrap().keep_alive;
+        assert!(keep_alive(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: keep-alive, close\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_random_bytes() {
+        let mut rng = Rng(0x5EED_0001);
+        for _ in 0..5000 {
+            let len = rng.below(512);
+            let buffer: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            check(&buffer);
+        }
+    }
+
+    #[test]
+    fn test_random_fragment_permutations() {
+        let fragments = [
+            "GET", "POST", " ", "  ", "/", "/about", "*", "HTTP/1.1", "HTTP/", "http/1.0",
+            "\r\n", "\n", "\r\n\r\n", "Connection:", " keep-alive", "Host: localhost", ":", "\t", "\0",
+        ];
+        let mut rng = Rng(0x5EED_0002);
+        for _ in 0..5000 {
+            let count = rng.below(12);
+            let buffer: String = (0..count).map(|_| fragments[rng.below(fragments.len())]).collect();
+            check(buffer.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_truncation_at_every_offset() {
+        let bytes = VALID.as_bytes();
+        for end in 0..=bytes.len() {
+            check(&bytes[..end]);
+        }
+    }
+
+    #[test]
+    fn test_extremely_long_tokens() {
+        let long = "A".repeat(1 << 20);
+        check(format!("{} / HTTP/1.1\r\n\r\n", long).as_bytes());
+        check(format!("GET /{} HTTP/1.1\r\n\r\n", long).as_bytes());
+        check(format!("GET / HTTP/{}\r\n\r\n", long).as_bytes());
+        check(format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", long).as_bytes());
+    }
+
+    #[test]
+    fn test_full_buffer_matches_head_only() {
+        let mut rng = Rng(0x5EED_0003);
+        for _ in 0..1000 {
+            // A valid head followed by a random (often non-UTF-8) body.
+            let mut buffer = VALID.as_bytes().to_vec();
+            let head_len = buffer.len();
+            let body_len = rng.below(256);
+            buffer.extend((0..body_len).map(|_| rng.next() as u8));
+
+            let full = parse_request(&buffer);
+            let head = parse_request(&buffer[..find_header_end(&buffer, true).unwrap()]);
+            assert_eq!(find_header_end(&buffer, true), Some(head_len));
+            assert!(full.is_some(), "binary body broke parsing");
+            assert_eq!(full, head);
+        }
+    }
+}
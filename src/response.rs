@@ -1,13 +1,715 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use crate::handlers;
+use crate::request::QueryError;
+use crate::log::{error, warn};
+
+/*
+The security_headers preset. Strict-Transport-Security belongs here too, but only over TLS, and
+this server has no TLS listener (yet): sending it over plain HTTP is meaningless at best.
+*/
+pub(crate) const SECURITY_HEADERS: [(&str, &str); 3] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "DENY"),
+    ("Referrer-Policy", "no-referrer"),
+];
+
+// Framing and connection-management headers, which only the server itself may set (RFC 7230 §6.1).
+const HOP_BY_HOP: [&str; 7] = ["Connection", "Transfer-Encoding", "Content-Length", "Keep-Alive", "TE", "Trailer", "Upgrade"];
+
+// A header field name: one or more token characters (RFC 7230 §3.2.6).
+fn is_token(name: &str) -> bool {
+    return !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+}
+
+// Check the [response_headers] table: legal names, values on one line, no framing headers.
+pub(crate) fn validate_response_headers<'a>(headers: impl IntoIterator<Item = (&'a String, &'a String)>) -> Result<(), String> {
+    for (name, value) in headers {
+        if !is_token(name) {
+            return Err(format!("response_headers: {:?} is not a valid header name", name));
+        }
+        if HOP_BY_HOP.iter().any(|hop| hop.eq_ignore_ascii_case(name)) {
+            return Err(format!("response_headers: {} is managed by the server and can't be set", name));
+        }
+        if value.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(format!("response_headers: the value of {} must not contain CR, LF or other control characters", name));
+        }
+    }
+    return Ok(());
+}
+
+// Bodies up to this size are sent in the same buffer as the head (see Response::write_to).
+const COALESCE_LIMIT: usize = 16 * 1024;
+
+// Fields that must never be sent as trailers: framing, routing, auth and the like (RFC 7230 §4.1.2).
+const FORBIDDEN_TRAILERS: [&str; 15] = [
+    "Content-Length", "Transfer-Encoding", "Trailer", "TE", "Host", "Content-Encoding", "Content-Type",
+    "Content-Range", "Authorization", "Set-Cookie", "Cache-Control", "Expect", "Max-Forwards", "Pragma", "Range",
+];
+
+/**
+The bytes of a response body: a buffer of its own, or a range of one shared with others (a
+cached static file), which is sent from where it is. Either way it reads as a [u8], so only
+code that replaces the body has to know the difference.
+*/
+#[derive(Clone)]
+pub enum BodyBytes {
+    Owned(Vec<u8>),
+    Shared(Arc<[u8]>, Range<usize>),
+}
+
+impl BodyBytes {
+    /// All of `bytes`, shared rather than copied.
+    pub fn shared(bytes: Arc<[u8]>) -> BodyBytes {
+        let len = bytes.len();
+        return BodyBytes::Shared(bytes, 0..len);
+    }
+
+    /// Just `range` of the body. A shared body narrows its range; an owned one is cut down in place.
+    pub fn slice(self, range: Range<usize>) -> BodyBytes {
+        return match self {
+            BodyBytes::Owned(mut bytes) => {
+                bytes.truncate(range.end);
+                bytes.drain(..range.start);
+                BodyBytes::Owned(bytes)
+            }
+            BodyBytes::Shared(bytes, within) => BodyBytes::Shared(bytes, within.start + range.start..within.start + range.end),
+        };
+    }
+
+    /// The bytes as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        return match self {
+            BodyBytes::Owned(bytes) => bytes,
+            BodyBytes::Shared(bytes, range) => &bytes[range.clone()],
+        };
+    }
+
+    /// The bytes as a Vec of their own (a copy only if they are shared).
+    pub fn into_vec(self) -> Vec<u8> {
+        return match self {
+            BodyBytes::Owned(bytes) => bytes,
+            shared => shared.as_slice().to_vec(),
+        };
+    }
+}
+
+impl Default for BodyBytes {
+    fn default() -> BodyBytes {
+        return BodyBytes::Owned(Vec::new());
+    }
+}
+
+impl Deref for BodyBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return self.as_slice();
+    }
+}
+
+impl AsRef<[u8]> for BodyBytes {
+    fn as_ref(&self) -> &[u8] {
+        return self.as_slice();
+    }
+}
+
+impl fmt::Debug for BodyBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return self.as_slice().fmt(f);
+    }
+}
+
+impl<T: AsRef<[u8]> + ?Sized> PartialEq<T> for BodyBytes {
+    fn eq(&self, other: &T) -> bool {
+        return self.as_slice() == other.as_ref();
+    }
+}
+
+impl<T: Into<Vec<u8>>> From<T> for BodyBytes {
+    fn from(bytes: T) -> BodyBytes {
+        return BodyBytes::Owned(bytes.into());
+    }
+}
+
+/**
+A body whose length isn't known up front: called once, it writes the body piece by piece.
+HTTP/1.1 clients get it with Transfer-Encoding: chunked, each write becoming one chunk;
+HTTP/1.0 clients get it as-is, delimited by closing the connection.
+*/
+pub type BodyStream = Box<dyn FnMut(&mut BodyWriter) -> io::Result<()> + Send>;
+
+/**
+What a BodyStream writes to. Besides the body itself, it can add trailers: header fields sent
+after the last chunk, for values only known once the body is complete (a checksum, say).
+They must be announced up front in a Trailer header; see Response::write_to for what is sent.
+*/
+pub struct BodyWriter<'a> {
+    out: &'a mut dyn Write,
+    trailers: Vec<(String, String)>,
+}
+
+impl BodyWriter<'_> {
+    /// Queue a trailer field, sent after the last chunk.
+    pub fn trailer(&mut self, name: &str, value: &str) {
+        self.trailers.push((name.to_string(), value.to_string()));
+    }
+}
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.out.write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.out.flush();
+    }
+}
+
+/// The status codes the server sends. The discriminant is the code.
 #[repr(u16)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum HTTPStatus {
     Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NoContent = 204,
+    PartialContent = 206,
+    MovedPermanently = 301,
+    NotModified = 304,
     BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
     NotFound = 404,
     MethodNotAllowed = 405,
     RequestTimeout = 408,
+    PreconditionFailed = 412,
     ContentTooLarge = 413,
-    ServiceUnavailable = 503
+    RangeNotSatisfiable = 416,
+    MisdirectedRequest = 421,
+    TooManyRequests = 429,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    ServiceUnavailable = 503,
+    HttpVersionNotSupported = 505
+}
+
+impl HTTPStatus {
+    /// The standard reason phrase sent after the numeric code in the status line.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            HTTPStatus::Ok => "OK",
+            HTTPStatus::Created => "Created",
+            HTTPStatus::Accepted => "Accepted",
+            HTTPStatus::NoContent => "No Content",
+            HTTPStatus::PartialContent => "Partial Content",
+            HTTPStatus::MovedPermanently => "Moved Permanently",
+            HTTPStatus::NotModified => "Not Modified",
+            HTTPStatus::BadRequest => "Bad Request",
+            HTTPStatus::Unauthorized => "Unauthorized",
+            HTTPStatus::Forbidden => "Forbidden",
+            HTTPStatus::NotFound => "Not Found",
+            HTTPStatus::MethodNotAllowed => "Method Not Allowed",
+            HTTPStatus::RequestTimeout => "Request Timeout",
+            HTTPStatus::PreconditionFailed => "Precondition Failed",
+            HTTPStatus::ContentTooLarge => "Content Too Large",
+            HTTPStatus::RangeNotSatisfiable => "Range Not Satisfiable",
+            HTTPStatus::MisdirectedRequest => "Misdirected Request",
+            HTTPStatus::TooManyRequests => "Too Many Requests",
+            HTTPStatus::InternalServerError => "Internal Server Error",
+            HTTPStatus::NotImplemented => "Not Implemented",
+            HTTPStatus::ServiceUnavailable => "Service Unavailable",
+            HTTPStatus::HttpVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+
+    /// The status for a numeric code, if it is one this server knows.
+    pub fn from_code(code: u16) -> Option<HTTPStatus> {
+        let status = match code {
+            200 => HTTPStatus::Ok,
+            201 => HTTPStatus::Created,
+            202 => HTTPStatus::Accepted,
+            204 => HTTPStatus::NoContent,
+            206 => HTTPStatus::PartialContent,
+            301 => HTTPStatus::MovedPermanently,
+            304 => HTTPStatus::NotModified,
+            400 => HTTPStatus::BadRequest,
+            401 => HTTPStatus::Unauthorized,
+            403 => HTTPStatus::Forbidden,
+            404 => HTTPStatus::NotFound,
+            405 => HTTPStatus::MethodNotAllowed,
+            408 => HTTPStatus::RequestTimeout,
+            412 => HTTPStatus::PreconditionFailed,
+            413 => HTTPStatus::ContentTooLarge,
+            416 => HTTPStatus::RangeNotSatisfiable,
+            421 => HTTPStatus::MisdirectedRequest,
+            429 => HTTPStatus::TooManyRequests,
+            500 => HTTPStatus::InternalServerError,
+            501 => HTTPStatus::NotImplemented,
+            503 => HTTPStatus::ServiceUnavailable,
+            505 => HTTPStatus::HttpVersionNotSupported,
+            _ => return None,
+        };
+        return Some(status);
+    }
+}
+
+/**
+A response's status: one of the codes listed in HTTPStatus, or any other code in 100..=599 with
+its own reason phrase (a 418, a vendor 5xx relayed from an upstream). Compares equal to an
+HTTPStatus with the same code, so `response.status == HTTPStatus::Ok` works either way.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    Known(HTTPStatus),
+    Custom(u16, String),
+}
+
+impl Status {
+    /// The numeric code, e.g. 404.
+    pub fn code(&self) -> u16 {
+        return match self {
+            Status::Known(status) => *status as u16,
+            Status::Custom(code, _) => *code,
+        };
+    }
+
+    /// The reason phrase sent after the code in the status line.
+    pub fn reason_phrase(&self) -> &str {
+        return match self {
+            Status::Known(status) => status.reason_phrase(),
+            Status::Custom(_, reason) => reason,
+        };
+    }
+
+    /// The class: 1 for informational up to 5 for server errors.
+    pub fn class(&self) -> u16 {
+        return self.code() / 100;
+    }
+}
+
+impl From<HTTPStatus> for Status {
+    fn from(status: HTTPStatus) -> Status {
+        return Status::Known(status);
+    }
+}
+
+impl PartialEq<HTTPStatus> for Status {
+    fn eq(&self, other: &HTTPStatus) -> bool {
+        return self.code() == *other as u16;
+    }
+}
+
+// As in the status line: "404 Not Found".
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{} {}", self.code(), self.reason_phrase());
+    }
+}
+
+/**
+A response under construction, for the cases build_response() can't express: extra headers
+(Allow, Location, ...) or no body at all. Content-Type, when there is a body, is just another
+header.
+
+```ignore
+Response::new(HTTPStatus::NoContent).header("Allow", "GET, POST").to_bytes()
+```
+
+Content-Length is computed when serializing, so it can never disagree with the body. A streamed
+body (see stream()) has no Content-Length at all.
+*/
+pub struct Response {
+    /// Protocol in the status line. Always HTTP/1.1 unless configured to mirror an HTTP/1.0 client.
+    pub version: &'static str,
+    /// Status code, and with it the reason phrase.
+    pub status: Status,
+    /// Header (name, value) pairs in the order they are sent. Content-Length is added when serializing.
+    pub headers: Vec<(String, String)>,
+    /// The body, unless `stream` is set.
+    pub body: BodyBytes,
+    /// Set instead of `body` when the length isn't known in advance.
+    pub stream: Option<BodyStream>,
+    /// Marks routine responses in the access log ($tag), e.g. "favicon"; those are logged at debug level.
+    pub log_tag: Option<&'static str>,
+    /// Send the head only (the answer to a HEAD request). Content-Length still counts the body a GET would get.
+    pub head_only: bool,
+}
+
+impl Response {
+    /// An HTTP/1.1 response with this status, no headers and no body.
+    pub fn new(status: HTTPStatus) -> Response {
+        return Response { version: "HTTP/1.1", status: Status::Known(status), headers: Vec::new(), body: BodyBytes::default(), stream: None, log_tag: None, head_only: false };
+    }
+
+    /**
+    A response with a status HTTPStatus doesn't list, such as 418 or an upstream's vendor 5xx.
+    The code must be within 100..=599 and the reason phrase on one line. A listed code gets that
+    status, standard reason phrase included.
+    */
+    pub fn with_status_code(code: u16, reason: &str) -> Result<Response, String> {
+        if !(100..=599).contains(&code) {
+            return Err(format!("status code {} is outside 100..=599", code));
+        }
+        if reason.chars().any(|c| c.is_control() && c != '\t') {
+            return Err(format!("the reason phrase of status {} must not contain CR, LF or other control characters", code));
+        }
+        let mut response = Response::new(HTTPStatus::Ok);
+        response.status = match HTTPStatus::from_code(code) {
+            Some(status) => Status::Known(status),
+            None => Status::Custom(code, reason.to_string()),
+        };
+        return Ok(response);
+    }
+
+    /**
+    Read back a response that was already serialized (build_response() output): status line,
+    headers and body. Content-Length is dropped; it is recomputed when the response is sent.
+    None if it doesn't look like a response, or its status code is outside 100..=599.
+    */
+    pub fn from_serialized(bytes: &[u8]) -> Option<Response> {
+        let end = bytes.windows(4).position(|w| w == b"\r\n\r\n")?;
+        let head = std::str::from_utf8(&bytes[..end]).ok()?;
+        let mut lines = head.split("\r\n");
+        let mut status_line = lines.next()?.splitn(3, ' ');
+        let version = status_line.next()?;
+        if !version.starts_with("HTTP/") {
+            return None;
+        }
+        let code = status_line.next()?.parse().ok()?;
+        let mut response = Response::with_status_code(code, status_line.next().unwrap_or_default()).ok()?;
+        for line in lines {
+            let (name, value) = line.split_once(':')?;
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                response = response.header(name.trim(), value.trim());
+            }
+        }
+        response.body = bytes[end + 4..].into();
+        return Some(response);
+    }
+
+    /// Append a header. Builder-style, so calls can be chained.
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        return self;
+    }
+
+    /**
+    Add `name` to Vary: the response depends on that request header, so a shared cache has to
+    key on it too. All the Vary headers already there, a handler's own included, are folded into
+    one comma-separated list with each name once (compared ignoring case). "*" says the response
+    varies on everything and absorbs the rest.
+    */
+    pub fn add_vary(&mut self, name: &str) {
+        let mut members: Vec<String> = Vec::new();
+        let existing = self.headers.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Vary"))
+            .flat_map(|(_, value)| value.split(','));
+        for member in existing.chain([name]).map(str::trim).filter(|member| !member.is_empty()) {
+            if !members.iter().any(|known| known.eq_ignore_ascii_case(member)) {
+                members.push(member.to_string());
+            }
+        }
+        if members.iter().any(|member| member == "*") {
+            members = vec!["*".to_string()];
+        }
+        self.headers.retain(|(key, _)| !key.eq_ignore_ascii_case("Vary"));
+        self.headers.push(("Vary".to_string(), members.join(", ")));
+    }
+
+    /// add_vary(), builder-style.
+    pub fn vary(mut self, name: &str) -> Response {
+        self.add_vary(name);
+        return self;
+    }
+
+    /// Tag the response for the access log (see `log_tag`).
+    pub fn log_tag(mut self, tag: &'static str) -> Response {
+        self.log_tag = Some(tag);
+        return self;
+    }
+
+    /// Value of the first header called `name` (case-insensitive), if any.
+    pub fn header_value(&self, name: &str) -> Option<&str> {
+        return self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str());
+    }
+
+    /// Set the body together with its Content-Type.
+    pub fn body(mut self, content_type: &str, body: impl Into<BodyBytes>) -> Response {
+        self.body = body.into();
+        return self.header("Content-Type", content_type);
+    }
+
+    /// Stream the body from `write_body` instead of sending a buffer (see BodyStream).
+    pub fn stream<F>(mut self, content_type: &str, write_body: F) -> Response
+    where
+        F: FnMut(&mut BodyWriter) -> io::Result<()> + Send + 'static,
+    {
+        self.stream = Some(Box::new(write_body));
+        return self.header("Content-Type", content_type);
+    }
+
+    /// True if the body goes out with chunked framing (finalize() decides, by adding the header).
+    pub fn is_chunked(&self) -> bool {
+        return self.header_value("Transfer-Encoding").is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+    }
+
+    /// The status line and headers, up to and including the blank line. The body is not included.
+    pub fn head_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{} {}\r\n", self.version, self.status);
+
+        // 1xx and 204 responses must not carry Content-Length (RFC 7230 §3.3.2), a 304's would describe
+        // the body it doesn't send, and a streamed body's length is unknown; everything else has one.
+        let bodiless = matches!(self.status.code(), 100..=199 | 204 | 304);
+        if !bodiless && self.stream.is_none() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        return head.into_bytes();
+    }
+
+    /// Serialize the status line, headers and body into one buffer (copies the body once).
+    /// A streamed body isn't included; only write_to() runs the stream. Neither is a head_only one's.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let head = self.head_bytes();
+        if self.head_only {
+            return head;
+        }
+        let mut bytes = Vec::with_capacity(head.len() + self.body.len());
+        bytes.extend_from_slice(&head);
+        bytes.extend_from_slice(&self.body);
+        return bytes;
+    }
+
+    /**
+    Write the response to `w` (a socket, a Vec, ...). A large body is written straight from its
+    own buffer after the head, so a multi-megabyte file is never copied just to be sent. Small
+    responses still go out as one buffer: two tiny writes can stall on Nagle's algorithm and
+    delayed ACKs, and copying a few KB costs nothing.
+    A streamed body is written as it is produced, after the head; it can only be sent once. Its
+    trailers follow the last chunk, if they were declared in the Trailer header and may appear
+    there (see allowed_trailers()); without chunked framing there is nowhere to put them.
+    A head_only response is just its head: the body, streamed or not, is never written.
+    */
+    pub fn write_to<W: Write>(&mut self, w: &mut W) -> io::Result<()> {
+        if self.head_only {
+            return w.write_all(&self.head_bytes());
+        }
+        if self.stream.is_some() {
+            // The head first: without the stream it would announce Content-Length: 0.
+            w.write_all(&self.head_bytes())?;
+            let Some(mut write_body) = self.stream.take() else { return Ok(()) };
+            if !self.is_chunked() {
+                return write_body(&mut BodyWriter { out: w, trailers: Vec::new() });
+            }
+            let mut chunked = ChunkedWriter::new(w);
+            let mut body = BodyWriter { out: &mut chunked, trailers: Vec::new() };
+            write_body(&mut body)?;
+            let trailers = allowed_trailers(self.header_value("Trailer"), body.trailers);
+            return chunked.finish(&trailers);
+        }
+        if self.body.len() <= COALESCE_LIMIT {
+            return w.write_all(&self.to_bytes());
+        }
+        w.write_all(&self.head_bytes())?;
+        return w.write_all(&self.body);
+    }
+}
+
+/**
+Anything a handler may return. The router turns it into a Response, so trivial handlers can
+return just their HTML:
+
+```ignore
+fn about(_req: &Request) -> &'static str { "<h1>About us</h1>" }
+```
+
+- Response: as is.
+- Vec<u8>: an already serialized response (what handlers used to return); 500 if it isn't one.
+- &'static str / String: 200 with that HTML (text/html; charset=utf-8).
+- (HTTPStatus, String): that status with that HTML.
+- HTTPStatus: the standard response for it (handlers::error(); 204 without a body).
+- QueryError: 400 with the error's message (see Request::query_as()).
+- Result<_, E>: the Ok value, or the response for the error, e.g. HTTPStatus or QueryError.
+*/
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+const HTML_UTF8: &str = "text/html; charset=utf-8";
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        return self;
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        return Response::from_serialized(&self).unwrap_or_else(|| {
+            error!("❌ Handler returned bytes that aren't a serialized response ({} bytes).", self.len());
+            handlers::internal_server_error()
+        });
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        return Response::new(HTTPStatus::Ok).body(HTML_UTF8, self);
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        return Response::new(HTTPStatus::Ok).body(HTML_UTF8, self);
+    }
+}
+
+impl IntoResponse for (HTTPStatus, String) {
+    fn into_response(self) -> Response {
+        return Response::new(self.0).body(HTML_UTF8, self.1);
+    }
+}
+
+impl IntoResponse for HTTPStatus {
+    fn into_response(self) -> Response {
+        if self == HTTPStatus::NoContent {
+            return Response::new(self);
+        }
+        return handlers::error(self);
+    }
+}
+
+// A 400 that names the parameter, so the client knows what to fix.
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response {
+        return Response::new(HTTPStatus::BadRequest).body("text/plain; charset=utf-8", format!("400 Bad Request: {}\n", self));
+    }
+}
+
+impl<R: IntoResponse, E: IntoResponse> IntoResponse for Result<R, E> {
+    fn into_response(self) -> Response {
+        return match self {
+            Ok(response) => response.into_response(),
+            Err(status) => status.into_response(),
+        };
+    }
+}
+
+/*
+Chunked transfer coding (RFC 7230 §4.1) over another writer: every non-empty write() becomes one
+chunk, "<size in hex>\r\n<data>\r\n", sent with a single write to the inner writer. finish()
+sends the terminating zero-size chunk and the trailers; without it the client keeps waiting for more.
+*/
+pub(crate) struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub(crate) fn new(inner: W) -> ChunkedWriter<W> {
+        return ChunkedWriter { inner };
+    }
+
+    // Write the last chunk, then the trailer fields and the blank line that ends the message.
+    pub(crate) fn finish(mut self, trailers: &[(String, String)]) -> io::Result<()> {
+        let mut end = String::from("0\r\n");
+        for (name, value) in trailers {
+            end.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        end.push_str("\r\n");
+        self.inner.write_all(end.as_bytes())?;
+        return self.inner.flush();
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A zero-size chunk would end the body, so empty writes send nothing.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut chunk = format!("{:X}\r\n", buf.len()).into_bytes();
+        chunk.extend_from_slice(buf);
+        chunk.extend_from_slice(b"\r\n");
+        self.inner.write_all(&chunk)?;
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+/*
+The trailers a stream registered that may actually be sent: the name must be listed in the
+response's Trailer header and not be a forbidden field, and the value must not contain CR/LF.
+Anything else is dropped with a warning. Clients that didn't send "TE: trailers" may ignore them,
+which is harmless.
+*/
+pub(crate) fn allowed_trailers(declared: Option<&str>, trailers: Vec<(String, String)>) -> Vec<(String, String)> {
+    let declared: Vec<&str> = declared.unwrap_or("").split(',').map(str::trim).collect();
+    return trailers.into_iter()
+        .filter(|(name, value)| {
+            let reason = if FORBIDDEN_TRAILERS.iter().any(|f| f.eq_ignore_ascii_case(name)) {
+                "not allowed as a trailer"
+            } else if !declared.iter().any(|d| d.eq_ignore_ascii_case(name)) {
+                "not declared in the Trailer header"
+            } else if value.contains(['\r', '\n']) {
+                "value contains a line break"
+            } else {
+                return true;
+            };
+            warn!("⚠️ Dropping trailer {}: {}", name, reason);
+            return false;
+        })
+        .collect();
+}
+
+/*
+Value of a Content-Disposition header that makes browsers save the response as `filename`
+instead of rendering it (RFC 6266).
+
+The quoted filename="..." form is always present: control characters are dropped (no header
+injection), '"' and '\\' are backslash-escaped, and non-ASCII characters become '_'. If the name
+isn't plain ASCII, an RFC 5987 filename*=UTF-8''... parameter carries the exact name as well;
+clients that understand it prefer it over the fallback.
+*/
+pub(crate) fn content_disposition_attachment(filename: &str) -> String {
+    let mut fallback = String::new();
+    for c in filename.chars().filter(|c| !c.is_control()) {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            c if c.is_ascii() => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+
+    if filename.is_ascii() {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+
+    // attr-char from RFC 5987; everything else is percent-encoded as UTF-8.
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    return format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded);
 }
 
 /*
@@ -20,36 +722,275 @@ Build a full HTTP response from a status line and body string.
 
 # Returns
 
-* A `String` representing the complete HTTP response to be sent to the client.
+* The complete HTTP response, as the bytes to be sent to the client.
+
+Only tests and benchmarks still use it: the server's own fixed responses are canned (canned.rs).
 */
-pub fn build_response(
+#[cfg(test)]
+pub(crate) fn build_response(
     status_code: HTTPStatus,
     reason_phrase: &str,
     content_type: &str,
     body: &str
 ) -> Vec<u8> {
-    // Compose the HTTP response headers and body
-    let response = format!(
-        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nContent-Type: {}\r\n\r\n{}",
+    // Compose the HTTP response headers; the body is appended as bytes, not formatted in.
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nContent-Type: {}\r\n\r\n",
         status_code as u16, // cast to int instead of implementing ‘Display’ trait for the enum (something like repr)
         reason_phrase,
         body.len(),
-        content_type,
-        body
+        content_type
     );
 
     // Return response as bytes for sending
-    return response.into_bytes();
+    let mut response = Vec::with_capacity(head.len() + body.len());
+    response.extend_from_slice(head.as_bytes());
+    response.extend_from_slice(body.as_bytes());
+    return response;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_query_error_response() {
+        let missing: Result<Response, QueryError> = Err(QueryError::Missing("page".to_string()));
+        let response = missing.into_response();
+        assert_eq!(response.status, HTTPStatus::BadRequest);
+        assert_eq!(response.body, b"400 Bad Request: missing query parameter `page`\n");
+    }
+
+    #[test]
+    fn test_custom_status_code() {
+        let teapot = Response::with_status_code(418, "I'm a teapot").unwrap().body("text/plain", "short and stout");
+        assert_eq!(teapot.to_bytes(), b"HTTP/1.1 418 I'm a teapot\r\nContent-Length: 15\r\nContent-Type: text/plain\r\n\r\nshort and stout");
+        assert_eq!((teapot.status.to_string(), teapot.status.class()), ("418 I'm a teapot".to_string(), 4));
+
+        // A listed code is the listed status, with its standard reason phrase.
+        let ok = Response::with_status_code(200, "Fine").unwrap();
+        assert_eq!((ok.status.clone(), ok.status.reason_phrase()), (Status::Known(HTTPStatus::Ok), "OK"));
+        assert!(Response::from_serialized(b"HTTP/1.1 599 Vendor Thing\r\n\r\n").is_some_and(|r| r.status.to_string() == "599 Vendor Thing"));
+
+        assert!(Response::with_status_code(99, "Too Low").is_err());
+        assert!(Response::with_status_code(600, "Too High").is_err());
+        assert!(Response::with_status_code(299, "Split\r\nSet-Cookie: x=1").is_err());
+        assert!(Response::from_serialized(b"HTTP/1.1 600 Nope\r\n\r\n").is_none());
+    }
+
     #[test]
     fn test_response_formatting() {
         let resp = build_response(HTTPStatus::Ok, "OK", "text/html", "200 OK");
         let text = String::from_utf8_lossy(&resp);
         assert!(text.contains("200 OK"));
     }
+
+    #[test]
+    fn test_into_response() {
+        // (status line, Content-Type, body) of what each kind of return value becomes.
+        let parts = |response: Response| {
+            let text = String::from_utf8(response.to_bytes()).unwrap();
+            let status_line = text.split("\r\n").next().unwrap().to_string();
+            return (status_line, response.header_value("Content-Type").map(str::to_string), response.body.into_vec());
+        };
+        let html = Some("text/html; charset=utf-8".to_string());
+        let plain = Some("text/plain".to_string());
+
+        assert_eq!(parts("<p>hi</p>".into_response()), ("HTTP/1.1 200 OK".to_string(), html.clone(), b"<p>hi</p>".to_vec()));
+        assert_eq!(parts(String::from("<p>x</p>").into_response()), ("HTTP/1.1 200 OK".to_string(), html.clone(), b"<p>x</p>".to_vec()));
+        assert_eq!(
+            parts((HTTPStatus::Created, "<p>made</p>".to_string()).into_response()),
+            ("HTTP/1.1 201 Created".to_string(), html.clone(), b"<p>made</p>".to_vec())
+        );
+        assert_eq!(
+            parts(HTTPStatus::NotFound.into_response()),
+            ("HTTP/1.1 404 Not Found".to_string(), plain.clone(), b"404 Not Found".to_vec())
+        );
+        assert_eq!(parts(HTTPStatus::NoContent.into_response()), ("HTTP/1.1 204 No Content".to_string(), None, Vec::new()));
+        assert_eq!(
+            parts(Err::<Response, _>(HTTPStatus::BadRequest).into_response()),
+            ("HTTP/1.1 400 Bad Request".to_string(), plain.clone(), b"400 Bad Request".to_vec())
+        );
+        let response = Response::new(HTTPStatus::Accepted).body("application/json", "{}");
+        assert_eq!(parts(Ok::<_, HTTPStatus>(response).into_response()).0, "HTTP/1.1 202 Accepted");
+
+        // Pre-serialized bytes survive the round trip unchanged.
+        let bytes = build_response(HTTPStatus::Forbidden, "Forbidden", "text/plain", "go away");
+        assert_eq!(bytes.clone().into_response().to_bytes(), bytes);
+        assert_eq!(b"garbage".to_vec().into_response().status, HTTPStatus::InternalServerError);
+    }
+
+    #[test]
+    fn test_validate_response_headers() {
+        let check = |name: &str, value: &str| validate_response_headers([(&name.to_string(), &value.to_string())]);
+        assert!(check("X-Served-By", "edge-3").is_ok());
+        assert!(check("X-Note", "a\tb").is_ok());
+        assert!(check("X Served", "x").unwrap_err().contains("not a valid header name"));
+        assert!(check("", "x").is_err());
+        assert!(check("X-Evil", "a\r\nSet-Cookie: x=1").unwrap_err().contains("CR, LF"));
+        for hop in ["Connection", "transfer-encoding", "Content-Length"] {
+            assert!(check(hop, "x").unwrap_err().contains("managed by the server"), "{}", hop);
+        }
+    }
+
+    // Records every write separately, to see how a response was split up.
+    struct Recorder(Vec<Vec<u8>>);
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut small = Response::new(HTTPStatus::Ok).body("text/plain", "hi");
+        let mut recorder = Recorder(Vec::new());
+        small.write_to(&mut recorder).unwrap();
+        assert_eq!(recorder.0, vec![small.to_bytes()]);
+
+        let mut large = Response::new(HTTPStatus::Ok).body("text/plain", vec![b'x'; COALESCE_LIMIT + 1]);
+        let mut recorder = Recorder(Vec::new());
+        large.write_to(&mut recorder).unwrap();
+        assert_eq!(recorder.0, vec![large.head_bytes(), large.body.to_vec()]);
+        assert_eq!(recorder.0.concat(), large.to_bytes());
+    }
+
+    #[test]
+    fn test_body_bytes_slice() {
+        let shared: Arc<[u8]> = b"0123456789".as_slice().into();
+        let range = BodyBytes::shared(Arc::clone(&shared)).slice(2..8).slice(1..3);
+        assert_eq!(range, b"34");
+        // Still the same buffer, not a copy of the range.
+        assert!(matches!(&range, BodyBytes::Shared(bytes, within) if Arc::ptr_eq(bytes, &shared) && *within == (3..5)));
+
+        let owned = BodyBytes::from(b"0123456789".to_vec()).slice(2..8);
+        assert_eq!((owned.len(), owned.into_vec()), (6, b"234567".to_vec()));
+
+        let mut response = Response::new(HTTPStatus::Ok).body("text/plain", BodyBytes::shared(shared).slice(0..4));
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert!(out.ends_with(b"Content-Length: 4\r\nContent-Type: text/plain\r\n\r\n0123"));
+    }
+
+    // The bytes of a chunked response whose stream makes one write per element of `pieces`.
+    fn chunked_body(pieces: Vec<Vec<u8>>) -> Vec<u8> {
+        let mut resp = Response::new(HTTPStatus::Ok)
+            .stream("text/plain", move |w| {
+                for piece in &pieces {
+                    w.write_all(piece)?;
+                }
+                return Ok(());
+            })
+            .header("Transfer-Encoding", "chunked");
+        let head = resp.head_bytes();
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        assert!(out.starts_with(&head));
+        return out[head.len()..].to_vec();
+    }
+
+    #[test]
+    fn test_chunked_framing() {
+        assert_eq!(chunked_body(vec![]), b"0\r\n\r\n");
+        assert_eq!(chunked_body(vec![b"".to_vec()]), b"0\r\n\r\n");
+        assert_eq!(
+            chunked_body(vec![b"hello".to_vec(), b"world!".to_vec()]),
+            b"5\r\nhello\r\n6\r\nworld!\r\n0\r\n\r\n"
+        );
+
+        let mut expected = b"12C\r\n".to_vec();
+        expected.extend_from_slice(&[b'x'; 300]);
+        expected.extend_from_slice(b"\r\n0\r\n\r\n");
+        assert_eq!(chunked_body(vec![vec![b'x'; 300]]), expected);
+    }
+
+    #[test]
+    fn test_chunked_trailers() {
+        let mut resp = Response::new(HTTPStatus::Ok)
+            .stream("text/plain", |w| {
+                w.write_all(b"abc")?;
+                w.trailer("X-Checksum", "900150983cd24fb0");
+                w.trailer("Content-Length", "3");
+                w.trailer("X-Undeclared", "1");
+                return Ok(());
+            })
+            .header("Trailer", "X-Checksum, Content-Length")
+            .header("Transfer-Encoding", "chunked");
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        assert!(out.ends_with(b"\r\n\r\n3\r\nabc\r\n0\r\nX-Checksum: 900150983cd24fb0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_allowed_trailers() {
+        let pair = |name: &str, value: &str| (name.to_string(), value.to_string());
+        let kept = allowed_trailers(
+            Some("x-digest, Transfer-Encoding, X-Bad"),
+            vec![pair("X-Digest", "a"), pair("Transfer-Encoding", "chunked"), pair("X-Bad", "a\r\nInjected: 1")],
+        );
+        assert_eq!(kept, vec![pair("X-Digest", "a")]);
+        assert!(allowed_trailers(None, vec![pair("X-Digest", "a")]).is_empty());
+    }
+
+    #[test]
+    fn test_streamed_head() {
+        let resp = Response::new(HTTPStatus::Ok).stream("text/plain", |w| w.write_all(b"x"));
+        let head = String::from_utf8(resp.head_bytes()).unwrap();
+        assert!(!head.contains("Content-Length"), "{}", head);
+
+        // Without chunked framing (an HTTP/1.0 client) the body is sent raw.
+        let mut resp = resp;
+        let mut out = Vec::new();
+        resp.write_to(&mut out).unwrap();
+        assert!(out.ends_with(b"\r\n\r\nx"));
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(content_disposition_attachment("report.pdf"), "attachment; filename=\"report.pdf\"");
+        assert_eq!(content_disposition_attachment("my report.pdf"), "attachment; filename=\"my report.pdf\"");
+        assert_eq!(
+            content_disposition_attachment("say \"hi\"\\.txt"),
+            "attachment; filename=\"say \\\"hi\\\"\\\\.txt\""
+        );
+        assert_eq!(content_disposition_attachment("a\r\nb.txt"), "attachment; filename=\"ab.txt\"");
+        assert_eq!(
+            content_disposition_attachment("résumé 1.pdf"),
+            "attachment; filename=\"r_sum_ 1.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%201.pdf"
+        );
+    }
+
+    #[test]
+    fn test_response_builder() {
+        let mut resp = Response::new(HTTPStatus::Ok).header("X-Test", "1");
+        resp.body = b"hi".into();
+        assert_eq!(
+            String::from_utf8(resp.to_bytes()).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nX-Test: 1\r\n\r\nhi"
+        );
+
+        let resp = Response::new(HTTPStatus::NoContent).header("Allow", "GET").to_bytes();
+        assert_eq!(String::from_utf8(resp).unwrap(), "HTTP/1.1 204 No Content\r\nAllow: GET\r\n\r\n");
+    }
+
+    #[test]
+    fn test_vary() {
+        // A handler's own Vary headers are merged in; each name appears once.
+        let mut resp = Response::new(HTTPStatus::Ok).header("Vary", "Accept, Cookie").header("vary", "accept-language");
+        resp.add_vary("Accept-Encoding");
+        resp.add_vary("accept");
+        resp.add_vary("Accept-Encoding");
+        let vary: Vec<&(String, String)> = resp.headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("Vary")).collect();
+        assert_eq!(vary.len(), 1);
+        assert_eq!(vary[0].1, "Accept, Cookie, accept-language, Accept-Encoding");
+
+        assert_eq!(Response::new(HTTPStatus::Ok).vary("Origin").header_value("Vary"), Some("Origin"));
+        assert_eq!(Response::new(HTTPStatus::Ok).header("Vary", "*").vary("Accept").header_value("Vary"), Some("*"));
+    }
 }
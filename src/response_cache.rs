@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::request::Request;
+use crate::response::{BodyBytes, HTTPStatus, Response, Status};
+
+// A route's response as it was first produced, minus anything the connection loop adds later.
+struct Entry {
+    status: Status,
+    headers: Vec<(String, String)>,
+    body: BodyBytes,
+    log_tag: Option<&'static str>,
+    expires: Instant,
+    // Value of `clock` when the entry was last used; the smallest is evicted first.
+    last_used: u64,
+}
+
+struct Lru {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+    bytes: usize,
+}
+
+/*
+Responses of routes registered with Router::cache_for(), keyed by method, path and query, so an
+expensive handler runs once per TTL instead of once per request. Shared by all worker threads
+behind one Mutex, which is never held while a handler runs (two simultaneous misses simply both
+run it). Like the static cache, the limits come from the config at each call, so a reload can
+shrink or disable it (response_cache_max_bytes or response_cache_max_entries at 0).
+
+Only plain 200s are stored: no errors, no streamed bodies, nothing with Set-Cookie (one client's
+session must never reach another) and nothing with Vary (the key has no request headers).
+*/
+pub struct ResponseCache {
+    lru: Mutex<Lru>,
+}
+
+impl ResponseCache {
+    pub fn new() -> ResponseCache {
+        return ResponseCache { lru: Mutex::new(Lru { entries: HashMap::new(), clock: 0, bytes: 0 }) };
+    }
+
+    /*
+    The cached response for `req` if there is a fresh one (X-Cache: HIT); otherwise `handler`'s,
+    stored for `ttl` when it may be (X-Cache: MISS).
+    */
+    pub fn get_or_insert<F>(&self, req: &Request, ttl: Duration, max_bytes: usize, max_entries: usize, handler: F) -> Response
+    where
+        F: FnOnce() -> Response,
+    {
+        let key = cache_key(req);
+        if let Some(hit) = self.get(&key) {
+            return hit.header("X-Cache", "HIT");
+        }
+        let response = handler();
+        if cacheable(&response) {
+            self.insert(key, &response, ttl, max_bytes, max_entries);
+        }
+        return response.header("X-Cache", "MISS");
+    }
+
+    fn get(&self, key: &str) -> Option<Response> {
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.clock += 1;
+        let clock = lru.clock;
+
+        match lru.entries.get_mut(key) {
+            Some(entry) if entry.expires > Instant::now() => {
+                entry.last_used = clock;
+                let mut response = Response::new(HTTPStatus::Ok);
+                response.status = entry.status.clone();
+                response.headers = entry.headers.clone();
+                response.body = entry.body.clone();
+                response.log_tag = entry.log_tag;
+                return Some(response);
+            }
+            Some(_) => lru.remove(key),
+            None => {}
+        }
+        return None;
+    }
+
+    // Store a copy of `response`, evicting least recently used entries until both limits are respected.
+    fn insert(&self, key: String, response: &Response, ttl: Duration, max_bytes: usize, max_entries: usize) {
+        let size = response.body.len();
+        if max_entries == 0 || size > max_bytes {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.remove(&key);
+        while !lru.entries.is_empty() && (lru.entries.len() >= max_entries || lru.bytes + size > max_bytes) {
+            let oldest = lru.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                lru.remove(&oldest);
+            }
+        }
+
+        lru.clock += 1;
+        let entry = Entry {
+            status: response.status.clone(),
+            headers: response.headers.clone(),
+            body: response.body.clone(),
+            log_tag: response.log_tag,
+            expires: Instant::now() + ttl,
+            last_used: lru.clock,
+        };
+        lru.bytes += size;
+        lru.entries.insert(key, entry);
+    }
+
+    // Drop every entry (POST /admin/cache/clear). Returns how many there were.
+    pub fn clear(&self) -> usize {
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        let count = lru.entries.len();
+        lru.entries.clear();
+        lru.bytes = 0;
+        return count;
+    }
+}
+
+impl Lru {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.body.len();
+        }
+    }
+}
+
+fn cache_key(req: &Request) -> String {
+    return match &req.query {
+        Some(query) => format!("{} {}?{}", req.method, req.path, query),
+        None => format!("{} {}", req.method, req.path),
+    };
+}
+
+fn cacheable(response: &Response) -> bool {
+    return response.status == HTTPStatus::Ok
+        && response.stream.is_none()
+        && response.header_value("Set-Cookie").is_none()
+        && response.header_value("Vary").is_none();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::parse_request;
+
+    #[test]
+    fn test_never_cached() {
+        let cache = ResponseCache::new();
+        let req = parse_request(b"GET /stats HTTP/1.1\r\n\r\n").unwrap();
+        let uncacheable = [
+            Response::new(HTTPStatus::NotFound),
+            Response::new(HTTPStatus::Ok).header("Set-Cookie", "session=1"),
+            Response::new(HTTPStatus::Ok).header("Vary", "Accept"),
+        ];
+        for response in uncacheable {
+            let status = response.status.clone();
+            let first = cache.get_or_insert(&req, Duration::from_secs(60), 1024, 16, || response);
+            assert_eq!((first.status.clone(), first.header_value("X-Cache")), (status, Some("MISS")));
+            assert_eq!(cache.clear(), 0);
+        }
+    }
+
+    #[test]
+    fn test_bounded_and_keyed_by_query() {
+        let cache = ResponseCache::new();
+        let page = |target: &str| parse_request(format!("GET {} HTTP/1.1\r\n\r\n", target).as_bytes()).unwrap();
+        let body = |text: &str| Response::new(HTTPStatus::Ok).body("text/plain", text.to_string());
+        let ttl = Duration::from_secs(60);
+
+        cache.get_or_insert(&page("/a?x=1"), ttl, 10, 2, || body("one"));
+        let other_query = cache.get_or_insert(&page("/a?x=2"), ttl, 10, 2, || body("two"));
+        assert_eq!(other_query.header_value("X-Cache"), Some("MISS"));
+        let hit = cache.get_or_insert(&page("/a?x=1"), ttl, 10, 2, || body("changed"));
+        assert_eq!((hit.body.as_slice(), hit.header_value("X-Cache")), (b"one".as_slice(), Some("HIT")));
+
+        // A third entry evicts the least recently used one (x=2); too big a body isn't stored at all.
+        cache.get_or_insert(&page("/b"), ttl, 10, 2, || body("three"));
+        cache.get_or_insert(&page("/c"), ttl, 10, 2, || body("far too long"));
+        assert_eq!(cache.get_or_insert(&page("/a?x=2"), ttl, 10, 2, || body("two")).header_value("X-Cache"), Some("MISS"));
+        assert_eq!(cache.clear(), 2);
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::handlers;
+use crate::request::Request;
+use crate::response::{HTTPStatus, IntoResponse, Response};
+
+/**
+What a handler that may fail returns. Err(status) means "answer with the standard error response
+for this status" (handlers::error()), which lets a handler bail out with `?` (see
+handlers::OrStatus).
+*/
+pub type HandlerResult = Result<Response, HTTPStatus>;
+
+/**
+A route handler as the router stores it: the request in, the response out. Handlers are written
+as plain functions (or closures) returning anything that implements IntoResponse; route() wraps
+them into this. Shared by every worker thread, hence Send + Sync.
+*/
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+fn boxed<F, R>(handler: F) -> Handler
+where
+    F: Fn(&Request) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    return Box::new(move |req| handler(req).into_response());
+}
+
+/// What the routing table has for a (method, path) pair.
+pub enum RouteMatch<'a> {
+    Found(&'a Handler),
+    // The path is routed, just not for this method: 405 with these in Allow (HEAD included with GET).
+    WrongMethod(Vec<&'static str>),
+    // Not a route at all: static files get their turn.
+    NotRouted,
+}
+
+/**
+What answers a request no route matched:
+- StaticFiles: files under root_directory and the [[mounts]] (the default). With spa_fallback,
+  a GET for a missing file gets the root index file instead, for single-page apps.
+- NotFound: always 404; an API-only server never touches the file system.
+- Custom: a handler of your own.
+
+run_server() picks between the first two (serve_static_files); Custom is for programs that set
+up the Router themselves (server::run_server_with()).
+*/
+#[derive(Default)]
+pub enum Fallback {
+    #[default]
+    StaticFiles,
+    NotFound,
+    Custom(Handler),
+}
+
+impl Fallback {
+    /// A Custom fallback from any handler, like Router::route() takes.
+    pub fn custom<F, R>(handler: F) -> Fallback
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        return Fallback::Custom(boxed(handler));
+    }
+}
+
+/**
+The routing table: exact path → one handler per method, so "GET /contact" can show a form while
+"POST /contact" processes it. Paths are matched after normalization (see dispatch.rs), and
+ignoring case after set_case_insensitive(true).
+*/
+#[derive(Default)]
+pub struct Router {
+    routes: HashMap<&'static str, Vec<(&'static str, Handler)>>,
+    fallback: Fallback,
+    // path → (method, how long its responses are cached), see cache_for().
+    cache_ttls: HashMap<&'static str, Vec<(&'static str, Duration)>>,
+    // path → (method, largest request body it accepts), see max_body().
+    body_limits: HashMap<&'static str, Vec<(&'static str, usize)>>,
+    // What the last route()/get()/post()/any() call registered, for cache_for() and max_body().
+    last_registered: Vec<(&'static str, &'static str)>,
+    // With case_insensitive: lowercased path → the path as registered.
+    case_insensitive: bool,
+    folded: HashMap<String, &'static str>,
+}
+
+impl Router {
+    /// An empty routing table that falls back to static files.
+    pub fn new() -> Router {
+        return Router::default();
+    }
+
+    /// Answer `method` requests for `path` with `handler`, replacing any handler already there.
+    pub fn route<F, R>(&mut self, method: &'static str, path: &'static str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        if self.case_insensitive {
+            self.fold(path);
+        }
+        let handlers = self.routes.entry(path).or_default();
+        handlers.retain(|(existing, _)| *existing != method);
+        handlers.push((method, boxed(handler)));
+        if let Some(ttls) = self.cache_ttls.get_mut(path) {
+            ttls.retain(|(cached, _)| *cached != method);
+        }
+        if let Some(limits) = self.body_limits.get_mut(path) {
+            limits.retain(|(limited, _)| *limited != method);
+        }
+        self.last_registered = vec![(method, path)];
+        return self;
+    }
+
+    /// route() for GET.
+    pub fn get<F, R>(&mut self, path: &'static str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        return self.route("GET", path, handler);
+    }
+
+    /// route() for POST.
+    pub fn post<F, R>(&mut self, path: &'static str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        return self.route("POST", path, handler);
+    }
+
+    /// The same handler for every method the server supports (handlers::ALLOWED_METHODS).
+    pub fn any<F, R>(&mut self, path: &'static str, handler: F) -> &mut Router
+    where
+        F: Fn(&Request) -> R + Clone + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        for method in handlers::ALLOWED_METHODS {
+            self.route(method, path, handler.clone());
+        }
+        self.last_registered = handlers::ALLOWED_METHODS.map(|method| (method, path)).to_vec();
+        return self;
+    }
+
+    /**
+    Cache the responses of the route(s) just registered for `ttl`, keyed by method, path and
+    query: `router.get("/stats", handlers::stats).cache_for(Duration::from_secs(60))`. Only GET
+    requests are answered from the cache, and only plain 200s without Set-Cookie or Vary are
+    stored (see response_cache.rs). Responses carry X-Cache: HIT or MISS.
+    */
+    pub fn cache_for(&mut self, ttl: Duration) -> &mut Router {
+        for (method, path) in &self.last_registered {
+            let ttls = self.cache_ttls.entry(path).or_default();
+            ttls.retain(|(cached, _)| cached != method);
+            ttls.push((method, ttl));
+        }
+        return self;
+    }
+
+    /// How long responses to (method, path) are cached, if that route uses cache_for().
+    pub fn cache_ttl(&self, method: &str, path: &str) -> Option<Duration> {
+        return self.cache_ttls.get(self.registered(path)?)?.iter()
+            .find(|(cached, _)| *cached == method)
+            .map(|(_, ttl)| *ttl);
+    }
+
+    /**
+    Accept request bodies of at most `bytes` for the route(s) just registered:
+    `router.post("/contact", contact).max_body(64 * 1024)`. A larger Content-Length is refused
+    with 413 before the body is read. This wins over the [limits] table and max_body_size (see
+    dispatch::body_limit()).
+    */
+    pub fn max_body(&mut self, bytes: usize) -> &mut Router {
+        for (method, path) in &self.last_registered {
+            let limits = self.body_limits.entry(path).or_default();
+            limits.retain(|(limited, _)| limited != method);
+            limits.push((method, bytes));
+        }
+        return self;
+    }
+
+    /// The body limit of (method, path), if that route uses max_body().
+    pub fn body_limit(&self, method: &str, path: &str) -> Option<usize> {
+        return self.body_limits.get(self.registered(path)?)?.iter()
+            .find(|(limited, _)| *limited == method)
+            .map(|(_, bytes)| *bytes);
+    }
+
+    /**
+    Match paths ignoring case (case_insensitive_routes), so "/About" reaches the "/about" handler.
+    The request keeps the path as sent. Two routes differing only in case can't both exist then:
+    registering the second one, or turning this on with both in place, panics.
+    */
+    pub fn set_case_insensitive(&mut self, on: bool) -> &mut Router {
+        self.case_insensitive = on;
+        self.folded.clear();
+        if on {
+            let mut paths: Vec<&'static str> = self.routes.keys().copied().collect();
+            paths.sort();
+            for path in paths {
+                self.fold(path);
+            }
+        }
+        return self;
+    }
+
+    fn fold(&mut self, path: &'static str) {
+        let key = path.to_lowercase();
+        if let Some(existing) = self.folded.get(&key) && *existing != path {
+            panic!("route {} differs from {} only in case, and routes are case-insensitive", path, existing);
+        }
+        self.folded.insert(key, path);
+    }
+
+    // The registered path a request for `path` matches, if any.
+    fn registered(&self, path: &str) -> Option<&'static str> {
+        if self.case_insensitive {
+            return self.folded.get(&path.to_lowercase()).copied();
+        }
+        return self.routes.get_key_value(path).map(|(registered, _)| *registered);
+    }
+
+    /// What answers requests no route matches (see Fallback).
+    pub fn set_fallback(&mut self, fallback: Fallback) -> &mut Router {
+        self.fallback = fallback;
+        return self;
+    }
+
+    /// The current fallback.
+    pub fn fallback(&self) -> &Fallback {
+        return &self.fallback;
+    }
+
+    /// Find the handler for a (method, path) pair.
+    pub fn lookup(&self, method: &str, path: &str) -> RouteMatch<'_> {
+        let Some(handlers) = self.registered(path).and_then(|registered| self.routes.get(registered)) else {
+            return RouteMatch::NotRouted;
+        };
+        if let Some((_, handler)) = handlers.iter().find(|(registered, _)| *registered == method) {
+            return RouteMatch::Found(handler);
+        }
+        // A GET route answers HEAD too (dispatch.rs runs HEAD as GET), so Allow says so.
+        let mut allowed = Vec::new();
+        for (registered, _) in handlers {
+            allowed.push(*registered);
+            if *registered == "GET" {
+                allowed.push("HEAD");
+            }
+        }
+        return RouteMatch::WrongMethod(allowed);
+    }
+}
+
+/**
+Registers several routes at once. Each entry is a method, a path literal and a handler (anything
+route() takes); without a leading `router;` the macro builds and returns a new Router:
+
+```
+use vibettp::{handlers, routes};
+
+let router = routes! {
+    GET "/" => handlers::home,
+    ANY "/about" => handlers::about,
+    POST "/echo" => handlers::echo_body,
+};
+```
+
+The method must be GET, POST or ANY (every method in handlers::ALLOWED_METHODS) and the path must
+start with '/'; anything else is a compile error:
+
+```compile_fail
+let router = vibettp::routes! { PATCH "/" => vibettp::handlers::home };
+```
+
+```compile_fail,E0080
+let router = vibettp::routes! { GET "about" => vibettp::handlers::about };
+```
+*/
+#[macro_export]
+macro_rules! routes {
+    (@route $router:ident, GET, $path:literal, $handler:expr) => {
+        $crate::routes!(@path $path);
+        $router.get($path, $handler);
+    };
+    (@route $router:ident, POST, $path:literal, $handler:expr) => {
+        $crate::routes!(@path $path);
+        $router.post($path, $handler);
+    };
+    (@route $router:ident, ANY, $path:literal, $handler:expr) => {
+        $crate::routes!(@path $path);
+        $router.any($path, $handler);
+    };
+    (@route $router:ident, $method:ident, $path:literal, $handler:expr) => {
+        compile_error!(concat!("routes!: unknown method `", stringify!($method), "` (expected GET, POST or ANY)"));
+    };
+    // Checked while compiling: a path that can never match is a typo, not a route.
+    (@path $path:literal) => {
+        const _: () = assert!(matches!($path.as_bytes(), [b'/', ..]), concat!("routes!: path must start with '/': ", $path));
+    };
+    ($($method:ident $path:literal => $handler:expr),* $(,)?) => {{
+        let mut router = $crate::router::Router::new();
+        $crate::routes!(router; $($method $path => $handler),*);
+        router
+    }};
+    ($router:ident; $($method:ident $path:literal => $handler:expr),* $(,)?) => {
+        $( $crate::routes!(@route $router, $method, $path, $handler); )*
+    };
+}
+
+/**
+The routes every server starts with. Settings they depend on (favicon_path) are read here, so
+changing them takes a restart. Embedders who don't want one of these simply don't call this.
+*/
+pub fn register_default_routes(router: &mut Router, config: &Config, maintenance: &Arc<AtomicBool>) {
+    // Read once: changing favicon_path needs a restart anyway.
+    let favicon = config.favicon_path.as_deref().map(handlers::load_favicon);
+    let maintenance = Arc::clone(maintenance);
+    routes! { router;
+        ANY "/" => handlers::home,
+        ANY "/about" => handlers::about,
+        GET "/favicon.ico" => move |req: &Request| handlers::favicon(req, favicon.as_ref()),
+        // Answered even in maintenance mode (see dispatch.rs): "degraded" then.
+        GET "/health" => move |_req: &Request| handlers::health(maintenance.load(Ordering::SeqCst)),
+        // One path, two handlers: GET explains, POST echoes.
+        GET "/echo" => handlers::echo_usage,
+        POST "/echo" => handlers::echo_body,
+        GET "/greet" => handlers::greet,
+    }
+    // A configured robots.txt wins over a file of that name in root_directory.
+    if let Some(robots) = config.robots_txt.clone() {
+        routes! { router;
+            GET "/robots.txt" => move |_req: &Request| handlers::robots_txt(&robots),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::{parse_request, Body};
+    use crate::response::HTTPStatus;
+
+    fn default_router() -> Router {
+        let mut router = Router::new();
+        register_default_routes(&mut router, &crate::config::test_config(".", ""), &Arc::default());
+        return router;
+    }
+
+    fn call(router: &Router, req: &Request) -> Response {
+        match router.lookup(&req.method, &req.path) {
+            RouteMatch::Found(handler) => return handler(req),
+            _ => panic!("{} {} is not routed", req.method, req.path),
+        }
+    }
+
+    #[test]
+    fn test_handler_per_method() {
+        let router = default_router();
+
+        let req = parse_request(b"GET /echo HTTP/1.1\r\n\r\n").unwrap();
+        let usage = call(&router, &req);
+        assert_eq!(usage.status, HTTPStatus::Ok);
+        assert!(String::from_utf8_lossy(&usage.body).contains("POST"));
+
+        let mut req = parse_request(b"POST /echo HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n").unwrap();
+        req.body = Body::Bytes(b"{\"a\":1}".to_vec());
+        let echoed = call(&router, &req);
+        assert_eq!(echoed.status, HTTPStatus::Ok);
+        assert_eq!(echoed.body, b"{\"a\":1}");
+        assert_eq!(echoed.header_value("Content-Type"), Some("application/json"));
+
+        // Registered with any(): every supported method.
+        assert!(matches!(router.lookup("POST", "/about"), RouteMatch::Found(_)));
+    }
+
+    #[test]
+    fn test_about_negotiates() {
+        let router = default_router();
+        let req = parse_request(b"GET /about HTTP/1.1\r\nAccept: application/json, */*;q=0.1\r\n\r\n").unwrap();
+        let json = call(&router, &req);
+        assert_eq!(json.header_value("Content-Type"), Some("application/json"));
+        assert_eq!(json.header_value("Vary"), Some("Accept"));
+
+        for raw in ["GET /about HTTP/1.1\r\n\r\n", "GET /about HTTP/1.1\r\nAccept: text/html,*/*;q=0.8\r\n\r\n"] {
+            let html = call(&router, &parse_request(raw.as_bytes()).unwrap());
+            assert!(String::from_utf8_lossy(&html.body).contains("About us"));
+            assert_eq!(html.header_value("Content-Type"), Some("text/html; charset=utf-8"));
+        }
+    }
+
+    #[test]
+    fn test_case_insensitive_routes() {
+        let mut router = default_router();
+        router.get("/Reports", handlers::about);
+        assert!(matches!(router.lookup("GET", "/About"), RouteMatch::NotRouted));
+        assert!(matches!(router.lookup("GET", "/reports"), RouteMatch::NotRouted));
+
+        router.set_case_insensitive(true);
+        for path in ["/about", "/About", "/ABOUT", "/reports", "/REPORTS"] {
+            assert!(matches!(router.lookup("GET", path), RouteMatch::Found(_)), "{}", path);
+        }
+        assert!(matches!(router.lookup("POST", "/ECHO"), RouteMatch::Found(_)));
+        // The handler still sees the path as the client sent it.
+        let req = parse_request(b"GET /GREET?name=Ada HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.path, "/GREET");
+        assert_eq!(call(&router, &req).status, HTTPStatus::Ok);
+
+        // Re-registering the same path is fine; one differing only in case is refused.
+        router.get("/about", handlers::home);
+        let collision = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            router.get("/ABOUT", handlers::home);
+        }));
+        assert!(collision.is_err());
+        let mut both = routes! { GET "/a" => handlers::home, GET "/A" => handlers::about };
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| { both.set_case_insensitive(true); })).is_err());
+    }
+
+    #[test]
+    fn test_routes_macro() {
+        let router = routes! {
+            GET "/contact" => handlers::home,
+            POST "/contact" => handlers::about,
+            ANY "/both" => |_req: &Request| "ok",
+        };
+        let req = parse_request(b"POST /contact HTTP/1.1\r\n\r\n").unwrap();
+        assert!(String::from_utf8_lossy(&call(&router, &req).body).contains("About us"));
+        assert!(matches!(router.lookup("GET", "/contact"), RouteMatch::Found(_)));
+        for method in handlers::ALLOWED_METHODS {
+            assert!(matches!(router.lookup(method, "/both"), RouteMatch::Found(_)));
+        }
+
+        // Adding to an existing router; a trailing comma is optional.
+        let mut router = Router::new();
+        routes! { router; GET "/a" => handlers::home, POST "/b" => handlers::about }
+        assert!(matches!(router.lookup("GET", "/a"), RouteMatch::Found(_)));
+        assert!(matches!(router.lookup("GET", "/b"), RouteMatch::WrongMethod(_)));
+    }
+
+    #[test]
+    fn test_wrong_method_and_unrouted() {
+        let mut router = Router::new();
+        router.get("/contact", handlers::home);
+        match router.lookup("POST", "/contact") {
+            RouteMatch::WrongMethod(allowed) => assert_eq!(allowed, ["GET", "HEAD"]),
+            _ => panic!("expected WrongMethod"),
+        }
+        router.post("/form", handlers::about);
+        match router.lookup("GET", "/form") {
+            RouteMatch::WrongMethod(allowed) => assert_eq!(allowed, ["POST"]),
+            _ => panic!("expected WrongMethod"),
+        }
+        router.any("/about", handlers::about);
+        match router.lookup("PUT", "/about") {
+            RouteMatch::WrongMethod(allowed) => assert_eq!(allowed, ["GET", "HEAD", "POST"]),
+            _ => panic!("expected WrongMethod"),
+        }
+        router.post("/contact", handlers::about);
+        assert!(matches!(router.lookup("POST", "/contact"), RouteMatch::Found(_)));
+        assert!(matches!(router.lookup("GET", "/elsewhere"), RouteMatch::NotRouted));
+    }
+
+    #[test]
+    fn test_max_body() {
+        let mut router = Router::new();
+        router.get("/contact", handlers::home);
+        router.post("/contact", handlers::about).max_body(1024);
+        router.any("/both", handlers::home).max_body(10);
+        assert_eq!(router.body_limit("POST", "/contact"), Some(1024));
+        assert_eq!(router.body_limit("GET", "/contact"), None);
+        assert_eq!(router.body_limit("POST", "/elsewhere"), None);
+        for method in handlers::ALLOWED_METHODS {
+            assert_eq!(router.body_limit(method, "/both"), Some(10));
+        }
+
+        // Registering the route again drops the old limit with it.
+        router.post("/contact", handlers::about);
+        assert_eq!(router.body_limit("POST", "/contact"), None);
+    }
+}
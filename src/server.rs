@@ -0,0 +1,1457 @@
+// size_of: Returns the byte size of a type (used when passing struct sizes to WinSock functions).
+// zeroed: Creates a zero-initialized instance of a struct (common for FFI where padding must be 0).
+use std::mem::{size_of, zeroed};
+
+// null_mut: Used to pass a null (null pointer) to C-style functions that expect optional parameters or indicate error.
+use std::ptr::null_mut;
+use std::fs;
+use std::thread::{self, JoinHandle};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+// Import all constants, types, and functions from WinSock (Windows socket API) via the windows-sys crate.
+// use windows_sys::Win32::Networking::WinSock::*;
+use windows_sys::Win32::Networking::WinSock::{
+    WSACleanup, WSAStartup, WSADATA, SOCKADDR, SOCKADDR_IN, IN_ADDR, IN_ADDR_0,
+    socket, bind, listen, accept, recv, send, closesocket, shutdown, WSAGetLastError,
+    INVALID_SOCKET, SOCKET_ERROR, SD_SEND, SD_BOTH, SOCKET, WSAEWOULDBLOCK, WSAETIMEDOUT,
+    AF_INET, SOCK_STREAM, IPPROTO_TCP, SOMAXCONN,
+    FD_SET, TIMEVAL, select, MSG_PEEK,
+    setsockopt, LINGER, SOL_SOCKET, SO_LINGER, SO_DONTLINGER,
+};
+
+// Import a helper function from http.rs that builds a static HTTP response.
+// use crate::response::build_response;
+
+// Import a helper from util.rs to convert a port number to network byte order (required by WinSock).
+use crate::util::{htons, ipv4_to_s_addr};
+
+// Import the function that parses a request to extract method and path.
+use crate::request::{parse_request_with, find_header_end, has_bare_lf, Body};
+use crate::spool::Spool;
+use crate::dispatch::{self, add_response_headers, ServerCtx};
+use crate::router::{self, Fallback, Router};
+use crate::compress;
+use crate::access_log::{self, AccessLogger};
+use crate::dump;
+use crate::stats::{self, ClientSlot, IpSlot, ServerStats};
+use crate::timing::{Breakdown, Phase};
+use crate::throttle::Throttle;
+use crate::workers::Workers;
+use crate::accept_queue::AcceptQueue;
+use crate::connections::{self, ConnState, Connections};
+use crate::proxy::{self, ProxyHeader};
+use crate::panics;
+use crate::response::{HTTPStatus, Response};
+use crate::canned::CannedResponses;
+use crate::config::{self, load_config, Config, Linger, Overflow, CONFIG_PATH};
+use crate::log::{self, error, warn, info, debug, trace};
+
+// const MAX_BODY_SIZE: usize = 6144; // 6KB (request line ~ 100B, headers ~ 1-2KB)
+
+// How often the accept loop wakes up without a new connection, to do its housekeeping.
+const HOUSEKEEPING_INTERVAL_MS: u64 = 1000;
+// How long a newcomer waits for an evicted connection's worker to give its slot back.
+const EVICTION_WAIT_MS: u64 = 200;
+// How often the accept loop looks for a free slot while connections are queued (overflow = "queue").
+const QUEUE_POLL_MS: u64 = 10;
+// Closing a connection, the most input read and discarded, and for how long (see close_client()).
+const DRAIN_MAX_BYTES: usize = 256 * 1024;
+const DRAIN_WAIT_MS: u64 = 200;
+// Refusals waiting for the closer thread at most (see Closer); more are closed without the drain.
+const CLOSER_BACKLOG: usize = 256;
+// Sockets one select() can watch: the size of WinSock's FD_SET.
+const FD_SET_CAPACITY: usize = 64;
+// Largest single send() while response_write_timeout applies (see send_some()).
+const DEADLINE_SEND_BYTES: usize = 64 * 1024;
+
+// A WinSock error code, as reported by WSAGetLastError() right after the failing call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct WsaError(pub i32);
+
+/*
+Send the whole buffer, however many send() calls that takes, giving up with WSAETIMEDOUT once
+`deadline` has passed (see send_some()).
+
+send() is allowed to accept fewer bytes than requested (large static files and congested clients
+make this likely), returning how many it actually queued. A single call whose return value is
+ignored therefore truncates the response while the Content-Length header still promises the full
+size. This loops, advancing the slice past whatever was accepted, until everything is written.
+
+Returns the number of bytes written (always data.len() on success).
+*/
+pub(crate) fn write_all_socket(sock: SOCKET, data: &[u8], deadline: Option<Instant>) -> Result<usize, WsaError> {
+    let mut written = 0;
+    while written < data.len() {
+        written += send_some(sock, &data[written..], deadline)?;
+    }
+    return Ok(written);
+}
+
+/*
+One send() of (the start of) `data`. Returns how many bytes it accepted, at least one.
+
+With a deadline, it first waits in select() until the client has made room in the send buffer,
+and sends at most DEADLINE_SEND_BYTES, so that a blocking send() never outlasts the deadline by
+much: a client reading one byte a second can't hold the worker past response_write_timeout.
+*/
+fn send_some(sock: SOCKET, data: &[u8], deadline: Option<Instant>) -> Result<usize, WsaError> {
+    // send() takes an i32 length, so very large buffers go out in several calls anyway.
+    let mut chunk_len = data.len().min(i32::MAX as usize);
+    if let Some(deadline) = deadline {
+        if !wait_writable(sock, deadline) {
+            return Err(WsaError(WSAETIMEDOUT));
+        }
+        chunk_len = chunk_len.min(DEADLINE_SEND_BYTES);
+    }
+
+    loop {
+        // Unsafe: FFI call. An invalid socket is reported as SOCKET_ERROR, not undefined behaviour.
+        let sent = unsafe { send(sock, data.as_ptr(), chunk_len as i32, 0) };
+
+        if sent == SOCKET_ERROR {
+            let code = unsafe { WSAGetLastError() };
+            /*
+            Only possible once non-blocking sockets are in use: the send buffer is full, so wait
+            briefly and retry instead of treating it as a failure.
+            */
+            if code == WSAEWOULDBLOCK {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            return Err(WsaError(code));
+        }
+
+        // A blocking send() never accepts 0 bytes of a non-empty buffer; bail out rather than spin.
+        if sent == 0 {
+            return Err(WsaError(0));
+        }
+
+        dump::outbound(&data[..sent as usize]);
+        return Ok(sent as usize);
+    }
+}
+
+// Wait until send() can take more bytes, or `deadline` passes (false). A select() error counts as ready.
+fn wait_writable(sock: SOCKET, deadline: Instant) -> bool {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return false;
+    }
+    let mut fds = FD_SET { fd_count: 1, fd_array: [sock; 64] };
+    let timeout = timeval(left.as_millis() as u64);
+    // Unsafe: FFI call. Failures are reported through the return value.
+    let ready = unsafe { select(0, null_mut(), &mut fds, null_mut(), &timeout) };
+    return ready != 0;
+}
+
+// When a response started now has to be sent by, per response_write_timeout.
+fn write_deadline(config: &Config) -> Option<Instant> {
+    return config.response_write_timeout_ms().map(|ms| Instant::now() + Duration::from_millis(ms));
+}
+
+// Send a complete response to the client, logging (but otherwise ignoring) failures.
+fn send_response(sock: SOCKET, response: &[u8], config: &Config) {
+    if let Err(e) = write_all_socket(sock, response, write_deadline(config)) {
+        warn!("❌ send() failed with WSA error {}.", e.0);
+    }
+}
+
+// io::Write over a raw socket, so Response::write_to() can send straight to the client.
+struct SocketWriter {
+    sock: SOCKET,
+    // Bytes handed to send() so far.
+    sent: usize,
+    deadline: Option<Instant>,
+    // max_bytes_per_second_per_connection, if set.
+    throttle: Option<Throttle>,
+}
+
+impl Write for SocketWriter {
+    /*
+    One send() per call: write_all() does the looping, and `sent` counts every byte that went out.
+    Throttled, the send is at most one chunk and may wait for its turn first. The deadline moves
+    by that wait: response_write_timeout is for clients that read too slowly, not for the cap.
+    */
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buf = buf;
+        if let Some(throttle) = &self.throttle {
+            let wait = throttle.wait(Instant::now());
+            if !wait.is_zero() {
+                thread::sleep(wait);
+                self.deadline = self.deadline.map(|deadline| deadline + wait);
+            }
+            buf = &buf[..buf.len().min(throttle.chunk_len())];
+        }
+        let written = send_some(self.sock, buf, self.deadline).map_err(|e| io::Error::from_raw_os_error(e.0))?;
+        if let Some(throttle) = &mut self.throttle {
+            throttle.record(written);
+        }
+        self.sent += written;
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+// What write_response() got out: the bytes sent and, if it stopped short, why.
+struct Sent {
+    bytes: usize,
+    error: Option<io::Error>,
+}
+
+impl Sent {
+    // The client's Content-Length promise was broken: nothing may follow on this connection.
+    fn truncated(&self) -> bool {
+        return self.error.is_some();
+    }
+
+    // The send ran out of response_write_timeout rather than failing.
+    fn timed_out(&self) -> bool {
+        return self.error.as_ref().and_then(io::Error::raw_os_error) == Some(WSAETIMEDOUT);
+    }
+}
+
+// Send a Response, head first and then the body from its own buffer (no combined copy), within response_write_timeout.
+fn write_response(sock: SOCKET, response: &mut Response, config: &Config) -> Sent {
+    let mut writer = socket_writer(sock, config);
+    let error = response.write_to(&mut writer).err();
+    return Sent { bytes: writer.sent, error };
+}
+
+// write_response() for a response already serialized (a canned one).
+fn write_serialized(sock: SOCKET, response: &[u8], config: &Config) -> Sent {
+    let mut writer = socket_writer(sock, config);
+    let error = writer.write_all(response).err();
+    return Sent { bytes: writer.sent, error };
+}
+
+fn socket_writer(sock: SOCKET, config: &Config) -> SocketWriter {
+    let throttle = Throttle::new(config.max_bytes_per_second_per_connection);
+    return SocketWriter { sock, sent: 0, deadline: write_deadline(config), throttle };
+}
+
+// A response that couldn't be sent on a connection being closed anyway: only worth a log line.
+fn warn_unsent(sent: Sent) {
+    if let Some(e) = sent.error {
+        warn!("❌ send() failed: {}.", e);
+    }
+}
+
+// Send a response on a connection that is about to be closed, saying so in the headers.
+fn send_closing(sock: SOCKET, response: Response, config: &Config) {
+    warn_unsent(write_response(sock, &mut add_response_headers(response, config).header("Connection", "close"), config));
+}
+
+// What a bounded wait for more request bytes produced.
+enum Received {
+    Data(usize),
+    // The client closed its side (recv() returned 0).
+    Closed,
+    TimedOut,
+    Failed,
+}
+
+/*
+A select() timeout of `ms` milliseconds. TIMEVAL splits it into whole seconds (tv_sec) and the
+remaining microseconds (tv_usec), so 1500ms is { 1, 500000 }.
+*/
+fn timeval(ms: u64) -> TIMEVAL {
+    return TIMEVAL {
+        tv_sec: (ms / 1000).min(i32::MAX as u64) as i32,
+        tv_usec: ((ms % 1000) * 1000) as i32,
+    };
+}
+
+/*
+Wait at most `timeout_ms` for the client, then recv() into `buffer`: the same select() +
+recv() pair that the header loop in run_server() spells out step by step.
+*/
+fn receive(sock: SOCKET, buffer: &mut [u8], timeout_ms: u64) -> Received {
+    let mut fds = FD_SET { fd_count: 1, fd_array: [sock; 64] };
+    let timeout = timeval(timeout_ms);
+
+    // Unsafe: FFI calls. Failures are reported through the return values.
+    let ready = unsafe { select(0, &mut fds, null_mut(), null_mut(), &timeout) };
+    if ready == 0 {
+        return Received::TimedOut;
+    }
+    if ready == SOCKET_ERROR {
+        return Received::Failed;
+    }
+
+    let received = unsafe { recv(sock, buffer.as_mut_ptr(), buffer.len() as i32, 0) };
+    return match received {
+        0 => Received::Closed,
+        n if n < 0 => Received::Failed,
+        n => {
+            dump::inbound(&buffer[..n as usize]);
+            Received::Data(n as usize)
+        }
+    };
+}
+
+/*
+True if the client has already shut down its sending side (as tests/common.rs does right after
+its request): the socket is readable and a peek finds the end of the stream, not data. Doesn't
+wait and consumes nothing, so pipelined bytes stay queued for the next request.
+*/
+fn peer_sent_eof(sock: SOCKET) -> bool {
+    let mut fds = FD_SET { fd_count: 1, fd_array: [sock; 64] };
+    let timeout = timeval(0);
+    let mut byte = 0u8;
+
+    // Unsafe: FFI calls. Failures are reported through the return values.
+    let ready = unsafe { select(0, &mut fds, null_mut(), null_mut(), &timeout) };
+    return ready == 1 && unsafe { recv(sock, &mut byte, 1, MSG_PEEK) } == 0;
+}
+
+// A spool file for a large request body couldn't be created or written: answer 500 and close.
+fn spool_failed(sock: SOCKET, action: &str, error: io::Error, ctx: &ServerCtx, config: &Arc<Config>) {
+    error!("❌ Cannot {} the spool file for a request body: {}", action, error);
+    send_canned(sock, HTTPStatus::InternalServerError, ctx, config);
+}
+
+// Send one of the connection loop's own error responses (see canned.rs); the connection is closed next.
+fn send_canned(sock: SOCKET, status: HTTPStatus, ctx: &ServerCtx, config: &Arc<Config>) {
+    send_response(sock, &ctx.canned.get(status, config), config);
+}
+
+// Answer 503 straight from the accept loop and close, for a connection that gets no worker.
+fn reject_busy(sock: SOCKET, canned: &CannedResponses, closer: &Closer, config: &Arc<Config>) {
+    reject(sock, &canned.get(HTTPStatus::ServiceUnavailable, config), closer, config);
+}
+
+// A connection parked in the accept queue: the socket, accepted but not read yet, and its IP slot.
+struct Parked {
+    sock: SOCKET,
+    peer: String,
+    ip_slot: Option<IpSlot>,
+}
+
+/*
+The queue's turn in the accept loop (overflow = "queue"). Connections that waited queue_timeout_ms
+get their 503 now, with Retry-After; then the one waiting longest is admitted, if a max_clients
+slot has come free and another worker thread may start.
+*/
+fn admit_queued(
+    queue: &mut AcceptQueue<Parked>, stats: &Arc<ServerStats>, workers: &mut Workers, canned: &CannedResponses, closer: &Closer,
+    config: &Arc<Config>
+) -> Option<(Parked, ClientSlot)> {
+    let expired = queue.expired(Duration::from_millis(config.queue_timeout_ms), Instant::now());
+    if !expired.is_empty() {
+        let refusal = canned.queue_timeout(config);
+        for parked in expired {
+            warn!("⌛ {} waited {}ms in the queue without getting a slot.", parked.peer, config.queue_timeout_ms);
+            reject(parked.sock, &refusal, closer, config);
+        }
+    }
+    if queue.is_empty() {
+        return None;
+    }
+    workers.reap();
+    if workers.len() >= config.max_worker_threads {
+        return None;
+    }
+    let slot = stats::try_admit(stats, config.max_clients)?;
+    let parked = queue.next()?;
+    debug!("⏳ {} leaves the queue ({} still waiting).", parked.peer, queue.len());
+    return Some((parked, slot));
+}
+
+// Send a canned refusal (503, 429) from the accept loop and leave the close to the closer thread.
+fn reject(sock: SOCKET, response: &[u8], closer: &Closer, config: &Config) {
+    send_response(sock, response, config);
+    closer.close(sock, config.linger);
+}
+
+/*
+Set a freshly accepted client socket up for the `linger` setting. "off" and "graceful" both make
+closesocket() return at once (SO_DONTLINGER, WinSock's default anyway); a timeout makes it wait up
+to that many seconds for unsent data (SO_LINGER). A timeout of 0 resets the connection on close.
+*/
+fn apply_linger(sock: SOCKET, linger: Linger) {
+    // Unsafe: FFI call, given a pointer to a value that outlives it.
+    let result = unsafe {
+        match linger {
+            Linger::Off | Linger::Graceful => {
+                let dont_linger: i32 = 1;
+                setsockopt(sock, SOL_SOCKET, SO_DONTLINGER, &dont_linger as *const i32 as *const u8, size_of::<i32>() as i32)
+            }
+            Linger::Timeout(seconds) => {
+                let value = LINGER { l_onoff: 1, l_linger: seconds };
+                setsockopt(sock, SOL_SOCKET, SO_LINGER, &value as *const LINGER as *const u8, size_of::<LINGER>() as i32)
+            }
+        }
+    };
+    if result == SOCKET_ERROR {
+        warn!("⚠️ Cannot set linger {:?} on a client socket (WSA error {}).", linger, unsafe { WSAGetLastError() });
+    }
+}
+
+/*
+The one way a client connection is closed. Unless linger is "off":
+1. Shut down the sending side: the client gets a FIN right behind the last response.
+2. Read and discard whatever the client still sends (the rest of a refused body, a request the
+   accept loop never looked at) until it closes its side too, for at most DRAIN_MAX_BYTES or
+   `wait_ms`. Closing a socket with unread input makes WinSock reset the connection, and a reset
+   can destroy the response before the client has read it (this is what test_413 used to hit).
+3. closesocket().
+*/
+fn close_client(sock: SOCKET, linger: Linger, wait_ms: u64) {
+    if linger != Linger::Off {
+        unsafe { shutdown(sock, SD_SEND) };
+        let deadline = Instant::now() + Duration::from_millis(wait_ms);
+        let mut buffer = [0u8; 4096];
+        let mut drained = 0;
+        while drained < DRAIN_MAX_BYTES {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            match receive(sock, &mut buffer, left.as_millis() as u64) {
+                Received::Data(n) => drained += n,
+                _ => break,
+            }
+        }
+    }
+    unsafe { closesocket(sock) };
+}
+
+/*
+close_client() for the accept loop's refusals (503, 429, the shutdown wake-up call). Its drain
+would hold up the next accept() meanwhile, so that a flood of refused connections would be
+accepted only as fast as they could be drained. close() shuts the sending side down at once and
+hands the socket to a thread of its own, which drains up to FD_SET_CAPACITY sockets with one
+select() and closes each once its client has closed too, after DRAIN_MAX_BYTES or after
+DRAIN_WAIT_MS. With CLOSER_BACKLOG sockets already waiting, a refusal is closed without the drain.
+
+finish() waits for the sockets handed over so far, so that they are closed before WSACleanup().
+*/
+struct Closer {
+    sender: SyncSender<SOCKET>,
+    thread: JoinHandle<()>,
+}
+
+impl Closer {
+    fn spawn() -> Closer {
+        let (sender, receiver) = mpsc::sync_channel(CLOSER_BACKLOG);
+        let thread = thread::spawn(move || run_closer(receiver));
+        return Closer { sender, thread };
+    }
+
+    fn close(&self, sock: SOCKET, linger: Linger) {
+        if linger == Linger::Off {
+            unsafe { closesocket(sock) };
+            return;
+        }
+        unsafe { shutdown(sock, SD_SEND) };
+        if let Err(TrySendError::Full(sock) | TrySendError::Disconnected(sock)) = self.sender.try_send(sock) {
+            unsafe { closesocket(sock) };
+        }
+    }
+
+    fn finish(self) {
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+}
+
+// A refused socket being drained by the closer thread.
+struct Draining {
+    sock: SOCKET,
+    drained: usize,
+    deadline: Instant,
+}
+
+fn run_closer(receiver: Receiver<SOCKET>) {
+    let mut draining: Vec<Draining> = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        // Nothing to drain: sleep until the next refusal, or stop once the accept loop is gone.
+        if draining.is_empty() {
+            let Ok(sock) = receiver.recv() else {
+                return;
+            };
+            draining.push(Draining { sock, drained: 0, deadline: Instant::now() + Duration::from_millis(DRAIN_WAIT_MS) });
+        }
+        while draining.len() < FD_SET_CAPACITY && let Ok(sock) = receiver.try_recv() {
+            draining.push(Draining { sock, drained: 0, deadline: Instant::now() + Duration::from_millis(DRAIN_WAIT_MS) });
+        }
+
+        // Wait for input on any of them, but look for new refusals at least as often as the queue does.
+        let mut fds = FD_SET { fd_count: draining.len() as u32, fd_array: [0; FD_SET_CAPACITY] };
+        for (slot, entry) in fds.fd_array.iter_mut().zip(&draining) {
+            *slot = entry.sock;
+        }
+        let soonest = draining.iter().map(|entry| entry.deadline).min().unwrap_or_else(Instant::now);
+        let wait_ms = (soonest.saturating_duration_since(Instant::now()).as_millis() as u64).min(QUEUE_POLL_MS);
+        // Unsafe: FFI call. On return, fds holds only the sockets with input (or a closed side) waiting.
+        let ready = unsafe { select(0, &mut fds, null_mut(), null_mut(), &timeval(wait_ms)) };
+        let readable = if ready > 0 { &fds.fd_array[..fds.fd_count as usize] } else { &[] };
+
+        let now = Instant::now();
+        draining.retain_mut(|entry| {
+            let mut done = now >= entry.deadline;
+            if readable.contains(&entry.sock) {
+                // Unsafe: FFI call into a buffer that outlives it.
+                let received = unsafe { recv(entry.sock, buffer.as_mut_ptr(), buffer.len() as i32, 0) };
+                entry.drained += received.max(0) as usize;
+                done |= received <= 0 || entry.drained >= DRAIN_MAX_BYTES;
+            }
+            if done {
+                unsafe { closesocket(entry.sock) };
+            }
+            return !done;
+        });
+    }
+}
+
+/*
+Wait at most `ms` for a connection to be ready for accept(). false means the time ran out; a
+select() error counts as ready, so that accept() reports it.
+*/
+fn connection_pending(sock: SOCKET, ms: u64) -> bool {
+    let mut fds = FD_SET { fd_count: 1, fd_array: [sock; 64] };
+    let timeout = timeval(ms);
+    // Unsafe: FFI call.
+    return unsafe { select(0, &mut fds, null_mut(), null_mut(), &timeout) } != 0;
+}
+
+/*
+The accept loop's housekeeping: close keep-alive connections idle for longer than the current
+idle timeout. Their workers normally time out by themselves, but a reload may have shortened
+keep_alive_timeout while they were already waiting out the old one.
+*/
+fn close_expired(connections: &Connections, config: &Config) {
+    for (id, peer) in connections.close_idle(Duration::from_millis(config.idle_timeout_ms())) {
+        info!("💤 Closing connection #{} ({}): idle past keep_alive_timeout.", id, peer);
+    }
+}
+
+// Connect to our own listening socket (and hang up) so a blocked accept() returns.
+fn wake_listener(config: &Config) {
+    // A wildcard bind address can't be connected to; loopback reaches the same listener.
+    let host = if config.bind_address == "0.0.0.0" { "127.0.0.1" } else { config.bind_address.as_str() };
+    if let Err(e) = std::net::TcpStream::connect((host, config.port)) {
+        error!("❌ Could not wake the accept loop: {}", e);
+    }
+}
+
+// IP address of a connected client.
+fn peer_ip(addr: &SOCKADDR_IN) -> Ipv4Addr {
+    // Unsafe: reading the IN_ADDR union; every variant is the same four bytes.
+    let ip = unsafe { addr.sin_addr.S_un.S_addr };
+    // In network byte order, i.e. the bytes in memory are already in the right order.
+    return Ipv4Addr::from(ip.to_ne_bytes());
+}
+
+// Count a connection from `ip` against max_connections_per_ip, or None (answer 429).
+fn admit_ip(stats: &Arc<ServerStats>, ip: IpAddr, config: &Config) -> Option<IpSlot> {
+    let ip_limit = if config.exempt_loopback && ip.is_loopback() { 0 } else { config.max_connections_per_ip };
+    return stats::try_admit_ip(stats, ip, ip_limit);
+}
+
+/*
+Read the PROXY protocol v1 line a load balancer sends ahead of the first request, within
+`timeout_ms`. Ok(None) is "PROXY UNKNOWN" (no client to name); Err says why there is no usable
+header, and the caller closes without an answer. Bytes after the line stay in `data`: they are
+the start of the HTTP request.
+*/
+fn read_proxy_header(sock: SOCKET, data: &mut Vec<u8>, timeout_ms: u64) -> Result<Option<SocketAddr>, &'static str> {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    let mut buffer = [0u8; proxy::MAX_V1_LEN];
+    loop {
+        match proxy::parse_v1(data) {
+            ProxyHeader::Complete { source, len } => {
+                data.drain(..len);
+                return Ok(source);
+            }
+            ProxyHeader::Invalid => return Err("malformed"),
+            ProxyHeader::Incomplete => {}
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now()).as_millis() as u64;
+        match receive(sock, &mut buffer, remaining) {
+            Received::Data(n) => data.extend_from_slice(&buffer[..n]),
+            Received::TimedOut => return Err("timed out"),
+            Received::Closed | Received::Failed => return Err("missing"),
+        }
+    }
+}
+
+// "a.b.c.d:port" of a connected client, for the logs.
+fn peer_address(addr: &SOCKADDR_IN) -> String {
+    return format!("{}:{}", peer_ip(addr), u16::from_be(addr.sin_port));
+}
+
+/**
+Run the server the way the `vibettp` binary does: config.toml from the working directory, with
+--quiet / -v / -vv on the command line overriding its log_level. Returns once it has shut down.
+*/
+pub fn run_with_default_config() {
+    run_server(log::level_from_args(std::env::args().skip(1)));
+}
+
+/**
+Run the server with config.toml from the working directory (CONFIG_PATH) until a shutdown is
+requested. `cli_level`, if given, takes precedence over the config's log_level.
+*/
+pub fn run_server(cli_level: Option<log::Level>) {
+    run_server_from(CONFIG_PATH, cli_level);
+}
+
+/**
+Like run_server(), with the config file at `config_path`: it is read, watched for edits and never
+served, exactly as config.toml is. The integration tests start their servers this way.
+*/
+pub fn run_server_from(config_path: &str, cli_level: Option<log::Level>) {
+    run_server_with(config_path, cli_level, |_| {});
+}
+
+/**
+Like run_server_from(), for a program with routes of its own: `customize` gets the Router once
+the default routes and the fallback are in place, to add routes or replace the fallback (with
+Fallback::custom(), say).
+*/
+pub fn run_server_with<F: FnOnce(&mut Router)>(config_path: &str, cli_level: Option<log::Level>, customize: F) {
+
+    let config = match load_config(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("❌ {}", e);
+            return;
+        }
+    };
+    // The same resolution `vibettp check` does, so the banner below shows what it would.
+    let effective = config::resolve(&config, cli_level);
+    log::set_level(effective.log_level);
+    log::set_style(config.log_style);
+    let bind_ip = match &effective.bind_ip {
+        Ok(ip) => *ip,
+        Err(e) => {
+            error!("❌ {}", e);
+            return;
+        }
+    };
+    for problem in &effective.problems {
+        warn!("⚠️ {}", problem);
+    }
+    // Panics are logged with the request that caused them (see panics.rs).
+    panics::install_hook();
+
+    // Access-log lines are written by a thread of their own (see access_log.rs), to the console or a file.
+    let access_log = match &config.access_log_path {
+        Some(path) => match fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => AccessLogger::spawn(file, false, access_log::QUEUE_CAPACITY),
+            Err(e) => {
+                error!("❌ Cannot open access_log_path {}: {}", path, e);
+                return;
+            }
+        },
+        None => AccessLogger::spawn(io::stdout(), true, access_log::QUEUE_CAPACITY),
+    };
+    let access_log = Arc::new(access_log);
+
+    /*
+    Share the config with every thread through a swappable snapshot, and watch the file so that
+    edits (max_clients, timeouts, root_directory...) take effect without a restart. Each new
+    connection and each request on a connection picks up whatever is current at that moment.
+    */
+    let shared_config = config::shared(config.clone());
+    config::spawn_config_watcher(config_path.to_string(), Arc::clone(&shared_config), Duration::from_secs(2));
+
+    // Unsafe block. Required for raw C-style FFI (Foreign Function Interface) work.
+    unsafe {
+        // Everything inside here could violate Rust’s safety guarantees if misused.
+
+        // --- Step 1: Initialize WinSock with version 2.2 ---
+
+        // Create a zero-initialized WSAData struct to receive startup information about WinSock.
+        let mut wsa_data: WSADATA = zeroed();
+
+        // Initialize WinSock with version 2.2 (0x0202). Return non-zero on error.
+        if WSAStartup(0x202, &mut wsa_data) != 0 {
+            // Log an error and exit if initialization fails.
+            error!("WSAStartup failed");
+            return;
+        }
+
+        // --- Step 2: Create a TCP socket (IPv4, stream-based) ---
+
+        /*
+        Create a new socket:
+         - AF_INET: IPv4
+         - SOCK_STREAM: TCP (not UDP)
+         - IPPROTO_TCP: TCP protocol
+        Return a socket handler (integer).
+        */
+        let sock = socket(AF_INET as i32, SOCK_STREAM as i32, IPPROTO_TCP as i32);
+
+        // Check if socket creation failed
+        if sock == INVALID_SOCKET {
+            // Log error, clean up, exit
+            error!("Socket creation failed");
+            WSACleanup();
+            return;
+        }
+
+        // --- Step 3: Configure socket address  ---
+
+        /*
+        Chosen address: 127.0.0.1 (loopback IP)
+        Chosen port: 7878
+        Both read from config file (bind_address may also be a host name, resolved above).
+        */
+
+        /*
+        Create an IPv4 address struct (SOCKADDR_IN) with the following fields:
+        - Address family: IPv4.
+        - Port: 7878, converted to network byte order (big endian) using htons.
+        - IP address: 127.0.0.1 (loopback), as the 32-bit S_addr field in network byte order
+          (see util::ipv4_to_s_addr).
+        - Padding to match C layout. Must be zeroed.
+        */
+        let addr_in = SOCKADDR_IN {
+            sin_family: AF_INET as u16,
+            sin_port: htons(config.port), // convert to network byte order
+            sin_addr: IN_ADDR {
+                S_un: IN_ADDR_0 {
+                    S_addr: ipv4_to_s_addr(bind_ip),
+                },
+            },
+            sin_zero: [0; 8], // padding, must be zeroed
+        };
+
+        // --- Step 4: Bind the socket to the address ---
+
+        // Bind the socket to IP/port.
+        if bind(
+            sock,
+            // Cast the address struct to the generic SOCKADDR type (what WinSock expects).
+            &addr_in as *const _ as *const SOCKADDR,
+            // Pass the size of the struct.
+            size_of::<SOCKADDR_IN>() as i32,
+        ) != 0 { // Returns non-zero on failure
+            // Log error, close socket, and exit if bind fails.
+            error!("Bind failed");
+            closesocket(sock);
+            WSACleanup();
+            return;
+        }
+
+        // --- Step 5: Begin listening for connections ---
+
+        // Start listening for incoming connections.
+        // SOMAXCONN is the max number of pending connections in queue.
+        if listen(sock, SOMAXCONN.try_into().unwrap()) != 0 {
+            // Log error and exit on failure.
+            error!("Listen failed");
+            closesocket(sock);
+            WSACleanup();
+            return;
+        }
+
+        // Inform user that the server is live.
+        info!("🌐 Listening on {}:{}...", bind_ip, config.port);
+        for line in effective.summary(&config) {
+            info!("⚙️ {}", line);
+        }
+        if config.debug_dump {
+            // Off unless the config says so; in a release build that deserves a loud reminder.
+            let build = if cfg!(debug_assertions) { "" } else { " (in a release build!)" };
+            warn!("🔬 debug_dump is on{}: every connection is recorded under {}/.", build, dump::DUMP_DIRECTORY);
+        }
+
+        // Set up routing table
+        let mut router = Router::new();
+        router.set_case_insensitive(config.case_insensitive_routes);
+        let maintenance = Arc::new(AtomicBool::new(config.maintenance));
+        router::register_default_routes(&mut router, &config, &maintenance);
+        if !config.serve_static_files {
+            router.set_fallback(Fallback::NotFound);
+        }
+        customize(&mut router);
+
+        // Routes, caches, shutdown flag, counters and connections, shared by every connection.
+        let mut ctx = ServerCtx::new(router);
+        ctx.config_file = Path::new(config_path).canonicalize().ok();
+        ctx.maintenance = maintenance;
+        let ctx = Arc::new(ctx);
+        if config.maintenance {
+            warn!("🚧 Starting in maintenance mode: 503 for everything but /health and the admin endpoints");
+        }
+
+        /*
+        Rust threads do not share memory by default. To share data (like how many clients
+        are connected), we use atomic types inside Arcs.
+        ServerCtx created the shared counters (see stats.rs), starting at 0 active clients, and
+        wrapped them in an Arc (Atomic Reference Counted pointer), so they can be shared across
+        threads; the line below takes one more reference for the accept loop. Atomics are
+        thread-safe and allow us to increment/decrement from multiple threads without locks. Arc
+        enables multiple threads to own a reference to the same counters.
+        */
+        let stats = Arc::clone(&ctx.stats);
+
+        // Handles of the worker threads, so panics get reported and shutdown can join them.
+        let mut workers = Workers::new();
+
+        // Every open connection and whether it is idle, so idle ones can be closed from here.
+        let connections = Arc::clone(&ctx.connections);
+
+        // Connections waiting for a max_clients slot, with overflow = "queue".
+        let mut queue: AcceptQueue<Parked> = AcceptQueue::new();
+
+        // Closes the refused connections, so that draining them doesn't hold up accept().
+        let closer = Closer::spawn();
+
+        // --- Step 6: Accept a client connection ---
+
+        // Loop forever to handle one connection at a time.
+        loop {
+            // Housekeeping first; a quiet second without connections runs it too.
+            close_expired(&connections, &config::current(&shared_config));
+
+            // A queued connection whose slot has come up goes first; otherwise wait for a new one.
+            let limits = config::current(&shared_config);
+            let (client_sock, peer, ip_slot, slot) = match admit_queued(&mut queue, &stats, &mut workers, &ctx.canned, &closer, &limits) {
+                Some((parked, slot)) => (parked.sock, parked.peer, parked.ip_slot, slot),
+                None => {
+                    // While connections wait, look for a freed slot far more often than housekeeping needs.
+                    let wait_ms = if queue.is_empty() { HOUSEKEEPING_INTERVAL_MS } else { QUEUE_POLL_MS };
+                    if !connection_pending(sock, wait_ms) {
+                        continue;
+                    }
+
+                    // Prepare a buffer to receive the client's address upon connection.
+                    let mut client_addr: SOCKADDR_IN = zeroed();
+                    let mut addr_len = size_of::<SOCKADDR_IN>() as i32;
+
+                    // Block and wait for an incoming connection.
+                    // Returns a new socket specific to the client.
+                    let client_sock = accept(
+                        sock,
+                        &mut client_addr as *mut _ as *mut SOCKADDR,
+                        &mut addr_len,
+                    );
+
+                    // Error handling if accept fails.
+                    if client_sock == INVALID_SOCKET {
+                        error!("Accept failed");
+                        closesocket(sock);
+                        break;
+                    }
+
+                    // A shutdown was requested (this connection is most likely just the wake-up call).
+                    if ctx.shutdown_requested() {
+                        info!("🛑 Shutting down: no longer accepting connections.");
+                        closer.close(client_sock, config::current(&shared_config).linger);
+                        closesocket(sock);
+                        while let Some(parked) = queue.next() {
+                            reject_busy(parked.sock, &ctx.canned, &closer, &limits);
+                        }
+                        break;
+                    }
+
+                    let peer = peer_address(&client_addr);
+                    apply_linger(client_sock, limits.linger);
+
+                    /*
+                    Join the workers that have finished (logging any that panicked), then make sure
+                    there is room for one more: max_worker_threads is a hard ceiling on threads, whatever
+                    the admission logic below decides.
+                    */
+                    workers.reap();
+                    if workers.len() >= limits.max_worker_threads {
+                        warn!("🚫 Worker thread limit ({}) reached.", limits.max_worker_threads);
+                        reject_busy(client_sock, &ctx.canned, &closer, &limits);
+                        continue;
+                    }
+
+                    /*
+                    One client must not take every slot: count its connections per IP first, so a
+                    refused one doesn't hold a max_clients slot even briefly. Like the slot below, the
+                    IpSlot is released when the worker drops it.
+                    Behind a PROXY-protocol load balancer every connection comes from the balancer, so
+                    the worker does this instead, once the PROXY line has named the real client.
+                    */
+                    let ip_slot = if limits.proxy_protocol {
+                        None
+                    } else {
+                        let ip = IpAddr::V4(peer_ip(&client_addr));
+                        let Some(ip_slot) = admit_ip(&stats, ip, &limits) else {
+                            warn!("🚫 Too many connections from {}.", ip);
+                            reject(client_sock, &ctx.canned.get(HTTPStatus::TooManyRequests, &limits), &closer, &limits);
+                            continue;
+                        };
+                        Some(ip_slot)
+                    };
+
+                    /*
+                    Full, but maybe only because of connections idling between requests: with
+                    evict_idle_when_full, close the longest-idle one and give its worker a moment to
+                    release the slot, instead of turning the newcomer away.
+                    */
+                    if limits.evict_idle_when_full && stats.active.load(Ordering::SeqCst) >= limits.max_clients
+                        && let Some((id, idle_peer)) = connections.evict_longest_idle() {
+                        info!("💤 Server full: closing idle connection #{} ({}) to make room.", id, idle_peer);
+                        stats::wait_for_room(&stats, limits.max_clients, Duration::from_millis(EVICTION_WAIT_MS));
+                    }
+
+                    /*
+                    Claim one of the max_clients slots, atomically: the check and the increment are a
+                    single compare-and-swap, so a burst of connections can't all squeeze past the limit.
+                    The slot is moved into the worker thread and released when it is dropped there.
+                    Nobody jumps the queue: while connections wait, a newcomer joins them at the back.
+                    */
+                    let slot = if queue.is_empty() { stats::try_admit(&stats, limits.max_clients) } else { None };
+                    let Some(slot) = slot else {
+                        if limits.overflow == Overflow::Queue {
+                            let parked = Parked { sock: client_sock, peer, ip_slot };
+                            match queue.park(parked, limits.queue_depth, Instant::now()) {
+                                Ok(()) => info!("⏳ Server full: queued a connection ({} waiting).", queue.len()),
+                                Err(parked) => {
+                                    warn!("🚫 Too many clients, and {} already queued.", queue.len());
+                                    reject_busy(parked.sock, &ctx.canned, &closer, &limits);
+                                }
+                            }
+                            continue;
+                        }
+                        warn!("🚫 Too many clients.");
+                        reject_busy(client_sock, &ctx.canned, &closer, &limits);
+                        continue;
+                    };
+                    (client_sock, peer, ip_slot, slot)
+                }
+            };
+
+            /*
+            Clone the Arc, not the underlying value.
+            Now the new thread owns a reference to the shared object too.
+
+            Why clone? What's clone?
+            Arc<T> implements Clone, which increments the reference count.
+            Cloning here means "make another reference to the same shared object".
+            You need to move the cloned reference into the thread since the original
+            cannot be accessed from inside the move closure.
+
+            Why same variable name?
+            Shadowing in Rust: let ctx = Arc::clone(&ctx);
+            This reuses the same name for the new clone, which is moved into the thread.
+            It’s fine and idiomatic in Rust, though you could use a new name
+            (e.g., let ctx_thread = Arc::clone(&ctx);) if clarity is needed.
+            */
+            let ctx = Arc::clone(&ctx);
+            let shared_config = Arc::clone(&shared_config);
+            let connections = Arc::clone(&connections);
+            let stats = Arc::clone(&stats);
+            let access_log = Arc::clone(&access_log);
+            let proxy_protocol = limits.proxy_protocol;
+            let spawned_peer = peer.clone();
+            let spawn_refusal = ctx.canned.get(HTTPStatus::ServiceUnavailable, &limits);
+
+            // --- Step 7: Read from client ---
+
+            /*
+            Spawn a new thread. Each client gets handled in its own thread (classic multithreaded
+            server model).
+            move closure takes ownership of the captured variables (like ctx, shared_config)
+            — which is why we cloned them first.
+            */
+            let spawned = workers.spawn(peer.clone(), move |connection_id| {
+                // Held for the whole connection; dropping them (even while panicking) frees the slots.
+                let _slot = slot;
+                let mut _ip_slot = ip_slot;
+                let mut peer = peer;
+                info!("📡 Client #{} connected from {}.", connection_id, peer);
+
+                // Shutting the socket down both ways wakes this thread out of select() when the accept loop closes it.
+                let connection = connections::register(&connections, connection_id, peer.clone(), move || {
+                    shutdown(client_sock, SD_BOTH);
+                });
+
+                // Wire dump for this connection, if debug_dump was on when it was accepted.
+                if config::current(&shared_config).debug_dump {
+                    dump::start(connection_id);
+                }
+
+                // --- Begin keep-alive-aware inner loop ---
+
+                /*
+                Bytes received but not consumed yet. Usually empty between requests, but a client
+                may pipeline: send its next request right behind the previous one's body. Checked
+                out of the pool, and back in it when the connection closes.
+                */
+                let pooling = config::current(&shared_config);
+                let mut request_data = ctx.buffer_pool.checkout(pooling.recv_buffer_bytes, pooling.buffer_pool);
+                // Requests answered so far on this connection (numbers $request_id in the access log).
+                let mut requests_served: u64 = 0;
+
+                /*
+                With proxy_protocol, the PROXY line comes before anything else. Its source address
+                is the client from now on, for the logs and for max_connections_per_ip (IPv6 sources
+                included, though the listener itself is IPv4). No valid line: close without a word, it's not an HTTP client.
+                */
+                let mut refused = false;
+                if proxy_protocol {
+                    let timeout_ms = config::current(&shared_config).request_timeout_ms();
+                    match read_proxy_header(client_sock, &mut request_data, timeout_ms) {
+                        Ok(Some(source)) => {
+                            debug!("🔀 Client #{} is {} (PROXY protocol via {}).", connection_id, source, peer);
+                            peer = source.to_string();
+                            let config = config::current(&shared_config);
+                            _ip_slot = admit_ip(&stats, source.ip(), &config);
+                            if _ip_slot.is_none() {
+                                warn!("🚫 Too many connections from {}.", source.ip());
+                                send_canned(client_sock, HTTPStatus::TooManyRequests, &ctx, &config);
+                                refused = true;
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(reason) => {
+                            warn!("🚫 PROXY header {} from {}, closing.", reason, peer);
+                            refused = true;
+                        }
+                    }
+                }
+
+                'client_loop: loop {
+                    // Settings for this request; a reload only affects the next one.
+                    let config = config::current(&shared_config);
+                    connection.set_state(if request_data.is_empty() { ConnState::Idle } else { ConnState::Active });
+
+                    // Draining for shutdown: finish a pipelined request, but don't wait for new ones.
+                    if ctx.shutdown_requested() && request_data.is_empty() {
+                        break 'client_loop;
+                    }
+                    // Nothing to serve: no (valid) PROXY line, or its client has too many connections.
+                    if refused {
+                        break 'client_loop;
+                    }
+
+                    // A raw buffer (recv_buffer_bytes, 8KB by default) to receive data from the incoming request, from the pool.
+                    let mut buffer = ctx.buffer_pool.checkout(config.recv_buffer_bytes, config.buffer_pool);
+                    buffer.resize(config.recv_buffer_bytes, 0);
+
+                    // Per-request deadline, counted from the request's first byte (idle time doesn't count).
+                    let mut start_time = (!request_data.is_empty()).then(Instant::now);
+
+                    loop {
+                        // Only try parsing once we have complete headers
+                        /*
+                        find_header_end() searches for the 4-byte "\r\n\r\n" pattern anywhere in
+                        the buffer (it works correctly even if it sits in the middle of the buffer,
+                        with body bytes after it); with lenient_line_endings, "\n\n" too. It is the
+                        same check parse_request() uses to separate the textual head from the body.
+                        A pipelined request may be complete before anything new is received, hence
+                        checking first.
+                        */
+                        if find_header_end(&request_data, config.lenient_line_endings).is_some() {
+                            break; // Found end of headers
+                        }
+                        // Strict line endings: a bare "\n" makes it a 400 now, not a wait for a "\r\n\r\n" that won't come.
+                        if !config.lenient_line_endings && has_bare_lf(&request_data) {
+                            break;
+                        }
+
+                        // Check if the socket is ready for reading with a timeout
+                        /*
+                        Initialize an empty FD_SET struct (file descriptor set) with all values set to 0.
+                        This will hold the list of sockets to monitor using select().
+                        */
+                        let mut fds = FD_SET {
+                            fd_count: 1,
+                            fd_array: [client_sock; 64], // fill first element, rest zeroed
+                        };
+
+                        /*
+                        Construct a TIMEVAL struct, which defines the timeout duration.
+                        tv_sec: seconds
+                        tv_usec: microseconds
+                        Nothing received yet means the connection is idle between requests, which
+                        has its own (usually longer) keep-alive timeout.
+                        */
+                        let timeout = timeval(config.read_timeout_ms(request_data.is_empty()));
+
+                        /*
+                        Call select() to block either until at least one socket in fds is ready to read,
+                        or until the timeout occurs
+                        Parameters:
+                        0: Ignored in WinSock, used in Unix to indicate max socket + 1
+                        &mut fds: monitor for read
+                        null_mut(): no write monitoring
+                        null_mut(): no exception monitoring
+                        &timeout: how long to wait
+                        */
+                        let ready = select(0, &mut fds, null_mut(), null_mut(), &timeout);
+
+                        /*
+                        If select() returns 0, that means timeout - no socket ready within the timeout.
+                        If select() returns -1, it means an error occurred.
+                        Break the client loop and close the connection.
+                        */
+                        if ready == 0 {
+                            match dispatch::on_timeout(&request_data) {
+                                Some(response) => {
+                                    info!("⏱️ Timeout waiting for the rest of the request.");
+                                    send_closing(client_sock, response, &config);
+                                }
+                                None => info!("💤 Idle connection timed out, closing."),
+                            }
+                            break 'client_loop;
+                        }
+                        else if ready == SOCKET_ERROR {
+                            error!("❌ select() failed.");
+                            break 'client_loop;
+                        }
+
+                        // Check elapsed time: a client trickling bytes must still finish in time.
+                        if start_time.is_some_and(|start| start.elapsed().as_millis() > config.request_timeout_ms() as u128) {
+                            info!("⏱️ Client took too long to send full request.");
+                            send_canned(client_sock, HTTPStatus::RequestTimeout, &ctx, &config);
+                            break 'client_loop;
+                        }
+
+                        // If select() indicates the socket is ready, proceed to call recv() safely.
+                        // Read bytes into the buffer from the client socket.
+                        // Returns the number of bytes read.
+                        let bytes_received = recv(
+                            client_sock,
+                            buffer.as_mut_ptr(),
+                            buffer.len() as i32,
+                            0,
+                        );
+
+                        /*
+                        The client closed its side (recv() == 0) or the socket failed (< 0). Either
+                        way nobody is left to read a response, so none is sent:
+                        - nothing received yet: a persistent connection simply ending between
+                          requests, the normal way for a keep-alive client to leave;
+                        - part of a request received: it was cut short, which is worth a line;
+                        - an error: log WinSock's code.
+                        */
+                        if bytes_received == 0 && request_data.is_empty() {
+                            info!("🔌 Client disconnected.");
+                            break 'client_loop;
+                        }
+                        if bytes_received == 0 {
+                            info!("🔌 Client disconnected in the middle of a request ({} bytes received).", request_data.len());
+                            break 'client_loop;
+                        }
+                        if bytes_received < 0 {
+                            let code = WSAGetLastError();
+                            warn!("❌ recv() failed with WSA error {}, closing the connection.", code);
+                            break 'client_loop;
+                        }
+
+                        if request_data.is_empty() {
+                            connection.set_state(ConnState::Active);
+                        }
+                        request_data.extend_from_slice(&buffer[..bytes_received as usize]);
+                        dump::inbound(&buffer[..bytes_received as usize]);
+                        start_time.get_or_insert_with(Instant::now);
+
+                        /*
+                        recv() pulls up to N bytes (N is the buffer size, recv_buffer_bytes).
+                        If the client sent more, the first N bytes are copied into the buffer, and the
+                        remaining data stays queued in the socket’s internal receive buffer, managed by the
+                        operating system. This data will be returned by the next recv() call.
+
+                        Where is that data exactly?
+                        The OS keeps a receive queue (buffer) per socket. It typically has a size limit
+                        (e.g., 64KB or more depending on OS settings). Until you call recv() again, the data
+                        sits there. If you never call recv() again and just close the socket, the OS drops the
+                        remaining data.
+                        */
+
+                        /*
+                        Impose limit on the size of the head; the body has its own, per-route limit.
+                        With a recv buffer larger than max_header_bytes a whole oversized head can
+                        arrive in one read, so a complete head is measured too.
+                        */
+                        let head_too_large = match find_header_end(&request_data, config.lenient_line_endings) {
+                            Some(head_len) => head_len > config.max_header_bytes,
+                            None => request_data.len() >= config.max_header_bytes,
+                        };
+                        if head_too_large {
+                            send_canned(client_sock, HTTPStatus::ContentTooLarge, &ctx, &config);
+
+                            /*
+                            The connection is closed by close_client() after the loop, which
+                            “gracefully” shuts down the write side first and drains what the client
+                            still sends, so that the client can finish reading before the connection
+                            is torn down. Closing straight away, the following error would occur:
+
+                            “thread 'test_413' panicked at tests\common.rs:16:42:
+                            called `Result::unwrap()` on an `Err` value: Os { code: 10054, kind:
+                            ConnectionReset, message: "An existing connection was forcibly closed by
+                            the remote host." }”
+
+                            (It means the server closed the TCP connection abruptly before the client
+                            finished reading the response. This is expected when handling
+                            payload-too-large (413) by immediately rejecting the request and closing
+                            the socket with the rest of the request still unread).
+
+                            - shutdown() is a syscall from WinSock to partially close a socket.
+                            - SD_SEND is a constant (value 1) telling it to close just the sending side.
+                            - Using raw sockets, not TcpStream which has std::net::Shutdown::Write.
+                            */
+                            break 'client_loop;
+                        }
+                    }
+
+                    /*
+                    | Behavior                      | Valid Practice| Notes                               |
+                    | ----------------------------- | ------------- | ----------------------------------- |
+                    | Reject if recv() == buf.len() | Yes           | Defensive and efficient             |
+                    | Try to read more chunks       | Risky         | Slower, invites abuse unless capped |
+                    | Trust Content-Length header   | Dangerous     | Headers can lie or be omitted       |
+                    */
+
+                    // Decode and print the raw HTTP request from the client, in debug_dump mode at
+                    // debug level (the dump files have the exact bytes; this is the quick look).
+                    if config.debug_dump {
+                        debug!(
+                            "🔍 Raw request:\n{}",
+                            String::from_utf8_lossy(&request_data)
+                        );
+                    }
+
+                    // Phase boundaries, for the timing histograms, timing_log and Server-Timing.
+                    let mut breakdown = Breakdown::default();
+                    let head_received = Instant::now();
+                    breakdown.add(Phase::Receive, start_time.map_or(Duration::ZERO, |start| head_received - start));
+
+                    trace!("Before parse request");
+                    let parsed = parse_request_with(&request_data, config.lenient_line_endings);
+                    let parse_done = Instant::now();
+                    breakdown.add(Phase::Parse, parse_done - head_received);
+                    if let Some(mut req) = parsed {
+                        // --- Step 8: Build and send HTTP response ---
+
+                        debug!(
+                            "📠 HTTP Version: {} Method: {}, Path: {}",
+                            req.version, req.method, req.path
+                        );
+
+                        /*
+                        Routing has to look at the body size before the body is read: an upload
+                        over the limit for its path is refused without receiving the rest of it.
+                        */
+                        let head_len = find_header_end(&request_data, config.lenient_line_endings).unwrap_or(request_data.len());
+                        let body_len = req.content_length.unwrap_or(0);
+                        let limit = dispatch::body_limit(&req, &config, &ctx.router);
+                        if body_len > limit {
+                            info!("📦 {} byte body for {} exceeds its {} byte limit.", body_len, req.path, limit);
+                            send_canned(client_sock, HTTPStatus::ContentTooLarge, &ctx, &config);
+                            break 'client_loop;
+                        }
+
+                        /*
+                        A body over body_memory_threshold is written to a spool file as it arrives
+                        rather than collected in request_data. The spool deletes its file if
+                        anything below breaks out early.
+                        */
+                        let mut spool = None;
+                        if body_len > config.body_memory_threshold {
+                            match Spool::create(&config) {
+                                Ok(created) => spool = Some(created),
+                                Err(e) => {
+                                    spool_failed(client_sock, "create", e, &ctx, &config);
+                                    break 'client_loop;
+                                }
+                            }
+                        }
+
+                        loop {
+                            let received_len = match spool.as_mut() {
+                                Some(spool) => {
+                                    // Move the body bytes received so far out of request_data.
+                                    let take = (body_len - spool.written).min(request_data.len() - head_len);
+                                    let chunk: Vec<u8> = request_data.drain(head_len..head_len + take).collect();
+                                    if let Err(e) = spool.write(&chunk) {
+                                        spool_failed(client_sock, "write", e, &ctx, &config);
+                                        break 'client_loop;
+                                    }
+                                    spool.written
+                                }
+                                None => request_data.len() - head_len,
+                            };
+                            if received_len >= body_len {
+                                break;
+                            }
+
+                            match receive(client_sock, &mut buffer, config.request_timeout_ms()) {
+                                Received::Data(n) => request_data.extend_from_slice(&buffer[..n]),
+                                Received::TimedOut => {
+                                    info!("⏱️ Timeout waiting for the request body.");
+                                    send_canned(client_sock, HTTPStatus::RequestTimeout, &ctx, &config);
+                                    break 'client_loop;
+                                }
+                                Received::Closed | Received::Failed => {
+                                    info!("🔌 Client disconnected before sending the whole body.");
+                                    break 'client_loop;
+                                }
+                            }
+                        }
+
+                        // Consume this request; whatever follows belongs to the next one.
+                        match spool {
+                            Some(spool) => {
+                                request_data.drain(..head_len);
+                                match spool.finish() {
+                                    Ok(body) => req.body = body,
+                                    Err(e) => {
+                                        spool_failed(client_sock, "write", e, &ctx, &config);
+                                        break 'client_loop;
+                                    }
+                                }
+                            }
+                            None => {
+                                req.body = Body::Bytes(request_data.drain(..head_len + body_len).skip(head_len).collect());
+                            }
+                        }
+
+                        let body_done = Instant::now();
+                        if body_len > 0 {
+                            breakdown.add(Phase::Body, body_done - parse_done);
+                        }
+
+                        /*
+                        A client that half-closed after sending still gets its whole response, but
+                        it can't send another request: say so (Connection: close) and end there.
+                        */
+                        if req.keep_alive && request_data.is_empty() && peer_sent_eof(client_sock) {
+                            debug!("🔌 Client #{} half-closed after its request.", connection_id);
+                            req.keep_alive = false;
+                        }
+
+                        let mut outcome = dispatch::handle_request(&mut req, &config, &ctx);
+                        compress::maybe_compress(&req, &mut outcome.response, &config);
+
+                        /*
+                        The connection survives only if the server, the client and the handler all
+                        agree, and the request's end is known: chunked request bodies aren't
+                        decoded, so after one of those the connection can't be reused.
+                        */
+                        let keep_alive = !outcome.close && !req.chunked && config.keep_alive && req.keep_alive
+                            && !dispatch::ends_by_close(&outcome.response, &req);
+                        // A plain 404 (a scanner's flood of them, say) goes out as canned bytes (see canned.rs).
+                        let canned = ctx.canned.not_found(&outcome.response, &req, keep_alive, &config);
+                        let mut response = match canned {
+                            Some(_) => outcome.response,
+                            None => dispatch::finalize(outcome.response, &req, keep_alive, &config),
+                        };
+                        breakdown.add(Phase::Handler, body_done.elapsed());
+                        if config.timing_header {
+                            response = response.header("Server-Timing", &breakdown.server_timing());
+                        }
+                        requests_served += 1;
+                        let request_id = format!("{}-{}", connection_id, requests_served);
+                        let mut record = access_log::Record::new(&peer, &req, &response, request_id);
+                        let send_start = Instant::now();
+                        let sent = match &canned {
+                            Some(bytes) => write_serialized(client_sock, bytes, &config),
+                            None => write_response(client_sock, &mut response, &config),
+                        };
+                        breakdown.add(Phase::Send, send_start.elapsed());
+                        connection.record_response(sent.bytes);
+                        stats.record_response(sent.bytes);
+                        stats.timings.record(&breakdown);
+                        if config.timing_log {
+                            debug!("📊 {} {} {}-{}: {}", req.method, req.path, connection_id, requests_served, breakdown.summary());
+                        }
+                        record.duration_ms = start_time.map_or(0, |start| start.elapsed().as_millis());
+                        // Routine requests (a tagged response, like /favicon.ico) only show up at debug level.
+                        let level = if record.tag.is_some() { log::Level::Debug } else { log::Level::Info };
+                        access_log.log(level, &config.access_log_format.render(&record));
+
+                        // The accept loop is blocked in accept(); a connection of our own wakes it up.
+                        if ctx.shutdown_requested() {
+                            wake_listener(&config);
+                        }
+
+                        /*
+                        A response cut short leaves the client waiting for bytes its Content-Length
+                        promised: another response on this connection would be read as the rest of
+                        this one. Close instead.
+                        */
+                        if sent.timed_out() {
+                            warn!(
+                                "🐢 Gave up sending to {} after {} bytes: response_write_timeout exceeded.",
+                                peer, sent.bytes
+                            );
+                        }
+                        else if let Some(e) = &sent.error {
+                            warn!("❌ send() to {} failed after {} bytes: {}.", peer, sent.bytes, e);
+                        }
+                        if sent.truncated() {
+                            break 'client_loop;
+                        }
+
+                        // Close client connection.
+                        if !keep_alive {
+                            break 'client_loop;
+                        }
+                    }
+                    else {
+                        // Malformed or ambiguous (e.g. smuggling-prone framing): refuse and close.
+                        warn!("⚠️ Failed to parse HTTP request.");
+                        send_canned(client_sock, HTTPStatus::BadRequest, &ctx, &config);
+                        break 'client_loop;
+                    }
+                }
+
+                // --- Step 9: Clean up sockets and Winsock ---
+
+                // Close both client and server sockets.
+                // Cleanup WinSock (equivalent to shutting down the library).
+                // (never reached in this loop, but good practice for future shutdown logic)
+
+                // Deregister first: once closed, the socket handle may be reused by another connection.
+                drop(connection);
+                close_client(client_sock, config::current(&shared_config).linger, DRAIN_WAIT_MS);
+                dump::stop();
+                info!("🔌 Connection closed.\n");
+            });
+            // No thread, no worker: the closure (and the slots it holds) is already dropped.
+            if let Err(e) = spawned {
+                error!("❌ Cannot start a worker thread for {}: {}", spawned_peer, e);
+                reject(client_sock, &spawn_refusal, &closer, &limits);
+            }
+        }
+
+        // Let the connections still in progress finish before tearing WinSock down. Idle ones
+        // would hold that up for their whole keep-alive timeout, so close them now.
+        connections.close_idle(Duration::ZERO);
+        workers.join_all();
+        closer.finish();
+        access_log.shutdown();
+        if access_log.dropped() > 0 {
+            warn!("⚠️ {} access-log lines were dropped: the log couldn't keep up.", access_log.dropped());
+        }
+        WSACleanup();
+        info!("👋 Server stopped.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeval() {
+        let t = timeval(1500);
+        assert_eq!((t.tv_sec, t.tv_usec), (1, 500_000));
+        let t = timeval(250);
+        assert_eq!((t.tv_sec, t.tv_usec), (0, 250_000));
+        let t = timeval(180_000);
+        assert_eq!((t.tv_sec, t.tv_usec), (180, 0));
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::Config;
+use crate::request::Body;
+
+/*
+A request body being written to disk as it arrives. Bodies over body_memory_threshold go here
+instead of into memory, so a few concurrent multi-megabyte uploads don't each cost their size in
+RAM. `body` owns the file from the moment it is created: if the client times out or disconnects
+halfway, dropping the Spool deletes the partial file.
+*/
+pub struct Spool {
+    file: File,
+    body: Body,
+    // Body bytes written so far.
+    pub written: usize,
+}
+
+// spool_directory, or the system's temporary directory when none is configured.
+pub fn spool_directory(config: &Config) -> PathBuf {
+    if config.spool_directory.trim().is_empty() {
+        return std::env::temp_dir();
+    }
+    return PathBuf::from(&config.spool_directory);
+}
+
+impl Spool {
+    pub fn create(config: &Config) -> io::Result<Spool> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let directory = spool_directory(config);
+        fs::create_dir_all(&directory)?;
+        let path = directory.join(format!(
+            "vibettp-{}-{}.body", std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::options().write(true).create_new(true).open(&path)?;
+        return Ok(Spool { file, body: Body::File(path), written: 0 });
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.written += data.len();
+        return Ok(());
+    }
+
+    // Close the file and hand it over as the request body.
+    pub fn finish(mut self) -> io::Result<Body> {
+        self.file.flush()?;
+        return Ok(self.body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::util::temp_root;
+
+    fn spool_config(dir: &std::path::Path) -> Config {
+        return test_config(".", &format!("spool_directory = {:?}\n", dir.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_spooled_body_deleted_with_request() {
+        let dir = temp_root("spool");
+        let config = spool_config(&dir);
+
+        let mut spool = Spool::create(&config).unwrap();
+        spool.write(b"hello ").unwrap();
+        spool.write(b"world").unwrap();
+        assert_eq!(spool.written, 11);
+        let body = spool.finish().unwrap();
+
+        let Body::File(path) = &body else { panic!("expected a spooled body") };
+        let path = path.clone();
+        assert_eq!(fs::read(&path).unwrap(), b"hello world");
+        assert_eq!(body.len(), 11);
+        assert_eq!(body.preview(5).unwrap(), b"hello");
+
+        drop(body);
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_abandoned_spool_is_deleted() {
+        let dir = temp_root("spool_abandoned");
+        let config = spool_config(&dir);
+
+        // A client that disconnects halfway: the Spool is dropped without finish().
+        let mut spool = Spool::create(&config).unwrap();
+        spool.write(b"partial").unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        drop(spool);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
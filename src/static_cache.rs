@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::response::BodyBytes;
+
+// Files larger than this are always read from disk: the cache is for the small, hot ones.
+pub const MAX_CACHED_FILE_SIZE: usize = 1024 * 1024;
+
+/*
+A static file as last read from disk. A cached one's body is shared (BodyBytes::Shared), so a hit
+neither copies it nor, as a response body, is copied to be sent; one the cache won't keep just
+owns its bytes.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedFile {
+    pub body: BodyBytes,
+    pub content_type: String,
+    pub etag: String,
+    // Modification time when it was read. A newer mtime on disk means the entry is stale.
+    pub modified: SystemTime,
+}
+
+struct Entry {
+    file: CachedFile,
+    // Value of `clock` when the entry was last used; the smallest is evicted first.
+    last_used: u64,
+}
+
+struct Lru {
+    entries: HashMap<PathBuf, Entry>,
+    clock: u64,
+    bytes: usize,
+}
+
+/*
+In-memory LRU cache of static file contents, keyed by the sanitized file system path, shared by
+all worker threads behind one Mutex (lookups are a hash probe; the lock is never held during
+disk I/O). The limits come from the config at each call, so a reload can shrink or disable the
+cache: with cache_max_bytes or cache_max_entries at 0 nothing is ever stored.
+
+Eviction scans for the least recently used entry, which is fine for the few hundred small files
+this is meant for.
+*/
+pub struct StaticCache {
+    lru: Mutex<Lru>,
+}
+
+impl StaticCache {
+    pub fn new() -> StaticCache {
+        return StaticCache { lru: Mutex::new(Lru { entries: HashMap::new(), clock: 0, bytes: 0 }) };
+    }
+
+    /*
+    The cached copy of `path`, if there is one and the file hasn't been modified since it was
+    read. `modified` is the mtime from a fresh stat; an entry with any other mtime is stale and
+    dropped on the spot.
+    */
+    pub fn get(&self, path: &Path, modified: SystemTime) -> Option<CachedFile> {
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.clock += 1;
+        let clock = lru.clock;
+
+        match lru.entries.get_mut(path) {
+            Some(entry) if entry.file.modified == modified => {
+                entry.last_used = clock;
+                return Some(entry.file.clone());
+            }
+            Some(_) => lru.remove(path),
+            None => {}
+        }
+        return None;
+    }
+
+    // Store `file`, evicting least recently used entries until both limits are respected.
+    pub fn insert(&self, path: PathBuf, file: CachedFile, max_bytes: usize, max_entries: usize) {
+        let size = file.body.len();
+        if !would_cache(size, max_bytes, max_entries) {
+            return;
+        }
+
+        let mut lru = self.lru.lock().unwrap_or_else(|e| e.into_inner());
+        lru.remove(&path);
+        while !lru.entries.is_empty() && (lru.entries.len() >= max_entries || lru.bytes + size > max_bytes) {
+            let oldest = lru.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(p, _)| p.clone());
+            if let Some(oldest) = oldest {
+                lru.remove(&oldest);
+            }
+        }
+
+        lru.clock += 1;
+        let last_used = lru.clock;
+        lru.bytes += size;
+        lru.entries.insert(path, Entry { file, last_used });
+    }
+}
+
+impl Lru {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.bytes -= entry.file.body.len();
+        }
+    }
+}
+
+/*
+Whether insert() would keep a body of `size` bytes under these limits. Callers check first, so a
+file the cache will refuse (too big, or the cache disabled) is never copied for it.
+*/
+pub fn would_cache(size: usize, max_bytes: usize, max_entries: usize) -> bool {
+    return max_entries > 0 && size <= MAX_CACHED_FILE_SIZE.min(max_bytes);
+}
+
+// A strong ETag from the file's size and modification time (what most static servers use).
+pub fn etag_for(length: u64, modified: SystemTime) -> String {
+    let nanos = modified.duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    return format!("\"{:x}-{:x}\"", length, nanos);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn file(size: usize, modified: SystemTime) -> CachedFile {
+        return CachedFile {
+            body: BodyBytes::shared(vec![b'x'; size].into()),
+            content_type: "text/plain".to_string(),
+            etag: etag_for(size as u64, modified),
+            modified,
+        };
+    }
+
+    #[test]
+    fn test_hit_and_invalidation() {
+        let cache = StaticCache::new();
+        let t0 = UNIX_EPOCH + Duration::from_secs(1000);
+        let t1 = t0 + Duration::from_secs(1);
+        cache.insert(PathBuf::from("a"), file(10, t0), 100, 10);
+
+        assert_eq!(cache.get(Path::new("a"), t0), Some(file(10, t0)));
+        assert_eq!(cache.get(Path::new("b"), t0), None);
+        // Modified on disk since: a miss, and the entry is gone.
+        assert_eq!(cache.get(Path::new("a"), t1), None);
+        assert_eq!(cache.get(Path::new("a"), t0), None);
+    }
+
+    #[test]
+    fn test_eviction_by_bytes() {
+        let cache = StaticCache::new();
+        let t = UNIX_EPOCH;
+        cache.insert(PathBuf::from("a"), file(40, t), 100, 10);
+        cache.insert(PathBuf::from("b"), file(40, t), 100, 10);
+        // Touch "a" so "b" becomes the oldest.
+        assert!(cache.get(Path::new("a"), t).is_some());
+        cache.insert(PathBuf::from("c"), file(40, t), 100, 10);
+
+        assert!(cache.get(Path::new("b"), t).is_none());
+        assert!(cache.get(Path::new("a"), t).is_some());
+        assert!(cache.get(Path::new("c"), t).is_some());
+    }
+
+    #[test]
+    fn test_eviction_by_entries_and_disabled() {
+        let cache = StaticCache::new();
+        let t = UNIX_EPOCH;
+        for name in ["a", "b", "c"] {
+            cache.insert(PathBuf::from(name), file(1, t), 100, 2);
+        }
+        assert!(cache.get(Path::new("a"), t).is_none());
+        assert!(cache.get(Path::new("b"), t).is_some());
+        assert!(cache.get(Path::new("c"), t).is_some());
+
+        // Zero limits, or a file over the byte budget: never stored.
+        let cache = StaticCache::new();
+        cache.insert(PathBuf::from("a"), file(1, t), 0, 10);
+        cache.insert(PathBuf::from("b"), file(1, t), 100, 0);
+        cache.insert(PathBuf::from("c"), file(101, t), 100, 10);
+        for name in ["a", "b", "c"] {
+            assert!(cache.get(Path::new(name), t).is_none());
+        }
+        assert!(!would_cache(1, 0, 10) && !would_cache(1, 100, 0) && !would_cache(101, 100, 10));
+        assert!(!would_cache(MAX_CACHED_FILE_SIZE + 1, usize::MAX, 10));
+        assert!(would_cache(100, 100, 1));
+    }
+}
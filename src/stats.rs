@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::timing::Timings;
+
+/*
+Counters shared by the accept loop and every worker thread.
+
+`active` is both a statistic and the admission control for max_clients: a connection only gets a
+worker if try_admit() manages to raise it while it is below the limit.
+*/
+#[derive(Default, Debug)]
+pub struct ServerStats {
+    // Connections currently being served.
+    pub active: AtomicUsize,
+    // Highest value `active` has ever reached.
+    pub peak_active: AtomicUsize,
+    // Connections admitted / turned away with 503 since startup.
+    pub admitted: AtomicU64,
+    pub rejected: AtomicU64,
+    // Open connections per client IP, for max_connections_per_ip. IPs with none are removed.
+    pub per_ip: Mutex<HashMap<IpAddr, usize>>,
+    // Connections turned away (429) because their IP already had too many.
+    pub rejected_per_ip: AtomicU64,
+    // Responses sent by workers since startup, and their bytes (heads included).
+    pub requests: AtomicU64,
+    pub bytes_sent: AtomicU64,
+    // How long each phase of answering a request takes (see timing.rs).
+    pub timings: Timings,
+}
+
+impl ServerStats {
+    // Count one response a worker sent. Its connection counts it too (ConnectionEntry::record_response()).
+    pub fn record_response(&self, bytes: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/*
+Proof that a connection holds one of the max_clients slots. Dropping it gives the slot back, so
+every way out of a worker (normal return, early break, panic unwinding) releases it exactly once.
+*/
+pub struct ClientSlot {
+    stats: Arc<ServerStats>,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.stats.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/*
+Take a slot if fewer than `max_clients` are in use, or None (answer 503).
+
+Checking with load() and then calling fetch_add() lets two threads both see max_clients - 1 and
+both get in. fetch_update() retries a compare-and-swap instead, so the check and the increment
+happen as one step: whichever thread loses the race sees the new count and is refused.
+*/
+pub fn try_admit(stats: &Arc<ServerStats>, max_clients: usize) -> Option<ClientSlot> {
+    let admitted = stats.active.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+        if active < max_clients { Some(active + 1) } else { None }
+    });
+
+    match admitted {
+        Ok(previous) => {
+            stats.peak_active.fetch_max(previous + 1, Ordering::SeqCst);
+            stats.admitted.fetch_add(1, Ordering::Relaxed);
+            return Some(ClientSlot { stats: Arc::clone(stats) });
+        }
+        Err(_) => {
+            stats.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+    }
+}
+
+/*
+Wait up to `within` for `active` to drop below `max_clients`, i.e. for a worker to finish and
+release its slot. Used after closing an idle connection to make room: the slot only comes back
+once that connection's worker notices and exits.
+*/
+pub fn wait_for_room(stats: &ServerStats, max_clients: usize, within: Duration) {
+    let deadline = Instant::now() + within;
+    while stats.active.load(Ordering::SeqCst) >= max_clients && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// Like ClientSlot, for one of an IP's max_connections_per_ip connections.
+pub struct IpSlot {
+    stats: Arc<ServerStats>,
+    ip: IpAddr,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut per_ip = self.stats.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/*
+Count a new connection from `ip`, or None (answer 429) if it already has `max_per_ip` open.
+0 means no limit; the connection is still counted. The map is only touched once per connection,
+so one Mutex is plenty.
+*/
+pub fn try_admit_ip(stats: &Arc<ServerStats>, ip: IpAddr, max_per_ip: usize) -> Option<IpSlot> {
+    let mut per_ip = stats.per_ip.lock().unwrap_or_else(|e| e.into_inner());
+    let count = per_ip.entry(ip).or_insert(0);
+    if max_per_ip > 0 && *count >= max_per_ip {
+        stats.rejected_per_ip.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+    *count += 1;
+    return Some(IpSlot { stats: Arc::clone(stats), ip });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_admission_never_exceeds_limit() {
+        let max_clients = 4;
+        let stats = Arc::new(ServerStats::default());
+        let barrier = Arc::new(Barrier::new(max_clients * 3));
+
+        // 3× max_clients "connections" arriving at once, each holding its slot for a moment.
+        let handles: Vec<_> = (0..max_clients * 3).map(|_| {
+            let stats = Arc::clone(&stats);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                let slot = try_admit(&stats, max_clients);
+                thread::sleep(Duration::from_millis(50));
+                return slot.is_some();
+            })
+        }).collect();
+        let admitted = handles.into_iter().map(|h| h.join().unwrap()).filter(|&a| a).count();
+
+        assert!(admitted >= 1);
+        assert!(stats.peak_active.load(Ordering::SeqCst) <= max_clients);
+        assert_eq!(stats.admitted.load(Ordering::SeqCst) as usize, admitted);
+        assert_eq!((stats.admitted.load(Ordering::SeqCst) + stats.rejected.load(Ordering::SeqCst)) as usize, max_clients * 3);
+        assert_eq!(stats.active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let stats = Arc::new(ServerStats::default());
+        let greedy = IpAddr::from([10, 0, 0, 1]);
+        let other = IpAddr::from([10, 0, 0, 2]);
+
+        let first = try_admit_ip(&stats, greedy, 2).unwrap();
+        let second = try_admit_ip(&stats, greedy, 2).unwrap();
+        assert!(try_admit_ip(&stats, greedy, 2).is_none());
+        // Other clients are unaffected.
+        assert!(try_admit_ip(&stats, other, 2).is_some());
+
+        drop(first);
+        assert!(try_admit_ip(&stats, greedy, 2).is_some());
+        assert_eq!(stats.rejected_per_ip.load(Ordering::SeqCst), 1);
+
+        // Everything released: nothing left in the map. 0 means unlimited.
+        drop(second);
+        assert!(stats.per_ip.lock().unwrap().is_empty());
+        let unlimited: Vec<_> = (0..10).map(|_| try_admit_ip(&stats, greedy, 0).unwrap()).collect();
+        assert_eq!(stats.per_ip.lock().unwrap()[&greedy], unlimited.len());
+    }
+
+    #[test]
+    fn test_per_ip_limit_ipv6() {
+        // PROXY-protocol clients can be IPv6; they are counted per full address like any other.
+        let stats = Arc::new(ServerStats::default());
+        let greedy: IpAddr = "2001:db8::1".parse().unwrap();
+        let neighbour: IpAddr = "2001:db8::2".parse().unwrap();
+
+        let _first = try_admit_ip(&stats, greedy, 1).unwrap();
+        assert!(try_admit_ip(&stats, greedy, 1).is_none());
+        assert!(try_admit_ip(&stats, neighbour, 1).is_some());
+        assert_eq!(stats.rejected_per_ip.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_slot_released_on_panic() {
+        let stats = Arc::new(ServerStats::default());
+        let slot = try_admit(&stats, 1).unwrap();
+        assert!(try_admit(&stats, 1).is_none());
+
+        let result = thread::spawn(move || {
+            let _slot = slot;
+            panic!("handler blew up");
+        }).join();
+        assert!(result.is_err());
+
+        assert_eq!(stats.active.load(Ordering::SeqCst), 0);
+        assert!(try_admit(&stats, 1).is_some());
+        assert_eq!(stats.rejected.load(Ordering::SeqCst), 1);
+    }
+}
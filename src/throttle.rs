@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+
+// Sends are sized to about this much of a second's allowance, so the rate stays smooth.
+const CHUNKS_PER_SECOND: u64 = 10;
+// Largest chunk, whatever the rate: the same bound send_some() puts on a send under a deadline.
+const MAX_CHUNK_BYTES: u64 = 64 * 1024;
+
+/*
+Paces one connection's sends to max_bytes_per_second_per_connection. Rather than sleeping a
+fixed time after every send, it works out when the bytes sent so far are due at the cap and waits
+only for whatever is left of that, before the next send. The first chunk is free, so a response
+smaller than one chunk never waits at all, and neither does the last send of a larger one.
+*/
+pub struct Throttle {
+    bytes_per_second: u64,
+    chunk: u64,
+    start: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    // None when there is no cap (0).
+    pub fn new(bytes_per_second: u64) -> Option<Throttle> {
+        if bytes_per_second == 0 {
+            return None;
+        }
+        let chunk = (bytes_per_second / CHUNKS_PER_SECOND).clamp(1, MAX_CHUNK_BYTES);
+        return Some(Throttle { bytes_per_second, chunk, start: Instant::now(), sent: 0 });
+    }
+
+    // The most the next send may carry.
+    pub fn chunk_len(&self) -> usize {
+        return self.chunk as usize;
+    }
+
+    // How long to hold off at `now` before the next send, to stay under the cap.
+    pub fn wait(&self, now: Instant) -> Duration {
+        let owed = self.sent.saturating_sub(self.chunk);
+        let due = self.start + Duration::from_secs_f64(owed as f64 / self.bytes_per_second as f64);
+        return due.saturating_duration_since(now);
+    }
+
+    pub fn record(&mut self, bytes: usize) {
+        self.sent += bytes as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacing() {
+        assert!(Throttle::new(0).is_none());
+        let mut throttle = Throttle::new(256 * 1024).unwrap();
+        let start = throttle.start;
+        assert_eq!(throttle.chunk_len(), 26214);
+
+        // The first chunk goes out at once, however early.
+        assert_eq!(throttle.wait(start), Duration::ZERO);
+        throttle.record(throttle.chunk_len());
+        assert_eq!(throttle.wait(start), Duration::ZERO);
+
+        // After that, 1 MiB is due 4 s in, minus the free chunk: ~3.9 s.
+        throttle.record(1024 * 1024 - throttle.chunk_len());
+        let wait = throttle.wait(start);
+        assert!(wait > Duration::from_millis(3850) && wait < Duration::from_millis(3950), "{:?}", wait);
+        assert_eq!(throttle.wait(start + Duration::from_secs(5)), Duration::ZERO);
+
+        // Chunks stay between one byte and the send_some() limit.
+        assert_eq!(Throttle::new(5).unwrap().chunk_len(), 1);
+        assert_eq!(Throttle::new(100 * 1024 * 1024).unwrap().chunk_len(), 64 * 1024);
+    }
+}
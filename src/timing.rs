@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// The steps of answering one request, in order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    // From the first byte of the request until the whole head is in.
+    Receive,
+    // parse_request() on the head.
+    Parse,
+    // Receiving the body (spooling a large one included).
+    Body,
+    // Routing and the handler, static file reads and compression included.
+    Handler,
+    // Writing the response to the socket.
+    Send,
+}
+
+pub const PHASES: [Phase; 5] = [Phase::Receive, Phase::Parse, Phase::Body, Phase::Handler, Phase::Send];
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        return match self {
+            Phase::Receive => "receive",
+            Phase::Parse => "parse",
+            Phase::Body => "body",
+            Phase::Handler => "handler",
+            Phase::Send => "send",
+        };
+    }
+}
+
+// Upper bounds, in microseconds, of the histogram buckets. One more bucket counts everything slower.
+pub const BUCKET_BOUNDS_US: [u64; 10] = [100, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000, 500_000, 2_500_000];
+
+// How long one phase took, over every request since startup.
+#[derive(Default, Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKET_BOUNDS_US.len() + 1],
+    count: AtomicU64,
+    total_us: AtomicU64,
+}
+
+impl Histogram {
+    pub fn record(&self, duration: Duration) {
+        let us = duration.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = BUCKET_BOUNDS_US.iter().position(|bound| us <= *bound).unwrap_or(BUCKET_BOUNDS_US.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        return self.count.load(Ordering::Relaxed);
+    }
+
+    // Per bucket, not cumulative: the last one is "slower than every bound".
+    pub fn bucket_counts(&self) -> Vec<u64> {
+        return self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect();
+    }
+}
+
+// One histogram per phase, in ServerStats.
+#[derive(Default, Debug)]
+pub struct Timings {
+    histograms: [Histogram; PHASES.len()],
+}
+
+impl Timings {
+    pub fn histogram(&self, phase: Phase) -> &Histogram {
+        return &self.histograms[phase as usize];
+    }
+
+    pub fn record(&self, breakdown: &Breakdown) {
+        for (phase, duration) in &breakdown.phases {
+            self.histogram(*phase).record(*duration);
+        }
+    }
+
+    /*
+    The histograms as text, one line per phase (GET /admin/timings):
+
+        handler count=12 total_ms=40.1 le_0.1ms=3 le_0.5ms=8 ... slower=0
+    */
+    pub fn report(&self) -> String {
+        let mut text = String::new();
+        for phase in PHASES {
+            let histogram = self.histogram(phase);
+            let total_ms = histogram.total_us.load(Ordering::Relaxed) as f64 / 1000.0;
+            text.push_str(&format!("{} count={} total_ms={:.1}", phase.name(), histogram.count(), total_ms));
+            let counts = histogram.bucket_counts();
+            for (bound, count) in BUCKET_BOUNDS_US.iter().zip(&counts) {
+                text.push_str(&format!(" le_{}ms={}", *bound as f64 / 1000.0, count));
+            }
+            text.push_str(&format!(" slower={}\n", counts[BUCKET_BOUNDS_US.len()]));
+        }
+        return text;
+    }
+}
+
+// The phases one request went through so far, with how long each took.
+#[derive(Default, Debug)]
+pub struct Breakdown {
+    phases: Vec<(Phase, Duration)>,
+}
+
+impl Breakdown {
+    pub fn add(&mut self, phase: Phase, duration: Duration) {
+        self.phases.push((phase, duration));
+    }
+
+    // The Server-Timing header value, "parse;dur=0.2, handler;dur=3.1" (milliseconds), for browser devtools.
+    pub fn server_timing(&self) -> String {
+        return self.phases.iter()
+            .map(|(phase, duration)| format!("{};dur={:.1}", phase.name(), millis(*duration)))
+            .collect::<Vec<String>>()
+            .join(", ");
+    }
+
+    // For the timing_log line: "receive=0.1ms parse=0.0ms handler=3.1ms".
+    pub fn summary(&self) -> String {
+        return self.phases.iter()
+            .map(|(phase, duration)| format!("{}={:.1}ms", phase.name(), millis(*duration)))
+            .collect::<Vec<String>>()
+            .join(" ");
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    return duration.as_secs_f64() * 1000.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_timing_format() {
+        let mut breakdown = Breakdown::default();
+        breakdown.add(Phase::Parse, Duration::from_micros(230));
+        breakdown.add(Phase::Handler, Duration::from_micros(3_140));
+        assert_eq!(breakdown.server_timing(), "parse;dur=0.2, handler;dur=3.1");
+        assert_eq!(breakdown.summary(), "parse=0.2ms handler=3.1ms");
+        assert_eq!(Breakdown::default().server_timing(), "");
+    }
+
+    #[test]
+    fn test_histogram_counts() {
+        let timings = Timings::default();
+        let mut breakdown = Breakdown::default();
+        breakdown.add(Phase::Handler, Duration::from_micros(50));
+        breakdown.add(Phase::Send, Duration::from_secs(10));
+        timings.record(&breakdown);
+        timings.record(&breakdown);
+
+        let handler = timings.histogram(Phase::Handler);
+        assert_eq!(handler.count(), 2);
+        assert_eq!(handler.bucket_counts()[0], 2);
+        // Past the last bound: the overflow bucket.
+        assert_eq!(timings.histogram(Phase::Send).bucket_counts()[BUCKET_BOUNDS_US.len()], 2);
+        assert_eq!(timings.histogram(Phase::Parse).count(), 0);
+
+        let report = timings.report();
+        assert!(report.contains("handler count=2 total_ms=0.1 le_0.1ms=2 le_0.5ms=0"), "{}", report);
+        assert!(report.contains("send count=2 total_ms=20000.0 ") && report.contains(" slower=2\n"), "{}", report);
+    }
+}
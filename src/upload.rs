@@ -0,0 +1,276 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::conditionals::{evaluate_preconditions, FileMeta, PreconditionResult};
+use crate::config::Config;
+use crate::handlers;
+use crate::request::{Body, Request};
+use crate::response::Response;
+use crate::static_cache::etag_for;
+use crate::util::{sanitize_path, is_denied_static_path};
+use crate::log::{error, info};
+
+/*
+The part of `url_path` below upload_prefix ("/uploads/a/b.bin" → "/a/b.bin"), or None when the
+path isn't strictly inside the upload area or no upload_directory is configured. The prefix
+itself ("/uploads", "/uploads/") is the directory, never a file, so it doesn't count.
+*/
+pub fn upload_subpath<'a>(url_path: &'a str, config: &Config) -> Option<&'a str> {
+    if config.upload_directory.is_empty() {
+        return None;
+    }
+    let rest = url_path.strip_prefix(config.upload_prefix.trim_end_matches('/'))?;
+    if !rest.starts_with('/') || rest == "/" {
+        return None;
+    }
+    return Some(rest);
+}
+
+/*
+The file a PUT or DELETE of `url_path` would create, replace or remove. Same rules as reading: sanitize_path()
+keeps it inside upload_directory (symlinks are never followed for writes), and dotfiles or
+denied extensions are refused. None means the request must be refused.
+*/
+pub fn resolve_upload_path(url_path: &str, config: &Config) -> Option<PathBuf> {
+    let rest = upload_subpath(url_path, config)?;
+    if rest.ends_with('/') || is_denied_static_path(url_path, config) {
+        return None;
+    }
+    let target = sanitize_path(rest, &config.upload_directory, false)?;
+    if target.is_dir() {
+        return None;
+    }
+    return Some(target);
+}
+
+/*
+Write `body` to `target`, creating parent directories as needed. The data goes to a hidden
+temporary file next to the target first and is renamed into place, so a GET never sees a
+half-written upload. A spooled body is already a complete file and is renamed into place
+directly; only if that fails (spool directory on another file system) is it copied.
+Returns true if the file is new, false if an existing one was replaced.
+*/
+pub fn store(target: &Path, body: &Body) -> io::Result<bool> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let existed = target.is_file();
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if let Body::File(spooled) = body
+        && fs::rename(spooled, target).is_ok() {
+        return Ok(!existed);
+    }
+
+    let name = target.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp = target.with_file_name(format!(
+        ".{}.{}-{}.part", name, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let copied = match body {
+        Body::Bytes(bytes) => fs::write(&temp, bytes),
+        Body::File(spooled) => fs::copy(spooled, &temp).map(|_| ()),
+    };
+    let written = copied.and_then(|_| fs::rename(&temp, target));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written?;
+    return Ok(!existed);
+}
+
+/*
+True if the request is conditional and its condition doesn't hold for the file at `target` as it
+is now: 412 then, and nothing is written or deleted. The ETag is the one a GET of the file gets
+(static_cache::etag_for), so a client can send back what it downloaded. The headers are weighed
+by conditionals::evaluate_preconditions(), as for a GET; for a write, If-None-Match: * is the
+one that matters, refusing to replace a file that exists.
+*/
+fn precondition_fails(req: &Request, target: &Path) -> bool {
+    let current = fs::metadata(target).ok()
+        .filter(|metadata| metadata.is_file())
+        .and_then(|metadata| Some((metadata.len(), metadata.modified().ok()?)));
+    let etag = current.map(|(length, modified)| etag_for(length, modified));
+    let meta = FileMeta { etag: etag.as_deref(), modified: current.map(|(_, modified)| modified) };
+    return evaluate_preconditions(req.conditionals(), &meta) == PreconditionResult::PreconditionFailed;
+}
+
+// PUT inside the upload area: 201 with Location for a new file, 204 for a replaced one.
+pub fn put(req: &Request, config: &Config) -> Response {
+    // sanitize_path() needs the base directory to exist.
+    if let Err(e) = fs::create_dir_all(&config.upload_directory) {
+        error!("❌ Cannot create upload_directory {}: {}", config.upload_directory, e);
+        return handlers::internal_server_error();
+    }
+    let Some(target) = resolve_upload_path(&req.path, config) else {
+        info!("🚫 Refusing upload to {}", req.path);
+        return handlers::forbidden();
+    };
+    if precondition_fails(req, &target) {
+        info!("🚫 Precondition failed for PUT {}", req.path);
+        return handlers::precondition_failed();
+    }
+
+    match store(&target, &req.body) {
+        Ok(true) => {
+            info!("📥 Stored {} ({} bytes)", req.path, req.body.len());
+            handlers::created(&req.path)
+        }
+        Ok(false) => {
+            info!("📥 Replaced {} ({} bytes)", req.path, req.body.len());
+            handlers::no_content()
+        }
+        Err(e) => {
+            error!("❌ Upload to {:?} failed: {}", target, e);
+            handlers::internal_server_error()
+        }
+    }
+}
+
+// DELETE inside the upload area: 204 once the file is gone, 404 if there was none.
+pub fn delete(req: &Request, config: &Config) -> Response {
+    if !Path::new(&config.upload_directory).is_dir() {
+        return handlers::not_found();
+    }
+    let Some(target) = resolve_upload_path(&req.path, config) else {
+        info!("🚫 Refusing DELETE of {}", req.path);
+        return handlers::forbidden();
+    };
+    if precondition_fails(req, &target) {
+        info!("🚫 Precondition failed for DELETE {}", req.path);
+        return handlers::precondition_failed();
+    }
+
+    match fs::remove_file(&target) {
+        Ok(()) => {
+            info!("🗑️ Deleted {}", req.path);
+            handlers::no_content()
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => handlers::not_found(),
+        Err(e) => {
+            error!("❌ Deleting {:?} failed: {}", target, e);
+            handlers::internal_server_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::request::parse_request;
+    use crate::response::HTTPStatus;
+    use crate::util::temp_root;
+
+    fn upload_config(dir: &Path) -> Config {
+        return test_config(".", &format!("allow_put = true\nupload_directory = {:?}\n", dir.to_str().unwrap()));
+    }
+
+    fn put_bytes(path: &str, body: &[u8], config: &Config) -> Response {
+        return put_with(path, "", body, config);
+    }
+
+    // put_bytes() with extra header lines, each ending in \r\n.
+    fn put_with(path: &str, headers: &str, body: &[u8], config: &Config) -> Response {
+        let raw = format!("PUT {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n", path, headers, body.len());
+        let mut req = parse_request(raw.as_bytes()).unwrap();
+        req.body = Body::Bytes(body.to_vec());
+        return put(&req, config);
+    }
+
+    #[test]
+    fn test_upload_subpath() {
+        let config = upload_config(Path::new("uploads"));
+        assert_eq!(upload_subpath("/uploads/a.bin", &config), Some("/a.bin"));
+        assert_eq!(upload_subpath("/uploads/x/a.bin", &config), Some("/x/a.bin"));
+        assert_eq!(upload_subpath("/uploads", &config), None);
+        assert_eq!(upload_subpath("/uploads/", &config), None);
+        assert_eq!(upload_subpath("/uploadsx/a.bin", &config), None);
+        assert_eq!(upload_subpath("/a.bin", &config), None);
+        assert_eq!(upload_subpath("/uploads/a.bin", &test_config(".", "")), None);
+    }
+
+    #[test]
+    fn test_put_creates_then_replaces() {
+        let dir = temp_root("upload");
+        let config = upload_config(&dir);
+
+        let resp = put_bytes("/uploads/nested/name.bin", &[0, 1, 2, 255], &config);
+        assert_eq!(resp.status, HTTPStatus::Created);
+        assert_eq!(resp.header_value("Location"), Some("/uploads/nested/name.bin"));
+        assert_eq!(fs::read(dir.join("nested").join("name.bin")).unwrap(), [0, 1, 2, 255]);
+
+        let resp = put_bytes("/uploads/nested/name.bin", b"new", &config);
+        assert_eq!(resp.status, HTTPStatus::NoContent);
+        assert_eq!(fs::read(dir.join("nested").join("name.bin")).unwrap(), b"new");
+
+        // No temporary files left behind.
+        assert_eq!(fs::read_dir(dir.join("nested")).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_spooled_upload_is_moved() {
+        let dir = temp_root("upload_spooled");
+        let config = upload_config(&dir);
+        let spooled = dir.join("incoming.body");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&spooled, b"large body").unwrap();
+
+        let mut req = parse_request(b"PUT /uploads/big.bin HTTP/1.1\r\nContent-Length: 10\r\n\r\n").unwrap();
+        req.body = Body::File(spooled.clone());
+        assert_eq!(put(&req, &config).status, HTTPStatus::Created);
+        assert_eq!(fs::read(dir.join("big.bin")).unwrap(), b"large body");
+        assert!(!spooled.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_put_refusals() {
+        let dir = temp_root("upload_refused");
+        let config = upload_config(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        for path in ["/uploads/.env", "/uploads/.git/config", "/uploads/sub", "/uploads/sub/"] {
+            let resp = put_bytes(path, b"x", &config);
+            assert_eq!(resp.status, HTTPStatus::Forbidden, "{}", path);
+        }
+        assert!(!dir.join(".env").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let dir = temp_root("upload_conditional");
+        let config = upload_config(&dir);
+        let file = dir.join("doc.txt");
+
+        // If-Match: * only writes over an existing file.
+        assert_eq!(put_with("/uploads/doc.txt", "If-Match: *\r\n", b"v1", &config).status, HTTPStatus::PreconditionFailed);
+        assert!(!file.exists());
+        assert_eq!(put_bytes("/uploads/doc.txt", b"v1", &config).status, HTTPStatus::Created);
+        let metadata = fs::metadata(&file).unwrap();
+        let etag = etag_for(metadata.len(), metadata.modified().unwrap());
+
+        // A stale ETag is refused and leaves the file alone; the current one goes through.
+        let stale = put_with("/uploads/doc.txt", "If-Match: \"0-0\"\r\n", b"lost update", &config);
+        assert_eq!(stale.status, HTTPStatus::PreconditionFailed);
+        assert_eq!(fs::read(&file).unwrap(), b"v1");
+        let current = put_with("/uploads/doc.txt", &format!("If-Match: {}\r\n", etag), b"v2", &config);
+        assert_eq!(current.status, HTTPStatus::NoContent);
+        assert_eq!(fs::read(&file).unwrap(), b"v2");
+
+        // If-None-Match: * only creates: the file exists now, so it isn't replaced.
+        assert_eq!(put_with("/uploads/doc.txt", "If-None-Match: *\r\n", b"v3", &config).status, HTTPStatus::PreconditionFailed);
+        assert_eq!(put_with("/uploads/new.txt", "If-None-Match: *\r\n", b"new", &config).status, HTTPStatus::Created);
+
+        // Modified since 1994: refused, for DELETE too.
+        let old = "If-Unmodified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n";
+        assert_eq!(put_with("/uploads/doc.txt", old, b"v3", &config).status, HTTPStatus::PreconditionFailed);
+        let raw = format!("DELETE /uploads/doc.txt HTTP/1.1\r\n{}\r\n", old);
+        assert_eq!(delete(&parse_request(raw.as_bytes()).unwrap(), &config).status, HTTPStatus::PreconditionFailed);
+        assert_eq!(fs::read(&file).unwrap(), b"v2");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,7 +1,10 @@
-use std::path::{Path, PathBuf};
+use std::net::Ipv4Addr;
+use std::path::{Component, Path, PathBuf};
 use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::config::Config;
+use crate::config::{Config, Rewrite};
+use crate::log::{error, debug, trace};
 
 // Converts a u16 port number to network byte order (big endian)
 // htons = "host to network short"
@@ -9,6 +12,92 @@ pub fn htons(port: u16) -> u16 {
     port.to_be()
 }
 
+/*
+An IPv4 address as the u32 that goes into IN_ADDR's S_addr. S_addr holds the address in network
+byte order: the four bytes in memory are a, b, c, d for a.b.c.d. Building the u32 from those
+bytes in native order (from_ne_bytes) puts them there on any machine. (from_le_bytes only
+happened to do the same because Windows on x86/ARM is little-endian.)
+*/
+pub fn ipv4_to_s_addr(ip: Ipv4Addr) -> u32 {
+    return u32::from_ne_bytes(ip.octets());
+}
+
+/*
+Decode %XX escapes in a URL path ("/my%20file.html" → "/my file.html").
+Returns None for a truncated or non-hex escape, or if the decoded bytes aren't valid UTF-8.
+'+' is left alone: it only means space in query strings, not in paths.
+*/
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    return String::from_utf8(decoded).ok();
+}
+
+/*
+Bring a request path into one canonical form so that routing and file lookup agree:
+"//about", "/./about" and "/about/." all become "/about".
+
+- percent-escapes are decoded first, so "%2e%2e" can't sneak past the ".." check;
+- empty segments (duplicate slashes) and "." segments are dropped;
+- any ".." segment is rejected (None → 400) rather than resolved, since it can only be an
+  attempt to climb out of the root;
+- a trailing slash is kept ("/docs/" names a directory), the empty path becomes "/".
+*/
+pub fn normalize_path(raw: &str) -> Option<String> {
+    let decoded = percent_decode(raw)?;
+    return normalize_segments(&decoded);
+}
+
+// The segment half of normalize_path(), for a path that has already been percent-decoded.
+fn normalize_segments(decoded: &str) -> Option<String> {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return None,
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if decoded.ends_with('/') && !segments.is_empty() {
+        normalized.push('/');
+    }
+    return Some(normalized);
+}
+
+// Windows device names: opening one reaches the device, not a file, with or without an extension.
+const RESERVED_DEVICE_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/*
+True if a path segment names a Windows device: "nul", "CON.html", "com3.tar.gz" or "aux:". Windows
+only looks at the part before the first dot (or colon), and ignores trailing spaces there.
+*/
+fn is_reserved_device_name(segment: &str) -> bool {
+    let stem = segment.split(['.', ':']).next().unwrap_or_default().trim_end_matches(' ');
+    return RESERVED_DEVICE_NAMES.iter().any(|name| name.eq_ignore_ascii_case(stem));
+}
+
 /*
 Prevent a user from requesting files outside the public directory using sneaky paths like:
 GET /../secret.txt
@@ -46,13 +135,29 @@ So: requested = ../etc/passwd ← user is trying to escape!
 
 
 */
-pub fn sanitize_path(url_path: &str) -> Option<PathBuf> {
-    println!("🔍 Entered sanitize_path()");
-    println!("📥 Raw URL path: {:?}", url_path);
+pub fn sanitize_path(url_path: &str, root_directory: &str, follow_symlinks: bool) -> Option<PathBuf> {
+    trace!("🔍 Entered sanitize_path()");
+    trace!("📥 Raw URL path: {:?}", url_path);
 
     // Disallow backslashes (Windows-specific), null bytes, or path traversal
     if url_path.contains("..") || url_path.contains('\\') || url_path.contains('\0') {
-        println!("⛔️ Rejected: Malicious characters found.");
+        debug!("⛔️ Rejected: Malicious characters found.");
+        return None;
+    }
+
+    /*
+    A colon only means something to NTFS: "secret.txt::$DATA" or "a.html:hidden:$DATA" open an
+    alternate data stream, and slip past extension-based deny rules on the way. Request paths are
+    always joined onto the base, so a drive letter ("C:") never belongs in one either.
+    */
+    if url_path.contains(':') {
+        debug!("⛔️ Rejected: NTFS stream syntax in {:?}.", url_path);
+        return None;
+    }
+
+    // CON, NUL, COM1 and friends are devices on Windows, whatever directory they seem to be in.
+    if url_path.split('/').any(is_reserved_device_name) {
+        debug!("⛔️ Rejected: Windows device name in {:?}.", url_path);
         return None;
     }
 
@@ -64,7 +169,19 @@ pub fn sanitize_path(url_path: &str) -> Option<PathBuf> {
     requested might now be "index.html" or "images/logo.png".
     */
     let requested = Path::new(url_path.trim_start_matches('/'));
-    println!("📂 Cleaned relative path: {:?}", requested);
+    trace!("📂 Cleaned relative path: {:?}", requested);
+
+    /*
+    Joining an absolute path replaces the base instead of extending it, and on Windows so does a
+    UNC share ("//server/share/file") or a verbatim "\\?\C:\" path, both of which show up as a
+    Prefix component. None of those may reach the join below.
+    */
+    let rooted = requested.is_absolute()
+        || requested.components().any(|component| matches!(component, Component::Prefix(_) | Component::RootDir));
+    if url_path.starts_with("//") || rooted {
+        debug!("⛔️ Rejected: {:?} is a UNC, verbatim or absolute path.", url_path);
+        return None;
+    }
 
     /*
     Prepend the public/ directory to whatever the user requested.
@@ -99,17 +216,15 @@ pub fn sanitize_path(url_path: &str) -> Option<PathBuf> {
     explicit (match, if let Err(e), etc.), but it defaults to implicit behaviour that can be painful.
     */
     // let base = Path::new("C:\\Users\\KYRIAKOS\\Desktop").canonicalize().ok()?;
-    let raw = fs::read_to_string("config.toml").expect("❌ Failed to read config file");
-    let config: Config = toml::from_str(&raw).expect("❌ Failed to parse config");
-
-    println!("📂 Root directory: {}", config.root_directory);
-    let base = match Path::new(&config.root_directory).canonicalize() {
+    // The root comes from the caller's config snapshot rather than re-reading config.toml here.
+    trace!("📂 Root directory: {}", root_directory);
+    let base = match Path::new(root_directory).canonicalize() {
         Ok(path) => {
-            println!("🛡 Canonical base dir: {:?}", path);
+            trace!("🛡 Canonical base dir: {:?}", path);
             path // Cannot be return path; here because this is the result of match
         }
         Err(e) => {
-            eprintln!("❌ Failed to canonicalize base directory: {}", e);
+            error!("❌ Failed to canonicalize base directory: {}", e);
             return None;
         }
     };
@@ -156,19 +271,49 @@ pub fn sanitize_path(url_path: &str) -> Option<PathBuf> {
     ALLOWED
     */
     let normalized = base.join(requested).components().collect::<PathBuf>();
-    println!("📌 Normalized full path: {:?}", normalized);
+    trace!("📌 Normalized full path: {:?}", normalized);
     /*
     Check if the requested path is inside the public/ directory.
     Prevent directory traversal attacks like ../../etc/passwd, which would escape the base dir.
     */
-    if normalized.starts_with(&base) {
-        println!("✅ Safe: Path is within base.");
-        return Some(normalized);
-    } else {
-        println!("🚫 Unsafe: Path escapes base.");
+    if !normalized.starts_with(&base) {
+        debug!("🚫 Unsafe: Path escapes base.");
         return None;
     }
 
+    /*
+    The check above is purely textual. A symlink (or a directory junction on Windows) inside the
+    root can still point anywhere, e.g. root/link -> C:\Windows, and "link/win.ini" passes the
+    string checks. So:
+    - unless follow_symlinks is set, refuse any path where the target or a directory on the way
+      to it is a symlink/reparse point (symlink_metadata doesn't follow the link itself);
+    - if the target exists, canonicalize it (which resolves every link) and check again that the
+      real location is still inside the canonical base.
+    A path that doesn't exist yet is left for the caller to 404.
+    */
+    if !follow_symlinks {
+        let mut current = base.clone();
+        for component in normalized.strip_prefix(&base).ok()?.components() {
+            current.push(component);
+            if fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink()) {
+                debug!("🚫 Unsafe: {:?} is a symlink and follow_symlinks is off.", current);
+                return None;
+            }
+        }
+    }
+    if normalized.exists() {
+        match normalized.canonicalize() {
+            Ok(resolved) if resolved.starts_with(&base) => {}
+            _ => {
+                debug!("🚫 Unsafe: Path resolves outside base.");
+                return None;
+            }
+        }
+    }
+
+    trace!("✅ Safe: Path is within base.");
+    return Some(normalized);
+
     /*
     📠 HTTP Version: HTTP/1.1 Method: GET, Path: /hello
     🔍 Entered sanitize_path()
@@ -188,3 +333,528 @@ pub fn sanitize_path(url_path: &str) -> Option<PathBuf> {
     safely interacts with the Win32 API.
     */
 }
+
+/*
+Should this (normalized, decoded) URL path be hidden from static serving?
+
+Any segment starting with a dot — the file itself or a directory on the way, as in
+"/a/.git/HEAD" — is hidden unless serve_hidden_files is set, and so is any file whose extension
+is in denied_extensions or whose name is in denied_filenames. Callers answer with 404 rather than 403, so probing for ".env" can't
+even confirm that it exists.
+*/
+pub fn is_denied_static_path(path: &str, config: &Config) -> bool {
+    if !config.serve_hidden_files && path.split('/').any(|segment| segment.starts_with('.')) {
+        return true;
+    }
+
+    let file_name = path.rsplit('/').next().unwrap_or("");
+    if config.denied_filenames.iter().any(|denied| denied.eq_ignore_ascii_case(file_name)) {
+        return true;
+    }
+    if let Some((_, extension)) = file_name.rsplit_once('.') {
+        return config.denied_extensions.iter().any(|denied| denied.eq_ignore_ascii_case(extension));
+    }
+    return false;
+}
+
+/*
+NTFS gives long names an 8.3 alias too ("SECRET~1.TXT" for "secret-configuration.txt"), which
+opens the same file, so a deny rule keyed on the long name never sees the request. For a request
+for `url_path` that resolved to `file`, the URL path with every alias replaced by the name on
+disk, or None if it used none. Only a segment with a '~' can be an alias, and canonicalize()
+expands it; a mere difference in case doesn't count.
+*/
+pub fn expand_short_names(url_path: &str, file: &Path) -> Option<String> {
+    let mut segments: Vec<String> = url_path.split('/').map(str::to_string).collect();
+    let mut expanded = false;
+    // Segments and path components match up from the end (a mount prefix isn't on disk).
+    let mut on_disk = Some(file);
+    for segment in segments.iter_mut().rev().filter(|segment| !segment.is_empty()) {
+        let Some(path) = on_disk else { break };
+        if segment.contains('~')
+            && let Some(name) = path.canonicalize().ok().and_then(|real| Some(real.file_name()?.to_string_lossy().into_owned()))
+            && !name.eq_ignore_ascii_case(segment) {
+            *segment = name;
+            expanded = true;
+        }
+        on_disk = path.parent();
+    }
+    return expanded.then(|| segments.join("/"));
+}
+
+/*
+Apply the first matching [[rewrites]] rule to a normalized path.
+
+Returns the path unchanged when no rule matches. The rule's `to` is decoded like any URL path,
+but the rest of `path` was decoded once already and isn't again (GET /docs/100%25.html arrives
+here as "/docs/100%.html", a stray "%" a second decode would reject). The result has its
+segments normalized once more, so a rule (or the remainder of a prefix match) can never
+introduce a "..": such a result is None, which the caller answers with 400 just like a
+malicious request.
+*/
+pub fn apply_rewrites(path: &str, rewrites: &[Rewrite]) -> Option<String> {
+    for rule in rewrites {
+        let rewritten = if rule.prefix {
+            let from = rule.from.trim_end_matches('/');
+            match path.strip_prefix(from) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                    format!("{}{}", percent_decode(rule.to.trim_end_matches('/'))?, rest)
+                }
+                _ => continue,
+            }
+        } else if path == rule.from {
+            percent_decode(&rule.to)?
+        } else {
+            continue;
+        };
+
+        return normalize_segments(&rewritten);
+    }
+
+    return Some(path.to_string());
+}
+
+/*
+Map a (normalized) URL path to a file system path, honouring [[mounts]].
+
+The mount with the longest matching prefix wins; its prefix is stripped and the rest is resolved
+against the mount's directory by sanitize_path(), with exactly the same traversal protection as
+the root. Matching is per path segment: "/static" and "/static/" both match "/static/app.css"
+and "/static", but never "/staticfiles". Paths outside every mount use root_directory.
+A configured upload_directory is mounted at upload_prefix, so uploaded files can be read back.
+*/
+pub fn resolve_static_path(url_path: &str, config: &Config) -> Option<PathBuf> {
+    let mut best: Option<(&str, &str)> = None;
+
+    let uploads = (!config.upload_directory.is_empty())
+        .then_some((config.upload_prefix.as_str(), config.upload_directory.as_str()));
+    let mounts = config.mounts.iter().map(|m| (m.prefix.as_str(), m.directory.as_str())).chain(uploads);
+
+    for (prefix, directory) in mounts {
+        let prefix = prefix.trim_end_matches('/');
+        let matches = match url_path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        if matches && best.is_none_or(|(longest, _)| prefix.len() > longest.len()) {
+            best = Some((prefix, directory));
+        }
+    }
+
+    return match best {
+        Some((prefix, directory)) => sanitize_path(&url_path[prefix.len()..], directory, config.follow_symlinks),
+        None => sanitize_path(url_path, &config.root_directory, config.follow_symlinks),
+    };
+}
+
+/*
+Turn a sanitized path into the file to actually serve.
+A regular file is served as-is. For a directory, the configured index file names are tried in
+order ("index.html", then "default.htm", ...) and the first one that exists wins; an empty list
+means directories are never served. Returns None when there is nothing to serve.
+*/
+pub fn resolve_static_file(path: &Path, index_files: &[String]) -> Option<PathBuf> {
+    if !path.is_dir() {
+        return Some(path.to_path_buf());
+    }
+
+    return index_files.iter()
+        .map(|name| path.join(name))
+        .find(|candidate| candidate.is_file());
+}
+
+/*
+64-bit FNV-1a: a tiny, fast, non-cryptographic hash. Good enough to tell two response bodies
+apart for an ETag; not for anything an attacker could benefit from colliding.
+*/
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+/*
+Compare secrets without returning early at the first differing byte, so response timing doesn't
+reveal how much of a guessed token was right. (The length still leaks; tokens are meant to be
+long random strings, so that's fine.)
+*/
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    return a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0;
+}
+
+// True if `path` is `prefix` or lies below it, segment by segment ("/api" covers "/api/x", not "/apix").
+pub fn under_prefix(path: &str, prefix: &str) -> bool {
+    return match path.strip_prefix(prefix.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    };
+}
+
+/*
+under_prefix(), ignoring ASCII case: what a rule guarding files must use, since NTFS opens
+"/API/secret.json" from the same directory as "/api/secret.json".
+*/
+pub fn under_prefix_ignoring_case(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    return match path.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => {
+            let rest = &path[prefix.len()..];
+            rest.is_empty() || rest.starts_with('/')
+        }
+        _ => false,
+    };
+}
+
+// `text` with the characters HTML gives a meaning to replaced by entities (safe in attributes too).
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+/*
+An IMF-fixdate ("Sun, 06 Nov 1994 08:49:37 GMT"), the only date format a server must accept in
+If-Unmodified-Since. None for anything else, which the caller treats as no header at all. The
+calendar date to days since the epoch is Howard Hinnant's days_from_civil, the inverse of the
+one access_log.rs formats with.
+*/
+pub fn parse_http_date(text: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let parts: Vec<&str> = text.trim().split(' ').collect();
+    let [weekday, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    if !weekday.ends_with(',') || day.len() != 2 || year.len() != 4 || time.len() != 8 {
+        return None;
+    }
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let clock: Vec<u64> = time.split(':').map(|field| field.parse().ok()).collect::<Option<_>>()?;
+    let [hours, minutes, seconds] = clock[..] else {
+        return None;
+    };
+    if !(1..=31).contains(&day) || year < 1970 || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146097 + doe - 719468) as u64;
+
+    return Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hours * 3600 + minutes * 60 + seconds));
+}
+
+// A fresh, empty directory for a unit test, unique per test name and process.
+#[cfg(test)]
+pub fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("vibettp_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    return root;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    #[test]
+    fn test_fnv1a_64() {
+        // Reference values from the FNV test suite.
+        assert_eq!(fnv1a_64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_64(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[test]
+    fn test_parse_http_date() {
+        let at = |secs: u64| Some(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), at(784111777));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), at(0));
+        assert_eq!(parse_http_date("Thu, 29 Feb 2024 12:00:00 GMT"), at(1709208000));
+        for invalid in ["", "Sunday, 06-Nov-94 08:49:37 GMT", "Sun Nov  6 08:49:37 1994", "Sun, 06 Nov 1994 08:49:37 UTC",
+                        "Sun, 06 Foo 1994 08:49:37 GMT", "Sun, 6 Nov 1994 08:49:37 GMT", "Sun, 06 Nov 1994 24:00:00 GMT"] {
+            assert_eq!(parse_http_date(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_ipv4_to_s_addr() {
+        let ip = Ipv4Addr::new(192, 168, 1, 20);
+        assert_eq!(ipv4_to_s_addr(ip).to_ne_bytes(), [192, 168, 1, 20]);
+        // As a number it is the address in big-endian order...
+        assert_eq!(u32::from_be(ipv4_to_s_addr(ip)), 0xC0A8_0114);
+        // ...which on a little-endian machine (every Windows target) reads "backwards".
+        #[cfg(target_endian = "little")]
+        assert_eq!(ipv4_to_s_addr(ip), 0x1401_A8C0);
+        assert_eq!(ipv4_to_s_addr(Ipv4Addr::new(127, 0, 0, 1)).to_ne_bytes(), [127, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        let cases: [(&str, Option<&str>); 6] = [
+            ("/plain", Some("/plain")),
+            ("/my%20file.html", Some("/my file.html")),
+            ("/%C3%A9t%C3%A9", Some("/été")),
+            ("/a+b", Some("/a+b")),
+            ("/bad%2", None),
+            ("/bad%zz", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(percent_decode(input).as_deref(), expected, "input {:?}", input);
+        }
+        // %FF on its own is not UTF-8.
+        assert_eq!(percent_decode("/%FF"), None);
+    }
+
+    #[test]
+    fn test_index_file_candidates() {
+        let root = temp_root("index");
+        std::fs::write(root.join("default.htm"), "default").unwrap();
+        let base = sanitize_path("/", root.to_str().unwrap(), false).unwrap();
+
+        let names = vec!["index.html".to_string(), "default.htm".to_string()];
+        let chosen = resolve_static_file(&base, &names).unwrap();
+        assert_eq!(chosen.file_name().unwrap(), "default.htm");
+        assert_eq!(std::fs::read_to_string(chosen).unwrap(), "default");
+
+        // An empty list disables index serving.
+        assert_eq!(resolve_static_file(&base, &[]), None);
+        // No candidate present.
+        assert_eq!(resolve_static_file(&base, &["index.xhtml".to_string()]), None);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_mount_points() {
+        let root = temp_root("mount_root");
+        let assets = temp_root("mount_assets");
+        let nested = temp_root("mount_nested");
+        std::fs::write(assets.join("app.css"), "css").unwrap();
+
+        let extra = format!(
+            "[[mounts]]\nprefix = \"/static/\"\ndirectory = {:?}\n\
+             [[mounts]]\nprefix = \"/static/deep\"\ndirectory = {:?}\n",
+            assets.to_str().unwrap(), nested.to_str().unwrap()
+        );
+        let config = test_config(root.to_str().unwrap(), &extra);
+        let canonical = |p: &PathBuf| p.canonicalize().unwrap();
+
+        let path = resolve_static_path("/static/app.css", &config).unwrap();
+        assert_eq!(path, canonical(&assets).join("app.css"));
+        assert_eq!(resolve_static_path("/static", &config).unwrap(), canonical(&assets));
+
+        // Longest prefix wins.
+        let path = resolve_static_path("/static/deep/x.txt", &config).unwrap();
+        assert_eq!(path, canonical(&nested).join("x.txt"));
+
+        // Segment-aware: "/staticfiles" is not under the "/static/" mount.
+        let path = resolve_static_path("/staticfiles/app.css", &config).unwrap();
+        assert_eq!(path, canonical(&root).join("staticfiles").join("app.css"));
+
+        // Traversal is refused for every mount, not just the root.
+        assert_eq!(resolve_static_path("/static/../secret.txt", &config), None);
+        assert_eq!(resolve_static_path("/static/deep/../../secret.txt", &config), None);
+        assert_eq!(resolve_static_path("/../secret.txt", &config), None);
+
+        for dir in [root, assets, nested] {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_rewrites() {
+        let rules = vec![
+            Rewrite { from: "/favicon.ico".to_string(), to: "/assets/icons/fav.ico".to_string(), prefix: false },
+            Rewrite { from: "/docs".to_string(), to: "/documentation".to_string(), prefix: true },
+            Rewrite { from: "/evil".to_string(), to: "/%2e%2e/secret".to_string(), prefix: false },
+        ];
+        let cases: [(&str, Option<&str>); 9] = [
+            ("/favicon.ico", Some("/assets/icons/fav.ico")),
+            // Already decoded by normalize_path(): "%" stays a literal percent sign.
+            ("/docs/100%.html", Some("/documentation/100%.html")),
+            ("/docs/%2e%2e/x", Some("/documentation/%2e%2e/x")),
+            ("/favicon.ico/x", Some("/favicon.ico/x")),
+            ("/docs", Some("/documentation")),
+            ("/docs/guide/", Some("/documentation/guide/")),
+            ("/docsearch", Some("/docsearch")),
+            ("/about", Some("/about")),
+            ("/evil", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(apply_rewrites(input, &rules).as_deref(), expected, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_hidden_and_denied_paths() {
+        let config = test_config(".", "denied_extensions = [\"env\", \"bak\", \"sqlite\"]\n");
+        assert!(is_denied_static_path("/.env", &config));
+        assert!(is_denied_static_path("/a/.git/HEAD", &config));
+        assert!(is_denied_static_path("/data/app.SQLITE", &config));
+        assert!(is_denied_static_path("/site.bak", &config));
+        assert!(!is_denied_static_path("/index.html", &config));
+        assert!(!is_denied_static_path("/", &config));
+
+        let config = test_config(".", "denied_filenames = [\"web.config\", \"config.toml\"]\n");
+        assert!(is_denied_static_path("/Web.Config", &config));
+        assert!(is_denied_static_path("/app/CONFIG.TOML", &config));
+        assert!(!is_denied_static_path("/web.config.html", &config));
+        assert!(!is_denied_static_path("/my-web.config", &config));
+
+        let config = test_config(".", "serve_hidden_files = true\n");
+        assert!(!is_denied_static_path("/.env", &config));
+        assert!(!is_denied_static_path("/.well-known/security.txt", &config));
+    }
+
+    #[test]
+    fn test_reserved_device_names() {
+        for reserved in ["CON", "con.html", "COM3", "nul.txt", "Lpt9.tar.gz", "aux:", "PRN .txt"] {
+            assert!(is_reserved_device_name(reserved), "{}", reserved);
+        }
+        for ordinary in ["console.html", "COM10", "com", "nulls.txt", "lpt", "index.html", ""] {
+            assert!(!is_reserved_device_name(ordinary), "{}", ordinary);
+        }
+
+        // Anywhere in the path, before the file system is even asked.
+        assert_eq!(sanitize_path("/nul", ".", false), None);
+        assert_eq!(sanitize_path("/docs/CON.html", ".", false), None);
+        assert_eq!(sanitize_path("/com3/readme.txt", ".", false), None);
+        assert!(sanitize_path("/console.html", ".", false).is_some());
+    }
+
+    #[test]
+    fn test_alternate_data_streams() {
+        for stream in ["/secret.txt::$DATA", "/index.html:evil:$DATA", "/download.zip:Zone.Identifier", "/C:/Windows/win.ini"] {
+            assert_eq!(sanitize_path(stream, ".", false), None, "{}", stream);
+        }
+        // A file really named with a colon can't exist on Windows, so refusing it loses nothing.
+        assert_eq!(sanitize_path("/notes:2024.txt", ".", false), None);
+        assert!(sanitize_path("/notes-2024.txt", ".", false).is_some());
+    }
+
+    #[test]
+    fn test_unc_and_verbatim_paths() {
+        for rooted in ["//etc/passwd", "//server/share/file", "/\\\\server\\share", "/\\\\?\\C:\\Windows\\win.ini"] {
+            assert_eq!(sanitize_path(rooted, ".", false), None, "{}", rooted);
+        }
+        assert!(sanitize_path("/etc/passwd", ".", false).is_some_and(|path| path.starts_with(Path::new(".").canonicalize().unwrap())));
+    }
+
+    #[test]
+    fn test_short_name_aliases() {
+        let root = temp_root("short_names");
+        std::fs::create_dir_all(root.join("private-documents")).unwrap();
+        std::fs::write(root.join("private-documents").join("secret-configuration.txt"), "secret").unwrap();
+        std::fs::write(root.join("plain~1.txt"), "a tilde, but a real name").unwrap();
+
+        // Names with no alias (or a real '~' in them) come back as None.
+        assert_eq!(expand_short_names("/plain~1.txt", &root.join("plain~1.txt")), None);
+        assert_eq!(expand_short_names("/missing~1.txt", &root.join("missing~1.txt")), None);
+
+        // Only NTFS volumes with 8.3 name generation on have the alias.
+        let alias = root.join("PRIVAT~1").join("SECRET~1.TXT");
+        if !alias.exists() {
+            println!("⚠️ Skipping: no 8.3 short names on this volume.");
+            let _ = std::fs::remove_dir_all(&root);
+            return;
+        }
+        assert_eq!(
+            expand_short_names("/files/PRIVAT~1/SECRET~1.TXT", &alias).as_deref(),
+            Some("/files/private-documents/secret-configuration.txt")
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    // Create a directory symlink, or None if the platform/privileges don't allow it.
+    fn make_dir_link(target: &Path, link: &Path) -> Option<()> {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(target, link).ok();
+        #[cfg(windows)]
+        return std::os::windows::fs::symlink_dir(target, link).ok();
+    }
+
+    #[test]
+    fn test_symlink_escape() {
+        let root = temp_root("symlink_root");
+        let outside = temp_root("symlink_outside");
+        std::fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::fs::create_dir_all(root.join("real")).unwrap();
+        std::fs::write(root.join("real").join("page.html"), "page").unwrap();
+
+        // Creating symlinks on Windows needs Developer Mode or admin rights.
+        if make_dir_link(&outside, &root.join("escape")).is_none()
+            || make_dir_link(&root.join("real"), &root.join("inner")).is_none() {
+            println!("⚠️ Skipping: cannot create symlinks here.");
+            return;
+        }
+        let root_str = root.to_str().unwrap();
+
+        // Escaping links are refused whether or not links are followed.
+        assert_eq!(sanitize_path("/escape/secret.txt", root_str, false), None);
+        assert_eq!(sanitize_path("/escape/secret.txt", root_str, true), None);
+        assert_eq!(sanitize_path("/escape", root_str, true), None);
+
+        // A link that stays inside the root is fine only when following links is allowed.
+        assert_eq!(sanitize_path("/inner/page.html", root_str, false), None);
+        assert!(sanitize_path("/inner/page.html", root_str, true).is_some());
+
+        // Plain files are unaffected.
+        assert!(sanitize_path("/real/page.html", root_str, false).is_some());
+
+        for dir in [root, outside] {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        let cases: [(&str, Option<&str>); 14] = [
+            ("", Some("/")),
+            ("/", Some("/")),
+            ("//", Some("/")),
+            ("/about", Some("/about")),
+            ("//about", Some("/about")),
+            ("/./about", Some("/about")),
+            ("/about/.", Some("/about")),
+            ("/about/./", Some("/about/")),
+            ("/docs//guide/", Some("/docs/guide/")),
+            ("/a/%2e/b", Some("/a/b")),
+            ("/../secret", None),
+            ("/a/%2e%2e/secret", None),
+            ("/a/%2E%2E", None),
+            ("/a%2Fb", Some("/a/b")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize_path(input).as_deref(), expected, "input {:?}", input);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::io;
+use std::thread::{self, JoinHandle};
+
+use crate::log::error;
+use crate::panics;
+
+// One connection's worker thread and who it is talking to (for the logs).
+struct Worker {
+    handle: JoinHandle<()>,
+    peer: String,
+}
+
+/*
+The accept loop's record of the worker threads it has started, keyed by connection id.
+
+Dropping a JoinHandle detaches the thread: a panic in it would go unnoticed and nothing would
+limit how many exist. Keeping the handles lets the accept loop reap finished workers (and report
+the ones that panicked), refuse to go past max_worker_threads, and join the rest on shutdown.
+Only the accept loop touches this, so it needs no locking.
+*/
+pub struct Workers {
+    next_id: u64,
+    running: HashMap<u64, Worker>,
+}
+
+impl Workers {
+    pub fn new() -> Workers {
+        return Workers { next_id: 1, running: HashMap::new() };
+    }
+
+    // Workers started and not yet reaped (some may have finished since the last reap()).
+    pub fn len(&self) -> usize {
+        return self.running.len();
+    }
+
+    /*
+    Run `work` on a new thread named "vibettp-worker-<id>", so a debugger or a thread dump shows
+    which connection each thread serves. It receives the connection id, which is also returned.
+    Err if the OS refused to create the thread; `work` is dropped without running.
+    */
+    pub fn spawn<F>(&mut self, peer: String, work: F) -> io::Result<u64>
+    where
+        F: FnOnce(u64) + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let handle = thread::Builder::new()
+            .name(format!("vibettp-worker-{}", id))
+            .spawn(move || work(id))?;
+        self.running.insert(id, Worker { handle, peer });
+        return Ok(id);
+    }
+
+    // Join every worker that has finished, logging the ones that panicked. Returns their ids.
+    pub fn reap(&mut self) -> Vec<u64> {
+        let finished: Vec<u64> = self.running.iter()
+            .filter(|(_, worker)| worker.handle.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+
+        let mut panicked = Vec::new();
+        for id in finished {
+            if let Some(worker) = self.running.remove(&id)
+                && join_and_report(id, worker) {
+                panicked.push(id);
+            }
+        }
+        return panicked;
+    }
+
+    // Wait for every remaining worker (shutdown).
+    pub fn join_all(&mut self) {
+        for (id, worker) in self.running.drain() {
+            join_and_report(id, worker);
+        }
+    }
+}
+
+// Join one worker; true (after logging it) if it panicked.
+fn join_and_report(id: u64, worker: Worker) -> bool {
+    match worker.handle.join() {
+        Ok(()) => return false,
+        Err(payload) => {
+            error!("💥 Worker for connection #{} ({}) panicked: {}", id, worker.peer, panics::message(&*payload));
+            return true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_until_finished(workers: &Workers) {
+        while workers.running.values().any(|worker| !worker.handle.is_finished()) {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_reap_reports_panics_and_keeps_going() {
+        let mut workers = Workers::new();
+        let ok = workers.spawn("127.0.0.1:50000".to_string(), |_| {}).unwrap();
+        let bad = workers.spawn("127.0.0.1:50001".to_string(), |id| panic!("handler failed on #{}", id)).unwrap();
+        assert_ne!(ok, bad);
+        assert_eq!(workers.len(), 2);
+
+        wait_until_finished(&workers);
+        assert_eq!(workers.reap(), vec![bad]);
+        assert_eq!(workers.len(), 0);
+
+        // Still usable afterwards.
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let id = workers.spawn("127.0.0.1:50002".to_string(), move |_| {
+            sender.send(thread::current().name().map(str::to_string)).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }).unwrap();
+        assert_eq!(workers.len(), 1);
+        assert_eq!(receiver.recv().unwrap(), Some(format!("vibettp-worker-{}", id)));
+        workers.join_all();
+        assert_eq!(workers.len(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::{send_request, TestServer};
+
+// The admin endpoints need a token in the config; these servers get this one.
+const TOKEN: &str = "vibettp-test-token";
+
+fn admin_server() -> TestServer {
+    return TestServer::start(&format!("admin_token = {:?}", TOKEN));
+}
+
+#[test]
+fn test_shutdown_requires_token() {
+    // Disabled: must look like any other missing path.
+    let response = send_request("POST /admin/shutdown HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("404 Not Found"), "Expected 404, got:\n{}", response);
+
+    let server = admin_server();
+    let response = server.send("POST /admin/shutdown HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("401 Unauthorized"), "Expected 401, got:\n{}", response);
+    assert!(response.contains("WWW-Authenticate: Bearer"), "Missing challenge:\n{}", response);
+    let response = server.send(
+        "POST /admin/shutdown HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer not-the-token\r\n\r\n"
+    );
+    assert!(response.contains("403 Forbidden"), "Expected 403, got:\n{}", response);
+}
+
+#[test]
+fn test_maintenance_toggle() {
+    let server = admin_server();
+    let toggle = |on: bool| server.send(&format!(
+        "POST /admin/maintenance?on={} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n", on, TOKEN
+    ));
+
+    let response = toggle(true);
+    assert!(response.contains("maintenance on"), "Expected maintenance on, got:\n{}", response);
+    let page = server.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let health = server.send("GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let head = server.send("HEAD /health HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let response = toggle(false);
+    assert!(response.contains("maintenance off"), "Expected maintenance off, got:\n{}", response);
+
+    assert!(page.contains("503 Service Unavailable"), "Expected 503, got:\n{}", page);
+    assert!(page.contains("Retry-After: "), "Missing Retry-After:\n{}", page);
+    assert!(health.contains("200 OK") && health.ends_with("degraded"), "Expected degraded, got:\n{}", health);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n") && head.ends_with("\r\n\r\n"), "Expected a bodiless 200, got:\n{}", head);
+    let health = server.send("GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(health.ends_with("ok"), "Expected ok, got:\n{}", health);
+}
+
+#[test]
+fn test_shutdown_stops_server() {
+    let server = admin_server();
+    let response = server.send(&format!(
+        "POST /admin/shutdown HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\n\r\n", TOKEN
+    ));
+    assert!(response.contains("202 Accepted"), "Expected 202, got:\n{}", response);
+
+    // The listener goes away once the accept loop has been woken up.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(&server.addr).is_ok() {
+        assert!(Instant::now() < deadline, "Server still accepting connections after shutdown");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
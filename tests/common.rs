@@ -1,19 +1,218 @@
-use std::net::TcpStream;
-use std::io::{Read, Write};
+// Each test file compiles its own copy of this module and uses only some of the helpers.
+#![allow(dead_code)]
 
-pub const SERVER_ADDR: &str = "127.0.0.1:7878";
+use std::collections::VecDeque;
+use std::net::{TcpListener, TcpStream};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
 
+use vibettp::config::{load_config, Config};
+use vibettp::router::Router;
+
+// What every test server is configured with, before its own port, directories and extra settings.
+pub const FIXTURE: &str = "tests/config.toml";
+
+/*
+A server under test, run on a thread of the test binary with the fixture config. Each one gets
+an unused port and a fresh directory holding its config file, its access log and www/, its
+root_directory. It serves until the test binary exits (or a test shuts it down).
+*/
+pub struct TestServer {
+    pub addr: String,
+    pub dir: PathBuf,
+    pub config_path: PathBuf,
+    // The settings it runs with, read back the way the server reads them, defaults included.
+    pub config: Config,
+}
+
+impl TestServer {
+    /*
+    A server with the fixture config plus `extra`, TOML whose keys replace or add to the fixture's.
+    `{dir}` in it stands for the server's directory; put paths in 'literal strings', since on
+    Windows it is full of backslashes.
+    */
+    pub fn start(extra: &str) -> TestServer {
+        return TestServer::start_with(extra, |_| {});
+    }
+
+    // start(), with `customize` given the Router to add routes or change the fallback.
+    pub fn start_with<F: FnOnce(&mut Router) + Send + 'static>(extra: &str, customize: F) -> TestServer {
+        // A port nothing listens on right now, as the system hands them out.
+        let probe = TcpListener::bind("127.0.0.1:0").expect("No free port");
+        let port = probe.local_addr().unwrap().port();
+        drop(probe);
+
+        let dir = std::env::temp_dir().join("vibettp-tests").join(port.to_string());
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("www")).expect("Failed to create the server directory");
+
+        let raw = fs::read_to_string(FIXTURE).expect("Failed to read the fixture config");
+        let mut config: toml::Table = toml::from_str(&raw).expect("Failed to parse the fixture config");
+        let extra = extra.replace("{dir}", &dir.to_string_lossy());
+        config.extend(toml::from_str::<toml::Table>(&extra).expect("Failed to parse the extra config"));
+        config.insert("port".to_string(), toml::Value::Integer(port as i64));
+        config.insert("root_directory".to_string(), dir.join("www").to_string_lossy().into_owned().into());
+        config.insert("access_log_path".to_string(), dir.join("access.log").to_string_lossy().into_owned().into());
+        let config_path = dir.join("config.toml");
+        fs::write(&config_path, toml::to_string(&config).unwrap()).expect("Failed to write the config");
+
+        let path = config_path.to_string_lossy().into_owned();
+        let loaded = load_config(&path).expect("The test server's config is invalid");
+        thread::spawn(move || vibettp::server::run_server_with(&path, None, customize));
+
+        // Listening once a connection goes through; it is closed unanswered, like any idle client.
+        let addr = format!("127.0.0.1:{}", port);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(&addr).is_err() {
+            assert!(Instant::now() < deadline, "The test server on {} did not start", addr);
+            thread::sleep(Duration::from_millis(20));
+        }
+        return TestServer { addr, dir, config_path, config: loaded };
+    }
+
+    // Where the server serves files from.
+    pub fn root_directory(&self) -> PathBuf {
+        return self.dir.join("www");
+    }
+
+    pub fn send(&self, request: &str) -> String {
+        // Connect to the server
+        let mut stream = TcpStream::connect(&self.addr).expect("Failed to connect");
+
+        // Send a basic HTTP request
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+        // Read the response into a string
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        return response;
+    }
+}
+
+// The server most tests share, started by whichever test needs it first: the fixture config as it is.
+pub fn server() -> &'static TestServer {
+    static SERVER: OnceLock<TestServer> = OnceLock::new();
+    return SERVER.get_or_init(|| TestServer::start(""));
+}
+
+// Send one request to the shared server, shut down the write side and read until it closes.
 pub fn send_request(request: &str) -> String {
-    // Connect to the (running) server
-    let mut stream = TcpStream::connect(SERVER_ADDR).expect("Failed to connect");
+    return server().send(request);
+}
+
+/*
+One persistent connection, for keep-alive tests. send() writes a request and reads exactly one
+response (Content-Length bytes of body, or everything until the close without one), so the next
+send() goes out on the same stream. write() and read_response() do the halves separately, for
+pipelining.
+*/
+pub struct TestConnection {
+    reader: BufReader<TcpStream>,
+    // Per request written and not answered yet: whether it was a HEAD (its response has no body).
+    pending_heads: VecDeque<bool>,
+}
+
+impl TestConnection {
+    pub fn open() -> TestConnection {
+        return TestConnection::open_to(server());
+    }
+
+    pub fn open_to(server: &TestServer) -> TestConnection {
+        let stream = TcpStream::connect(&server.addr).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        return TestConnection { reader: BufReader::new(stream), pending_heads: VecDeque::new() };
+    }
 
-    // Send a basic HTTP request
-    stream.write_all(request.as_bytes()).unwrap();
-    stream.shutdown(std::net::Shutdown::Write).unwrap();
+    pub fn send(&mut self, request: &str) -> String {
+        self.write(request);
+        return self.read_response();
+    }
 
-    // Read the response into a string
-    let mut response = String::new();
-    stream.read_to_string(&mut response).unwrap();
+    pub fn write(&mut self, request: &str) {
+        self.reader.get_mut().write_all(request.as_bytes()).expect("Failed to send the request");
+        self.pending_heads.push_back(request.starts_with("HEAD "));
+    }
+
+    // The next response, head and body as one string like send_request() returns; "" if the server closed.
+    pub fn read_response(&mut self) -> String {
+        let head_only = self.pending_heads.pop_front().unwrap_or(false);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).expect("Failed to read the response head") == 0 {
+                return head;
+            }
+            head.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+
+        let status: u16 = head.split_whitespace().nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+        let mut body = Vec::new();
+        match content_length(&head) {
+            _ if head_only || status < 200 || status == 204 || status == 304 => {}
+            Some(length) => {
+                (&mut self.reader).take(length as u64).read_to_end(&mut body).expect("Failed to read the body");
+                assert_eq!(body.len(), length, "Body cut short:\n{}", head);
+            }
+            None => {
+                self.reader.read_to_end(&mut body).expect("Failed to read the body");
+            }
+        }
+        return head + &String::from_utf8_lossy(&body);
+    }
+
+    // True once the server has closed the connection (nothing more arrives before the end).
+    pub fn closed_by_server(&mut self) -> bool {
+        return matches!(self.reader.fill_buf(), Ok(rest) if rest.is_empty());
+    }
+}
+
+/*
+Shrink `stream`'s receive buffer (SO_RCVBUF) to `bytes`. The small window it advertises keeps the
+server's send buffer full, so a large response goes out in many short send() calls.
+*/
+pub fn shrink_receive_buffer(stream: &TcpStream, bytes: i32) {
+    #[cfg(windows)]
+    {
+        use std::os::windows::io::AsRawSocket;
+        use windows_sys::Win32::Networking::WinSock::{setsockopt, SOL_SOCKET, SO_RCVBUF};
+        // Unsafe: FFI call; the option value is a live i32 of the length passed.
+        let value = &bytes as *const i32 as *const u8;
+        let result = unsafe { setsockopt(stream.as_raw_socket() as usize, SOL_SOCKET, SO_RCVBUF, value, size_of::<i32>() as i32) };
+        assert_eq!(result, 0, "setsockopt(SO_RCVBUF) failed");
+    }
+    #[cfg(not(windows))]
+    let _ = (stream, bytes);
+}
+
+// The root_directory of the shared server.
+pub fn root_directory() -> String {
+    return server().root_directory().to_string_lossy().into_owned();
+}
+
+// Split a response into its head and body at the first blank line.
+pub fn split_response(response: &str) -> (&str, &str) {
+    return response.split_once("\r\n\r\n").expect("Response has no header terminator");
+}
+
+// The value of the Content-Length header in a response head, if present.
+pub fn content_length(head: &str) -> Option<usize> {
+    return head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse().ok());
+}
 
-    return response;
+// True if a response's body is as long as its Content-Length says: nothing lost to a reset.
+pub fn whole_body(response: &str) -> bool {
+    let (head, body) = split_response(response);
+    return content_length(head) == Some(body.len());
 }
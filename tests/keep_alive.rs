@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+use common::{server, root_directory, split_response, whole_body, TestConnection};
+
+// All of these need a server started with keep_alive = true.
+
+#[test]
+fn test_sequential_requests_on_one_connection() {
+    if !server().config.keep_alive {
+        return;
+    }
+    let name = "vibettp_keep_alive_test.txt";
+    let path = Path::new(&root_directory()).join(name);
+    let contents = "keep-alive ".repeat(2_000);
+    fs::write(&path, &contents).expect("Failed to create test file");
+
+    let mut connection = TestConnection::open();
+    let routed = connection.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let file = connection.send(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: identity\r\n\r\n", name));
+    let about = connection.send("GET /about HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let _ = fs::remove_file(&path);
+
+    for response in [&routed, &file, &about] {
+        let (head, _) = split_response(response);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+        assert!(!head.contains("Connection: close"), "Connection closed early:\n{}", head);
+        assert!(whole_body(response), "Truncated:\n{}", response);
+    }
+    assert_eq!(split_response(&file).1, contents);
+}
+
+#[test]
+fn test_pipelined_requests() {
+    if !server().config.keep_alive {
+        return;
+    }
+    // Both requests in one write: the second sits in the server's buffer while the first is answered.
+    let mut connection = TestConnection::open();
+    connection.write("GET /about HTTP/1.1\r\nHost: localhost\r\n\r\nHEAD / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let about = connection.read_response();
+    let head = connection.read_response();
+    assert!(about.starts_with("HTTP/1.1 200 OK") && whole_body(&about), "First response:\n{}", about);
+    assert!(head.starts_with("HTTP/1.1 200 OK"), "Second response:\n{}", head);
+    assert_eq!(split_response(&head).1, "", "HEAD response with a body");
+
+    // The connection is still good for a third.
+    let again = connection.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(again.starts_with("HTTP/1.1 200 OK") && whole_body(&again), "Third response:\n{}", again);
+}
+
+#[test]
+fn test_connection_close_ends_it() {
+    if !server().config.keep_alive {
+        return;
+    }
+    let mut connection = TestConnection::open();
+    let first = connection.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(first.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", first);
+    let last = connection.send("GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(split_response(&last).0.contains("Connection: close"), "Missing Connection: close:\n{}", last);
+    assert!(whole_body(&last), "Truncated:\n{}", last);
+    assert!(connection.closed_by_server(), "Still open after Connection: close");
+}
@@ -1,5 +1,12 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
 mod common;
-use common::{send_request};
+use common::{send_request, server, shrink_receive_buffer, TestServer, TestConnection, root_directory, split_response, content_length, whole_body};
+use vibettp::config::Linger;
+use vibettp::request::Request;
+use vibettp::response::HTTPStatus;
+use vibettp::router::Fallback;
 
 /*
 Tests using Rust’s built-in #[test] attribute are executed in parallel by default (via cargo test).
@@ -19,10 +26,48 @@ fn test_400() {
 
 #[test]
 fn test_400_2() {
-    let response = send_request("NOT_A_REQUEST");
+    let response = send_request("NOT_A_REQUEST\r\n\r\n");
     assert!(response.contains("400 Bad Request"), "Expected 400, got:\n{}", response);
 }
 
+// send_request() shuts down its write side after the request: a normal end, answered once.
+#[test]
+fn test_half_close_after_complete_request() {
+    let response = send_request("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+    assert!(!response.contains("400 Bad Request"), "Spurious 400 after the response:\n{}", response);
+}
+
+// Send, shut down the write side, read: complete responses, and the server ends the connection.
+#[test]
+fn test_half_close_routed_and_static() {
+    let response = send_request("POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello");
+    let (head, body) = split_response(&response);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert!(head.contains("Connection: close"), "Half-closed client kept alive:\n{}", head);
+    assert_eq!(body, "hello");
+
+    let name = "vibettp_half_close_test.txt";
+    let path = std::path::Path::new(&root_directory()).join(name);
+    let contents = "0123456789".repeat(10_000);
+    std::fs::write(&path, &contents).expect("Failed to create test file");
+    let response = send_request(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: identity\r\n\r\n", name));
+    let _ = std::fs::remove_file(&path);
+
+    let (head, body) = split_response(&response);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert!(head.contains("Connection: close"), "Half-closed client kept alive:\n{}", head);
+    assert_eq!(content_length(head), Some(contents.len()));
+    assert_eq!(body, contents, "Body was truncated");
+}
+
+// A client that leaves halfway through its request is gone: no 400 into the closed socket.
+#[test]
+fn test_truncated_request_gets_no_response() {
+    let response = send_request("GET / HTTP/1.1\r\nHost: loc");
+    assert_eq!(response, "", "Expected no response, got:\n{}", response);
+}
+
 #[test]
 fn test_404() {
     let response = send_request("GET /test HTTP/1.1\r\nHost: localhost\r\n\r\n");
@@ -33,12 +78,667 @@ fn test_404() {
 fn test_405() {
     let response = send_request("PUT / HTTP/1.1\r\nHost: localhost\r\n\r\n");
     assert!(response.contains("405 Method Not Allowed"), "Expected 405, got:\n{}", response);
+    let allow = response.lines()
+        .find(|line| line.starts_with("Allow:"))
+        .unwrap_or_else(|| panic!("405 without Allow header:\n{}", response));
+    assert!(allow.contains("GET"), "Allow does not list GET: {}", allow);
+}
+
+#[test]
+fn test_405_put_outside_upload_area() {
+    // PUT is on, but not here: Allow lists everything else the server accepts, HEAD and DELETE included.
+    let server = TestServer::start("allow_put = true\nallow_delete = true\nupload_directory = '{dir}/uploads'");
+    let response = server.send("PUT /about HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 405 "), "Expected 405, got:\n{}", response);
+    assert!(response.contains("\r\nAllow: GET, POST, HEAD, DELETE\r\n"), "Wrong Allow header:\n{}", response);
 }
 
 #[test]
 fn test_413() {
-    let mut large_body = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9000\r\n\r\n".to_string();
-    large_body.push_str(&"A".repeat(9000));
-    let response = send_request(&large_body);
+    // Headers alone larger than max_header_bytes.
+    let mut large_head = "GET / HTTP/1.1\r\nHost: localhost\r\n".to_string();
+    large_head.push_str(&format!("X-Padding: {}\r\n\r\n", "A".repeat(server().config.max_header_bytes + 800)));
+    let response = send_request(&large_head);
+    assert!(response.contains("413 Content Too Large"), "Expected 413, got:\n{}", response);
+}
+
+#[test]
+fn test_headers_within_limit() {
+    // A fat header that still fits under max_header_bytes is served normally.
+    let padding = "A".repeat(server().config.max_header_bytes - 200);
+    let response = send_request(&format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\nConnection: close\r\n\r\n", padding
+    ));
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+}
+
+#[test]
+fn test_413_body_refused_before_it_is_read() {
+    // Over the default max_body_size: answered from the headers, without sending the body.
+    let response = send_request("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2000000000\r\n\r\nAAAA");
     assert!(response.contains("413 Content Too Large"), "Expected 413, got:\n{}", response);
 }
+
+#[test]
+fn test_413_never_reset() {
+    // Refused with request bytes still unread: the graceful close must not turn into a reset.
+    if server().config.linger == Linger::Off {
+        return;
+    }
+    let mut large_head = "GET / HTTP/1.1\r\nHost: localhost\r\n".to_string();
+    large_head.push_str(&format!("X-Padding: {}\r\n\r\n", "A".repeat(server().config.max_header_bytes * 4)));
+    let large_body = format!("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2000000000\r\n\r\n{}", "A".repeat(64 * 1024));
+    for round in 0..20 {
+        for request in [&large_head, &large_body] {
+            let response = send_request(request);
+            assert!(response.contains("413 Content Too Large"), "Round {}: expected 413, got:\n{}", round, response);
+        }
+    }
+}
+
+#[test]
+fn test_route_body_limit() {
+    let server = TestServer::start("[limits]\n\"/contact\" = 1024");
+    let body = "A".repeat(100 * 1024);
+
+    // Over the /contact limit: 413 from the headers, and the connection is closed without reading the rest.
+    let mut connection = TestConnection::open_to(&server);
+    connection.write(&format!("POST /contact HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), &body[..4096]));
+    let response = connection.read_response();
+    assert!(response.starts_with("HTTP/1.1 413 "), "Expected 413, got:\n{}", response);
+    assert!(response.contains("\r\nConnection: close\r\n"), "Not closing:\n{}", response);
+    assert!(connection.closed_by_server(), "Connection left open after the 413");
+
+    // Elsewhere the default max_body_size applies, and the same body goes through.
+    let response = server.send(&format!("POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", &response[..response.len().min(200)]);
+    assert_eq!(content_length(&response), Some(body.len()));
+}
+
+#[test]
+fn test_route_registered_with_max_body() {
+    let server = TestServer::start_with("", |router| {
+        router.post("/feedback", |req: &Request| format!("{} bytes", req.body.len())).max_body(1024);
+    });
+    let body = "A".repeat(100 * 1024);
+
+    // Over the route's own limit, though well under max_body_size: 413 before the body is read.
+    let mut connection = TestConnection::open_to(&server);
+    connection.write(&format!("POST /feedback HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", body.len(), &body[..4096]));
+    let response = connection.read_response();
+    assert!(response.starts_with("HTTP/1.1 413 "), "Expected 413, got:\n{}", response);
+    assert!(connection.closed_by_server(), "Connection left open after the 413");
+
+    let response = server.send("POST /feedback HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello");
+    assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("5 bytes"), "{}", response);
+}
+
+// Every error answer arrives in full before the close, even with request bytes left unread.
+#[test]
+fn test_error_bodies_arrive_whole() {
+    let requests = [
+        ("400", "NOT_A_REQUEST\r\nX-Filler: AAAA\r\n\r\n".to_string()),
+        ("405", format!("PATCH / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\n\r\n{}", "A".repeat(4096))),
+        ("413", format!("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2000000000\r\n\r\n{}", "A".repeat(16 * 1024))),
+    ];
+    for (status, request) in &requests {
+        let response = send_request(request);
+        assert!(response.starts_with(&format!("HTTP/1.1 {} ", status)), "Expected {}, got:\n{}", status, response);
+        assert!(whole_body(&response), "Truncated {}:\n{}", status, response);
+    }
+}
+
+#[test]
+fn test_post_body_within_limit() {
+    let body = "A".repeat(100 * 1024);
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body
+    );
+    let response = send_request(&request);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+}
+
+#[test]
+fn test_spooled_body_above_memory_threshold() {
+    // Over the default body_memory_threshold (256 KiB), under max_body_size: spooled to disk.
+    let body = "B".repeat(600 * 1024);
+    let request = format!(
+        "POST /debug/echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body
+    );
+    let response = TestServer::start("debug_endpoints = true").send(&request);
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+    assert!(response.contains(&format!("body length: {}\n", body.len())), "{}", response);
+}
+
+#[test]
+fn test_pipelined_request_after_body() {
+    // The body must be consumed exactly, so the second request is parsed as a request.
+    let response = send_request(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+         GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    );
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 2, "Expected two responses:\n{}", response);
+    assert!(response.contains("About us"), "Second request was not served:\n{}", response);
+}
+
+#[test]
+fn test_large_static_file_is_complete() {
+    // A few megabytes is far more than a single send() call will reliably accept.
+    let name = "vibettp_large_file_test.html";
+    let path = std::path::Path::new(&root_directory()).join(name);
+    // Numbered lines, so a lost or repeated chunk shows up as a mismatch and not just a short body.
+    let contents: String = (0..4 * 1024 * 1024 / 8).map(|line| format!("{:07}\n", line)).collect();
+    std::fs::write(&path, &contents).expect("Failed to create test file");
+
+    // A tiny receive window on our side forces the server through partial sends.
+    let mut stream = TcpStream::connect(&server().addr).expect("Failed to connect");
+    shrink_receive_buffer(&stream, 1024);
+    stream.write_all(format!("GET /{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", name).as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    let (head, body) = split_response(&response);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert_eq!(content_length(head), Some(contents.len()));
+    assert_eq!(body.len(), contents.len(), "Body was truncated");
+    assert!(body == contents, "Body differs from the file");
+}
+
+#[test]
+fn test_options_asterisk() {
+    let response = send_request("OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let (head, body) = split_response(&response);
+    assert!(head.starts_with("HTTP/1.1 204 No Content"), "Expected 204, got:\n{}", head);
+    assert!(head.contains("\r\nAllow: GET, POST"), "Missing Allow header:\n{}", head);
+    assert!(body.is_empty(), "204 must not have a body:\n{}", body);
+}
+
+#[test]
+fn test_echo_route_per_method() {
+    let response = send_request("GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let (head, body) = split_response(&response);
+    assert!(head.contains("200 OK") && body.contains("POST a body"), "Expected the usage text:\n{}", response);
+
+    let response = send_request(
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Type: text/x-test\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+    );
+    let (head, body) = split_response(&response);
+    assert!(head.contains("\r\nContent-Type: text/x-test"), "{}", head);
+    assert_eq!(body, "hello");
+}
+
+#[test]
+fn test_redundant_segments_reach_route() {
+    for path in ["//about", "/./about", "/about/."] {
+        let response = send_request(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path));
+        assert!(response.contains("About us"), "{} did not reach /about:\n{}", path, response);
+    }
+}
+
+#[test]
+fn test_multiple_byte_ranges() {
+    let name = "vibettp_ranges_test.txt";
+    let path = std::path::Path::new(&root_directory()).join(name);
+    std::fs::write(&path, "0123456789abcdefghij").expect("Failed to create test file");
+
+    let response = send_request(&format!(
+        "GET /{} HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-3,10-12\r\n\r\n", name
+    ));
+    let _ = std::fs::remove_file(&path);
+
+    let (head, body) = split_response(&response);
+    assert!(head.starts_with("HTTP/1.1 206 Partial Content"), "Expected 206, got:\n{}", head);
+    assert!(head.contains("multipart/byteranges; boundary="), "Not multipart:\n{}", head);
+    assert_eq!(content_length(head), Some(body.len()));
+    assert!(body.contains("Content-Range: bytes 0-3/20\r\n\r\n0123\r\n"), "Bad first part:\n{}", body);
+    assert!(body.contains("Content-Range: bytes 10-12/20\r\n\r\nabc\r\n"), "Bad second part:\n{}", body);
+}
+
+#[test]
+fn test_markdown_rendered_and_raw() {
+    let server = TestServer::start("render_markdown = true");
+    let name = "vibettp_markdown_test.md";
+    let source = "# Notes\n\n- *one* <script>alert(1)</script>\n";
+    std::fs::write(server.root_directory().join(name), source).expect("Failed to create test file");
+
+    let rendered = server.send(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", name));
+    let raw = server.send(&format!("GET /{}?raw=1 HTTP/1.1\r\nHost: localhost\r\n\r\n", name));
+
+    let (head, body) = split_response(&raw);
+    assert!(head.contains("\r\nContent-Type: text/markdown; charset=utf-8"), "Expected the source, got:\n{}", head);
+    assert_eq!(body, source);
+
+    let (head, body) = split_response(&rendered);
+    assert!(head.contains("\r\nContent-Type: text/html; charset=utf-8"), "Not rendered:\n{}", head);
+    assert!(body.contains("<h1>Notes</h1>\n<ul>\n<li><em>one</em> &lt;script&gt;alert(1)&lt;/script&gt;</li>"), "{}", body);
+    assert!(!body.contains("<script>"), "{}", body);
+
+    // The default server leaves .md files alone.
+    let path = std::path::Path::new(&root_directory()).join(name);
+    std::fs::write(&path, source).expect("Failed to create test file");
+    let plain = send_request(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n", name));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(split_response(&plain).1, source, "Rendered without render_markdown");
+}
+
+#[test]
+fn test_custom_routes_and_fallback() {
+    let server = TestServer::start_with("", |router| {
+        router.get("/version", |_req: &Request| "1.2.3");
+        router.set_fallback(Fallback::custom(|req: &Request| (HTTPStatus::NotFound, format!("No page at {}", req.path))));
+    });
+    std::fs::write(server.root_directory().join("page.txt"), "on disk").expect("Failed to create test file");
+
+    let response = server.send("GET /version HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("1.2.3"), "{}", response);
+    // Unrouted paths go to the custom fallback, not the disk; the default routes stay.
+    let response = server.send("GET /page.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 ") && response.ends_with("No page at /page.txt"), "{}", response);
+    let response = server.send("GET /about HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+}
+
+#[test]
+fn test_directory_listing_formats_agree() {
+    let server = TestServer::start("directory_listing = true");
+    let dir = server.root_directory().join("vibettp_listing_test");
+    std::fs::create_dir_all(dir.join("sub")).expect("Failed to create test directory");
+    std::fs::write(dir.join("b.txt"), "bb").expect("Failed to create test file");
+    std::fs::write(dir.join("A b&c.txt"), "a").expect("Failed to create test file");
+
+    let request = |accept: &str| server.send(&format!(
+        "GET /vibettp_listing_test/ HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n\r\n", accept
+    ));
+    let (json, html) = (request("application/json"), request("text/html"));
+
+    let (head, body) = split_response(&json);
+    assert!(head.contains("\r\nContent-Type: application/json\r\n") && head.contains("\r\nVary: Accept"), "{}", head);
+    let names: Vec<&str> = body.split("{\"name\":\"").skip(1).map(|rest| rest.split('"').next().unwrap()).collect();
+    assert_eq!(names, ["sub", "A b&c.txt", "b.txt"]);
+    assert!(body.contains("\"size\":2,") && body.contains("\"is_dir\":true"), "{}", body);
+
+    let (head, body) = split_response(&html);
+    assert!(head.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"), "{}", head);
+    let links: Vec<&str> = body.split("<tr><td><a href=\"").skip(1).map(|rest| rest.split('"').next().unwrap()).collect();
+    assert_eq!(links, ["../", "/vibettp_listing_test/sub/", "/vibettp_listing_test/A%20b%26c.txt", "/vibettp_listing_test/b.txt"]);
+}
+
+#[test]
+fn test_head_matches_ranged_get() {
+    let name = "vibettp_head_range_test.txt";
+    let path = std::path::Path::new(&root_directory()).join(name);
+    std::fs::write(&path, "0123456789abcdefghij").expect("Failed to create test file");
+
+    let request = |method: &str| send_request(&format!(
+        "{} /{} HTTP/1.1\r\nHost: localhost\r\nRange: bytes=5-9\r\n\r\n", method, name
+    ));
+    let (get, head) = (request("GET"), request("HEAD"));
+    let _ = std::fs::remove_file(&path);
+
+    // Server-Timing is the one header allowed to differ between the two.
+    let headers = |response: &str| -> Vec<String> {
+        return split_response(response).0.lines().filter(|line| !line.starts_with("Server-Timing:")).map(String::from).collect();
+    };
+    assert!(get.starts_with("HTTP/1.1 206 Partial Content"), "Expected 206, got:\n{}", get);
+    assert_eq!(split_response(&get).1, "56789");
+    assert_eq!(headers(&get), headers(&head));
+    assert!(head.contains("\r\nContent-Range: bytes 5-9/20\r\n") && head.contains("\r\nAccept-Ranges: bytes\r\n"), "{}", head);
+    assert_eq!(split_response(&head).1, "", "HEAD response with a body:\n{}", head);
+}
+
+#[test]
+fn test_http_1_0_closes_connection() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    // No write-side shutdown: an HTTP/1.0 request alone must make the server close.
+    let mut stream = TcpStream::connect(&server().addr).expect("Failed to connect to server");
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\n\r\n").expect("Failed to write request");
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("Server kept the HTTP/1.0 connection open");
+    let (head, _) = split_response(&response);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert!(head.contains("\r\nConnection: close"), "Missing Connection: close:\n{}", head);
+}
+
+/*
+The timeout tests wait out the server's timeouts (keep_alive_timeout_ms while idle, timeout_ms
+mid-request), a few seconds each with the fixture config.
+*/
+fn read_after_timeout(server: &TestServer, partial_request: &[u8], wait_ms: u64) -> String {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    let mut stream = TcpStream::connect(&server.addr).expect("Failed to connect to server");
+    stream.set_read_timeout(Some(Duration::from_millis(wait_ms + 5000))).unwrap();
+    stream.write_all(partial_request).expect("Failed to write request");
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("Server did not close the connection after its timeout");
+    return response;
+}
+
+#[test]
+fn test_idle_connection_closes_quietly() {
+    let response = read_after_timeout(server(), b"", server().config.idle_timeout_ms());
+    assert!(response.is_empty(), "Idle connection got a response:\n{}", response);
+}
+
+#[test]
+fn test_partial_request_times_out() {
+    let response = read_after_timeout(server(), b"GET / HTTP/1.1\r\nHost: local", server().config.request_timeout_ms());
+    let (head, _) = split_response(&response);
+    assert!(head.starts_with("HTTP/1.1 408 Request Timeout"), "Expected 408, got:\n{}", head);
+    assert!(head.contains("\r\nConnection: close"), "Missing Connection: close:\n{}", head);
+    assert!(whole_body(&response), "Truncated 408:\n{}", response);
+}
+
+#[test]
+fn test_sub_second_timeout() {
+    use std::time::{Duration, Instant};
+
+    let server = TestServer::start("timeout_ms = 300");
+    let started = Instant::now();
+    let response = read_after_timeout(&server, b"GET / HTTP/1.1\r\nHost: local", server.config.request_timeout_ms());
+    let elapsed = started.elapsed();
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "Expected 408, got:\n{}", response);
+    assert!(elapsed < Duration::from_secs(1), "408 took {:?}", elapsed);
+}
+
+#[test]
+fn test_idle_and_request_timeouts_apply_separately() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let server = TestServer::start("keep_alive_timeout_ms = 1000\ntimeout_ms = 10000");
+
+    // Silent for longer than the idle timeout: closed after about a second, without a response.
+    let started = Instant::now();
+    let response = read_after_timeout(&server, b"", 2000);
+    assert!(response.is_empty(), "Idle connection got a response:\n{}", response);
+    assert!(started.elapsed() < Duration::from_secs(3), "Idle connection held for {:?}", started.elapsed());
+
+    // A request trickled in over 5 s is mid-request the whole time, so the 10 s timeout applies.
+    let mut stream = TcpStream::connect(&server.addr).expect("Failed to connect to server");
+    stream.set_read_timeout(Some(Duration::from_secs(15))).unwrap();
+    let pieces = ["GET / HTTP/1.1\r\n", "Host: localhost\r\n", "X-Slow: 1\r\n", "X-Slow: 2\r\n", "X-Slow: 3\r\n", "Connection: close\r\n"];
+    for piece in pieces {
+        stream.write_all(piece.as_bytes()).expect("Closed while the request was still coming in");
+        thread::sleep(Duration::from_secs(1));
+    }
+    stream.write_all(b"\r\n").expect("Failed to finish the request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("Failed to read the response");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200 after a slow request, got:\n{}", response);
+}
+
+#[test]
+fn test_slow_reader_is_cut_off() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::{Duration, Instant};
+
+    let server = TestServer::start("response_write_timeout = \"2s\"");
+    let limit = server.config.response_write_timeout_ms().unwrap();
+
+    // Far more than the socket buffers hold. set_len() leaves it to the file system to fill in the zeros.
+    let name = "vibettp_slow_reader_test.bin";
+    let path = server.root_directory().join(name);
+    let size = 64 * 1024 * 1024;
+    std::fs::File::create(&path).and_then(|file| file.set_len(size as u64)).expect("Failed to create test file");
+
+    let mut stream = TcpStream::connect(&server.addr).expect("Failed to connect to server");
+    stream.set_read_timeout(Some(Duration::from_millis(limit + 5000))).unwrap();
+    let request = format!("GET /{} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: identity\r\n\r\n", name);
+    stream.write_all(request.as_bytes()).unwrap();
+
+    // Don't read until well past the deadline; then whatever the socket buffers held, and the end.
+    let started = Instant::now();
+    std::thread::sleep(Duration::from_millis(limit + 1000));
+    let mut received = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+    let closed = loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => break true,
+            Ok(n) => received += n,
+            Err(e) => break !matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut),
+        }
+    };
+    let _ = std::fs::remove_file(&path);
+
+    assert!(closed, "The server kept sending past response_write_timeout");
+    assert!(received < size, "The whole {} byte response arrived", size);
+    assert!(started.elapsed() < Duration::from_millis(limit + 5000), "Closed only after {:?}", started.elapsed());
+}
+
+#[test]
+fn test_download_is_throttled() {
+    use std::time::{Duration, Instant};
+
+    // A 1 MiB file at 256 KiB per second: about 4 s.
+    let server = TestServer::start("max_bytes_per_second_per_connection = 262144");
+    let cap = server.config.max_bytes_per_second_per_connection;
+    let size: u64 = 1024 * 1024;
+    let expected = Duration::from_secs_f64(size as f64 / cap as f64);
+    let name = "vibettp_throttle_test.bin";
+    std::fs::write(server.root_directory().join(name), vec![b'x'; size as usize]).expect("Failed to create test file");
+
+    let started = Instant::now();
+    let response = server.send(&format!("GET /{} HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: identity\r\n\r\n", name));
+    let elapsed = started.elapsed();
+
+    assert!(whole_body(&response), "Truncated after {:?}", elapsed);
+    assert!(elapsed > expected.mul_f64(0.8), "{} bytes in {:?}, faster than the cap", size, elapsed);
+    assert!(elapsed < expected.mul_f64(1.2) + Duration::from_secs(1), "{} bytes in {:?}, expected about {:?}", size, elapsed, expected);
+
+    // A small file goes out at once.
+    let started = Instant::now();
+    assert!(server.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").contains("200 OK"));
+    assert!(started.elapsed() < Duration::from_millis(500), "A small response took {:?}", started.elapsed());
+}
+
+#[test]
+fn test_keep_alive_header_advertises_idle_timeout() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    if !server().config.keep_alive {
+        return;
+    }
+    let mut stream = TcpStream::connect(&server().addr).expect("Failed to connect to server");
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").expect("Failed to write request");
+
+    // Read just the head; the connection stays open.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).expect("Connection closed before the end of the head");
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let expected = format!("\r\nKeep-Alive: timeout={}\r\n", server().config.idle_timeout_ms() / 1000);
+    assert!(head.contains(&expected), "Missing {:?}:\n{}", expected.trim(), head);
+}
+
+#[test]
+fn test_debug_echo() {
+    let request = "POST /debug/echo?x=1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello";
+    let response = send_request(request);
+    assert!(response.contains("404 Not Found"), "/debug/echo must not exist when disabled:\n{}", response);
+
+    let response = TestServer::start("debug_endpoints = true").send(request);
+    let (head, body) = split_response(&response);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert!(body.contains("method: POST\n"), "{}", body);
+    assert!(body.contains("  x = 1\n"), "{}", body);
+    assert!(body.contains("body length: 5\n"), "{}", body);
+    assert!(body.contains("\"hello\""), "{}", body);
+}
+
+#[test]
+fn test_server_survives_panicking_handlers() {
+    let server = TestServer::start("debug_endpoints = true");
+    for round in 0..3 {
+        let response = server.send("GET /debug/panic HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 "), "Round {}: expected 500, got:\n{}", round, response);
+        assert!(whole_body(&response), "Truncated 500:\n{}", response);
+    }
+
+    // The panics cost those requests only: the server still answers on a keep-alive connection.
+    let mut connection = TestConnection::open_to(&server);
+    for path in ["/", "/about"] {
+        let response = connection.send(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path));
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200 for {}, got:\n{}", path, response);
+    }
+}
+
+#[test]
+fn test_chunked_response_keeps_connection() {
+    // A streamed (chunked) response, then a second request on the same connection.
+    let response = TestServer::start("debug_endpoints = true").send(
+        "GET /debug/echo?stream=1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+    );
+    let (head, rest) = split_response(&response);
+    assert!(head.contains("\r\nTransfer-Encoding: chunked"), "Not chunked:\n{}", head);
+    assert!(content_length(head).is_none(), "Chunked response with a Content-Length:\n{}", head);
+    assert!(rest.contains("path: /debug/echo\n"), "{}", rest);
+    assert!(head.contains("\r\nTrailer: X-Echo-Lines"), "Trailer not declared:\n{}", head);
+    let (_, after_last_chunk) = rest.split_once("\r\n0\r\n").expect("No last chunk");
+    assert!(after_last_chunk.starts_with("X-Echo-Lines: "), "Trailer missing:\n{}", after_last_chunk);
+    assert!(after_last_chunk.contains("\r\n\r\nHTTP/1.1 200 OK"), "Second response missing after the trailers:\n{}", rest);
+    assert!(rest.ends_with("<h1>About us</h1>"), "{}", rest);
+}
+
+#[test]
+fn test_put_upload_round_trip() {
+    let body = "uploaded \u{00e9}\u{00e8} 0123456789".repeat(50);
+    let request = format!(
+        "PUT /uploads/vibettp_put_test.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body
+    );
+    let put = send_request(&request);
+    assert!(put.contains("405 Method Not Allowed"), "PUT must be refused when disabled:\n{}", put);
+
+    let server = TestServer::start("allow_put = true\nupload_directory = '{dir}/uploads'");
+    let put = server.send(&request);
+    assert!(put.contains("201 Created") || put.contains("204 No Content"), "Upload failed:\n{}", put);
+    let stored = std::fs::read(server.dir.join("uploads").join("vibettp_put_test.txt")).expect("Upload not stored");
+    assert_eq!(stored, body.as_bytes());
+
+    let get = server.send("GET /uploads/vibettp_put_test.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let (head, received) = split_response(&get);
+    assert!(head.contains("200 OK"), "Expected 200, got:\n{}", head);
+    assert_eq!(received.as_bytes(), body.as_bytes());
+}
+
+#[test]
+fn test_delete_upload() {
+    let request = |path: &str| format!("DELETE {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    let response = send_request(&request("/uploads/vibettp_delete_test.txt"));
+    assert!(response.contains("405 Method Not Allowed"), "DELETE must be refused when disabled:\n{}", response);
+
+    // Happy path needs a file to delete; allow_put is the only way to create one over HTTP.
+    let server = TestServer::start("allow_put = true\nallow_delete = true\nupload_directory = '{dir}/uploads'");
+    let delete = |path: &str| server.send(&request(path));
+    let put = server.send(
+        "PUT /uploads/vibettp_delete_test.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nConnection: close\r\n\r\nbye"
+    );
+    assert!(put.contains("201 Created") || put.contains("204 No Content"), "Upload failed:\n{}", put);
+    let response = delete("/uploads/vibettp_delete_test.txt");
+    assert!(response.contains("204 No Content"), "Expected 204, got:\n{}", response);
+    assert!(!server.dir.join("uploads").join("vibettp_delete_test.txt").exists(), "Still on disk after the 204");
+
+    let response = delete("/uploads/vibettp_delete_test.txt");
+    assert!(response.contains("404 Not Found"), "Expected 404 for a missing file, got:\n{}", response);
+
+    // Outside the upload area, or escaping it (to the server's config, next to uploads/): refused without touching the file.
+    let response = delete("/vibettp_not_an_upload.txt");
+    assert!(response.contains("403 Forbidden"), "Expected 403, got:\n{}", response);
+    let response = delete("/uploads/../config.toml");
+    assert!(response.contains("400 Bad Request"), "Expected 400, got:\n{}", response);
+    assert!(server.config_path.exists());
+}
+
+#[test]
+fn test_security_headers_on_success_only() {
+    let server = TestServer::start("security_headers = true");
+    let response = server.send("GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let (head, _) = split_response(&response);
+    for header in ["X-Content-Type-Options: nosniff", "X-Frame-Options: DENY", "Referrer-Policy: no-referrer"] {
+        assert!(head.contains(&format!("\r\n{}\r\n", header)), "Missing {}:\n{}", header, head);
+    }
+    assert!(!head.contains("Strict-Transport-Security"), "HSTS over plain HTTP:\n{}", head);
+
+    let response = server.send("GET /vibettp-no-such-page HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let (head, _) = split_response(&response);
+    assert!(head.contains("404 Not Found"), "{}", head);
+    assert!(!head.contains("X-Frame-Options"), "Security headers on an error page:\n{}", head);
+
+    // Off, as in the fixture: none at all.
+    let response = send_request("GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(!response.contains("X-Frame-Options"), "Security headers while off:\n{}", response);
+}
+
+#[test]
+fn test_about_content_negotiation() {
+    let response = send_request("GET /about HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\nConnection: close\r\n\r\n");
+    let (head, body) = split_response(&response);
+    assert!(head.contains("Content-Type: application/json"), "{}", head);
+    assert!(body.starts_with('{'), "{}", body);
+
+    let response = send_request("GET /about HTTP/1.1\r\nHost: localhost\r\nAccept: text/html, */*;q=0.1\r\nConnection: close\r\n\r\n");
+    assert!(response.contains("About us") && response.contains("text/html"), "{}", response);
+}
+
+/*
+A hand-written PROXY v1 line ahead of the request. With proxy_protocol on, it is consumed and a
+connection without one is closed unanswered; with it off, the line is just a malformed request.
+Note that with proxy_protocol on, the other tests (which send plain HTTP) are refused too.
+*/
+#[test]
+fn test_proxy_protocol_line() {
+    let proxied = "PROXY TCP4 203.0.113.7 127.0.0.1 56324 7878\r\n\
+                   GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    let response = send_request(proxied);
+    if server().config.proxy_protocol {
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+        let response = send_request("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        assert!(response.is_empty(), "Answered without a PROXY line:\n{}", response);
+    } else {
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request"), "Expected 400, got:\n{}", response);
+    }
+}
+
+/*
+max_connections_per_ip behind a PROXY-protocol balancer, with IPv6 clients: the second connection
+from the same address gets 429, while another address is still served.
+*/
+#[test]
+fn test_proxy_protocol_ipv6_per_ip_limit() {
+    let server = TestServer::start("proxy_protocol = true\nmax_connections_per_ip = 1");
+    let proxied = |source: &str| format!(
+        "PROXY TCP6 {} ::1 56324 7878\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n", source);
+
+    // Held open by keep-alive, so it keeps counting.
+    let mut first = TestConnection::open_to(&server);
+    let response = first.send(&proxied("2001:db8::7"));
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+
+    let response = TestConnection::open_to(&server).send(&proxied("2001:db8::7"));
+    assert!(response.starts_with("HTTP/1.1 429"), "Expected 429, got:\n{}", response);
+
+    let response = TestConnection::open_to(&server).send(&proxied("2001:db8::8"));
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+}
@@ -1,19 +1,22 @@
 use std::thread;
 use std::time::Duration;
 use std::net::TcpStream;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::sync::{Arc, Barrier};
 
 mod common;
 
-use common::{send_request, SERVER_ADDR};
+use common::{whole_body, TestServer};
 
 #[test]
 fn test_503() {
+    let server = TestServer::start("max_clients = 4");
     // Spawn 4 clients to saturate the server
     let mut handles = vec![];
     for _ in 0..4 {
-        handles.push(thread::spawn(|| {
-            let mut stream = TcpStream::connect(SERVER_ADDR).unwrap();
+        let addr = server.addr.clone();
+        handles.push(thread::spawn(move || {
+            let mut stream = TcpStream::connect(&addr).unwrap();
             let request = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
             stream.write_all(request.as_bytes()).unwrap();
             thread::sleep(Duration::from_secs(3)); // Keep connection open
@@ -24,7 +27,7 @@ fn test_503() {
     thread::sleep(Duration::from_millis(500));
 
     // Attempt a 5th connection
-    let response = send_request("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n");
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n");
     assert!(response.contains("503 Service Unavailable"), "Expected 503, got:\n{}", response);
 
     /*
@@ -50,3 +53,194 @@ fn test_503() {
         }
     }
 }
+
+// Every max_clients slot taken by an answered keep-alive connection, open until the streams are dropped.
+fn fill_slots(server: &TestServer) -> Vec<TcpStream> {
+    let held = (0..server.config.max_clients).map(|_| {
+        let mut stream = TcpStream::connect(&server.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n").unwrap();
+        let mut buffer = [0u8; 4096];
+        let _ = stream.read(&mut buffer);
+        return stream;
+    }).collect();
+    thread::sleep(Duration::from_millis(200));
+    return held;
+}
+
+#[test]
+fn test_503_never_reset() {
+    let server = TestServer::start("max_clients = 2");
+    let held = fill_slots(&server);
+
+    // Every newcomer gets its whole 503, even with a request body the server never reads.
+    let request = format!("POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8192\r\n\r\n{}", "A".repeat(8192));
+    for round in 0..20 {
+        let response = server.send(&request);
+        assert!(response.contains("503 Service Unavailable"), "Round {}: expected 503, got:\n{}", round, response);
+        assert!(whole_body(&response), "Round {}: truncated 503:\n{}", round, response);
+    }
+    drop(held);
+}
+
+#[test]
+fn test_refusals_do_not_stall_accept() {
+    use std::time::Instant;
+
+    // Refused clients that neither send nor hang up: draining each one must not hold up the next accept().
+    let server = TestServer::start("max_clients = 1");
+    let held = fill_slots(&server);
+    let silent: Vec<TcpStream> = (0..100).map(|_| TcpStream::connect(&server.addr).unwrap()).collect();
+
+    let started = Instant::now();
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("503 Service Unavailable"), "Expected 503, got:\n{}", response);
+    assert!(started.elapsed() < Duration::from_secs(1), "Refused only after {:?}", started.elapsed());
+    drop((held, silent));
+}
+
+#[test]
+fn test_burst_never_exceeds_max_clients() {
+    let server = TestServer::start("max_clients = 4");
+    let config = &server.config;
+    // 3× max_clients connections at the same instant; at most max_clients may be served.
+    let total = config.max_clients * 3;
+    let barrier = Arc::new(Barrier::new(total));
+    let handles: Vec<_> = (0..total).map(|_| {
+        let barrier = Arc::clone(&barrier);
+        let addr = server.addr.clone();
+        thread::spawn(move || {
+            barrier.wait();
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            let request = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n";
+            stream.write_all(request.as_bytes()).unwrap();
+
+            // Admitted connections stay open (keep-alive), so read what arrives within the timeout.
+            let mut buffer = [0u8; 4096];
+            let n = stream.read(&mut buffer).unwrap_or(0);
+            let response = String::from_utf8_lossy(&buffer[..n]).to_string();
+            thread::sleep(Duration::from_secs(1)); // Hold the slot while the others are answered
+            return response.contains("200 OK");
+        })
+    }).collect();
+
+    let served = handles.into_iter().map(|h| h.join().unwrap()).filter(|&ok| ok).count();
+    assert!(served >= 1, "No connection was served");
+    assert!(served <= config.max_clients, "{} connections served at once, max_clients is {}", served, config.max_clients);
+}
+
+// `count` answered keep-alive connections, each hung up after `hold` on a thread of its own.
+fn hold_slots(server: &TestServer, count: usize, hold: Duration) -> Vec<thread::JoinHandle<()>> {
+    return (0..count).map(|_| {
+        let addr = server.addr.clone();
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(&addr).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n").unwrap();
+            let mut buffer = [0u8; 4096];
+            let _ = stream.read(&mut buffer);
+            thread::sleep(hold);
+        })
+    }).collect();
+}
+
+#[test]
+fn test_queued_client_is_served() {
+    use std::time::Instant;
+
+    // The held connections finish after a second; the queue keeps the newcomer longer than that.
+    let server = TestServer::start("max_clients = 2\noverflow = \"queue\"\nqueue_timeout_ms = 5000");
+    let holders = hold_slots(&server, server.config.max_clients, Duration::from_secs(1));
+    thread::sleep(Duration::from_millis(300));
+
+    // Over max_clients: parked rather than refused, and answered once a holder hangs up.
+    let started = Instant::now();
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200 after waiting, got:\n{}", response);
+    assert!(started.elapsed() >= Duration::from_millis(400), "Served after {:?}, before any slot was free", started.elapsed());
+    for holder in holders {
+        let _ = holder.join();
+    }
+}
+
+#[test]
+fn test_queue_timeout_gets_retry_after() {
+    // Nobody hangs up within queue_timeout_ms: the newcomer's wait ends in a 503.
+    let server = TestServer::start("max_clients = 2\noverflow = \"queue\"\nqueue_timeout_ms = 500");
+    let holders = hold_slots(&server, server.config.max_clients, Duration::from_secs(3));
+    thread::sleep(Duration::from_millis(300));
+
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"), "Expected 503, got:\n{}", response);
+    assert!(response.contains("\r\nRetry-After: 1\r\n"), "Missing Retry-After:\n{}", response);
+    assert!(whole_body(&response), "Truncated 503:\n{}", response);
+    for holder in holders {
+        let _ = holder.join();
+    }
+}
+
+#[test]
+fn test_per_ip_limit() {
+    // A per-IP limit below max_clients that applies to loopback.
+    let server = TestServer::start("max_clients = 4\nmax_connections_per_ip = 2\nexempt_loopback = false");
+    let limit = server.config.max_connections_per_ip;
+
+    // `limit` connections from 127.0.0.1 are admitted and stay open...
+    let mut open: Vec<TcpStream> = (0..limit).map(|_| TcpStream::connect(&server.addr).unwrap()).collect();
+    thread::sleep(Duration::from_millis(300));
+
+    // ...the next one from the same IP is refused...
+    let mut extra = TcpStream::connect(&server.addr).unwrap();
+    extra.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    let mut response = String::new();
+    let _ = extra.read_to_string(&mut response);
+    assert!(response.contains("429 Too Many Requests"), "Expected 429, got:\n{}", response);
+
+    // ...while the earlier ones are still served.
+    let first = &mut open[0];
+    first.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+    first.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    first.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+}
+
+#[test]
+fn test_per_ip_limit_exempts_loopback() {
+    let server = TestServer::start("max_clients = 4\nmax_connections_per_ip = 2\nexempt_loopback = true");
+    let _open: Vec<TcpStream> = (0..2).map(|_| TcpStream::connect(&server.addr).unwrap()).collect();
+    thread::sleep(Duration::from_millis(300));
+
+    // The third connection from 127.0.0.1 is served all the same.
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+}
+
+#[test]
+fn test_idle_connection_evicted_when_full() {
+    let server = TestServer::start("max_clients = 4\nevict_idle_when_full = true");
+
+    // Take every slot with a keep-alive connection that has been answered and now sits idle.
+    let mut idle: Vec<TcpStream> = (0..server.config.max_clients).map(|_| {
+        let mut stream = TcpStream::connect(&server.addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all(b"GET /about HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n").unwrap();
+        let mut buffer = [0u8; 4096];
+        let n = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..n]).contains("200 OK"));
+        thread::sleep(Duration::from_millis(50));
+        return stream;
+    }).collect();
+
+    // A newcomer is served instead of getting a 503...
+    let response = server.send("GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "Expected 200, got:\n{}", response);
+
+    // ...because the longest-idle connection was closed.
+    let mut rest = Vec::new();
+    let result = idle[0].read_to_end(&mut rest);
+    assert!(
+        !matches!(&result, Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)),
+        "The longest-idle connection is still open"
+    );
+}